@@ -12,4 +12,5 @@ fn main() {
     let reader = Reader::new(mmap).unwrap();
     let metadata = reader.metadata();
     println!("{:#?}", metadata);
+    println!("{:#?}", reader.info());
 }
@@ -0,0 +1,61 @@
+//! Counts the allocations done to merge large values with an owning
+//! merge function and with a `BorrowedMerge` one.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use oxidized_mtbl::{Merger, Reader, WriterBuilder};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn concat(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+    Ok(vals.concat())
+}
+
+fn concat_borrowed(_key: &[u8], vals: &[&[u8]]) -> Result<Vec<u8>, ()> {
+    Ok(vals.concat())
+}
+
+fn sources() -> Vec<Reader<Vec<u8>>> {
+    let value = vec![42; 16 * 1024];
+    (0..8).map(|_| {
+        let mut writer = WriterBuilder::new().memory();
+        for key in 0..1000u32 {
+            writer.insert(key.to_be_bytes(), &value).unwrap();
+        }
+        Reader::new(writer.into_inner().unwrap()).unwrap()
+    }).collect()
+}
+
+fn main() {
+    let mut builder = Merger::builder(concat);
+    builder.extend(sources());
+    let before = (Instant::now(), ALLOCATIONS.load(Ordering::Relaxed));
+    let mut iter = builder.build().into_merge_iter().unwrap();
+    while let Some(result) = iter.next() { result.unwrap(); }
+    println!("owned: {} allocations in {:.02?}", ALLOCATIONS.load(Ordering::Relaxed) - before.1, before.0.elapsed());
+
+    let mut builder = Merger::builder_borrowed(concat_borrowed);
+    builder.extend(sources());
+    let before = (Instant::now(), ALLOCATIONS.load(Ordering::Relaxed));
+    let mut iter = builder.build().into_merge_iter().unwrap();
+    while let Some(result) = iter.next() { result.unwrap(); }
+    println!("borrowed: {} allocations in {:.02?}", ALLOCATIONS.load(Ordering::Relaxed) - before.1, before.0.elapsed());
+}
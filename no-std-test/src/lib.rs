@@ -0,0 +1,48 @@
+//! Decodes a table with oxidized-mtbl built without the `std` feature.
+//!
+//! `table.mtbl` has been written with a block size of 256 bytes, no compression,
+//! and contains the keys `key-000` to `key-199` associated to `value-{i}`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use oxidized_mtbl::{Error, Reader};
+
+pub static TABLE: &[u8] = include_bytes!("../table.mtbl");
+
+/// Collects all the entries of the table.
+pub fn entries(table: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+    let reader = Reader::new(table)?;
+    let mut iter = reader.into_iter()?;
+    let mut entries = Vec::new();
+    while let Some(result) = iter.next() {
+        let (key, val) = result?;
+        entries.push((key.to_vec(), val.to_vec()));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn decode_prebuilt_table() {
+        let reader = Reader::new(TABLE).unwrap();
+        assert_eq!(reader.metadata().count_entries, 200);
+        assert!(reader.metadata().count_data_blocks > 1);
+
+        let entries = entries(TABLE).unwrap();
+        assert_eq!(entries.len(), 200);
+        for (i, (key, val)) in entries.iter().enumerate() {
+            assert_eq!(key, format!("key-{:03}", i).as_bytes());
+            assert_eq!(val, format!("value-{}", i).as_bytes());
+        }
+
+        let val = reader.get(b"key-142").unwrap().unwrap();
+        assert_eq!(val.as_ref(), b"value-142");
+    }
+}
@@ -0,0 +1,121 @@
+use std::io;
+
+use memmap::Mmap;
+
+use crate::reader::Reader;
+use crate::{BytesView, DynBytes, InnerBytesView};
+
+/// How the bytes of a reader are about to be accessed, see `Reader::advise`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// The entries are scanned in order, the pages can be read ahead aggressively.
+    Sequential,
+    /// The entries are looked up in no particular order, reading ahead is useless.
+    Random,
+    /// The bytes will be accessed soon, they can be read in advance.
+    WillNeed,
+    /// The bytes will not be accessed soon, their pages can be freed.
+    DontNeed,
+}
+
+/// The backings of a reader that can be told how they will be accessed.
+///
+/// Only the memory mapped backings do something, the bytes in memory ignore the advice.
+pub trait Advise {
+    /// Advises on the access of `len` bytes starting at `offset`.
+    fn advise(&self, _offset: usize, _len: usize, _access: Access) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Advise for Mmap {
+    #[cfg(unix)]
+    fn advise(&self, offset: usize, len: usize, access: Access) -> io::Result<()> {
+        let advice = match access {
+            Access::Sequential => libc::MADV_SEQUENTIAL,
+            Access::Random => libc::MADV_RANDOM,
+            Access::WillNeed => libc::MADV_WILLNEED,
+            Access::DontNeed => libc::MADV_DONTNEED,
+        };
+
+        if len == 0 { return Ok(()) }
+        assert!(offset + len <= self.len());
+
+        // madvise wants an address aligned on a page, like the start of the mapping
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let aligned_offset = offset - offset % page_size;
+        let ptr = unsafe { self.as_ptr().add(aligned_offset) };
+        let len = len + offset - aligned_offset;
+
+        match unsafe { libc::madvise(ptr as *mut libc::c_void, len, advice) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl Advise for Vec<u8> {}
+impl Advise for [u8] {}
+impl Advise for DynBytes {}
+impl<A: Advise + ?Sized> Advise for &A {
+    fn advise(&self, offset: usize, len: usize, access: Access) -> io::Result<()> {
+        (**self).advise(offset, len, access)
+    }
+}
+
+impl<A: Advise> BytesView<A> {
+    fn advise(&self, access: Access) -> io::Result<()> {
+        match &self.inner {
+            InnerBytesView::Data(data) => data.advise(self.offset, self.length, access),
+            InnerBytesView::Bytes(_) | InnerBytesView::Pooled(_) => Ok(()),
+        }
+    }
+}
+
+impl<A: AsRef<[u8]> + Advise> Reader<A> {
+    /// Advises the operating system on how the bytes of this reader are about to be
+    /// accessed, e.g. `Access::Sequential` before a full scan. Only the memory mapped
+    /// readers are affected, it never changes what is read.
+    pub fn advise(&self, access: Access) -> io::Result<()> {
+        self.data().advise(access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    #[test]
+    fn advise() {
+        let mut writer = WriterBuilder::new().build(tempfile::tempfile().unwrap());
+        for i in 0..200_000u32 {
+            writer.insert(i.to_be_bytes(), format!("value-{}", i)).unwrap();
+        }
+        let file = writer.into_inner().unwrap();
+        let mmap = unsafe { Mmap::map(&file).unwrap() };
+        assert!(mmap.len() > 1024 * 1024);
+
+        let reader = Reader::new(mmap).unwrap();
+        for &access in &[Access::WillNeed, Access::Random, Access::DontNeed, Access::Sequential] {
+            reader.advise(access).unwrap();
+        }
+
+        let mut count = 0u32;
+        let mut iter = reader.into_iter().unwrap();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, format!("value-{}", count).as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 200_000);
+
+        // the bytes in memory ignore the advice
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("hello", "world").unwrap();
+        let vec = writer.into_inner().unwrap();
+        Reader::new(vec.as_slice()).unwrap().advise(Access::Sequential).unwrap();
+        Reader::new(vec).unwrap().advise(Access::DontNeed).unwrap();
+    }
+}
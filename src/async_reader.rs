@@ -0,0 +1,333 @@
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::sync::Arc;
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Mutex;
+
+use crate::block::{Block, BlockIter};
+use crate::checksum::ChecksumType;
+use crate::error::{Error, MtblError};
+use crate::index::{Index, IndexIter};
+use crate::reader::decode_block;
+use crate::varint::varint_decode64;
+use crate::{BytesView, Metadata, ReaderBuilder, METADATA_SIZE};
+
+/// A source of bytes read by ranges, e.g. an object in a remote storage.
+pub trait RangeFetcher {
+    /// Returns the total size of the file.
+    fn size(&self) -> impl Future<Output = io::Result<u64>> + Send;
+
+    /// Returns exactly `len` bytes of the file starting at `offset`, the reader
+    /// returns an error if fewer bytes are returned.
+    fn read_range(&self, offset: u64, len: usize) -> impl Future<Output = io::Result<Vec<u8>>> + Send;
+}
+
+impl RangeFetcher for Mutex<File> {
+    async fn size(&self) -> io::Result<u64> {
+        let file = self.lock().await;
+        Ok(file.metadata().await?.len())
+    }
+
+    async fn read_range(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = self.lock().await;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut bytes = vec![0; len];
+        file.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+}
+
+impl ReaderBuilder {
+    /// Reads the metadata and the index block of the file, the data blocks
+    /// are then fetched on demand, one range request per block.
+    pub async fn read_async<F: RangeFetcher>(&self, fetcher: F) -> Result<AsyncReader<F>, Error> {
+        let size = fetcher.size().await?;
+        if size < METADATA_SIZE as u64 {
+            return Err(Error::from(MtblError::InvalidMetadataSize));
+        }
+
+        let metadata_offset = size - METADATA_SIZE as u64;
+        let metadata_bytes = fetcher.read_range(metadata_offset, METADATA_SIZE).await?;
+        let metadata_bytes = metadata_bytes.get(..METADATA_SIZE).ok_or(MtblError::InvalidMetadataSize)?;
+        let metadata = Metadata::read_from_bytes(metadata_bytes, self.verify_checksums)?;
+        let compression_dictionary = self.dictionary_for(&metadata)?;
+
+        let index_start = metadata.index_start();
+        if metadata.index_block_offset >= metadata_offset || index_start > metadata.index_block_offset {
+            return Err(Error::from(MtblError::InvalidIndexBlockOffset));
+        }
+
+        // the index is everything between the last data block and the metadata
+        let index_len = (metadata_offset - index_start) as usize;
+        let index_bytes = fetcher.read_range(index_start, index_len).await?;
+        if index_bytes.len() < index_len {
+            return Err(Error::from(MtblError::InvalidIndexLength));
+        }
+        let index_bytes = BytesView::from(index_bytes);
+        let checksum_type = if self.verify_checksums { Some(metadata.checksum_type) } else { None };
+        let max_decompressed_block_size = self.max_decompressed_block_size;
+        let decode_index_block = |offset: u64| decode_block(
+            &index_bytes,
+            (offset - index_start) as usize,
+            metadata.file_version,
+            checksum_type,
+            metadata.index_compression_algorithm,
+            None,
+            max_decompressed_block_size,
+            None,
+        );
+
+        let index = decode_index_block(metadata.index_block_offset)?;
+        let index = if metadata.count_index_segments == 0 {
+            Index::single(index)
+        } else {
+            Index::two_level(index, |offset| {
+                if offset < index_start || offset >= metadata.index_block_offset {
+                    return Err(Error::from(MtblError::InvalidIndexEntry));
+                }
+                decode_index_block(offset)
+            })?
+        };
+        let index = Arc::new(index);
+
+        let mut block_offsets = Vec::with_capacity(metadata.count_data_blocks as usize);
+        let mut index_iter = IndexIter::init(index.clone());
+        index_iter.seek_to_first()?;
+        while let Some((_key, val)) = index_iter.get() {
+            let mut offset = 0;
+            varint_decode64(val, &mut offset);
+            // the blocks are fetched by the ranges between these offsets
+            if offset >= index_start || block_offsets.last().is_some_and(|last| offset <= *last) {
+                return Err(Error::from(MtblError::InvalidIndexEntry));
+            }
+            block_offsets.push(offset);
+            index_iter.next()?;
+        }
+
+        Ok(AsyncReader { fetcher, metadata, checksum_type, compression_dictionary, max_decompressed_block_size, index, block_offsets })
+    }
+}
+
+/// A reader that fetches the blocks of the file on demand from a `RangeFetcher`.
+pub struct AsyncReader<F> {
+    fetcher: F,
+    metadata: Metadata,
+    checksum_type: Option<ChecksumType>,
+    compression_dictionary: Option<Arc<[u8]>>,
+    max_decompressed_block_size: usize,
+    index: Arc<Index<Vec<u8>>>,
+    block_offsets: Vec<u64>,
+}
+
+impl<F: RangeFetcher> AsyncReader<F> {
+    pub async fn new(fetcher: F) -> Result<AsyncReader<F>, Error> {
+        ReaderBuilder::new().read_async(fetcher).await
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Fetches and decodes the `n`th data block of the file.
+    async fn block(&self, n: usize) -> Result<Block<Vec<u8>>, Error> {
+        let start = self.block_offsets[n];
+        let end = self.block_offsets.get(n + 1).copied().unwrap_or(self.metadata.index_start());
+        let len = (end - start) as usize;
+        let bytes = self.fetcher.read_range(start, len).await?;
+        if bytes.len() < len {
+            return Err(Error::from(MtblError::InvalidBlock));
+        }
+
+        decode_block(
+            &BytesView::from(bytes),
+            0,
+            self.metadata.file_version,
+            self.checksum_type,
+            self.metadata.compression_algorithm,
+            self.compression_dictionary.as_deref(),
+            self.max_decompressed_block_size,
+            None,
+        )
+        .map(|block| {
+            block.with_repeated_values(self.metadata.file_version.repeated_values())
+                .with_value_checksums(self.metadata.file_version.value_checksums())
+        })
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek(key)?;
+
+        let offset = match index_iter.get() {
+            Some((_key, val)) => {
+                let mut offset = 0;
+                varint_decode64(val, &mut offset);
+                offset
+            },
+            None => return Ok(None),
+        };
+
+        let n = self.block_offsets.binary_search(&offset).map_err(|_| MtblError::InvalidBlock)?;
+        let mut bi = BlockIter::init(Arc::new(self.block(n).await?));
+        bi.seek(key)?;
+
+        match bi.get() {
+            Some((k, v)) if k == key => Ok(Some(v.to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a stream over the entries of the file, in key order.
+    pub fn iter(&self) -> AsyncReaderIter<'_, F> {
+        AsyncReaderIter { reader: self, next_block: 0, bi: None, first: true }
+    }
+}
+
+pub struct AsyncReaderIter<'a, F> {
+    reader: &'a AsyncReader<F>,
+    next_block: usize,
+    bi: Option<BlockIter<Vec<u8>>>,
+    first: bool,
+}
+
+impl<F: RangeFetcher> AsyncReaderIter<'_, F> {
+    pub async fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
+        loop {
+            if let Some(bi) = &mut self.bi {
+                let moved = if self.first {
+                    self.first = false;
+                    bi.status().map(|()| bi.key().is_some())
+                } else {
+                    bi.next()
+                };
+                match moved {
+                    Ok(true) => break,
+                    Ok(false) => (),
+                    Err(e) => {
+                        self.bi = None;
+                        self.next_block = self.reader.block_offsets.len();
+                        return Some(Err(Error::from(e)));
+                    },
+                }
+            }
+
+            if self.next_block >= self.reader.block_offsets.len() {
+                self.bi = None;
+                return None;
+            }
+
+            match self.reader.block(self.next_block).await {
+                Ok(block) => {
+                    let mut bi = BlockIter::init(Arc::new(block));
+                    // an error is returned by the next loop
+                    let _ = bi.seek_to_first();
+                    self.bi = Some(bi);
+                    self.first = true;
+                    self.next_block += 1;
+                },
+                Err(e) => {
+                    self.next_block = self.reader.block_offsets.len();
+                    return Some(Err(e));
+                },
+            }
+        }
+
+        self.bi.as_ref().and_then(|bi| bi.get()).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompressionType, Reader, WriterBuilder};
+
+    struct Memory(Vec<u8>);
+
+    impl RangeFetcher for Memory {
+        async fn size(&self) -> io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+
+        async fn read_range(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            let bytes = self.0.get(offset as usize..).and_then(|bytes| bytes.get(..len));
+            bytes.map(<[u8]>::to_vec).ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+        }
+    }
+
+    /// Returns at most `max_len` bytes for each range, like a misbehaving remote storage.
+    struct Truncated { data: Vec<u8>, max_len: usize }
+
+    impl RangeFetcher for Truncated {
+        async fn size(&self) -> io::Result<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        async fn read_range(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            let offset = offset as usize;
+            Ok(self.data[offset..offset + len.min(self.max_len)].to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_sync_reader() {
+        let mut writer = WriterBuilder::new()
+            .compression_type(CompressionType::Snappy)
+            .block_size(1024)
+            .memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.clone()).unwrap();
+        let expected: Vec<_> = (&reader).into_iter().collect::<Result<_, _>>().unwrap();
+
+        let reader = AsyncReader::new(Memory(vec)).await.unwrap();
+        assert!(reader.metadata().count_data_blocks > 1);
+
+        let mut entries = Vec::new();
+        let mut iter = reader.iter();
+        while let Some(result) = iter.next().await {
+            let (key, val) = result.unwrap();
+            entries.push((key.to_vec(), val.to_vec()));
+        }
+        assert_eq!(entries, expected);
+
+        for (key, val) in &expected {
+            assert_eq!(reader.get(key).await.unwrap().as_ref(), Some(val));
+        }
+        assert_eq!(reader.get(b"missing").await.unwrap(), None);
+        assert_eq!(reader.get(&1000u32.to_be_bytes()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn untrusted_bytes() {
+        let mut writer = WriterBuilder::new()
+            .compression_type(CompressionType::None)
+            .block_size(2048)
+            .memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let error = AsyncReader::new(Truncated { data: vec.clone(), max_len: 16 }).await.err().unwrap();
+        assert!(matches!(error, Error::Mtbl(MtblError::InvalidMetadataSize)), "{:?}", error);
+
+        // the metadata and the index are complete but not the data blocks
+        let truncated = Truncated { data: vec.clone(), max_len: METADATA_SIZE };
+        let reader = AsyncReader::new(truncated).await.unwrap();
+        let error = reader.get(&0u32.to_be_bytes()).await.unwrap_err();
+        assert!(matches!(error, Error::Mtbl(MtblError::InvalidBlock)), "{:?}", error);
+
+        // the size of the first data block is bigger than the fetched bytes
+        let mut corrupted = vec;
+        corrupted[0] = 0xff;
+        corrupted[1] = 0x7f;
+        let reader = ReaderBuilder::new().verify_checksums(false).read_async(Memory(corrupted)).await.unwrap();
+        let error = reader.iter().next().await.unwrap().unwrap_err();
+        assert!(matches!(error, Error::Mtbl(MtblError::InvalidBlock)), "{:?}", error);
+    }
+}
@@ -1,51 +1,120 @@
-use std::mem;
-use std::sync::Arc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
 
 use byteorder::{ByteOrder, LittleEndian};
 
+use crate::error::MtblError;
 use crate::varint::varint_decode32;
-use crate::BytesView;
+use crate::{BytesView, DynBytes, VALUE_CHECKSUM_SIZE};
+
+/// The width of the restart points of a block, the 64-bit ones are only
+/// used by the blocks whose restart points do not fit in 32 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RestartWidth {
+    Bits32,
+    Bits64,
+}
 
-#[derive(Clone)]
+/// A decoded block of prefix compressed entries followed by its restart points,
+/// the data and index blocks of a file, see `blocks`.
 pub struct Block<A> {
     data: BytesView<A>,
     restart_offset: u64,
+    /// Whether the restart points are stored as 64-bit integers.
+    restart64: bool,
+    /// Whether an entry can reuse the value of the previous one, see `FileVersion::FormatV3`.
+    repeated_values: bool,
+    /// Whether every value is followed by its checksum, see `FileVersion::FormatV5`.
+    value_checksums: bool,
 }
 
-impl<A: AsRef<[u8]>> Block<A> {
-    pub fn init(data: BytesView<A>) -> Option<Block<A>> {
-        let mut restart_offset;
+impl<A> Clone for Block<A> {
+    fn clone(&self) -> Block<A> {
+        Block {
+            data: self.data.clone(),
+            restart_offset: self.restart_offset,
+            restart64: self.restart64,
+            repeated_values: self.repeated_values,
+            value_checksums: self.value_checksums,
+        }
+    }
+}
 
-        if data.len() < mem::size_of::<u32>() {
-            return None;
-        } else {
-            restart_offset = data.len() - (1 + num_restarts(data.as_ref()) as usize) * mem::size_of::<u32>();
+impl<A: AsRef<[u8]>> Block<A> {
+    /// Returns an `MtblError::InvalidBlock` error if the block is malformed, an empty
+    /// block still contains a restart point and the number of restart points.
+    pub fn init(data: BytesView<A>) -> Result<Block<A>, MtblError> {
+        if data.len() < 2 * mem::size_of::<u32>() || num_restarts(data.as_ref()) == 0 {
+            return Err(MtblError::InvalidBlock);
         }
 
+        let restarts_size = (1 + num_restarts(data.as_ref()) as usize) * mem::size_of::<u32>();
+        let restart_offset = data.len().checked_sub(restarts_size).ok_or(MtblError::InvalidBlock)?;
+
         // Check if a 32-bit restart array would leave room for restart offsets
         // too large for an unsigned 32 bit integer. The writer performs this
         // same check, and will switch to 64 bit restart offsets if necessary.
         // We detect this situation here, and do the same.
-        if restart_offset > u32::max_value() as usize {
-            restart_offset = data.len() - (
-                mem::size_of::<u32>() + num_restarts(data.as_ref()) as usize * mem::size_of::<u64>()
-            );
-            // b->restart_offset is the offset of the first byte after
-            // the entries stored in the block. If that offset fits
-            // in a 32 bit unsigned integer field, the block should have
-            // used 32 bit restart offsets. We consider a block where
-            // a 32 bit restart offset array would begin after UINT32_MAX
-            // and a 64 bit restart array would begin before to be malformed.
-            if restart_offset <= u32::max_value() as usize {
-                return None;
-            }
+        let restart64 = restart_offset > u32::MAX as usize;
+        let block = Block::init_with_restart_width(data, restart64).ok_or(MtblError::InvalidBlock)?;
+
+        // b->restart_offset is the offset of the first byte after
+        // the entries stored in the block. If that offset fits
+        // in a 32 bit unsigned integer field, the block should have
+        // used 32 bit restart offsets. We consider a block where
+        // a 32 bit restart offset array would begin after UINT32_MAX
+        // and a 64 bit restart array would begin before to be malformed.
+        if restart64 && block.restart_offset <= u32::MAX as u64 {
+            return Err(MtblError::InvalidBlock);
         }
 
-        if restart_offset > data.len() - mem::size_of::<u32>() {
+        Ok(block)
+    }
+
+    /// Decodes a block whose restart points are stored as 64-bit integers
+    /// if `restart64` is `true`, 32-bit integers otherwise.
+    pub(crate) fn init_with_restart_width(data: BytesView<A>, restart64: bool) -> Option<Block<A>> {
+        if data.len() < 2 * mem::size_of::<u32>() || num_restarts(data.as_ref()) == 0 {
             return None;
         }
 
-        Some(Block { data, restart_offset: restart_offset as u64 })
+        let width = if restart64 { mem::size_of::<u64>() } else { mem::size_of::<u32>() };
+        let restarts_size = (num_restarts(data.as_ref()) as usize).checked_mul(width)?;
+        // the restart array and its length must fit in the block
+        let restart_offset = data.len().checked_sub(mem::size_of::<u32>() + restarts_size)?;
+
+        Some(Block { data, restart_offset: restart_offset as u64, restart64, repeated_values: false, value_checksums: false })
+    }
+
+    pub fn restart_width(&self) -> RestartWidth {
+        if self.restart64 { RestartWidth::Bits64 } else { RestartWidth::Bits32 }
+    }
+
+    /// Decodes the value lengths of the entries like the data blocks of a `FileVersion::FormatV3` file,
+    /// for the blocks built with `BlockBuilder::set_repeated_values`.
+    pub(crate) fn with_repeated_values(mut self, repeated_values: bool) -> Block<A> {
+        self.repeated_values = repeated_values;
+        self
+    }
+
+    /// Strips the checksum that follows the values of the data blocks of a
+    /// `FileVersion::FormatV5` file, the values are returned without it.
+    pub(crate) fn with_value_checksums(mut self, value_checksums: bool) -> Block<A> {
+        self.value_checksums = value_checksums;
+        self
+    }
+}
+
+impl<A: AsRef<[u8]> + Send + Sync + 'static> Block<A> {
+    pub(crate) fn into_dyn(self) -> Block<DynBytes> {
+        Block {
+            data: self.data.into_dyn(),
+            restart_offset: self.restart_offset,
+            restart64: self.restart64,
+            repeated_values: self.repeated_values,
+            value_checksums: self.value_checksums,
+        }
     }
 }
 
@@ -55,11 +124,19 @@ impl<A: AsRef<[u8]>> AsRef<[u8]> for Block<A> {
     }
 }
 
+/// The number of restart points stored at the end of the block, zero if there is no room for it.
 fn num_restarts(data: &[u8]) -> u32 {
-    assert!(data.len() >= 2 * mem::size_of::<u32>());
-    LittleEndian::read_u32(&data[data.len() - mem::size_of::<u32>()..])
+    match data.len().checked_sub(mem::size_of::<u32>()) {
+        Some(offset) => LittleEndian::read_u32(&data[offset..]),
+        None => 0,
+    }
 }
 
+/// Iterates over the entries of a `Block`, in both directions.
+///
+/// The iterator starts unpositioned, one of the `seek` methods must be called first.
+/// An entry that cannot be decoded leaves the iterator invalid, the moves then return
+/// an `MtblError::InvalidBlock` error.
 pub struct BlockIter<A> {
     pub(crate) block: Arc<Block<A>>,
     restarts: u64,
@@ -68,16 +145,23 @@ pub struct BlockIter<A> {
     restart_index: u32,
     next: Option<u64>,
     pub(crate) key: Vec<u8>,
+    /// The offset of the current key in the block, when it does not share a prefix.
+    key_offset: Option<usize>,
     pub(crate) val: Option<(usize, usize)>,
+    /// Whether an entry could not be decoded, the iterator stays invalid.
+    corrupted: bool,
 }
 
 impl<A: AsRef<[u8]>> BlockIter<A> {
+    /// Creates an unpositioned iterator over the entries of `b`.
     pub fn init(b: Arc<Block<A>>) -> BlockIter<A> {
-        assert!(b.data.len() >= 2 * mem::size_of::<u32>());
+        BlockIter::init_with_key_buffer(b, Vec::new())
+    }
 
+    /// Like `init` but the keys are decoded in `key`, an already allocated buffer.
+    pub fn init_with_key_buffer(b: Arc<Block<A>>, mut key: Vec<u8>) -> BlockIter<A> {
+        // the restart points were checked when the block was decoded
         let num_restarts = num_restarts(b.data.as_ref());
-        assert!(num_restarts > 0);
-
         let restart_offset = b.restart_offset;
 
         BlockIter {
@@ -87,28 +171,46 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
             current: restart_offset,
             restart_index: num_restarts,
             next: None,
-            key: Vec::new(),
+            key: { key.clear(); key },
+            key_offset: None,
             val: None,
+            corrupted: false,
         }
     }
 
-    fn restart_point(&self, idx: u32) -> u64 {
-        assert!(idx < self.num_restarts);
+    /// Returns an `MtblError::InvalidBlock` error if an entry could not be decoded.
+    pub(crate) fn status(&self) -> Result<(), MtblError> {
+        if self.corrupted { Err(MtblError::InvalidBlock) } else { Ok(()) }
+    }
+
+    /// Marks the block as corrupted and leaves the iterator invalid.
+    fn corrupt(&mut self) {
+        self.corrupted = true;
+        self.current = self.restarts;
+        self.restart_index = self.num_restarts;
+    }
 
-        let offset = self.restarts as usize + idx as usize * mem::size_of::<u32>();
-        if self.restarts > u32::max_value() as u64 {
+    fn restart_point(&self, idx: u32) -> u64 {
+        if self.block.restart64 {
+            let offset = self.restarts as usize + idx as usize * mem::size_of::<u64>();
             LittleEndian::read_u64(&self.block.data.as_ref()[offset..])
         } else {
+            let offset = self.restarts as usize + idx as usize * mem::size_of::<u32>();
             LittleEndian::read_u32(&self.block.data.as_ref()[offset..]) as u64
         }
     }
 
     fn seek_to_restart_point(&mut self, idx: u32) {
         self.key.clear();
+        self.key_offset = None;
+        self.val = None;
 
         self.restart_index = idx;
         let offset = self.restart_point(idx);
         self.next = Some(offset);
+        if offset > self.restarts {
+            self.corrupt();
+        }
     }
 
     fn next_entry_offset(&self) -> u64 {
@@ -117,6 +219,10 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
     }
 
     fn parse_next_key(&mut self) -> bool {
+        if self.corrupted {
+            return false;
+        }
+
         self.current = self.next_entry_offset();
 
         if self.current >= self.restarts {
@@ -127,15 +233,40 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
         }
 
         // decode next entry
-        let (shared, non_shared, value_length, p) =
-            decode_entry(self.block.data.as_ref(), self.current as usize, self.restarts as usize).unwrap();
-        assert!(self.key.capacity() >= shared as usize);
+        let entry = decode_entry(
+            self.block.data.as_ref(),
+            self.current as usize,
+            self.restarts as usize,
+            self.block.repeated_values,
+        );
+        let (shared, non_shared, value_length, p) = match entry {
+            // the shared prefix must be part of the previous key
+            Ok(entry) if entry.0 as usize <= self.key.len() => entry,
+            _ => {
+                // corruption
+                self.corrupt();
+                return false;
+            },
+        };
 
         self.key.truncate(shared as usize);
         self.key.extend_from_slice(&self.block.data.as_ref()[p..p + non_shared as usize]);
-
-        self.next = Some(p as u64 + non_shared as u64 + value_length as u64);
-        self.val = Some((p + non_shared as usize, value_length as usize));
+        self.key_offset = if shared == 0 { Some(p) } else { None };
+
+        let checksum_size = if self.block.value_checksums { VALUE_CHECKSUM_SIZE } else { 0 };
+        match value_length {
+            Some(value_length) if value_length as usize >= checksum_size => {
+                self.next = Some(p as u64 + non_shared as u64 + value_length as u64);
+                self.val = Some((p + non_shared as usize, value_length as usize - checksum_size));
+            },
+            // the value of the previous entry, never at a restart point
+            None if self.val.is_some() => self.next = Some(p as u64 + non_shared as u64),
+            _ => {
+                // corruption
+                self.corrupt();
+                return false;
+            },
+        }
         while self.restart_index + 1 < self.num_restarts && self.restart_point(self.restart_index + 1) < self.current {
             self.restart_index += 1;
         }
@@ -146,12 +277,28 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
         self.current < self.restarts
     }
 
-    pub fn seek_to_first(&mut self) {
+    /// Moves to the first entry of the block, returns `false` if there is none.
+    pub fn seek_to_first(&mut self) -> Result<bool, MtblError> {
         self.seek_to_restart_point(0);
         self.parse_next_key();
+        self.status().map(|()| self.valid())
+    }
+
+    /// Moves to the last entry of the block, returns `false` if there is none.
+    pub fn seek_to_last(&mut self) -> Result<bool, MtblError> {
+        self.seek_to_restart_point(self.num_restarts - 1);
+        while self.parse_next_key() && self.next_entry_offset() < self.restarts {
+            // keep skipping
+        }
+        self.status().map(|()| self.valid())
     }
 
-    pub fn seek(&mut self, target: &[u8]) {
+    /// Moves to the first entry with a key greater than or equal to `target`.
+    ///
+    /// The keys at the restart points are stored whole, an entry there sharing
+    /// a prefix with the previous key is a corruption of the block, the
+    /// iterator is then left invalid and an error is returned.
+    pub fn seek(&mut self, target: &[u8]) -> Result<(), MtblError> {
         // binary search in restart array to find the first restart point
         // with a key >= target
         let mut left: u32 = 0;
@@ -161,13 +308,20 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
             let mid = (left + right + 1) / 2;
             let region_offset = self.restart_point(mid);
 
-            let (shared, non_shared, _value_length, key_offset) =
-                decode_entry(&self.block.data.as_ref(), region_offset as usize, self.restarts as usize).unwrap();
-
-            if shared != 0 {
-                // corruption
-                return;
-            }
+            let entry = decode_entry(
+                self.block.data.as_ref(),
+                region_offset as usize,
+                self.restarts as usize,
+                self.block.repeated_values,
+            );
+            let (non_shared, key_offset) = match entry {
+                Ok((0, non_shared, _value_length, key_offset)) => (non_shared, key_offset),
+                _ => {
+                    // corruption
+                    self.corrupt();
+                    return Err(MtblError::InvalidBlock);
+                },
+            };
 
             let key = &self.block.data.as_ref()[key_offset..key_offset + non_shared as usize];
             if key < target {
@@ -183,38 +337,116 @@ impl<A: AsRef<[u8]>> BlockIter<A> {
 
         // linear search within restart block for first key >= target
         self.seek_to_restart_point(left);
-        loop {
-            if !self.parse_next_key() {
-                return;
-            }
+        while self.parse_next_key() {
             if self.key.as_slice() >= target {
-                return;
+                break;
             }
         }
+
+        self.status()
     }
 
-    pub fn next(&mut self) -> bool {
+    /// Moves to the next entry, returns `false` if there is none.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool, MtblError> {
+        self.status()?;
         if !self.valid() {
-            return false;
+            return Ok(false);
         }
         self.parse_next_key();
-        self.valid()
+        self.status().map(|()| self.valid())
+    }
+
+    /// Moves to the previous entry, returns `false` if there is none.
+    pub fn prev(&mut self) -> Result<bool, MtblError> {
+        self.status()?;
+        if !self.valid() {
+            return Ok(false);
+        }
+
+        // scan backwards to a restart point before the current entry
+        let original = self.current;
+        while self.restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                // no more entries, mark as invalid
+                self.current = self.restarts;
+                self.restart_index = self.num_restarts;
+                return Ok(false);
+            }
+            self.restart_index -= 1;
+        }
+
+        // loop until the end of the current entry hits the start of the original entry
+        self.seek_to_restart_point(self.restart_index);
+        while self.parse_next_key() && self.next_entry_offset() < original {
+            // keep skipping
+        }
+
+        self.status().map(|()| self.valid())
+    }
+
+    /// Returns the key of the current entry, without looking at its value.
+    pub fn key(&self) -> Option<&[u8]> {
+        if self.valid() { Some(self.key.as_slice()) } else { None }
     }
 
+    /// Returns the current entry, `None` if the iterator is not positioned on one.
     pub fn get(&self) -> Option<(&[u8], &[u8])> {
         if !self.valid() {
             return None;
         }
 
         let key = self.key.as_slice();
-        let (val_offset, val_len) = self.val.unwrap();
+        let (val_offset, val_len) = self.val?;
 
         return Some((key, &self.block.data.as_ref()[val_offset..val_offset + val_len]));
     }
+
+    /// Returns the checksum stored after the current value, `None` if the
+    /// block has none, see `Block::with_value_checksums`.
+    pub(crate) fn value_checksum(&self) -> Option<u32> {
+        if !self.valid() || !self.block.value_checksums {
+            return None;
+        }
+
+        let (val_offset, val_len) = self.val?;
+        Some(LittleEndian::read_u32(&self.block.data.as_ref()[val_offset + val_len..]))
+    }
+
+    /// Returns the current key, sharing the bytes of the block when the key is
+    /// stored whole, the keys that share a prefix with the previous one are copied.
+    pub fn key_view(&self) -> Option<BytesView<A>> {
+        if !self.valid() {
+            return None;
+        }
+
+        match self.key_offset {
+            Some(offset) => Some(self.block.data.slice(offset, self.key.len())),
+            None => Some(BytesView::from_bytes(self.key.clone())),
+        }
+    }
+
+    /// Returns the current value, sharing the bytes of the block.
+    pub fn val_view(&self) -> Option<BytesView<A>> {
+        if !self.valid() {
+            return None;
+        }
+
+        let (val_offset, val_len) = self.val?;
+        Some(self.block.data.slice(val_offset, val_len))
+    }
 }
 
-fn decode_entry(data: &[u8], mut p: usize, limit: usize) -> Result<(u32, u32, u32, usize), ()> {
-    if limit - p < 3 {
+/// Decodes the lengths of the entry at `p`, the length of the value is `None` when the entry
+/// has the value of the previous entry, only found in the blocks with `repeated_values`.
+fn decode_entry(
+    data: &[u8],
+    mut p: usize,
+    limit: usize,
+    repeated_values: bool,
+) -> Result<(u32, u32, Option<u32>, usize), ()>
+{
+    if limit.checked_sub(p).is_none_or(|remaining| remaining < 3) {
         return Err(());
     }
 
@@ -226,13 +458,92 @@ fn decode_entry(data: &[u8], mut p: usize, limit: usize) -> Result<(u32, u32, u3
         // fast path
         p += 3;
     } else {
-        p += varint_decode32(&data[p..], &mut shared);
-        p += varint_decode32(&data[p..], &mut non_shared);
-        p += varint_decode32(&data[p..], &mut value_length);
-        assert!(p <= limit);
+        for value in [&mut shared, &mut non_shared, &mut value_length] {
+            let len = if p < limit { varint_decode32(&data[p..limit], value) } else { 0 };
+            if len == 0 {
+                return Err(());
+            }
+            p += len;
+        }
     }
 
-    assert!(!((limit - p) < (non_shared + value_length) as usize));
+    // the value lengths are shifted by one, zero being a repeated value
+    let value_length = if repeated_values { value_length.checked_sub(1) } else { Some(value_length) };
+
+    if ((limit - p) as u64) < non_shared as u64 + value_length.unwrap_or(0) as u64 {
+        return Err(());
+    }
 
     Ok((shared, non_shared, value_length, p))
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn block(bytes: &[u8]) -> Result<Block<Vec<u8>>, MtblError> {
+        Block::init(BytesView::from(bytes.to_vec()))
+    }
+
+    #[test]
+    fn degenerate_blocks() {
+        assert!(block(&[]).is_err());
+        assert!(block(&[0; 4]).is_err());
+        assert!(block(&[1, 0, 0, 0]).is_err());
+        assert!(block(&[0; 7]).is_err());
+        // no restart point
+        assert!(block(&[0; 8]).is_err());
+        assert!(block(&[0; 64]).is_err());
+        // more restart points than the block can hold
+        assert!(block(&[0, 0, 0, 0, 2, 0, 0, 0]).is_err());
+        assert!(block(&[0xff; 8]).is_err());
+        assert!(Block::init_with_restart_width(BytesView::from(vec![0; 8]), true).is_none());
+    }
+
+    #[test]
+    fn empty_block() {
+        // a single restart point at offset zero, then the number of restart points
+        let block = block(&[0, 0, 0, 0, 1, 0, 0, 0]).unwrap();
+        let mut iter = BlockIter::init(Arc::new(block));
+        assert!(!iter.seek_to_first().unwrap());
+        assert!(iter.get().is_none());
+        assert!(!iter.seek_to_last().unwrap());
+        assert!(iter.get().is_none());
+        iter.seek(b"hello").unwrap();
+        assert!(iter.get().is_none());
+        assert!(!iter.next().unwrap());
+        assert!(!iter.prev().unwrap());
+    }
+
+    #[test]
+    fn garbage_entries() {
+        let garbage: [&[u8]; 4] = [
+            // unterminated varints
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1, 0, 0, 0],
+            // a key sharing a prefix at a restart point
+            &[1, 1, 1, b'a', b'b', 0, 0, 0, 0, 1, 0, 0, 0],
+            // a value past the restart points
+            &[0, 1, 9, b'a', b'b', 0, 0, 0, 0, 1, 0, 0, 0],
+            // a restart point past the entries
+            &[0, 1, 1, b'a', b'b', 9, 0, 0, 0, 1, 0, 0, 0],
+        ];
+
+        for bytes in garbage {
+            let mut iter = BlockIter::init(Arc::new(block(bytes).unwrap()));
+            assert!(matches!(iter.seek_to_first(), Err(MtblError::InvalidBlock)));
+            assert!(iter.get().is_none());
+            assert!(matches!(iter.seek_to_last(), Err(MtblError::InvalidBlock)));
+            assert!(iter.get().is_none());
+            assert!(iter.next().is_err());
+            assert!(iter.prev().is_err());
+            assert!(matches!(iter.seek(b"a"), Err(MtblError::InvalidBlock)));
+        }
+
+        let mut iter = BlockIter::init(Arc::new(block(&[0, 1, 1, b'a', b'b', 0, 0, 0, 0, 1, 0, 0, 0]).unwrap()));
+        assert!(iter.seek_to_first().unwrap());
+        assert_eq!(iter.get(), Some((&b"a"[..], &b"b"[..])));
+        assert!(!iter.next().unwrap());
+    }
+}
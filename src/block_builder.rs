@@ -1,29 +1,80 @@
-use std::mem;
+use std::{cmp, mem};
 use byteorder::{LittleEndian, WriteBytesExt};
 use crate::varint::varint_encode32;
 
+/// Builds a block of prefix compressed entries, the format of the data and index
+/// blocks of a file, to be read back with a `Block`.
 #[derive(Clone)]
 pub struct BlockBuilder {
     block_restart_interval: usize,
     buf: Vec<u8>,
     last_key: Vec<u8>,
     restarts: Vec<u64>,
+    prefix_compression: bool,
     finished: bool,
     counter: usize,
+    count_entries: usize,
+    /// Writes 64-bit restart points even for blocks smaller than 4GiB.
+    force_restarts64: bool,
+    /// Whether an entry can reuse the value of the previous one, see `FileVersion::FormatV3`.
+    repeated_values: bool,
+    /// Writes the repeated values in full, in the format of the repeated values.
+    keep_repeated_values: bool,
+    last_val: Vec<u8>,
 }
 
 impl BlockBuilder {
+    /// Every `block_restart_interval` entries the key is stored whole and its offset is
+    /// recorded as a restart point, the other keys only store what differs from the
+    /// previous one. A seek binary searches the restart points then decodes at most
+    /// that many entries, a bigger interval makes smaller blocks but slower seeks.
+    /// An interval of zero is treated as one.
     pub fn new(block_restart_interval: usize) -> Self {
         BlockBuilder {
-            block_restart_interval,
+            block_restart_interval: cmp::max(block_restart_interval, 1),
             buf: Vec::with_capacity(65536),
             last_key: Vec::with_capacity(256),
             restarts: vec![0],
+            prefix_compression: true,
             finished: false,
             counter: 0,
+            count_entries: 0,
+            force_restarts64: false,
+            repeated_values: false,
+            keep_repeated_values: false,
+            last_val: Vec::new(),
         }
     }
 
+    /// Stores every key whole when disabled, it is enabled by default.
+    pub fn set_prefix_compression(&mut self, enabled: bool) {
+        self.prefix_compression = enabled;
+    }
+
+    /// Stores a value equal to the value of the previous entry as a zero value length,
+    /// the other value lengths are then shifted by one. The entries at the restart
+    /// points always store their values.
+    pub fn set_repeated_values(&mut self, enabled: bool) {
+        self.repeated_values = enabled;
+    }
+
+    /// Writes the values equal to the value of the previous entry in full, with the
+    /// value lengths of `set_repeated_values`. The block is read the same way.
+    pub(crate) fn set_keep_repeated_values(&mut self, keep: bool) {
+        self.keep_repeated_values = keep;
+    }
+
+    /// Exercises the 64-bit restart points written for blocks bigger than 4GiB.
+    #[cfg(test)]
+    pub fn force_64bit_restarts(&mut self, force: bool) {
+        self.force_restarts64 = force;
+    }
+
+    fn restart64(&self) -> bool {
+        self.force_restarts64 || self.buf.len() > u32::MAX as usize
+    }
+
+    /// Clears the builder to start a new block, required after a `finish`.
     pub fn reset(&mut self) {
         self.buf.clear();
         self.last_key.clear();
@@ -31,14 +82,22 @@ impl BlockBuilder {
         self.restarts.push(0);
         self.finished = false;
         self.counter = 0;
+        self.count_entries = 0;
+        self.last_val.clear();
     }
 
     pub fn is_empty(&self) -> bool {
         self.buf.is_empty()
     }
 
+    /// The number of entries added since the last reset.
+    pub fn len(&self) -> usize {
+        self.count_entries
+    }
+
+    /// The size of the block if it was finished now.
     pub fn current_size_estimate(&self) -> usize {
-        let factor = if self.buf.len() > u32::max_value() as usize {
+        let factor = if self.restart64() {
             mem::size_of::<u64>()
         } else {
             mem::size_of::<u64>() / 2
@@ -46,6 +105,8 @@ impl BlockBuilder {
         self.buf.len() + (self.restarts.len() * factor) + mem::size_of::<u32>()
     }
 
+    /// Appends an entry, the keys must be added in increasing order
+    /// for the seeks of the block to work.
     pub fn add(&mut self, key: &[u8], val: &[u8]) {
         assert!(self.counter <= self.block_restart_interval);
         assert!(!self.finished);
@@ -54,7 +115,9 @@ impl BlockBuilder {
 
         // see how much sharing to do with previous key
         if self.counter < self.block_restart_interval {
-            shared = self.last_key.iter().zip(key).take_while(|(l, k)| l == k).count();
+            if self.prefix_compression {
+                shared = self.last_key.iter().zip(key).take_while(|(l, k)| l == k).count();
+            }
         } else {
             // restart compression
             self.restarts.push(self.buf.len() as u64);
@@ -62,6 +125,13 @@ impl BlockBuilder {
         }
 
         let non_shared = key.len() - shared;
+        let dedup = self.repeated_values && !self.keep_repeated_values;
+        let repeated = dedup && self.counter > 0 && self.last_val == val;
+        let value_length = match (self.repeated_values, repeated) {
+            (false, _) => val.len() as u32,
+            (true, false) => val.len() as u32 + 1,
+            (true, true) => 0,
+        };
 
         // ensure enough buffer space is available
         self.buf.reserve(5 * 3 + key.len() + val.len());
@@ -70,20 +140,28 @@ impl BlockBuilder {
         let mut buf = [0; 10];
         self.buf.extend_from_slice(varint_encode32(&mut buf, shared as u32));
         self.buf.extend_from_slice(varint_encode32(&mut buf, non_shared as u32));
-        self.buf.extend_from_slice(varint_encode32(&mut buf, val.len() as u32));
+        self.buf.extend_from_slice(varint_encode32(&mut buf, value_length));
 
         // add key suffix to buffer followed by value
         self.buf.extend_from_slice(&key[shared..]);
-        self.buf.extend_from_slice(val);
+        if !repeated {
+            self.buf.extend_from_slice(val);
+        }
+        if dedup && !repeated {
+            self.last_val.clear();
+            self.last_val.extend_from_slice(val);
+        }
 
         // update state
         self.last_key.clear();
         self.last_key.extend_from_slice(key);
         self.counter += 1;
+        self.count_entries += 1;
     }
 
+    /// Appends the restart points and returns the bytes of the block.
     pub fn finish(&mut self) -> Vec<u8> {
-        let restart64 = self.buf.len() > u32::max_value() as usize;
+        let restart64 = self.restart64();
 
         let estimate = self.current_size_estimate();
         self.buf.reserve(estimate);
@@ -103,3 +181,118 @@ impl BlockBuilder {
         mem::replace(&mut self.buf, Vec::with_capacity(65536))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::block::{Block, BlockIter, RestartWidth};
+    use crate::BytesView;
+
+    fn entries(block: Block<Vec<u8>>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut iter = BlockIter::init(Arc::new(block));
+        iter.seek_to_first().unwrap();
+        let mut entries = Vec::new();
+        while let Some((key, val)) = iter.get() {
+            entries.push((key.to_vec(), val.to_vec()));
+            iter.next().unwrap();
+        }
+        entries
+    }
+
+    #[test]
+    fn zero_restart_interval() {
+        let mut builder = BlockBuilder::new(0);
+        for i in 0..10u32 {
+            builder.add(&i.to_be_bytes(), i.to_string().as_bytes());
+        }
+        let expected: Vec<_> = (0..10u32).map(|i| (i.to_be_bytes().to_vec(), i.to_string().into_bytes())).collect();
+        let block = Block::init(BytesView::from(builder.finish())).unwrap();
+        assert_eq!(entries(block), expected);
+    }
+
+    #[test]
+    fn restarts_64bit() {
+        let build = |force| {
+            let mut builder = BlockBuilder::new(4);
+            builder.force_64bit_restarts(force);
+            for i in 0..100u32 {
+                builder.add(format!("key-{:03}", i).as_bytes(), i.to_string().as_bytes());
+            }
+            builder.finish()
+        };
+
+        let block32 = build(false);
+        let block64 = build(true);
+        // 25 restart points, 4 more bytes each
+        assert_eq!(block64.len(), block32.len() + 25 * 4);
+
+        let block32 = Block::init(BytesView::from(block32)).unwrap();
+        let block64 = Block::init_with_restart_width(BytesView::from(block64), true).unwrap();
+        assert_eq!(block32.restart_width(), RestartWidth::Bits32);
+        assert_eq!(block64.restart_width(), RestartWidth::Bits64);
+        let expected = entries(block32.clone());
+        assert_eq!(expected.len(), 100);
+        assert_eq!(entries(block64.clone()), expected);
+
+        // the seeks use a binary search on the restart points
+        for block in [block32, block64] {
+            let mut iter = BlockIter::init(Arc::new(block));
+            for i in 0..100u32 {
+                let key = format!("key-{:03}", i);
+                iter.seek(key.as_bytes()).unwrap();
+                assert_eq!(iter.get().unwrap().0, key.as_bytes());
+            }
+            assert!(iter.seek_to_last().unwrap());
+            assert_eq!(iter.get().unwrap().0, b"key-099");
+            assert!(iter.prev().unwrap());
+            assert_eq!(iter.get().unwrap().0, b"key-098");
+        }
+    }
+
+    #[test]
+    fn seek_long_common_prefixes() {
+        use byteorder::ByteOrder;
+        use crate::MtblError;
+
+        // the keys share 300 bytes, more than a one byte varint, across many restart intervals
+        let prefix = "p".repeat(300);
+        let key = |i: u32| format!("{}{:05}", prefix, i * 2).into_bytes();
+
+        let mut builder = BlockBuilder::new(4);
+        for i in 0..100u32 {
+            builder.add(&key(i), i.to_string().as_bytes());
+        }
+        let data = builder.finish();
+
+        let mut iter = BlockIter::init(Arc::new(Block::init(BytesView::from(data.clone())).unwrap()));
+        for i in 0..100u32 {
+            iter.seek(&key(i)).unwrap();
+            assert_eq!(iter.get(), Some((&key(i)[..], i.to_string().as_bytes())));
+
+            // between two keys, lands on the next one
+            let mut between = key(i);
+            *between.last_mut().unwrap() += 1;
+            iter.seek(&between).unwrap();
+            assert_eq!(iter.key().map(<[u8]>::to_vec), if i < 99 { Some(key(i + 1)) } else { None });
+        }
+        iter.seek(prefix.as_bytes()).unwrap();
+        assert_eq!(iter.key(), Some(&key(0)[..]));
+
+        // the entries at the restart points store whole keys
+        let restarts_offset = data.len() - 4 - 25 * 4;
+        for r in 0..25 {
+            let restart = LittleEndian::read_u32(&data[restarts_offset + r * 4..]) as usize;
+            assert_eq!(data[restart], 0);
+        }
+
+        // a restart point sharing a prefix is an error, not a silent stale position
+        let mut corrupted = data;
+        let restart = LittleEndian::read_u32(&corrupted[restarts_offset + 12 * 4..]) as usize;
+        corrupted[restart] = 3;
+        let mut iter = BlockIter::init(Arc::new(Block::init(BytesView::from(corrupted)).unwrap()));
+        iter.seek_to_first().unwrap();
+        assert!(matches!(iter.seek(&key(90)), Err(MtblError::InvalidBlock)));
+        assert_eq!(iter.get(), None);
+    }
+}
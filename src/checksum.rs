@@ -0,0 +1,148 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u64)]
+pub enum ChecksumType {
+    /// The files written before the checksum type was stored in the metadata use crc32c.
+    Crc32c = 0,
+    None = 1,
+    /// The lower 32 bits of the xxh3 64-bit hash, faster than crc32c on large blocks.
+    ///
+    /// Only supported with the `xxh3` feature, see `ChecksumType::is_supported`.
+    Xxh3 = 2,
+}
+
+impl ChecksumType {
+    pub(crate) fn from_u64(value: u64) -> Option<ChecksumType> {
+        match value {
+            0 => Some(ChecksumType::Crc32c),
+            1 => Some(ChecksumType::None),
+            2 => Some(ChecksumType::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Whether the files using this type can be written and read in this build,
+    /// depending on the enabled features. Without the `checksum` feature the crc32c
+    /// checksums are neither computed nor verified.
+    pub fn is_supported(self) -> bool {
+        match self {
+            ChecksumType::Crc32c | ChecksumType::None => true,
+            ChecksumType::Xxh3 => cfg!(feature = "xxh3"),
+        }
+    }
+}
+
+/// Computes the checksum of a block, `None` if there is no checksum to compute
+/// or if crc32c is not enabled in this build.
+pub fn checksum(type_: ChecksumType, data: &[u8]) -> Option<u32> {
+    match type_ {
+        ChecksumType::None => None,
+        ChecksumType::Crc32c => crc32c_checksum(data),
+        ChecksumType::Xxh3 => xxh3_checksum(data),
+    }
+}
+
+#[cfg(feature = "checksum")]
+fn crc32c_checksum(data: &[u8]) -> Option<u32> {
+    Some(crc32c::crc32c(data))
+}
+
+#[cfg(not(feature = "checksum"))]
+fn crc32c_checksum(_data: &[u8]) -> Option<u32> {
+    None
+}
+
+#[cfg(feature = "xxh3")]
+fn xxh3_checksum(data: &[u8]) -> Option<u32> {
+    Some(xxhash_rust::xxh3::xxh3_64(data) as u32)
+}
+
+#[cfg(not(feature = "xxh3"))]
+fn xxh3_checksum(_data: &[u8]) -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, feature = "checksum", feature = "xxh3"))]
+mod tests {
+    use super::*;
+    use crate::METADATA_SIZE;
+    use crate::error::{Error, MtblError};
+    use crate::{Reader, ReaderBuilder, WriterBuilder};
+
+    fn write(checksum_type: ChecksumType) -> Vec<u8> {
+        let mut writer = WriterBuilder::new().checksum_type(checksum_type).memory();
+        for i in 0..100u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    /// Flips the value of the first entry, a "0" that follows the key 0u32.
+    fn corrupt(vec: &mut [u8]) {
+        let pos = vec.windows(5).position(|w| w == [0, 0, 0, 0, b'0']).unwrap();
+        vec[pos + 4] = b'X';
+    }
+
+    fn read_all(vec: &[u8]) -> Result<usize, Error> {
+        let mut iter = Reader::new(vec)?.into_iter()?;
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            result?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    #[test]
+    fn xxh3_mismatch() {
+        let mut vec = write(ChecksumType::Xxh3);
+        assert_eq!(Reader::new(vec.as_slice()).unwrap().metadata().checksum_type, ChecksumType::Xxh3);
+        assert_eq!(read_all(&vec).unwrap(), 100);
+
+        corrupt(&mut vec);
+        match read_all(&vec) {
+            Err(Error::Mtbl(MtblError::ChecksumMismatch)) => (),
+            otherwise => panic!("expected a checksum mismatch, got {:?}", otherwise),
+        }
+
+        let reader = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice()).unwrap();
+        assert_eq!(reader.into_iter().unwrap().next().unwrap().unwrap().1, b"X");
+    }
+
+    #[test]
+    fn crc32c_cross_read() {
+        let mut vec = write(ChecksumType::Crc32c);
+
+        // the files written before the checksum type existed store zeroes there
+        let checksum_offset = vec.len() - METADATA_SIZE + 10 * 8;
+        assert!(vec[checksum_offset..checksum_offset + 8].iter().all(|b| *b == 0));
+        assert_eq!(Reader::new(vec.as_slice()).unwrap().metadata().checksum_type, ChecksumType::Crc32c);
+        assert_eq!(read_all(&vec).unwrap(), 100);
+
+        corrupt(&mut vec);
+        match read_all(&vec) {
+            Err(Error::Mtbl(MtblError::ChecksumMismatch)) => (),
+            otherwise => panic!("expected a checksum mismatch, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn checksum_versions() {
+        use crate::FileVersion;
+
+        // the other checksums than crc32c are not part of mtbl
+        let version = |vec: Vec<u8>| Reader::new(vec).unwrap().metadata().file_version;
+        assert_eq!(version(write(ChecksumType::Crc32c)), FileVersion::FormatV2);
+        assert_eq!(version(write(ChecksumType::None)), FileVersion::FormatV3);
+        assert_eq!(version(write(ChecksumType::Xxh3)), FileVersion::FormatV3);
+    }
+
+    #[test]
+    fn no_checksum() {
+        let mut vec = write(ChecksumType::None);
+        corrupt(&mut vec);
+        assert_eq!(read_all(&vec).unwrap(), 100);
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.into_iter().unwrap().next().unwrap().unwrap().1, b"X");
+    }
+}
@@ -1,7 +1,10 @@
-use std::borrow::Cow;
-use std::error::Error;
-use std::str::FromStr;
-use std::{fmt, io};
+use alloc::borrow::Cow;
+use core::{cmp, fmt};
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u64)]
@@ -12,10 +15,15 @@ pub enum CompressionType {
     Lz4 = 3,
     Lz4hc = 4,
     Zstd = 5,
+    /// Snappy in the frame format, readable by the tools that do not support the raw
+    /// format used by `Snappy`, it is not part of mtbl and only used by `FileVersion::FormatV3`
+    /// and the later formats.
+    SnappyFramed = 6,
 }
 
 impl CompressionType {
-    pub(crate) fn from_u64(value: u64) -> Option<CompressionType> {
+    /// The compression type of an id stored in the metadata, `None` if it is unknown.
+    pub fn from_u64(value: u64) -> Option<CompressionType> {
         match value {
             0 => Some(CompressionType::None),
             1 => Some(CompressionType::Snappy),
@@ -23,23 +31,81 @@ impl CompressionType {
             3 => Some(CompressionType::Lz4),
             4 => Some(CompressionType::Lz4hc),
             5 => Some(CompressionType::Zstd),
+            6 => Some(CompressionType::SnappyFramed),
             _ => None,
         }
     }
+
+    /// The id of this compression type as stored in the metadata.
+    pub fn as_u64(self) -> u64 {
+        self as u64
+    }
+
+    const ALL: [CompressionType; 7] = [
+        CompressionType::None,
+        CompressionType::Snappy,
+        CompressionType::Zlib,
+        CompressionType::Lz4,
+        CompressionType::Lz4hc,
+        CompressionType::Zstd,
+        CompressionType::SnappyFramed,
+    ];
+
+    /// The compression types supported by this build, depending on the enabled features.
+    pub fn all() -> impl Iterator<Item = CompressionType> {
+        CompressionType::ALL.iter().copied().filter(|c| c.is_supported())
+    }
+
+    /// Whether the blocks can be compressed and decompressed with this type in this build.
+    pub fn is_supported(self) -> bool {
+        match self {
+            CompressionType::None => true,
+            CompressionType::Snappy | CompressionType::SnappyFramed => cfg!(feature = "snappy"),
+            CompressionType::Zlib => cfg!(feature = "zlib"),
+            CompressionType::Zstd => cfg!(feature = "zstd"),
+            CompressionType::Lz4 | CompressionType::Lz4hc => false,
+        }
+    }
+
+    /// The level the blocks are compressed with when `level` is asked for: the types
+    /// without levels ignore it and use zero, zlib uses at most 9 and zstd at most 22,
+    /// zero being the default level of zstd.
+    pub fn effective_level(self, level: u32) -> u32 {
+        match self {
+            CompressionType::Zlib => cmp::min(level, 9),
+            CompressionType::Zstd => cmp::min(level, 22),
+            _ => 0,
+        }
+    }
+
+    /// The name of the type, as parsed by `FromStr`.
+    pub fn name(self) -> &'static str {
+        match self {
+            CompressionType::None => "none",
+            CompressionType::Snappy => "snappy",
+            CompressionType::Zlib => "zlib",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Lz4hc => "lz4hc",
+            CompressionType::Zstd => "zstd",
+            CompressionType::SnappyFramed => "snappy_framed",
+        }
+    }
 }
 
 impl FromStr for CompressionType {
     type Err = InvalidCompressionType;
 
+    /// Parses the name of a compression type, ignoring the case.
     fn from_str(name: &str) -> Result<Self, Self::Err> {
-        match name {
-            "snappy" => Ok(CompressionType::Snappy),
-            "zlib" => Ok(CompressionType::Zlib),
-            "lz4" => Ok(CompressionType::Lz4),
-            "lz4hc" => Ok(CompressionType::Lz4hc),
-            "zstd" => Ok(CompressionType::Zstd),
-            _ => Err(InvalidCompressionType),
-        }
+        CompressionType::ALL.iter().copied()
+            .find(|c| c.name().eq_ignore_ascii_case(name))
+            .ok_or(InvalidCompressionType)
+    }
+}
+
+impl fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
     }
 }
 
@@ -52,14 +118,79 @@ impl fmt::Display for InvalidCompressionType {
     }
 }
 
-impl Error for InvalidCompressionType {}
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidCompressionType {}
 
-pub fn decompress(type_: CompressionType, data: &[u8]) -> io::Result<Cow<[u8]>> {
+#[cfg(not(feature = "std"))]
+pub fn decompress<'a>(
+    type_: CompressionType,
+    _dictionary: Option<&[u8]>,
+    data: &'a [u8],
+    _max_size: usize,
+) -> Result<Cow<'a, [u8]>, crate::error::MtblError>
+{
     match type_ {
         CompressionType::None => Ok(Cow::Borrowed(data)),
-        CompressionType::Zlib => zlib_decompress(data),
-        CompressionType::Snappy => snappy_decompress(data),
-        CompressionType::Zstd => zstd_decompress(data),
+        _ => Err(crate::error::MtblError::UnsupportedCompressionAlgorithm),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+thread_local! {
+    static DECOMPRESSIONS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// The number of blocks decompressed by the current thread so far.
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn decompressions() -> usize {
+    DECOMPRESSIONS.with(|c| c.get())
+}
+
+/// Decompresses a block, an error is returned as soon as the decompressed
+/// size exceeds `max_size`, before allocating more than that.
+#[cfg(feature = "std")]
+pub fn decompress<'a>(
+    type_: CompressionType,
+    dictionary: Option<&[u8]>,
+    data: &'a [u8],
+    max_size: usize,
+) -> io::Result<Cow<'a, [u8]>>
+{
+    match type_ {
+        CompressionType::None => Ok(Cow::Borrowed(data)),
+        _ => {
+            let mut buffer = Vec::new();
+            decompress_into(type_, dictionary, data, max_size, &mut buffer)?;
+            Ok(Cow::Owned(buffer))
+        },
+    }
+}
+
+/// Like `decompress` but into `buffer`, cleared first, e.g. a buffer reused from a pool.
+#[cfg(feature = "std")]
+pub fn decompress_into(
+    type_: CompressionType,
+    dictionary: Option<&[u8]>,
+    data: &[u8],
+    max_size: usize,
+    buffer: &mut Vec<u8>,
+) -> io::Result<()>
+{
+    #[cfg(test)]
+    if type_ != CompressionType::None {
+        DECOMPRESSIONS.with(|c| c.set(c.get() + 1));
+    }
+
+    buffer.clear();
+    match type_ {
+        CompressionType::None => {
+            buffer.extend_from_slice(data);
+            Ok(())
+        },
+        CompressionType::Zlib => zlib_decompress(data, max_size, buffer),
+        CompressionType::Snappy => snappy_decompress(data, max_size, buffer),
+        CompressionType::SnappyFramed => snappy_framed_decompress(data, max_size, buffer),
+        CompressionType::Zstd => zstd_decompress(dictionary, data, max_size, buffer),
         other => {
             let error = format!("unsupported {:?} decompression", other);
             Err(io::Error::new(io::ErrorKind::Other, error))
@@ -67,12 +198,29 @@ pub fn decompress(type_: CompressionType, data: &[u8]) -> io::Result<Cow<[u8]>>
     }
 }
 
-pub fn compress(type_: CompressionType, level: u32, data: &[u8]) -> io::Result<Cow<[u8]>> {
+/// The parameters of the zstd encoder, ignored by the other compressions.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdParams {
+    pub long_distance: bool,
+    pub workers: u32,
+}
+
+#[cfg(feature = "std")]
+pub fn compress<'a>(
+    type_: CompressionType,
+    level: u32,
+    dictionary: Option<&[u8]>,
+    zstd: ZstdParams,
+    data: &'a [u8],
+) -> io::Result<Cow<'a, [u8]>>
+{
     match type_ {
         CompressionType::None => Ok(Cow::Borrowed(data)),
         CompressionType::Zlib => zlib_compress(data, level),
         CompressionType::Snappy => snappy_compress(data, level),
-        CompressionType::Zstd => zstd_compress(data, level),
+        CompressionType::SnappyFramed => snappy_framed_compress(data, level),
+        CompressionType::Zstd => zstd_compress(dictionary, zstd, data, level),
         other => {
             let error = format!("unsupported {:?} decompression", other);
             Err(io::Error::new(io::ErrorKind::Other, error))
@@ -80,19 +228,32 @@ pub fn compress(type_: CompressionType, level: u32, data: &[u8]) -> io::Result<C
     }
 }
 
+#[cfg(any(feature = "zlib", feature = "snappy", feature = "zstd"))]
+fn size_exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, crate::error::MtblError::DecompressedSizeExceeded)
+}
+
+/// Reads the whole decompressed stream, without reading more than `max_size` bytes.
+#[cfg(any(feature = "zlib", feature = "snappy", feature = "zstd"))]
+fn read_bounded<R: io::Read>(reader: R, max_size: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    use std::io::Read;
+    reader.take((max_size as u64).saturating_add(1)).read_to_end(buffer)?;
+    if buffer.len() > max_size {
+        return Err(size_exceeded());
+    }
+    Ok(())
+}
+
 // --------- zlib ---------
 
 #[cfg(feature = "zlib")]
-fn zlib_decompress(data: &[u8]) -> io::Result<Cow<[u8]>> {
-    use std::io::Read;
-    let mut decoder = flate2::read::ZlibDecoder::new(data);
-    let mut buffer = Vec::new();
-    decoder.read_to_end(&mut buffer)?;
-    Ok(Cow::Owned(buffer))
+fn zlib_decompress(data: &[u8], max_size: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    let decoder = flate2::read::ZlibDecoder::new(data);
+    read_bounded(decoder, max_size, buffer)
 }
 
-#[cfg(not(feature = "zlib"))]
-fn zlib_decompress(_data: &[u8]) -> io::Result<Cow<[u8]>> {
+#[cfg(all(feature = "std", not(feature = "zlib")))]
+fn zlib_decompress(_data: &[u8], _max_size: usize, _buffer: &mut Vec<u8>) -> io::Result<()> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported zlib decompression"))
 }
 
@@ -105,7 +266,7 @@ fn zlib_compress(data: &[u8], level: u32) -> io::Result<Cow<[u8]>> {
     encoder.finish().map(Cow::Owned)
 }
 
-#[cfg(not(feature = "zlib"))]
+#[cfg(all(feature = "std", not(feature = "zlib")))]
 fn zlib_compress(_data: &[u8], _level: u32) -> io::Result<Cow<[u8]>> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported zlib compression"))
 }
@@ -113,13 +274,21 @@ fn zlib_compress(_data: &[u8], _level: u32) -> io::Result<Cow<[u8]>> {
 // --------- snappy ---------
 
 #[cfg(feature = "snappy")]
-fn snappy_decompress(data: &[u8]) -> io::Result<Cow<[u8]>> {
+fn snappy_decompress(data: &[u8], max_size: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    // the decompressed length is stored at the start of the block
+    let len = snap::raw::decompress_len(data)?;
+    if len > max_size {
+        return Err(size_exceeded());
+    }
+    buffer.resize(len, 0);
     let mut decoder = snap::raw::Decoder::new();
-    decoder.decompress_vec(data).map_err(Into::into).map(Cow::Owned)
+    let len = decoder.decompress(data, buffer)?;
+    buffer.truncate(len);
+    Ok(())
 }
 
-#[cfg(not(feature = "snappy"))]
-fn snappy_decompress(_data: &[u8]) -> io::Result<Cow<[u8]>> {
+#[cfg(all(feature = "std", not(feature = "snappy")))]
+fn snappy_decompress(_data: &[u8], _max_size: usize, _buffer: &mut Vec<u8>) -> io::Result<()> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported snappy decompression"))
 }
 
@@ -129,33 +298,196 @@ fn snappy_compress(data: &[u8], _level: u32) -> io::Result<Cow<[u8]>> {
     decoder.compress_vec(data).map_err(Into::into).map(Cow::Owned)
 }
 
-#[cfg(not(feature = "snappy"))]
+#[cfg(all(feature = "std", not(feature = "snappy")))]
 fn snappy_compress(_data: &[u8], _level: u32) -> io::Result<Cow<[u8]>> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported snappy compression"))
 }
 
+#[cfg(feature = "snappy")]
+fn snappy_framed_decompress(data: &[u8], max_size: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    let decoder = snap::read::FrameDecoder::new(data);
+    read_bounded(decoder, max_size, buffer)
+}
+
+#[cfg(all(feature = "std", not(feature = "snappy")))]
+fn snappy_framed_decompress(_data: &[u8], _max_size: usize, _buffer: &mut Vec<u8>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "unsupported snappy decompression"))
+}
+
+#[cfg(feature = "snappy")]
+fn snappy_framed_compress(data: &[u8], _level: u32) -> io::Result<Cow<'_, [u8]>> {
+    use std::io::Write;
+    let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+    encoder.write_all(data)?;
+    encoder.into_inner().map_err(|e| e.into_error()).map(Cow::Owned)
+}
+
+#[cfg(all(feature = "std", not(feature = "snappy")))]
+fn snappy_framed_compress(_data: &[u8], _level: u32) -> io::Result<Cow<'_, [u8]>> {
+    Err(io::Error::new(io::ErrorKind::Other, "unsupported snappy compression"))
+}
+
 // --------- zstd ---------
 
+/// The magic number that starts a zstd dictionary, followed by the dictionary id.
+const ZSTD_DICT_MAGIC: u32 = 0xEC30A437;
+
+/// Returns the id stored in the header of a zstd dictionary, the raw content
+/// dictionaries do not have one and are identified by a hash of their content.
+/// It is never zero, which means no dictionary in the metadata.
+pub fn zstd_dictionary_id(dictionary: &[u8]) -> u32 {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    let id = if dictionary.len() >= 8 && LittleEndian::read_u32(dictionary) == ZSTD_DICT_MAGIC {
+        LittleEndian::read_u32(&dictionary[4..])
+    } else {
+        // the 32-bit FNV-1a hash
+        dictionary.iter().fold(0x811c_9dc5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+    };
+    cmp::max(id, 1)
+}
+
+/// Trains a zstd dictionary of at most `dict_size` bytes from the given samples.
+#[cfg(feature = "zstd_dict")]
+pub fn train_dictionary(samples: &[&[u8]], dict_size: usize) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, dict_size)
+}
+
 #[cfg(feature = "zstd")]
-fn zstd_decompress(data: &[u8]) -> io::Result<Cow<[u8]>> {
-    let mut buffer = Vec::new();
-    zstd::stream::copy_decode(data, &mut buffer)?;
-    Ok(Cow::Owned(buffer))
+fn zstd_decompress(dictionary: Option<&[u8]>, data: &[u8], max_size: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    match dictionary {
+        Some(dictionary) => read_bounded(zstd::stream::read::Decoder::with_dictionary(data, dictionary)?, max_size, buffer),
+        None => read_bounded(zstd::stream::read::Decoder::new(data)?, max_size, buffer),
+    }
 }
 
-#[cfg(not(feature = "zstd"))]
-fn zstd_decompress(_data: &[u8]) -> io::Result<Cow<[u8]>> {
+#[cfg(all(feature = "std", not(feature = "zstd")))]
+fn zstd_decompress(_dictionary: Option<&[u8]>, _data: &[u8], _max_size: usize, _buffer: &mut Vec<u8>) -> io::Result<()> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported zstd decompression"))
 }
 
 #[cfg(feature = "zstd")]
-fn zstd_compress(data: &[u8], level: u32) -> io::Result<Cow<[u8]>> {
-    let mut buffer = Vec::new();
-    zstd::stream::copy_encode(data, &mut buffer, level as i32)?;
+fn zstd_compress<'a>(dictionary: Option<&[u8]>, params: ZstdParams, data: &'a [u8], level: u32) -> io::Result<Cow<'a, [u8]>> {
+    use std::io::Write;
+    use zstd::stream::raw::{CParameter, Encoder};
+
+    let mut encoder = Encoder::with_dictionary(level as i32, dictionary.unwrap_or_default())?;
+    if params.long_distance {
+        encoder.set_parameter(CParameter::EnableLongDistanceMatching(true))?;
+    }
+    if params.workers > 0 {
+        encoder.set_parameter(CParameter::NbWorkers(params.workers))?;
+    }
+
+    let mut writer = zstd::stream::zio::Writer::new(Vec::new(), encoder);
+    writer.write_all(data)?;
+    writer.finish()?;
+    let (buffer, _) = writer.into_inner();
     Ok(Cow::Owned(buffer))
 }
 
-#[cfg(not(feature = "zstd"))]
-fn zstd_compress(_data: &[u8], _level: u32) -> io::Result<Cow<[u8]>> {
+#[cfg(all(feature = "std", not(feature = "zstd")))]
+fn zstd_compress<'a>(_dictionary: Option<&[u8]>, _params: ZstdParams, _data: &'a [u8], _level: u32) -> io::Result<Cow<'a, [u8]>> {
     Err(io::Error::new(io::ErrorKind::Other, "unsupported zstd compression"))
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    #[test]
+    fn names() {
+        for &type_ in CompressionType::ALL.iter() {
+            assert_eq!(type_.to_string().parse(), Ok(type_));
+            assert_eq!(type_.name().to_uppercase().parse(), Ok(type_));
+        }
+        assert_eq!("Zstd".parse(), Ok(CompressionType::Zstd));
+        assert_eq!("none".parse(), Ok(CompressionType::None));
+        assert_eq!("brotli".parse::<CompressionType>(), Err(InvalidCompressionType));
+        assert_eq!("".parse::<CompressionType>(), Err(InvalidCompressionType));
+
+        let all: Vec<_> = CompressionType::all().collect();
+        assert!(all.contains(&CompressionType::None));
+        assert_eq!(all.contains(&CompressionType::Snappy), cfg!(feature = "snappy"));
+        assert!(!all.contains(&CompressionType::Lz4));
+        assert_eq!(all.contains(&CompressionType::Zstd), cfg!(feature = "zstd"));
+    }
+
+    #[test]
+    fn ids() {
+        for &type_ in CompressionType::ALL.iter() {
+            assert_eq!(CompressionType::from_u64(type_.as_u64()), Some(type_));
+        }
+        assert_eq!(CompressionType::Zstd.as_u64(), 5);
+        assert_eq!(CompressionType::from_u64(7), None);
+        assert_eq!(CompressionType::from_u64(u64::MAX), None);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn snappy_framed() {
+        use crate::Reader;
+
+        let data = b"hello hello hello hello hello hello".repeat(100);
+
+        let framed = compress(CompressionType::SnappyFramed, 0, None, ZstdParams::default(), &data).unwrap();
+        // the frame format starts with a stream identifier chunk
+        assert!(framed.starts_with(b"\xff\x06\x00\x00sNaPpY"));
+        let decompressed = decompress(CompressionType::SnappyFramed, None, &framed, usize::MAX).unwrap();
+        assert_eq!(decompressed.as_ref(), data.as_slice());
+
+        // the raw and framed formats are not compatible
+        assert!(decompress(CompressionType::Snappy, None, &framed, usize::MAX).map_or(true, |d| d.as_ref() != data.as_slice()));
+        let raw = compress(CompressionType::Snappy, 0, None, ZstdParams::default(), &data).unwrap();
+        assert!(decompress(CompressionType::SnappyFramed, None, &raw, usize::MAX).is_err());
+
+        let mut writer = WriterBuilder::new()
+            .with_compression_type(CompressionType::SnappyFramed)
+            .with_block_size(256)
+            .memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().compression_algorithm, CompressionType::SnappyFramed);
+        assert_eq!(reader.metadata().file_version, crate::FileVersion::FormatV3);
+        let entries: Vec<_> = (&reader).into_iter().collect::<Result<_, _>>().unwrap();
+        let expected: Vec<_> = (0..1000u32).map(|i| (i.to_be_bytes().to_vec(), i.to_string().into_bytes())).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(CompressionType::None.effective_level(99), 0);
+        assert_eq!(CompressionType::Snappy.effective_level(99), 0);
+        assert_eq!(CompressionType::Zlib.effective_level(6), 6);
+        assert_eq!(CompressionType::Zlib.effective_level(99), 9);
+        assert_eq!(CompressionType::Zstd.effective_level(0), 0);
+        assert_eq!(CompressionType::Zstd.effective_level(99), 22);
+
+        let writer = WriterBuilder::new().with_compression_level(99).memory();
+        assert_eq!(writer.compression_level(), 0);
+
+        #[cfg(feature = "zlib")]
+        {
+            use crate::Reader;
+
+            let mut writer = WriterBuilder::new()
+                .with_compression_type(CompressionType::Zlib)
+                .with_compression_level(99)
+                .with_block_size(256)
+                .memory();
+            assert_eq!(writer.compression_level(), 9);
+            for i in 0..1000u32 {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            let vec = writer.into_inner().unwrap();
+
+            let reader = Reader::new(vec.as_slice()).unwrap();
+            assert_eq!(reader.get_owned(&500u32.to_be_bytes()).unwrap(), Some(b"500".to_vec()));
+        }
+    }
+}
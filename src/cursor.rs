@@ -0,0 +1,413 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{mem, slice};
+
+use crate::block::{Block, BlockIter};
+use crate::error::Error;
+use crate::index::IndexIter;
+use crate::reader::Reader;
+use crate::BytesView;
+
+impl<A: AsRef<[u8]>> Reader<A> {
+    /// Returns a cursor positioned nowhere, one of the seek methods must be called first.
+    pub fn cursor(&self) -> Cursor<'_, A> {
+        Cursor {
+            reader: self,
+            index_iter: IndexIter::init(self.index().clone()),
+            bi: None,
+            key_buffer: Vec::new(),
+        }
+    }
+
+    /// Returns the entries of the `keys` found in the table, the keys must be sorted.
+    ///
+    /// The table and the keys are walked together, the index is only searched
+    /// when a key is after the current block, every block is read at most once.
+    pub fn probe<'k>(&self, keys: &'k [&'k [u8]]) -> ProbeIter<'_, 'k, A> {
+        ProbeIter { cursor: self.cursor(), keys: keys.iter(), done: false }
+    }
+}
+
+/// A stateful cursor over the entries of a `Reader`, it can be moved in both directions.
+/// It borrows the reader, unlike `Reader::into_double_ended_iter` which consumes it.
+///
+/// The movement methods return `true` if the cursor is on an entry afterwards.
+pub struct Cursor<'a, A> {
+    reader: &'a Reader<A>,
+    index_iter: IndexIter<A>,
+    bi: Option<BlockIter<A>>,
+    /// The key buffer of the last block iterator, reused by the next one.
+    key_buffer: Vec<u8>,
+}
+
+impl<A: AsRef<[u8]>> Cursor<'_, A> {
+    /// Moves to the first entry with a key greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<bool, Error> {
+        let key = &*self.reader.transform_key(key);
+        self.seek_transformed(key)
+    }
+
+    /// Like `seek` but the cursor never moves backward, it stays on its entry if
+    /// the key is before it. A key in the current block is searched in the block
+    /// only, the index and the next blocks are not read.
+    pub fn seek_forward(&mut self, key: &[u8]) -> Result<bool, Error> {
+        let key = &*self.reader.transform_key(key);
+        let bi = match &mut self.bi {
+            Some(bi) if bi.key().is_some_and(|current| current >= key) => return Ok(true),
+            // the key of the index entry is greater than or equal to the keys of its block
+            Some(bi) if self.index_iter.key().is_some_and(|last| key <= last) => bi,
+            _ => return self.seek_transformed(key),
+        };
+
+        bi.seek(key)?;
+        if bi.get().is_some() {
+            return Ok(true);
+        }
+        self.index_iter.next()?;
+        self.load_block(false)
+    }
+
+    /// Moves to the first entry with a key greater than or equal to `key`, already transformed.
+    fn seek_transformed(&mut self, key: &[u8]) -> Result<bool, Error> {
+        self.index_iter.seek(key)?;
+        match self.reader.block_at_index(&self.index_iter)? {
+            Some(b) => {
+                let mut bi = self.block_iter(b);
+                bi.seek(key)?;
+                if bi.get().is_some() {
+                    self.bi = Some(bi);
+                    return Ok(true);
+                }
+                self.key_buffer = bi.key;
+                self.index_iter.next()?;
+                self.load_block(false)
+            },
+            None => {
+                self.clear_block();
+                Ok(false)
+            },
+        }
+    }
+
+    /// Returns the value of `key` and leaves the cursor on it, if found. The buffers
+    /// of the cursor are reused from one lookup to the next, unlike `Reader::get`.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let key = self.reader.transform_key(key);
+        if self.reader.out_of_bounds(&key) {
+            self.clear_block();
+            return Ok(None);
+        }
+
+        // the key can only be in the block pointed by the index, the next one is never read
+        self.index_iter.seek(&key)?;
+        match self.reader.block_at_index(&self.index_iter)? {
+            Some(b) => {
+                let mut bi = self.block_iter(b);
+                bi.seek(&key)?;
+                self.bi = Some(bi);
+                if self.current().is_none() {
+                    self.clear_block();
+                }
+            },
+            None => self.clear_block(),
+        }
+
+        Ok(self.current().filter(|(k, _)| *k == &*key).map(|(_, v)| v))
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<bool, Error> {
+        self.index_iter.seek_to_first()?;
+        self.load_block(false)
+    }
+
+    pub fn seek_to_last(&mut self) -> Result<bool, Error> {
+        self.index_iter.seek_to_last()?;
+        self.load_block(true)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool, Error> {
+        match self.bi.as_mut().map(|bi| bi.next()) {
+            Some(Ok(true)) => Ok(true),
+            Some(Ok(false)) => {
+                self.index_iter.next()?;
+                self.load_block(false)
+            },
+            Some(Err(e)) => {
+                self.clear_block();
+                Err(Error::from(e))
+            },
+            None => Ok(false),
+        }
+    }
+
+    pub fn prev(&mut self) -> Result<bool, Error> {
+        match self.bi.as_mut().map(|bi| bi.prev()) {
+            Some(Ok(true)) => Ok(true),
+            Some(Ok(false)) => {
+                self.index_iter.prev()?;
+                self.load_block(true)
+            },
+            Some(Err(e)) => {
+                self.clear_block();
+                Err(Error::from(e))
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the entry the cursor is on, if any.
+    pub fn current(&self) -> Option<(&[u8], &[u8])> {
+        self.bi.as_ref().and_then(|bi| bi.get())
+    }
+
+    /// Returns the value the cursor is on, it shares the bytes of the block
+    /// and stays valid once the cursor has moved, nothing is copied.
+    pub fn current_value_view(&self) -> Option<BytesView<A>> {
+        self.bi.as_ref().and_then(|bi| bi.val_view())
+    }
+
+    /// Loads the block pointed by the index and moves to its first entry,
+    /// or its last if `last` is `true`, skipping the empty blocks.
+    fn load_block(&mut self, last: bool) -> Result<bool, Error> {
+        while let Some(b) = self.reader.block_at_index(&self.index_iter)? {
+            let mut bi = self.block_iter(b);
+            let found = if last { bi.seek_to_last() } else { bi.seek_to_first() };
+            if let Ok(true) = found {
+                self.bi = Some(bi);
+                return Ok(true);
+            }
+            self.key_buffer = bi.key;
+            found?;
+            if last { self.index_iter.prev()?; } else { self.index_iter.next()?; }
+        }
+
+        self.clear_block();
+        Ok(false)
+    }
+
+    /// Iterates over a new block, with the key buffer of the previous block iterator.
+    fn block_iter(&mut self, b: Block<A>) -> BlockIter<A> {
+        let key = match self.bi.take() {
+            Some(bi) => bi.key,
+            None => mem::take(&mut self.key_buffer),
+        };
+        BlockIter::init_with_key_buffer(Arc::new(b), key)
+    }
+
+    /// Moves the cursor nowhere, its key buffer is kept.
+    fn clear_block(&mut self) {
+        if let Some(bi) = self.bi.take() {
+            self.key_buffer = bi.key;
+        }
+    }
+}
+
+/// The entries of the probed keys found in a table, see `Reader::probe`.
+pub struct ProbeIter<'a, 'k, A> {
+    cursor: Cursor<'a, A>,
+    keys: slice::Iter<'k, &'k [u8]>,
+    done: bool,
+}
+
+impl<'k, A: AsRef<[u8]>> Iterator for ProbeIter<'_, 'k, A> {
+    type Item = Result<(&'k [u8], BytesView<A>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        for &key in self.keys.by_ref() {
+            match self.cursor.seek_forward(key) {
+                Ok(true) => (),
+                // the table is exhausted, the next keys are not in it
+                Ok(false) => break,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            }
+
+            let stored = self.cursor.reader.transform_key(key);
+            if self.cursor.current().is_some_and(|(k, _)| k == &*stored) {
+                return self.cursor.current_value_view().map(|val| Ok((key, val)));
+            }
+        }
+
+        self.done = true;
+        None
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    fn key(i: u32) -> [u8; 4] {
+        i.to_be_bytes()
+    }
+
+    #[test]
+    fn cursor() {
+        // only the even keys
+        let mut writer = WriterBuilder::new().with_block_size(64).with_block_restart_interval(3).memory();
+        for i in (0..1000u32).step_by(2) {
+            writer.insert(key(i), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 10);
+
+        let current_key = |cursor: &Cursor<_>| cursor.current().map(|(k, _)| k.to_vec());
+
+        // many cursors on the same reader
+        let mut cursor = reader.cursor();
+        let mut other = reader.cursor();
+        assert_eq!(cursor.current(), None);
+
+        // seek then prev
+        assert!(cursor.seek(&key(501)).unwrap());
+        assert_eq!(current_key(&cursor), Some(key(502).to_vec()));
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.current(), Some((&key(500)[..], &b"500"[..])));
+
+        assert!(other.seek_to_first().unwrap());
+        assert_eq!(current_key(&other), Some(key(0).to_vec()));
+        assert!(!other.prev().unwrap());
+        assert_eq!(other.current(), None);
+
+        // seek past the end
+        assert!(!cursor.seek(&key(999)).unwrap());
+        assert_eq!(cursor.current(), None);
+        assert!(!cursor.next().unwrap());
+
+        assert!(cursor.seek_to_last().unwrap());
+        assert_eq!(current_key(&cursor), Some(key(998).to_vec()));
+        assert!(!cursor.next().unwrap());
+
+        // alternating next and prev, across the blocks
+        assert!(cursor.seek(&key(0)).unwrap());
+        for i in (0..998u32).step_by(2) {
+            assert!(cursor.next().unwrap());
+            assert_eq!(current_key(&cursor), Some(key(i + 2).to_vec()));
+            assert!(cursor.prev().unwrap());
+            assert_eq!(current_key(&cursor), Some(key(i).to_vec()));
+            assert!(cursor.next().unwrap());
+        }
+
+        // a full backward scan
+        assert!(cursor.seek_to_last().unwrap());
+        let mut count = 1;
+        while cursor.prev().unwrap() {
+            count += 1;
+        }
+        assert_eq!(count, 500);
+
+        let empty = Reader::new(WriterBuilder::new().memory().into_inner().unwrap()).unwrap();
+        let mut cursor = empty.cursor();
+        assert!(cursor.current_value_view().is_none());
+        assert!(!cursor.seek_to_first().unwrap());
+        assert!(!cursor.seek_to_last().unwrap());
+        assert!(!cursor.seek(b"hello").unwrap());
+    }
+
+    #[test]
+    fn current_value_view() {
+        let mut writer = WriterBuilder::new().with_block_size(64).memory();
+        for i in 0..1000u32 {
+            writer.insert(key(i), format!("value-{}", i)).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut cursor = reader.cursor();
+        assert!(cursor.current_value_view().is_none());
+        assert!(cursor.seek(&key(42)).unwrap());
+        let view = cursor.current_value_view().unwrap();
+
+        // the cursor moves past the block of the captured value
+        let mut views = vec![(42, view)];
+        for i in 43..300u32 {
+            assert!(cursor.next().unwrap());
+            if i % 50 == 0 {
+                views.push((i, cursor.current_value_view().unwrap()));
+            }
+        }
+        assert!(!cursor.seek(&key(1000)).unwrap());
+        drop(cursor);
+
+        for (i, view) in views {
+            assert_eq!(view.as_ref(), format!("value-{}", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn get() {
+        // only the even keys
+        let mut writer = WriterBuilder::new().with_block_size(128).memory();
+        for i in (0..20_000u32).step_by(2) {
+            writer.insert(key(i), format!("value-{}", i)).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut cursor = reader.cursor();
+        assert_eq!(cursor.get(&key(42)).unwrap(), Some(&b"value-42"[..]));
+        assert_eq!(cursor.get(&key(43)).unwrap(), None);
+        assert_eq!(cursor.get(&key(20_000)).unwrap(), None);
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.get(&key(0)).unwrap(), Some(&b"value-0"[..]));
+        assert!(cursor.next().unwrap());
+        assert_eq!(cursor.current(), Some((&key(2)[..], &b"value-2"[..])));
+
+        for i in 0..21_000 {
+            let i = i * 7919 % 21_000;
+            let value = cursor.get(&key(i)).unwrap();
+            assert_eq!(value.is_some(), i % 2 == 0 && i < 20_000);
+        }
+    }
+
+    fn probe_reader() -> Reader<Vec<u8>> {
+        // only the even keys
+        let mut writer = WriterBuilder::new().with_block_size(128).memory();
+        for i in (0..2000u32).step_by(2) {
+            writer.insert(key(i), i.to_string()).unwrap();
+        }
+        Reader::new(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn probe() {
+        let reader = probe_reader();
+        let blocks = reader.metadata().count_data_blocks as usize;
+        assert!(blocks > 10);
+
+        let keys: Vec<_> = (0..2100u32).map(key).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+
+        let found: Vec<_> = reader.probe(&keys).map(Result::unwrap).collect();
+        assert_eq!(found.len(), 1000);
+        for (i, (k, val)) in (0..2000u32).step_by(2).zip(found) {
+            assert_eq!(k, key(i));
+            assert_eq!(val.as_ref(), i.to_string().as_bytes());
+        }
+    }
+
+    quickcheck! {
+        fn qc_probe_like_get(probes: Vec<u16>) -> bool {
+            let reader = probe_reader();
+            let mut probes: Vec<_> = probes.into_iter().map(|i| key(i as u32 % 2100)).collect();
+            probes.sort_unstable();
+            probes.dedup();
+            let probes: Vec<&[u8]> = probes.iter().map(|k| &k[..]).collect();
+
+            let found: Vec<_> = reader.probe(&probes)
+                .map(|result| result.map(|(k, v)| (k.to_vec(), v.as_ref().to_vec())))
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let expected: Vec<_> = probes.iter()
+                .filter_map(|k| reader.get_owned(k).unwrap().map(|v| (k.to_vec(), v)))
+                .collect();
+            found == expected
+        }
+    }
+}
@@ -1,18 +1,27 @@
-use std::{fmt, io, error};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{error, io};
 
 #[derive(Debug)]
 pub enum Error<U=()> {
     Mtbl(MtblError),
+    #[cfg(feature = "std")]
     Io(io::Error),
-    Merge(U),
+    /// The merge function failed on the values of this key.
+    MergeKey { key: Vec<u8>, error: U },
 }
 
-impl<U> Error<U> {
+impl Error {
+    /// Converts the errors of the readers and the merger iterators to the merge error
+    /// type of the caller, a failed merge without error value keeps its key.
+    #[cfg(feature = "std")]
     pub(crate) fn convert_merge_error<V>(self) -> Error<V> {
         match self {
             Error::Mtbl(mtbl) => Error::Mtbl(mtbl),
             Error::Io(io) => Error::Io(io),
-            Error::Merge(_) => panic!("cannot convert a merge error"),
+            Error::MergeKey { key, error: () } => Error::Mtbl(MtblError::MergeFailed { key }),
         }
     }
 }
@@ -21,16 +30,26 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Mtbl(mtbl) => write!(f, "{}", mtbl),
+            #[cfg(feature = "std")]
             Error::Io(io) => write!(f, "{}", io),
-            Error::Merge(_) => f.write_str("<user merge error>"),
+            Error::MergeKey { key, .. } => {
+                write!(f, "<user merge error> on key {:?}", String::from_utf8_lossy(key))
+            },
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error { }
 
+#[cfg(feature = "std")]
 impl<U> From<io::Error> for Error<U> {
     fn from(err: io::Error) -> Error<U> {
+        // the errors of this crate returned through io::Error, e.g. by the decompression
+        if err.get_ref().is_some_and(|e| e.is::<MtblError>()) {
+            let mtbl = err.into_inner().unwrap().downcast::<MtblError>().unwrap();
+            return Error::Mtbl(*mtbl);
+        }
         Error::Io(err)
     }
 }
@@ -48,7 +67,36 @@ pub enum MtblError {
     InvalidIndexLength,
     InvalidFormatVersion,
     InvalidCompressionAlgorithm,
+    UnsupportedCompressionAlgorithm,
+    InvalidChecksumAlgorithm,
+    /// A checksum type not enabled in this build, see `ChecksumType::is_supported`.
+    UnsupportedChecksumAlgorithm,
+    ChecksumMismatch,
     InvalidBlock,
+    MissingCompressionDictionary,
+    InvalidCompressionDictionary,
+    DiskBudgetExceeded,
+    InvalidIndexEntry,
+    DecompressedSizeExceeded,
+    /// A key found in many sources of a merger that forbids the duplicates.
+    DuplicateKey { key: Vec<u8> },
+    /// A key bigger than the limit of the writer, see `WriterBuilder::max_key_size`.
+    KeyTooLarge,
+    /// A value bigger than the limit of the writer, see `WriterBuilder::max_value_size`.
+    ValueTooLarge,
+    /// The number of entries stored in the metadata is not the number of entries
+    /// found in the data blocks, see `Reader::validate_entry_count`.
+    EntryCountMismatch { declared: u64, found: u64 },
+    /// The sizes of the keys stored in the metadata exceed it, see `Metadata::min_key`.
+    InvalidKeyRange,
+    /// The checksum of the value of this key does not match, see `Reader::verify_entry_checksums`.
+    EntryChecksumMismatch { key: Vec<u8> },
+    /// The partition of this key is out of the writers or smaller than the one of
+    /// the previous key, see `Merger::partition_write`.
+    InvalidPartition { key: Vec<u8>, partition: usize },
+    /// The merge function failed on the values of this key with the `()` error,
+    /// see `Error::MergeKey`.
+    MergeFailed { key: Vec<u8> },
 }
 
 impl fmt::Display for MtblError {
@@ -59,9 +107,35 @@ impl fmt::Display for MtblError {
             MtblError::InvalidIndexLength => f.write_str("invalid index length"),
             MtblError::InvalidFormatVersion => f.write_str("invalid format version"),
             MtblError::InvalidCompressionAlgorithm => f.write_str("invalid compression algorithm"),
+            MtblError::UnsupportedCompressionAlgorithm => f.write_str("unsupported compression algorithm"),
+            MtblError::InvalidChecksumAlgorithm => f.write_str("invalid checksum algorithm"),
+            MtblError::UnsupportedChecksumAlgorithm => f.write_str("unsupported checksum algorithm"),
+            MtblError::ChecksumMismatch => f.write_str("checksum mismatch"),
             MtblError::InvalidBlock => f.write_str("invalid block"),
+            MtblError::MissingCompressionDictionary => f.write_str("missing compression dictionary"),
+            MtblError::InvalidCompressionDictionary => f.write_str("invalid compression dictionary"),
+            MtblError::DiskBudgetExceeded => f.write_str("disk budget exceeded"),
+            MtblError::InvalidIndexEntry => f.write_str("invalid index entry"),
+            MtblError::DecompressedSizeExceeded => f.write_str("decompressed size exceeded"),
+            MtblError::DuplicateKey { key } => write!(f, "duplicate key {:?}", String::from_utf8_lossy(key)),
+            MtblError::KeyTooLarge => f.write_str("key too large"),
+            MtblError::ValueTooLarge => f.write_str("value too large"),
+            MtblError::EntryCountMismatch { declared, found } => {
+                write!(f, "entry count mismatch, {} declared but {} found", declared, found)
+            },
+            MtblError::InvalidKeyRange => f.write_str("invalid key range"),
+            MtblError::EntryChecksumMismatch { key } => {
+                write!(f, "checksum mismatch on the value of key {:?}", String::from_utf8_lossy(key))
+            },
+            MtblError::InvalidPartition { key, partition } => {
+                write!(f, "invalid partition {} for key {:?}", partition, String::from_utf8_lossy(key))
+            },
+            MtblError::MergeFailed { key } => {
+                write!(f, "merge failed on key {:?}", String::from_utf8_lossy(key))
+            },
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for MtblError { }
@@ -0,0 +1,176 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::block::{Block, BlockIter};
+use crate::error::{Error, MtblError};
+use crate::varint::varint_decode64;
+use crate::DynBytes;
+
+/// The index of a table, a single block or the segments of a two-level
+/// index, see `WriterBuilder::two_level_index`.
+pub(crate) struct Index<A> {
+    segments: Vec<Arc<Block<A>>>,
+    /// The last key of every segment, the sparse top-level index,
+    /// empty when the index is a single block.
+    last_keys: Vec<Box<[u8]>>,
+}
+
+impl<A: AsRef<[u8]>> Index<A> {
+    pub(crate) fn single(block: Block<A>) -> Index<A> {
+        Index { segments: vec![Arc::new(block)], last_keys: Vec::new() }
+    }
+
+    /// Reads the segments referenced by the entries of the `top` block,
+    /// `segment(offset)` decodes the segment written at `offset`.
+    pub(crate) fn two_level<F>(top: Block<A>, mut segment: F) -> Result<Index<A>, Error>
+    where F: FnMut(u64) -> Result<Block<A>, Error>,
+    {
+        let mut segments = Vec::new();
+        let mut last_keys: Vec<Box<[u8]>> = Vec::new();
+
+        let mut iter = BlockIter::init(Arc::new(top));
+        iter.seek_to_first()?;
+        while let Some((key, val)) = iter.get() {
+            if last_keys.last().is_some_and(|last| **last >= *key) {
+                return Err(Error::from(MtblError::InvalidIndexEntry));
+            }
+            let mut offset = 0;
+            varint_decode64(val, &mut offset);
+            segments.push(Arc::new(segment(offset)?));
+            last_keys.push(Box::from(key));
+            iter.next()?;
+        }
+
+        Ok(Index { segments, last_keys })
+    }
+
+    /// Whether the index is split in segments under a top-level index.
+    pub(crate) fn is_two_level(&self) -> bool {
+        !self.last_keys.is_empty()
+    }
+
+    /// The size of the decoded index in memory.
+    pub(crate) fn size(&self) -> usize {
+        let segments: usize = self.segments.iter().map(|block| (**block).as_ref().len()).sum();
+        let last_keys: usize = self.last_keys.iter().map(|key| key.len()).sum();
+        segments + last_keys
+    }
+}
+
+impl<A: AsRef<[u8]> + Send + Sync + 'static> Index<A> {
+    pub(crate) fn to_dyn(&self) -> Index<DynBytes> {
+        Index {
+            segments: self.segments.iter().map(|block| Arc::new((**block).clone().into_dyn())).collect(),
+            last_keys: self.last_keys.clone(),
+        }
+    }
+}
+
+/// Iterates over the entries of an `Index` like a `BlockIter` over its
+/// single block, a seek first searches the top-level index then one segment.
+pub(crate) struct IndexIter<A> {
+    index: Arc<Index<A>>,
+    /// The segment the iterator is positioned in.
+    segment: usize,
+    iter: Option<BlockIter<A>>,
+}
+
+impl<A: AsRef<[u8]>> IndexIter<A> {
+    /// Creates an unpositioned iterator over the entries of `index`.
+    pub(crate) fn init(index: Arc<Index<A>>) -> IndexIter<A> {
+        IndexIter { index, segment: 0, iter: None }
+    }
+
+    /// Moves the iterator to the `segment`-th segment, it must then be positioned.
+    fn enter(&mut self, segment: usize) -> Option<&mut BlockIter<A>> {
+        let block = self.index.segments.get(segment)?;
+        if self.iter.is_none() || self.segment != segment {
+            self.segment = segment;
+            self.iter = Some(BlockIter::init(block.clone()));
+        }
+        self.iter.as_mut()
+    }
+
+    /// Moves to the first entry, returns `false` if there is none.
+    pub(crate) fn seek_to_first(&mut self) -> Result<bool, MtblError> {
+        match self.enter(0) {
+            Some(iter) => iter.seek_to_first(),
+            None => {
+                self.iter = None;
+                Ok(false)
+            },
+        }
+    }
+
+    /// Moves to the last entry, returns `false` if there is none.
+    pub(crate) fn seek_to_last(&mut self) -> Result<bool, MtblError> {
+        match self.enter(self.index.segments.len().wrapping_sub(1)) {
+            Some(iter) => iter.seek_to_last(),
+            None => {
+                self.iter = None;
+                Ok(false)
+            },
+        }
+    }
+
+    /// Moves to the first entry with a key greater than or equal to `target`.
+    pub(crate) fn seek(&mut self, target: &[u8]) -> Result<(), MtblError> {
+        let segment = if self.index.is_two_level() {
+            self.index.last_keys.partition_point(|key| **key < *target)
+        } else {
+            0
+        };
+
+        match self.enter(segment) {
+            Some(iter) => iter.seek(target),
+            None => {
+                self.iter = None;
+                Ok(())
+            },
+        }
+    }
+
+    /// Moves to the next entry, returns `false` if there is none.
+    pub(crate) fn next(&mut self) -> Result<bool, MtblError> {
+        match &mut self.iter {
+            Some(iter) if iter.key().is_some() => {
+                if iter.next()? {
+                    return Ok(true);
+                }
+            },
+            _ => return Ok(false),
+        }
+
+        match self.enter(self.segment + 1) {
+            Some(iter) => iter.seek_to_first(),
+            None => Ok(false),
+        }
+    }
+
+    /// Moves to the previous entry, returns `false` if there is none.
+    pub(crate) fn prev(&mut self) -> Result<bool, MtblError> {
+        match &mut self.iter {
+            Some(iter) if iter.key().is_some() => {
+                if iter.prev()? {
+                    return Ok(true);
+                }
+            },
+            _ => return Ok(false),
+        }
+
+        match self.segment.checked_sub(1).and_then(|segment| self.enter(segment)) {
+            Some(iter) => iter.seek_to_last(),
+            None => Ok(false),
+        }
+    }
+
+    pub(crate) fn key(&self) -> Option<&[u8]> {
+        self.iter.as_ref()?.key()
+    }
+
+    pub(crate) fn get(&self) -> Option<(&[u8], &[u8])> {
+        self.iter.as_ref()?.get()
+    }
+}
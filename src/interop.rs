@@ -0,0 +1,312 @@
+//! Import and export of the entries as text, one `key\tvalue\n` line per entry.
+//!
+//! The keys and the values are encoded with a `TsvEncoding`, the encoded bytes never
+//! contain a tab nor a newline, therefore any key or value goes through a round trip unchanged.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+use crate::validator::is_in_order;
+use crate::{Error, Reader, Sorter, Writer};
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HEX_ALPHABET: &[u8; 16] = b"0123456789abcdef";
+
+/// How the keys and the values are encoded in the lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TsvEncoding {
+    /// Two lowercase hexadecimal digits per byte, the uppercase ones are accepted when decoding.
+    Hex,
+    /// The standard base64 alphabet (RFC 4648), with the `=` padding.
+    Base64,
+}
+
+impl TsvEncoding {
+    fn encode(self, bytes: &[u8], out: &mut Vec<u8>) {
+        match self {
+            TsvEncoding::Hex => hex_encode(bytes, out),
+            TsvEncoding::Base64 => base64_encode(bytes, out),
+        }
+    }
+
+    fn decode(self, text: &[u8], out: &mut Vec<u8>) -> Option<()> {
+        match self {
+            TsvEncoding::Hex => hex_decode(text, out),
+            TsvEncoding::Base64 => base64_decode(text, out),
+        }
+    }
+}
+
+/// Reads the `key\tvalue` lines of `input` and inserts them in `writer`,
+/// returns the number of entries inserted.
+///
+/// The lines must be sorted by decoded key, a line whose key is not greater than the
+/// key of the previous line is an `InvalidData` error, see `import_tsv_into_sorter`
+/// for the unsorted lines. An empty line is ignored and a line ending with `\r\n` is accepted.
+pub fn import_tsv<R: Read, W: Write>(
+    input: R,
+    writer: &mut Writer<W>,
+    encoding: TsvEncoding,
+) -> io::Result<u64>
+{
+    let mut last_key: Option<Vec<u8>> = None;
+    read_tsv(input, encoding, |line_number, key, val| {
+        match &mut last_key {
+            Some(last_key) if !is_in_order(last_key, key) => {
+                let message = format!("out-of-order tsv entry at line {}", line_number);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            },
+            Some(last_key) => {
+                last_key.clear();
+                last_key.extend_from_slice(key);
+            },
+            None => last_key = Some(key.to_vec()),
+        }
+        writer.insert(key, val)
+    })
+}
+
+/// Like `import_tsv` but the entries are inserted in `sorter`, the lines can be
+/// in any order and the values of the keys found on many lines are merged.
+pub fn import_tsv_into_sorter<R: Read, MF, U>(
+    input: R,
+    sorter: &mut Sorter<MF>,
+    encoding: TsvEncoding,
+) -> Result<u64, Error<U>>
+where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>
+{
+    read_tsv(input, encoding, |_line_number, key, val| sorter.insert(key, val))
+}
+
+/// Decodes the lines of `input` and calls `insert` with the line number,
+/// the key and the value of every entry, returns the number of entries.
+fn read_tsv<R, E, F>(input: R, encoding: TsvEncoding, mut insert: F) -> Result<u64, E>
+where R: Read,
+      E: From<io::Error>,
+      F: FnMut(usize, &[u8], &[u8]) -> Result<(), E>,
+{
+    let mut input = BufReader::new(input);
+    let mut line = Vec::new();
+    let mut key = Vec::new();
+    let mut val = Vec::new();
+    let mut count = 0;
+
+    for line_number in 1.. {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 { break }
+
+        let mut text = line.as_slice();
+        text = text.strip_suffix(b"\n").unwrap_or(text);
+        text = text.strip_suffix(b"\r").unwrap_or(text);
+        if text.is_empty() { continue }
+
+        let invalid_line = || {
+            let message = format!("invalid tsv entry at line {}", line_number);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        };
+
+        let tab = text.iter().position(|&b| b == b'\t').ok_or_else(invalid_line)?;
+        key.clear();
+        val.clear();
+        encoding.decode(&text[..tab], &mut key).ok_or_else(invalid_line)?;
+        encoding.decode(&text[tab + 1..], &mut val).ok_or_else(invalid_line)?;
+
+        insert(line_number, &key, &val)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Writes all the entries of `reader` to `out` as `key\tvalue` lines,
+/// returns the number of entries written.
+pub fn export_tsv<A: AsRef<[u8]>, W: Write>(
+    reader: &Reader<A>,
+    out: W,
+    encoding: TsvEncoding,
+) -> Result<u64, Error>
+{
+    let mut out = BufWriter::new(out);
+    let mut line = Vec::new();
+    let mut count = 0;
+
+    let mut cursor = reader.cursor();
+    let mut valid = cursor.seek_to_first()?;
+    while valid {
+        if let Some((key, val)) = cursor.current() {
+            line.clear();
+            encoding.encode(key, &mut line);
+            line.push(b'\t');
+            encoding.encode(val, &mut line);
+            line.push(b'\n');
+            out.write_all(&line)?;
+            count += 1;
+        }
+        valid = cursor.next()?;
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+fn hex_encode(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(HEX_ALPHABET[(b >> 4) as usize]);
+        out.push(HEX_ALPHABET[(b & 0x0f) as usize]);
+    }
+}
+
+fn hex_decode(text: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    fn digit(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|d| d as u8)
+    }
+
+    if text.len() % 2 != 0 { return None }
+    for pair in text.chunks(2) {
+        out.push(digit(pair[0])? << 4 | digit(pair[1])?);
+    }
+    Some(())
+}
+
+fn base64_encode(bytes: &[u8], out: &mut Vec<u8>) {
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+}
+
+fn base64_decode(text: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    fn sextet(c: u8) -> Option<u32> {
+        BASE64_ALPHABET.iter().position(|&a| a == c).map(|p| p as u32)
+    }
+
+    if text.len() % 4 != 0 { return None }
+    let nb_chunks = text.len() / 4;
+    for (i, chunk) in text.chunks(4).enumerate() {
+        // the padding is only allowed at the end of the last chunk
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && i + 1 != nb_chunks) { return None }
+
+        let mut n = 0;
+        for &c in &chunk[..4 - padding] {
+            n = n << 6 | sextet(c)?;
+        }
+        n <<= 6 * padding;
+
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        out.extend_from_slice(&decoded[..3 - padding]);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    #[test]
+    fn encodings() {
+        let mut out = Vec::new();
+        base64_encode(b"foobar", &mut out);
+        assert_eq!(out, b"Zm9vYmFy");
+
+        out.clear();
+        base64_encode(b"fooba", &mut out);
+        assert_eq!(out, b"Zm9vYmE=");
+
+        out.clear();
+        hex_encode(b"\x00\t\n\xff", &mut out);
+        assert_eq!(out, b"00090aff");
+
+        for len in 0..10 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 97) as u8).collect();
+            for &encoding in &[TsvEncoding::Hex, TsvEncoding::Base64] {
+                let mut text = Vec::new();
+                encoding.encode(&bytes, &mut text);
+                let mut decoded = Vec::new();
+                encoding.decode(&text, &mut decoded).unwrap();
+                assert_eq!(decoded, bytes);
+            }
+        }
+
+        let mut out = Vec::new();
+        assert!(hex_decode(b"0", &mut out).is_none());
+        assert!(hex_decode(b"zz", &mut out).is_none());
+        assert!(base64_decode(b"Zm9", &mut out).is_none());
+        assert!(base64_decode(b"Zg==Zg==", &mut out).is_none());
+        assert!(hex_decode(b"0A", &mut out).is_some());
+    }
+
+    #[test]
+    fn tsv_round_trip() {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = (0..500u32)
+            .map(|i| {
+                let key = format!("key\t{:04}\n", i).into_bytes();
+                let val = format!("value\n\t{}\r\n", i).repeat(i as usize % 5).into_bytes();
+                (key, val)
+            })
+            .collect();
+        entries.push((vec![0xff, b'\t', 0], vec![0, b'\n', 0xff]));
+
+        let mut writer = WriterBuilder::new().with_block_size(128).memory();
+        for (key, val) in &entries {
+            writer.insert(key, val).unwrap();
+        }
+        let original = writer.into_inner().unwrap();
+        let reader = Reader::new(original.as_slice()).unwrap();
+
+        for &encoding in &[TsvEncoding::Hex, TsvEncoding::Base64] {
+            let mut tsv = Vec::new();
+            assert_eq!(export_tsv(&reader, &mut tsv, encoding).unwrap(), entries.len() as u64);
+            assert_eq!(tsv.iter().filter(|&&b| b == b'\n').count(), entries.len());
+            assert_eq!(tsv.iter().filter(|&&b| b == b'\t').count(), entries.len());
+
+            let mut writer = WriterBuilder::new().memory();
+            assert_eq!(import_tsv(tsv.as_slice(), &mut writer, encoding).unwrap(), entries.len() as u64);
+            let imported = writer.into_inner().unwrap();
+
+            let reader = Reader::new(imported).unwrap();
+            let mut iter = reader.into_iter().unwrap();
+            let mut expected = entries.iter();
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                let (ekey, eval) = expected.next().unwrap();
+                assert_eq!((key, val), (ekey.as_slice(), eval.as_slice()));
+            }
+            assert!(expected.next().is_none());
+        }
+    }
+
+    #[test]
+    fn import_invalid_lines() {
+        let mut writer = WriterBuilder::new().memory();
+        let err = import_tsv(&b"6869\t6869\r\n\n6869\n"[..], &mut writer, TsvEncoding::Hex).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "invalid tsv entry at line 3");
+    }
+
+    #[test]
+    fn import_unsorted_lines() {
+        let tsv = b"62\t31\n61\t32\n62\t33\n";
+
+        let mut writer = WriterBuilder::new().memory();
+        let err = import_tsv(&tsv[..], &mut writer, TsvEncoding::Hex).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "out-of-order tsv entry at line 2");
+
+        let mut sorter = Sorter::new(|_key: &[u8], vals: &[Vec<u8>]| Ok::<_, ()>(vals.concat()));
+        assert_eq!(import_tsv_into_sorter(&tsv[..], &mut sorter, TsvEncoding::Hex).unwrap(), 3);
+        let mut writer = WriterBuilder::new().memory();
+        sorter.write_into(&mut writer).unwrap();
+
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let entries: Vec<_> = reader.to_btreemap().unwrap().into_iter().collect();
+        assert_eq!(entries, vec![(b"a".to_vec(), b"2".to_vec()), (b"b".to_vec(), b"13".to_vec())]);
+    }
+}
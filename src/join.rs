@@ -0,0 +1,280 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::error::Error;
+use crate::reader::{Reader, ReaderIntoIter};
+
+/// A key with its value in the left and right readers.
+type OuterEntry = (Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>);
+
+impl<A: AsRef<[u8]>> Reader<A> {
+    /// Returns the keys present in both readers with their two values.
+    pub fn inner_join<B: AsRef<[u8]>>(self, other: Reader<B>) -> Result<InnerJoin<A, B>, Error> {
+        FullOuterJoin::new(self, other).map(|iter| InnerJoin { iter })
+    }
+
+    /// Returns the keys of this reader with their value in the other reader, if any.
+    pub fn left_join<B: AsRef<[u8]>>(self, other: Reader<B>) -> Result<LeftJoin<A, B>, Error> {
+        FullOuterJoin::new(self, other).map(|iter| LeftJoin { iter })
+    }
+
+    /// Returns the keys present in any of the readers with their value in each reader.
+    pub fn full_outer_join<B: AsRef<[u8]>>(self, other: Reader<B>) -> Result<FullOuterJoin<A, B>, Error> {
+        FullOuterJoin::new(self, other)
+    }
+}
+
+/// One side of a join, an iterator positioned on its current entry. The entries
+/// are compared in place and only copied when they are returned.
+struct JoinSide<A> {
+    iter: ReaderIntoIter<A>,
+    positioned: bool,
+    /// The error met when moving to the next entry, returned after the current one.
+    error: Option<Error>,
+}
+
+impl<A: AsRef<[u8]>> JoinSide<A> {
+    fn new(reader: Reader<A>) -> Result<JoinSide<A>, Error> {
+        let mut side = JoinSide { iter: reader.into_iter()?, positioned: false, error: None };
+        side.advance();
+        match side.error.take() {
+            Some(e) => Err(e),
+            None => Ok(side),
+        }
+    }
+
+    fn advance(&mut self) {
+        self.positioned = match self.iter.next() {
+            Some(Ok(_)) => true,
+            Some(Err(e)) => {
+                self.error = Some(e);
+                false
+            },
+            None => false,
+        };
+    }
+
+    fn key(&self) -> Option<&[u8]> {
+        self.current().map(|(key, _)| key)
+    }
+
+    fn current(&self) -> Option<(&[u8], &[u8])> {
+        if self.positioned { self.iter.current() } else { None }
+    }
+
+    /// Returns a copy of the current value, if asked for, and moves to the next entry.
+    fn take_val(&mut self, copy: bool) -> Option<Vec<u8>> {
+        let val = if copy { self.current().map(|(_, val)| val.to_vec()) } else { None };
+        self.advance();
+        val
+    }
+}
+
+pub struct FullOuterJoin<A, B> {
+    left: JoinSide<A>,
+    right: JoinSide<B>,
+    done: bool,
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>> FullOuterJoin<A, B> {
+    fn new(left: Reader<A>, right: Reader<B>) -> Result<FullOuterJoin<A, B>, Error> {
+        Ok(FullOuterJoin { left: JoinSide::new(left)?, right: JoinSide::new(right)?, done: false })
+    }
+
+    /// Returns the next key found on both sides, or only on the left or right
+    /// side when `left_only` or `right_only`, the other keys are skipped.
+    fn next_entry(&mut self, left_only: bool, right_only: bool) -> Result<Option<OuterEntry>, Error> {
+        loop {
+            if let Some(e) = self.left.error.take().or_else(|| self.right.error.take()) {
+                return Err(e);
+            }
+
+            let ordering = match (self.left.key(), self.right.key()) {
+                (Some(left), Some(right)) => left.cmp(right),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return Ok(None),
+            };
+
+            // the side with the smallest key is advanced, both when the keys are equal
+            match ordering {
+                Ordering::Less => {
+                    let key = self.left.key().filter(|_| left_only).map(<[u8]>::to_vec);
+                    let left = self.left.take_val(left_only);
+                    if let Some(key) = key {
+                        return Ok(Some((key, left, None)));
+                    }
+                },
+                Ordering::Greater => {
+                    let key = self.right.key().filter(|_| right_only).map(<[u8]>::to_vec);
+                    let right = self.right.take_val(right_only);
+                    if let Some(key) = key {
+                        return Ok(Some((key, None, right)));
+                    }
+                },
+                Ordering::Equal => {
+                    let key = self.left.key().map(<[u8]>::to_vec);
+                    let left = self.left.take_val(true);
+                    let right = self.right.take_val(true);
+                    return Ok(key.map(|key| (key, left, right)));
+                },
+            }
+        }
+    }
+
+    /// Stops at the end or on the first error.
+    fn next_joined(&mut self, left_only: bool, right_only: bool) -> Option<Result<OuterEntry, Error>> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.next_entry(left_only, right_only).transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>> Iterator for FullOuterJoin<A, B> {
+    type Item = Result<OuterEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_joined(true, true)
+    }
+}
+
+pub struct InnerJoin<A, B> {
+    iter: FullOuterJoin<A, B>,
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>> Iterator for InnerJoin<A, B> {
+    type Item = Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next_joined(false, false)? {
+            Ok((key, Some(left), Some(right))) => Some(Ok((key, left, right))),
+            Ok(_) => unreachable!("only the keys of both sides are returned"),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+pub struct LeftJoin<A, B> {
+    iter: FullOuterJoin<A, B>,
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>> Iterator for LeftJoin<A, B> {
+    type Item = Result<(Vec<u8>, Vec<u8>, Option<Vec<u8>>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next_joined(true, false)? {
+            Ok((key, Some(left), right)) => Some(Ok((key, left, right))),
+            Ok(_) => unreachable!("only the keys of the left side are returned"),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    fn table(keys: &[u32], tag: &str) -> Vec<u8> {
+        let mut writer = WriterBuilder::new().with_block_size(64).memory();
+        for key in keys {
+            writer.insert(key.to_be_bytes(), format!("{}{}", tag, key)).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn reader(keys: &[u32], tag: &str) -> Reader<Vec<u8>> {
+        Reader::new(table(keys, tag)).unwrap()
+    }
+
+    fn val(tag: &str, key: u32) -> Vec<u8> {
+        format!("{}{}", tag, key).into_bytes()
+    }
+
+    /// The expected full outer join computed from the key sets.
+    fn expected(left: &[u32], right: &[u32]) -> Vec<OuterEntry> {
+        let mut keys: Vec<_> = left.iter().chain(right).cloned().collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys.into_iter().map(|k| {
+            let l = if left.contains(&k) { Some(val("l", k)) } else { None };
+            let r = if right.contains(&k) { Some(val("r", k)) } else { None };
+            (k.to_be_bytes().to_vec(), l, r)
+        }).collect()
+    }
+
+    #[test]
+    fn joins() {
+        let evens: Vec<u32> = (0..200).step_by(2).collect();
+        let threes: Vec<u32> = (0..200).step_by(3).collect();
+        let disjoint: Vec<u32> = (1000..1100).collect();
+
+        let cases = [
+            (&evens, &disjoint),
+            (&disjoint, &evens),
+            (&evens, &evens),
+            (&evens, &threes),
+            (&threes, &evens),
+        ];
+
+        for (left, right) in cases.iter() {
+            let expected = expected(left, right);
+
+            let full: Vec<_> = reader(left, "l").full_outer_join(reader(right, "r")).unwrap()
+                .collect::<Result<_, _>>().unwrap();
+            assert_eq!(full, expected);
+
+            let inner: Vec<_> = reader(left, "l").inner_join(reader(right, "r")).unwrap()
+                .collect::<Result<_, _>>().unwrap();
+            let inner_expected: Vec<_> = expected.iter().cloned()
+                .filter_map(|(k, l, r)| Some((k, l?, r?)))
+                .collect();
+            assert_eq!(inner, inner_expected);
+
+            let left_join: Vec<_> = reader(left, "l").left_join(reader(right, "r")).unwrap()
+                .collect::<Result<_, _>>().unwrap();
+            let left_expected: Vec<_> = expected.iter().cloned()
+                .filter_map(|(k, l, r)| Some((k, l?, r)))
+                .collect();
+            assert_eq!(left_join, left_expected);
+        }
+
+        let inner = reader(&evens, "l").inner_join(reader(&disjoint, "r")).unwrap();
+        assert_eq!(inner.count(), 0);
+        let inner = reader(&evens, "l").inner_join(reader(&threes, "r")).unwrap();
+        assert_eq!(inner.count(), (0..200).step_by(6).count());
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let keys: Vec<u32> = (0..200).collect();
+        let mut vec = table(&keys, "l");
+
+        // corrupts the second data block
+        let corrupted = Reader::new(vec.as_slice()).unwrap();
+        let (_, raw) = corrupted.raw_block_at(1).unwrap();
+        let middle = raw.as_ptr() as usize - vec.as_ptr() as usize + raw.len() / 2;
+        vec[middle] ^= 0xff;
+        let mut first_block = Vec::new();
+        let mut scan = Reader::new(vec.as_slice()).unwrap().iter_block(0).unwrap();
+        while let Some(result) = scan.next() {
+            first_block.push(result.unwrap().0.to_vec());
+        }
+
+        // the entries read before the error are all returned first
+        let mut iter = Reader::new(vec).unwrap().full_outer_join(reader(&keys, "r")).unwrap();
+        for (key, i) in first_block.iter().zip(0..) {
+            let (k, l, r) = iter.next().unwrap().unwrap();
+            assert_eq!((&k, l, r), (key, Some(val("l", i)), Some(val("r", i))));
+        }
+        let error = iter.next().unwrap().unwrap_err();
+        assert!(matches!(error, Error::Mtbl(crate::MtblError::ChecksumMismatch)), "{:?}", error);
+        assert!(iter.next().is_none());
+    }
+}
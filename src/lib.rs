@@ -1,53 +1,204 @@
-#[cfg(test)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(test, feature = "std"))]
 #[macro_use] extern crate quickcheck;
 
+#[cfg(feature = "std")]
 const DEFAULT_BLOCK_RESTART_INTERVAL: usize = 16;
 const DEFAULT_BLOCK_SIZE: u64 = 8192;
-const MIN_BLOCK_SIZE: u64 = 1024;
+/// The smallest block that can still hold the restart trailer of a block.
+#[cfg(feature = "std")]
+const MIN_BLOCK_SIZE: u64 = 16;
+
+#[cfg(feature = "std")]
+const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
 
+#[cfg(feature = "std")]
 const DEFAULT_COMPRESSION_LEVEL: u32 = 0;
 const DEFAULT_COMPRESSION_TYPE: CompressionType = CompressionType::None;
 
+#[cfg(feature = "std")]
 const DEFAULT_NB_CHUNKS: usize = 25;
+#[cfg(feature = "std")]
 const MIN_NB_CHUNKS: usize = 1;
+#[cfg(feature = "std")]
 const DEFAULT_SORTER_MEMORY: usize = 1_073_741_824; // 1GB
+#[cfg(feature = "std")]
 const MIN_SORTER_MEMORY: usize = 10_485_760; // 10MB
+#[cfg(feature = "std")]
 const INITIAL_SORTER_VEC_SIZE: usize = 131_072; // 128KB
 
 const METADATA_SIZE: usize = 512;
 
 const MAGIC: u32 = 0x4D54424C;
 const MAGIC_V1: u32 = 0x77846676;
+const MAGIC_V3: u32 = 0x4D544233;
+const MAGIC_V4: u32 = 0x4D544234;
+const MAGIC_V5: u32 = 0x4D544235;
 
-use std::sync::Arc;
+/// The size of the checksum that follows every value of a `FileVersion::FormatV5` file.
+const VALUE_CHECKSUM_SIZE: usize = 4;
 
-pub use error::Error;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use self::pool::PooledBytes;
+
+#[cfg(feature = "std")]
+pub use self::advise::{Access, Advise};
+#[cfg(feature = "tokio")]
+pub use self::async_reader::{AsyncReader, AsyncReaderIter, RangeFetcher};
+pub use block::RestartWidth;
+pub use cursor::{Cursor, ProbeIter};
+pub use error::{Error, MtblError};
+pub use checksum::ChecksumType;
+#[cfg(feature = "std")]
+pub use self::interop::{export_tsv, import_tsv, import_tsv_into_sorter, TsvEncoding};
 pub use compression::CompressionType;
-pub use self::metadata::Metadata;
-pub use self::reader::{Reader, ReaderBuilder, ReaderIntoGet, ReaderIntoIter};
-pub use self::writer::{Writer, WriterBuilder};
-pub use self::merger::{Merger, MergerBuilder, MergerIter};
-pub use self::sorter::{Sorter, SorterBuilder};
+#[cfg(feature = "zstd_dict")]
+pub use compression::train_dictionary;
+pub use self::join::{FullOuterJoin, InnerJoin, LeftJoin};
+pub use self::metadata::{Metadata, TableInfo};
+pub use self::pool::BufferPool;
+pub use self::reader::{BlockScan, Reader, ReaderBuilder, ReaderEntries, ReaderIntoDoubleEndedIter, ReaderIntoGet, ReaderIntoIter, ReaderPrefixRev, ReaderViews};
+pub use self::validator::{OrderViolation, Validator};
+#[cfg(feature = "std")]
+pub use self::writer::{write_btreemap, BlockSender, SyncWrite, Writer, WriterBuilder};
+#[cfg(feature = "std")]
+pub use self::merger::{BorrowedMerge, CompactionPolicy, MergeStats, Merger, MergerBuilder, MergerIter, MergeStrategy};
+#[cfg(feature = "std")]
+pub use self::sorter::{ChunkReaderMode, Sorter, SorterBuilder};
+#[cfg(feature = "serde")]
+pub use self::typed::{BigEndian, KeyCodec, TypedReader, TypedReaderIntoIter, TypedWriter};
 
+#[cfg(feature = "std")]
+mod advise;
+#[cfg(feature = "tokio")]
+mod async_reader;
 mod block;
+#[cfg(feature = "std")]
 mod block_builder;
+mod checksum;
 mod compression;
+mod cursor;
 mod error;
+mod index;
+#[cfg(feature = "std")]
+mod interop;
+mod join;
+#[cfg(feature = "std")]
+mod loser_tree;
+#[cfg(feature = "std")]
 mod merger;
 mod metadata;
+mod pool;
+#[cfg(feature = "std")]
+mod prefetch;
 mod reader;
+#[cfg(feature = "std")]
 mod sorter;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "serde")]
+mod typed;
+mod validator;
 mod varint;
+#[cfg(feature = "std")]
 mod writer;
 
+/// The prefix compressed blocks the files are made of, to build other formats on them.
+///
+/// ```
+/// use std::sync::Arc;
+/// use oxidized_mtbl::BytesView;
+/// use oxidized_mtbl::blocks::{Block, BlockBuilder, BlockIter};
+///
+/// // a restart point every 4 entries
+/// let mut builder = BlockBuilder::new(4);
+/// for i in 0..100u32 {
+///     builder.add(format!("key-{:03}", i).as_bytes(), i.to_string().as_bytes());
+/// }
+/// let bytes = builder.finish();
+///
+/// let block = Block::init(BytesView::from(bytes)).unwrap();
+/// let mut iter = BlockIter::init(Arc::new(block));
+/// iter.seek(b"key-042").unwrap();
+/// assert_eq!(iter.get(), Some((&b"key-042"[..], &b"42"[..])));
+/// assert!(iter.next().unwrap());
+/// assert_eq!(iter.key(), Some(&b"key-043"[..]));
+/// ```
+pub mod blocks {
+    pub use crate::block::{Block, BlockIter, RestartWidth};
+    #[cfg(feature = "std")]
+    pub use crate::block_builder::BlockBuilder;
+    pub use crate::varint::{varint_decode32, varint_decode64, varint_length_packed};
+    #[cfg(feature = "std")]
+    pub use crate::varint::{varint_encode32, varint_encode64};
+}
+
+/// A predicate on keys, used to skip entries during iteration.
+pub type KeyFilter = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// A normalization of the keys searched for in a reader, see `ReaderBuilder::key_transform`.
+pub type KeyTransform = Box<dyn Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync>;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u32)]
 pub enum FileVersion {
     FormatV1 = 0,
     FormatV2 = 1,
+    /// The V2 format where an entry can reuse the value of the previous entry of its
+    /// data block, see `WriterBuilder::dedup_repeated_values`, where the index block
+    /// can be compressed, see `WriterBuilder::index_compression_type`, and where the
+    /// blocks can use another checksum than crc32c or `CompressionType::SnappyFramed`,
+    /// it is not part of mtbl.
+    FormatV3 = 2,
+    /// The V3 format where the index can be split in segments under a sparse
+    /// top-level index, see `WriterBuilder::two_level_index`, it is not part of mtbl.
+    FormatV4 = 3,
+    /// The V4 format where every value is followed by its checksum, see
+    /// `WriterBuilder::per_entry_checksum`, it is not part of mtbl.
+    FormatV5 = 4,
+}
+
+impl FileVersion {
+    /// Whether the data blocks can store a value as a reference to the previous one.
+    pub(crate) fn repeated_values(self) -> bool {
+        matches!(self, FileVersion::FormatV3 | FileVersion::FormatV4 | FileVersion::FormatV5)
+    }
+
+    /// Whether the index block can be compressed.
+    pub(crate) fn index_compression(self) -> bool {
+        self >= FileVersion::FormatV3
+    }
+
+    /// Whether the index can be split in segments under a top-level index.
+    pub(crate) fn index_segments(self) -> bool {
+        matches!(self, FileVersion::FormatV4 | FileVersion::FormatV5)
+    }
+
+    /// Whether the blocks can use another checksum than crc32c.
+    pub(crate) fn checksum_types(self) -> bool {
+        self >= FileVersion::FormatV3
+    }
+
+    /// Whether the blocks can be compressed with the compression types that mtbl does not know.
+    pub(crate) fn extra_compression_types(self) -> bool {
+        self >= FileVersion::FormatV3
+    }
+
+    /// Whether every value is followed by its checksum.
+    pub(crate) fn value_checksums(self) -> bool {
+        self == FileVersion::FormatV5
+    }
 }
 
-#[derive(Clone)]
 pub struct BytesView<A: ?Sized> {
     inner: InnerBytesView<A>,
     offset: usize,
@@ -56,6 +207,8 @@ pub struct BytesView<A: ?Sized> {
 
 enum InnerBytesView<A: ?Sized> {
     Bytes(Arc<[u8]>),
+    #[cfg(feature = "std")]
+    Pooled(Arc<PooledBytes>),
     Data(Arc<A>),
 }
 
@@ -63,6 +216,8 @@ impl<A: AsRef<[u8]>> AsRef<[u8]> for InnerBytesView<A> {
     fn as_ref(&self) -> &[u8] {
         match self {
             InnerBytesView::Bytes(bytes) => bytes.as_ref(),
+            #[cfg(feature = "std")]
+            InnerBytesView::Pooled(bytes) => (**bytes).as_ref(),
             InnerBytesView::Data(data) => (**data).as_ref(),
         }
     }
@@ -75,6 +230,14 @@ impl<A> BytesView<A> {
         BytesView { inner, offset: 0, length }
     }
 
+    /// The bytes are given back to the pool once this view and its slices are dropped.
+    #[cfg(feature = "std")]
+    fn from_pooled(bytes: Vec<u8>, pool: Arc<dyn BufferPool>) -> Self {
+        let length = bytes.len();
+        let inner = InnerBytesView::Pooled(Arc::new(PooledBytes::new(bytes, pool)));
+        BytesView { inner, offset: 0, length }
+    }
+
     fn slice(&self, offset: usize, length: usize) -> Self {
         assert!(offset + length <= self.length);
         BytesView {
@@ -89,10 +252,30 @@ impl<A> BytesView<A> {
     }
 }
 
+impl<A: AsRef<[u8]> + Send + Sync + 'static> BytesView<A> {
+    fn into_dyn(self) -> BytesView<DynBytes> {
+        let inner = match self.inner {
+            InnerBytesView::Bytes(bytes) => InnerBytesView::Bytes(bytes),
+            #[cfg(feature = "std")]
+            InnerBytesView::Pooled(bytes) => InnerBytesView::Pooled(bytes),
+            InnerBytesView::Data(data) => InnerBytesView::Data(Arc::new(DynBytes(data))),
+        };
+        BytesView { inner, offset: self.offset, length: self.length }
+    }
+}
+
+impl<A> Clone for BytesView<A> {
+    fn clone(&self) -> BytesView<A> {
+        BytesView { inner: self.inner.clone(), offset: self.offset, length: self.length }
+    }
+}
+
 impl<A> Clone for InnerBytesView<A> {
     fn clone(&self) -> InnerBytesView<A> {
         match self {
             InnerBytesView::Bytes(bytes) => InnerBytesView::Bytes(bytes.clone()),
+            #[cfg(feature = "std")]
+            InnerBytesView::Pooled(bytes) => InnerBytesView::Pooled(bytes.clone()),
             InnerBytesView::Data(data) => InnerBytesView::Data(data.clone()),
         }
     }
@@ -112,3 +295,20 @@ impl<A: AsRef<[u8]>> AsRef<[u8]> for BytesView<A> {
         &slice[self.offset..self.offset + self.length]
     }
 }
+
+/// Bytes of any type, used to merge or store together readers of
+/// different backing types (e.g. a `Mmap` and a `Vec<u8>`).
+#[derive(Clone)]
+pub struct DynBytes(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl DynBytes {
+    pub fn new<A: AsRef<[u8]> + Send + Sync + 'static>(data: A) -> DynBytes {
+        DynBytes(Arc::new(data))
+    }
+}
+
+impl AsRef<[u8]> for DynBytes {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
@@ -0,0 +1,115 @@
+/// A tournament tree of losers, every internal node stores the loser of the match
+/// played at that node and the root stores the overall winner (the smallest leaf).
+///
+/// Replacing the winner only replays the matches on the path from its leaf to the
+/// root, that is exactly `log2(k)` comparisons, where a binary heap needs up to twice as many.
+pub struct LoserTree<T> {
+    /// The leaves of the tree, `None` when a leaf is exhausted.
+    leaves: Vec<Option<T>>,
+    /// The index of the winner leaf at position zero and the losers at the other positions.
+    tree: Vec<usize>,
+}
+
+impl<T: Ord> LoserTree<T> {
+    pub fn new(leaves: Vec<T>) -> LoserTree<T> {
+        let leaves: Vec<_> = leaves.into_iter().map(Some).collect();
+        let k = leaves.len();
+
+        let mut tree = vec![0; k];
+        let mut winners = vec![0; 2 * k];
+        (0..k).for_each(|i| winners[k + i] = i);
+
+        let mut lt = LoserTree { leaves, tree: Vec::new() };
+        for node in (1..k).rev() {
+            let (a, b) = (winners[2 * node], winners[2 * node + 1]);
+            let (winner, loser) = if lt.less(b, a) { (b, a) } else { (a, b) };
+            winners[node] = winner;
+            tree[node] = loser;
+        }
+
+        if k > 0 {
+            tree[0] = winners[1];
+        }
+
+        lt.tree = tree;
+        lt
+    }
+
+    fn less(&self, a: usize, b: usize) -> bool {
+        match (&self.leaves[a], &self.leaves[b]) {
+            (Some(a), Some(b)) => a < b,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Returns the smallest leaf.
+    pub fn peek(&self) -> Option<&T> {
+        let winner = *self.tree.first()?;
+        self.leaves[winner].as_ref()
+    }
+
+    /// Returns the smallest leaf, `replay` must be called if it is modified.
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        let winner = *self.tree.first()?;
+        self.leaves[winner].as_mut()
+    }
+
+    /// Removes the smallest leaf from the tree.
+    pub fn pop(&mut self) -> Option<T> {
+        let winner = *self.tree.first()?;
+        let leaf = self.leaves[winner].take();
+        self.replay();
+        leaf
+    }
+
+    /// Replays the matches of the winner leaf, must be called after it has been modified.
+    pub fn replay(&mut self) {
+        let k = self.leaves.len();
+        let mut winner = match self.tree.first() {
+            Some(winner) => *winner,
+            None => return,
+        };
+
+        let mut node = (k + winner) / 2;
+        while node >= 1 {
+            let loser = self.tree[node];
+            if self.less(loser, winner) {
+                self.tree[node] = winner;
+                winner = loser;
+            }
+            node /= 2;
+        }
+
+        self.tree[0] = winner;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    quickcheck! {
+        fn qc_sorted_like_a_heap(lists: Vec<Vec<u32>>) -> bool {
+            let mut lists: Vec<Vec<u32>> = lists;
+            lists.iter_mut().for_each(|l| { l.sort_unstable(); l.reverse(); });
+
+            let mut expected: Vec<u32> = lists.iter().flatten().cloned().collect();
+            expected.sort_unstable();
+
+            let heads: Vec<_> = lists.iter_mut().enumerate().filter_map(|(i, l)| l.pop().map(|x| (x, i))).collect();
+            let mut tree = LoserTree::new(heads);
+
+            let mut output = Vec::new();
+            while let Some(&(x, i)) = tree.peek() {
+                output.push(x);
+                match lists[i].pop() {
+                    Some(next) => { *tree.peek_mut().unwrap() = (next, i); tree.replay(); },
+                    None => { tree.pop(); },
+                }
+            }
+
+            output == expected
+        }
+    }
+}
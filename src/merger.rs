@@ -1,22 +1,103 @@
 use std::collections::binary_heap::{BinaryHeap, PeekMut};
-use std::cmp::{Reverse, Ordering};
-use std::{mem, io};
+use std::borrow::Cow;
+use std::cmp::{self, Reverse, Ordering};
+use std::io::Seek;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::{self, SyncSender};
+use std::{mem, io, panic, thread};
 
-use crate::{Error, Writer, Reader, ReaderIntoIter};
+use memmap::Mmap;
+
+use crate::loser_tree::LoserTree;
+use crate::stream::{BlockStream, StreamIter};
+use crate::{BytesView, DynBytes, Error, KeyFilter, MtblError, Writer, WriterBuilder, Reader, ReaderIntoIter};
+
+/// The number of entries sent at once to the writer of a partition.
+const PARTITION_BATCH_SIZE: usize = 1024;
+/// The number of batches waiting to be written by the writer of a partition.
+const PARTITION_CHANNEL_BOUND: usize = 4;
+
+type PartitionBatch = Vec<(Vec<u8>, Vec<u8>)>;
+
+type SharedOpener<A> = Arc<dyn Fn() -> Result<Reader<A>, Error> + Send + Sync>;
+type SharedStreamOpener = Arc<dyn Fn() -> Result<BlockStream, Error> + Send + Sync>;
+
+/// A source of a merger, read when the merge starts.
+#[allow(clippy::large_enum_variant)]
+enum Source<A> {
+    Reader(Reader<A>),
+    /// A reader only opened by the function when the source is merged.
+    Lazy(SharedOpener<A>),
+    /// A table read block by block from its start, opened by the function.
+    Stream(SharedStreamOpener),
+}
+
+impl<A> Clone for Source<A> {
+    fn clone(&self) -> Source<A> {
+        match self {
+            Source::Reader(reader) => Source::Reader(reader.clone()),
+            Source::Lazy(open) => Source::Lazy(open.clone()),
+            Source::Stream(open) => Source::Stream(open.clone()),
+        }
+    }
+}
+
+impl<A: AsRef<[u8]>> Source<A> {
+    fn into_iter(self) -> Result<SourceIter<A>, Error> {
+        match self {
+            Source::Reader(reader) => reader.into_iter().map(SourceIter::Reader),
+            Source::Lazy(open) => open()?.into_iter().map(SourceIter::Reader),
+            Source::Stream(open) => open().map(|stream| SourceIter::Stream(StreamIter::new(stream))),
+        }
+    }
+}
+
+/// The entries of a source being merged.
+#[allow(clippy::large_enum_variant)]
+enum SourceIter<A> {
+    Reader(ReaderIntoIter<A>),
+    Stream(StreamIter<A>),
+}
+
+impl<A: AsRef<[u8]>> SourceIter<A> {
+    #[allow(clippy::type_complexity)]
+    fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
+        match self {
+            SourceIter::Reader(iter) => iter.next(),
+            SourceIter::Stream(iter) => iter.next(),
+        }
+    }
+
+    fn current(&self) -> Option<(&[u8], &[u8])> {
+        match self {
+            SourceIter::Reader(iter) => iter.current(),
+            SourceIter::Stream(iter) => iter.current(),
+        }
+    }
+
+    fn current_val_view(&self) -> Option<BytesView<A>> {
+        match self {
+            SourceIter::Reader(iter) => iter.current_val_view(),
+            SourceIter::Stream(iter) => iter.current_val_view(),
+        }
+    }
+}
 
 pub struct Entry<A> {
-    iter: ReaderIntoIter<A>,
+    iter: SourceIter<A>,
+    /// The position of the source in the merger, used to order equal keys.
+    index: usize,
     key: Vec<u8>,
-    val: Vec<u8>,
 }
 
 impl<A: AsRef<[u8]>> Entry<A> {
     // also fills the entry
-    fn new(iter: ReaderIntoIter<A>) -> Result<Option<Entry<A>>, Error> {
+    fn new(iter: SourceIter<A>, index: usize) -> Result<Option<Entry<A>>, Error> {
         let mut entry = Entry {
             iter,
+            index,
             key: Vec::with_capacity(256),
-            val: Vec::with_capacity(256),
         };
 
         if !entry.fill()? {
@@ -28,23 +109,38 @@ impl<A: AsRef<[u8]>> Entry<A> {
 
     fn fill(&mut self) -> Result<bool, Error> {
         self.key.clear();
-        self.val.clear();
 
         match self.iter.next() {
             Some(result) => {
-                let (key, val) = result?;
+                let (key, _val) = result?;
                 self.key.extend_from_slice(key);
-                self.val.extend_from_slice(val);
                 Ok(true)
             },
             None => Ok(false),
         }
     }
+
+    /// The value of the entry, it is not copied out of the block of the source.
+    fn val(&self) -> &[u8] {
+        self.iter.current().map_or(&[], |(_key, val)| val)
+    }
+
+    fn val_view(&self) -> Option<BytesView<A>> {
+        self.iter.current_val_view()
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of entry comparisons done by the current thread.
+    static COMPARISONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
 }
 
 impl<A: AsRef<[u8]>> Ord for Entry<A> {
     fn cmp(&self, other: &Entry<A>) -> Ordering {
-        self.key.cmp(&other.key)
+        #[cfg(test)]
+        COMPARISONS.with(|c| c.set(c.get() + 1));
+        self.key.cmp(&other.key).then(self.index.cmp(&other.index))
     }
 }
 
@@ -52,7 +148,7 @@ impl<A: AsRef<[u8]>> Eq for Entry<A> {}
 
 impl<A: AsRef<[u8]>> PartialEq for Entry<A> {
     fn eq(&self, other: &Entry<A>) -> bool {
-        self.key == other.key
+        self.key == other.key && self.index == other.index
     }
 }
 
@@ -62,15 +158,102 @@ impl<A: AsRef<[u8]>> PartialOrd for Entry<A> {
     }
 }
 
+/// The algorithm used to find the smallest key among the sources.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeStrategy {
+    /// A binary heap, the default.
+    #[default]
+    Heap,
+    /// A tournament tree of losers, it does fewer comparisons
+    /// per entry and is faster when merging many sources.
+    LoserTree,
+}
+
+type SharedKeyFilter = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// A merge function of the form `Fn(&[u8], &[&[u8]]) -> Result<Vec<u8>, U>`, the values
+/// are slices of the blocks of the sources, see `MergerBuilder::merge_borrowed`.
+#[derive(Debug, Clone, Copy)]
+pub struct BorrowedMerge<MB>(pub MB);
+
 #[derive(Clone)]
 pub struct MergerBuilder<A, MF> {
-    sources: Vec<Reader<A>>,
+    sources: Vec<Source<A>>,
     merge: MF,
+    strategy: MergeStrategy,
+    key_filter: Option<SharedKeyFilter>,
+    max_fan_in: Option<usize>,
+    skip_failing_sources: bool,
+    forbid_duplicates: bool,
 }
 
 impl<A, MF> MergerBuilder<A, MF> {
     pub fn new(merge: MF) -> Self {
-        MergerBuilder { merge, sources: Vec::new() }
+        MergerBuilder {
+            merge,
+            sources: Vec::new(),
+            strategy: MergeStrategy::default(),
+            key_filter: None,
+            max_fan_in: None,
+            skip_failing_sources: false,
+            forbid_duplicates: false,
+        }
+    }
+
+    pub fn strategy(&mut self, strategy: MergeStrategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Only the keys for which the filter returns `true` are returned,
+    /// the others are skipped without ever calling the merge function.
+    pub fn key_filter(&mut self, filter: KeyFilter) -> &mut Self {
+        self.key_filter = Some(Arc::from(filter));
+        self
+    }
+
+    /// Merges at most `count` sources at once in `write_into`, the sources are merged
+    /// by batches into temporary tables, themselves merged until `count` tables remain.
+    /// Each source is dropped once its batch is merged.
+    ///
+    /// The readers added with `add` are opened beforehand, their files or mmaps stay
+    /// open until their batch is merged. The sources added with `add_lazy` are only
+    /// opened when their batch is merged, at most `count` of them are open at once.
+    ///
+    /// The merge function must then be associative, it receives the already
+    /// merged values of the previous stages. `count` is at least two.
+    pub fn max_fan_in(&mut self, count: usize) -> &mut Self {
+        self.max_fan_in = Some(cmp::max(count, 2));
+        self
+    }
+
+    /// Replaces the merge function by one that receives borrowed values, they
+    /// are not copied into a `Vec<u8>` each, see `BorrowedMerge`.
+    pub fn merge_borrowed<MB>(self, merge: MB) -> MergerBuilder<A, BorrowedMerge<MB>> {
+        MergerBuilder {
+            sources: self.sources,
+            merge: BorrowedMerge(merge),
+            strategy: self.strategy,
+            key_filter: self.key_filter,
+            max_fan_in: self.max_fan_in,
+            skip_failing_sources: self.skip_failing_sources,
+            forbid_duplicates: self.forbid_duplicates,
+        }
+    }
+
+    /// Skips the sources that cannot be read from, e.g. a corrupted first block,
+    /// instead of returning their error. The errors that occur once the merge
+    /// has started are always returned.
+    pub fn skip_failing_sources(&mut self, skip: bool) -> &mut Self {
+        self.skip_failing_sources = skip;
+        self
+    }
+
+    /// Returns a `MtblError::DuplicateKey` error when a key is found in many sources
+    /// instead of merging its values, for sources that are expected to be disjoint.
+    pub fn forbid_duplicates(&mut self, forbid: bool) -> &mut Self {
+        self.forbid_duplicates = forbid;
+        self
     }
 
     pub fn add(&mut self, source: Reader<A>) -> &mut Self {
@@ -79,23 +262,68 @@ impl<A, MF> MergerBuilder<A, MF> {
     }
 
     pub fn push(&mut self, source: Reader<A>) {
-        self.sources.push(source);
+        self.sources.push(Source::Reader(source));
+    }
+
+    /// Adds a source opened by `open` only when it is merged, e.g. a file opened and
+    /// memory mapped once its batch is merged, see `max_fan_in`. The function may be
+    /// called again if the builder is cloned.
+    pub fn add_lazy<F>(&mut self, open: F) -> &mut Self
+    where F: Fn() -> Result<Reader<A>, Error> + Send + Sync + 'static,
+    {
+        self.push_lazy(open);
+        self
+    }
+
+    pub fn push_lazy<F>(&mut self, open: F)
+    where F: Fn() -> Result<Reader<A>, Error> + Send + Sync + 'static,
+    {
+        self.sources.push(Source::Lazy(Arc::new(open)));
+    }
+
+    /// Adds a table read block by block from its start, e.g. through a `BufReader`,
+    /// instead of a `Reader` over its whole bytes. `open` is called when the merge starts.
+    pub(crate) fn push_stream(&mut self, open: SharedStreamOpener) {
+        self.sources.push(Source::Stream(open));
     }
 
     pub fn build(self) -> Merger<A, MF> {
-        Merger { sources: self.sources, merge: self.merge }
+        Merger {
+            sources: self.sources,
+            merge: self.merge,
+            strategy: self.strategy,
+            key_filter: self.key_filter,
+            max_fan_in: self.max_fan_in,
+            skip_failing_sources: self.skip_failing_sources,
+            forbid_duplicates: self.forbid_duplicates,
+        }
+    }
+}
+
+impl<MF> MergerBuilder<DynBytes, MF> {
+    /// Adds a source of any backing type, see `Reader::into_dyn`.
+    pub fn add_dyn<B>(&mut self, source: Reader<B>) -> &mut Self
+    where B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        self.push(source.into_dyn());
+        self
     }
 }
 
 impl<A, MF> Extend<Reader<A>> for MergerBuilder<A, MF> {
     fn extend<T: IntoIterator<Item=Reader<A>>>(&mut self, iter: T) {
-        self.sources.extend(iter);
+        self.sources.extend(iter.into_iter().map(Source::Reader));
     }
 }
 
 pub struct Merger<A, MF> {
-    sources: Vec<Reader<A>>,
+    sources: Vec<Source<A>>,
     merge: MF,
+    strategy: MergeStrategy,
+    key_filter: Option<SharedKeyFilter>,
+    max_fan_in: Option<usize>,
+    skip_failing_sources: bool,
+    forbid_duplicates: bool,
 }
 
 impl<A, MF> Merger<A, MF> {
@@ -104,42 +332,184 @@ impl<A, MF> Merger<A, MF> {
     }
 }
 
-impl<A: AsRef<[u8]>, MF> Merger<A, MF> {
-    pub fn into_merge_iter(self) -> Result<MergerIter<A, MF>, Error> {
-        let mut heap = BinaryHeap::new();
-        for source in self.sources {
-            let iter = source.into_iter()?;
-            if let Some(entry) = Entry::new(iter)? {
-                heap.push(Reverse(entry));
+impl<A, MB> Merger<A, BorrowedMerge<MB>> {
+    /// A builder with a merge function that receives borrowed values, see `BorrowedMerge`.
+    pub fn builder_borrowed(merge: MB) -> MergerBuilder<A, BorrowedMerge<MB>> {
+        MergerBuilder::new(BorrowedMerge(merge))
+    }
+}
+
+impl<A> Merger<A, ()> {
+    /// A builder without merge function, for the mergers that only return the
+    /// values of every key with `into_iter` or compact the sources with `compact_into`.
+    pub fn builder_multi() -> MergerBuilder<A, ()> {
+        MergerBuilder::new(())
+    }
+}
+
+/// What to do with the values that survive a compaction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CompactionPolicy {
+    /// Every surviving value is written.
+    KeepAll,
+    /// Surviving values equal to this marker are tombstones,
+    /// the key is removed from the output.
+    Tombstone(Vec<u8>),
+}
+
+/// The entries of the sources, ordered by key.
+enum Queue<A> {
+    Heap(BinaryHeap<Reverse<Entry<A>>>),
+    LoserTree(LoserTree<Entry<A>>),
+}
+
+impl<A: AsRef<[u8]>> Queue<A> {
+    fn peek(&self) -> Option<&Entry<A>> {
+        match self {
+            Queue::Heap(heap) => heap.peek().map(|e| &e.0),
+            Queue::LoserTree(tree) => tree.peek(),
+        }
+    }
+
+    /// Gives the entry with the smallest key to `f` and moves this entry forward.
+    fn advance_with<F: FnOnce(&Entry<A>)>(&mut self, f: F) -> Result<(), Error> {
+        match self {
+            Queue::Heap(heap) => {
+                let mut entry = heap.peek_mut().expect("advance called on an empty heap");
+                f(&entry.0);
+                if entry.0.fill()? { drop(entry) } else { PeekMut::pop(entry); }
+            },
+            Queue::LoserTree(tree) => {
+                let entry = tree.peek_mut().expect("advance called on an empty tree");
+                f(entry);
+                if entry.fill()? { tree.replay() } else { tree.pop(); }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// The sources being merged, the entry with the smallest key always passes the key filter.
+struct Sources<A> {
+    queue: Queue<A>,
+    key_filter: Option<SharedKeyFilter>,
+}
+
+impl<A: AsRef<[u8]>> Sources<A> {
+    fn new(
+        sources: Vec<Source<A>>,
+        strategy: MergeStrategy,
+        key_filter: Option<SharedKeyFilter>,
+        skip_failing_sources: bool,
+    ) -> Result<Sources<A>, Error>
+    {
+        let mut entries = Vec::with_capacity(sources.len());
+        for (index, source) in sources.into_iter().enumerate() {
+            match source.into_iter().and_then(|iter| Entry::new(iter, index)) {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => (),
+                Err(_) if skip_failing_sources => (),
+                Err(e) => return Err(e),
             }
         }
 
+        let queue = match strategy {
+            MergeStrategy::Heap => Queue::Heap(entries.into_iter().map(Reverse).collect()),
+            MergeStrategy::LoserTree => Queue::LoserTree(LoserTree::new(entries)),
+        };
+
+        let mut sources = Sources { queue, key_filter };
+        sources.skip_filtered()?;
+        Ok(sources)
+    }
+
+    fn skip_filtered(&mut self) -> Result<(), Error> {
+        if let Some(filter) = &self.key_filter {
+            while self.queue.peek().is_some_and(|e| !filter(&e.key)) {
+                self.queue.advance_with(|_| ())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entry with the smallest key.
+    fn peek(&self) -> Option<&Entry<A>> {
+        self.queue.peek()
+    }
+
+    /// Copies the value of the entry with the smallest key and moves this entry forward.
+    fn advance(&mut self) -> Result<Vec<u8>, Error> {
+        let mut val = Vec::new();
+        self.advance_with(|entry| val = entry.val().to_vec())?;
+        Ok(val)
+    }
+
+    /// Like `advance` but the entry is only lent to `f`, its value is not copied.
+    fn advance_with<F: FnOnce(&Entry<A>)>(&mut self, f: F) -> Result<(), Error> {
+        self.queue.advance_with(f)?;
+        self.skip_filtered()
+    }
+}
+
+impl<A: AsRef<[u8]>, MF> Merger<A, MF> {
+    pub fn into_merge_iter(self) -> Result<MergerIter<A, MF>, Error> {
+        let sources = Sources::new(self.sources, self.strategy, self.key_filter, self.skip_failing_sources)?;
+
         Ok(MergerIter {
             merge: self.merge,
-            heap,
+            sources,
             cur_key: Vec::new(),
             cur_vals: Vec::new(),
+            cur_views: Vec::new(),
+            cur_slices: Vec::new(),
             merged_val: Vec::new(),
-            pending: false,
+            stats: MergeStats::default(),
+            forbid_duplicates: self.forbid_duplicates,
         })
     }
 
     pub fn into_iter(self) -> Result<MultiIter<A>, Error> {
-        let mut heap = BinaryHeap::new();
-        for source in self.sources {
-            let iter = source.into_iter()?;
-            if let Some(entry) = Entry::new(iter)? {
-                heap.push(Reverse(entry));
-            }
-        }
+        let sources = Sources::new(self.sources, self.strategy, self.key_filter, self.skip_failing_sources)?;
 
         Ok(MultiIter {
-            heap,
+            sources,
             cur_key: Vec::new(),
             cur_vals: Vec::new(),
             pending: false,
         })
     }
+
+    /// Writes the sources into the writer keeping, for every key, only the value
+    /// of the first source that contains it, sources must be ordered newest first.
+    /// The merge function is never called.
+    pub fn compact_into<W: io::Write>(
+        self,
+        writer: &mut Writer<W>,
+        policy: CompactionPolicy,
+    ) -> Result<(), Error>
+    {
+        let mut sources = Sources::new(self.sources, self.strategy, self.key_filter, self.skip_failing_sources)?;
+        let mut key = Vec::new();
+
+        while let Some(entry) = sources.peek() {
+            // Equal keys are ordered by source index, the first one is the newest.
+            key.clear();
+            key.extend_from_slice(&entry.key);
+            let val = sources.advance()?;
+
+            // Skip the values shadowed by the newest one.
+            while sources.peek().is_some_and(|e| e.key == key) {
+                sources.advance()?;
+            }
+
+            match &policy {
+                CompactionPolicy::Tombstone(tombstone) if val == *tombstone => (),
+                _ => writer.insert(&key, &val)?,
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<A, MF, U> Merger<A, MF>
@@ -147,22 +517,309 @@ where A: AsRef<[u8]>,
       MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>,
 {
     pub fn write_into<W: io::Write>(self, writer: &mut Writer<W>) -> Result<(), Error<U>> {
+        write_staged(self, writer)
+    }
+
+    /// Writes every merged entry into the writer at the index returned by `partitioner`
+    /// for its key, the writers insert and compress their entries on their own thread.
+    /// Returns the underlying writers, once their files are finished.
+    ///
+    /// The partitioner must be monotonic, the partition of a key is never smaller than
+    /// the one of the previous key, for the entries of each writer to stay sorted.
+    /// The partitions must be smaller than the number of writers. An `InvalidPartition`
+    /// error is returned otherwise, the entries already routed are written.
+    pub fn partition_write<W, P>(self, partitioner: P, writers: Vec<Writer<W>>) -> Result<Vec<W>, Error<U>>
+    where W: io::Write + Send,
+          P: Fn(&[u8]) -> usize,
+    {
         let mut iter = self.into_merge_iter().map_err(Error::convert_merge_error)?;
-        while let Some(result) = iter.next() {
+
+        thread::scope(|scope| {
+            let mut senders = Vec::with_capacity(writers.len());
+            let mut handles = Vec::with_capacity(writers.len());
+            for mut writer in writers {
+                let (sender, receiver) = mpsc::sync_channel::<PartitionBatch>(PARTITION_CHANNEL_BOUND);
+                senders.push(sender);
+                handles.push(scope.spawn(move || {
+                    for batch in receiver {
+                        for (key, val) in batch {
+                            writer.insert_taking(&key, val)?;
+                        }
+                    }
+                    writer.into_inner()
+                }));
+            }
+
+            // the senders are dropped by route_partitions to let the writers finish
+            let routed = route_partitions(&mut iter, partitioner, senders);
+
+            let mut inners = Vec::with_capacity(handles.len());
+            let mut write_error = None;
+            for handle in handles {
+                match handle.join() {
+                    Ok(Ok(inner)) => inners.push(inner),
+                    Ok(Err(e)) => { write_error.get_or_insert(e); },
+                    Err(panic) => panic::resume_unwind(panic),
+                }
+            }
+
+            routed?;
+            match write_error {
+                Some(e) => Err(Error::from(e)),
+                None => Ok(inners),
+            }
+        })
+    }
+
+    /// Merges all the sources at once, regardless of `max_fan_in`.
+    fn write_merged_into<W: io::Write>(self, writer: &mut Writer<W>) -> Result<(), Error<U>> {
+        let mut iter = self.into_merge_iter().map_err(Error::convert_merge_error)?;
+        while let Some(result) = iter.next_taking() {
+            let (key, val) = result?;
+            writer.insert_cow(key, val)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A, MB, U> Merger<A, BorrowedMerge<MB>>
+where A: AsRef<[u8]>,
+      MB: Fn(&[u8], &[&[u8]]) -> Result<Vec<u8>, U>,
+{
+    pub fn write_into<W: io::Write>(self, writer: &mut Writer<W>) -> Result<(), Error<U>> {
+        write_staged(self, writer)
+    }
+
+    /// Merges all the sources at once, regardless of `max_fan_in`.
+    fn write_merged_into<W: io::Write>(self, writer: &mut Writer<W>) -> Result<(), Error<U>> {
+        let mut iter = self.into_merge_iter().map_err(Error::convert_merge_error)?;
+        while let Some(result) = iter.next_taking() {
             let (key, val) = result?;
-            writer.insert(key, val)?;
+            writer.insert_cow(key, val)?;
         }
         Ok(())
     }
 }
 
+/// Sends the merged entries by batches to the writer of their partition, see `Merger::partition_write`.
+/// Stops early if a writer is gone, its error is returned by its thread.
+fn route_partitions<A, MF, U, P>(
+    iter: &mut MergerIter<A, MF>,
+    partitioner: P,
+    senders: Vec<SyncSender<PartitionBatch>>,
+) -> Result<(), Error<U>>
+where A: AsRef<[u8]>,
+      MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>,
+      P: Fn(&[u8]) -> usize,
+{
+    let mut batches = vec![Vec::new(); senders.len()];
+    let mut last_partition = 0;
+
+    while let Some(result) = iter.next() {
+        let (key, val) = result?;
+        let partition = partitioner(key);
+        if partition >= senders.len() || partition < last_partition {
+            return Err(Error::from(MtblError::InvalidPartition { key: key.to_vec(), partition }));
+        }
+        last_partition = partition;
+
+        let batch = &mut batches[partition];
+        batch.push((key.to_vec(), val.to_vec()));
+        if batch.len() == PARTITION_BATCH_SIZE && senders[partition].send(mem::take(batch)).is_err() {
+            return Ok(());
+        }
+    }
+
+    for (sender, batch) in senders.iter().zip(batches) {
+        if !batch.is_empty() && sender.send(batch).is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// A table written in the temporary file of a merge stage.
+struct Run {
+    mmap: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl AsRef<[u8]> for Run {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap[self.range.clone()]
+    }
+}
+
+/// A merge function that can merge the sources by stages, see `MergerBuilder::max_fan_in`.
+trait StagedMerge<U> {
+    /// Merges all the sources of the merger at once with this merge function.
+    fn write_batch<A, W>(&self, merger: Merger<A, ()>, writer: &mut Writer<W>) -> Result<(), Error<U>>
+    where A: AsRef<[u8]>,
+          W: io::Write;
+}
+
+impl<MF, U> StagedMerge<U> for MF
+where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>,
+{
+    fn write_batch<A, W>(&self, merger: Merger<A, ()>, writer: &mut Writer<W>) -> Result<(), Error<U>>
+    where A: AsRef<[u8]>,
+          W: io::Write,
+    {
+        merger.with_merge(self).write_merged_into(writer)
+    }
+}
+
+impl<MB, U> StagedMerge<U> for BorrowedMerge<MB>
+where MB: Fn(&[u8], &[&[u8]]) -> Result<Vec<u8>, U>,
+{
+    fn write_batch<A, W>(&self, merger: Merger<A, ()>, writer: &mut Writer<W>) -> Result<(), Error<U>>
+    where A: AsRef<[u8]>,
+          W: io::Write,
+    {
+        merger.with_merge(BorrowedMerge(&self.0)).write_merged_into(writer)
+    }
+}
+
+impl<A, MF> Merger<A, MF> {
+    /// Returns the merge function and the merger without it.
+    fn split_merge(self) -> (Merger<A, ()>, MF) {
+        let merger = Merger {
+            sources: self.sources,
+            merge: (),
+            strategy: self.strategy,
+            key_filter: self.key_filter,
+            max_fan_in: self.max_fan_in,
+            skip_failing_sources: self.skip_failing_sources,
+            forbid_duplicates: self.forbid_duplicates,
+        };
+        (merger, self.merge)
+    }
+}
+
+impl<A> Merger<A, ()> {
+    fn with_merge<M>(self, merge: M) -> Merger<A, M> {
+        Merger {
+            sources: self.sources,
+            merge,
+            strategy: self.strategy,
+            key_filter: self.key_filter,
+            max_fan_in: self.max_fan_in,
+            skip_failing_sources: self.skip_failing_sources,
+            forbid_duplicates: self.forbid_duplicates,
+        }
+    }
+}
+
+/// Merges the sources into the writer by stages of at most `max_fan_in` sources.
+fn write_staged<A, M, U, W>(merger: Merger<A, M>, writer: &mut Writer<W>) -> Result<(), Error<U>>
+where A: AsRef<[u8]>,
+      M: StagedMerge<U>,
+      W: io::Write,
+{
+    let (merger, merge) = merger.split_merge();
+    let count = match merger.max_fan_in {
+        Some(count) if merger.sources.len() > count => count,
+        _ => return merge.write_batch(merger, writer),
+    };
+
+    let Merger { sources, strategy, key_filter, skip_failing_sources, forbid_duplicates, .. } = merger;
+    let mut runs = merge_stage(sources, count, &merge, strategy, key_filter, skip_failing_sources, forbid_duplicates)?;
+    while runs.len() > count {
+        runs = merge_stage(runs, count, &merge, strategy, None, false, forbid_duplicates)?;
+    }
+
+    let merger = Merger {
+        sources: runs,
+        merge: (),
+        strategy,
+        key_filter: None,
+        max_fan_in: None,
+        skip_failing_sources: false,
+        forbid_duplicates,
+    };
+    merge.write_batch(merger, writer)
+}
+
+/// Merges the sources by batches of `count`, the tables of a stage are written
+/// one after the other in a single temporary file to keep a single mmap per stage.
+/// The sources of a batch are only opened when the batch is merged.
+fn merge_stage<A, M, U>(
+    sources: Vec<Source<A>>,
+    count: usize,
+    merge: &M,
+    strategy: MergeStrategy,
+    key_filter: Option<SharedKeyFilter>,
+    skip_failing_sources: bool,
+    forbid_duplicates: bool,
+) -> Result<Vec<Source<Run>>, Error<U>>
+where A: AsRef<[u8]>,
+      M: StagedMerge<U>,
+{
+    let mut file = tempfile::tempfile()?;
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    let mut sources = sources.into_iter();
+    loop {
+        let batch: Vec<_> = sources.by_ref().take(count).collect();
+        if batch.is_empty() { break }
+
+        let merger = Merger {
+            sources: batch,
+            merge: (),
+            strategy,
+            key_filter: key_filter.clone(),
+            max_fan_in: None,
+            skip_failing_sources,
+            forbid_duplicates,
+        };
+        let mut writer = WriterBuilder::new().build(&mut file);
+        merge.write_batch(merger, &mut writer)?;
+        writer.finish()?;
+
+        let end = file.stream_position()? as usize;
+        ranges.push(start..end);
+        start = end;
+    }
+
+    let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+    ranges.into_iter()
+        .map(|range| Reader::new(Run { mmap: mmap.clone(), range }).map(Source::Reader).map_err(Error::convert_merge_error))
+        .collect()
+}
+
 pub struct MergerIter<A, MF> {
     merge: MF,
-    heap: BinaryHeap<Reverse<Entry<A>>>,
+    sources: Sources<A>,
     cur_key: Vec<u8>,
     cur_vals: Vec<Vec<u8>>,
+    /// The values of the current key as views of the blocks of the sources,
+    /// only used with a `BorrowedMerge`.
+    cur_views: Vec<BytesView<A>>,
+    /// The allocation of the slices of these views given to the merge function.
+    cur_slices: Vec<&'static [u8]>,
     merged_val: Vec<u8>,
-    pending: bool,
+    stats: MergeStats,
+    forbid_duplicates: bool,
+}
+
+/// The counters of a `MergerIter`, see `MergerIter::stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MergeStats {
+    /// The number of entries read from the sources.
+    pub source_rows: u64,
+    /// The number of keys returned.
+    pub output_keys: u64,
+    /// The number of times the merge function was called, once per key found in many sources.
+    pub merge_calls: u64,
+}
+
+impl<A, MF> MergerIter<A, MF> {
+    /// Returns the counters of the entries read and returned so far.
+    pub fn stats(&self) -> MergeStats {
+        self.stats
+    }
 }
 
 impl<A, MF, U> MergerIter<A, MF>
@@ -170,51 +827,144 @@ where A: AsRef<[u8]>,
       MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>,
 {
     pub fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error<U>>> {
-        self.cur_key.clear();
-        self.cur_vals.clear();
+        let mut count = 0;
 
-        loop {
-            let mut entry = match self.heap.peek_mut() {
-                Some(e) => e,
-                None => break,
-            };
+        while let Some(entry) = self.sources.peek() {
+            if count == 0 {
+                // the previous key is still there, the keys are returned in strictly increasing order
+                debug_assert!(self.stats.source_rows == 0 || entry.key > self.cur_key, "merged keys out of order");
+                self.cur_key.clear();
+                self.cur_key.extend_from_slice(&entry.key);
+            } else if self.cur_key != entry.key {
+                break;
+            }
 
-            if self.cur_key.is_empty() {
-                self.cur_key.extend_from_slice(&entry.0.key);
-                self.cur_vals.clear();
-                self.pending = true;
+            // the value is copied into a buffer reused from one key to the next
+            if count == self.cur_vals.len() {
+                self.cur_vals.push(Vec::new());
             }
+            let buf = &mut self.cur_vals[count];
+            let result = self.sources.advance_with(|entry| {
+                buf.clear();
+                buf.extend_from_slice(entry.val());
+            });
+            if let Err(e) = result {
+                return Some(Err(e.convert_merge_error()));
+            }
+            self.stats.source_rows += 1;
+            count += 1;
+        }
 
-            if self.cur_key == entry.0.key {
-                self.cur_vals.push(mem::take(&mut entry.0.val));
-                match entry.0.fill() {
-                    Ok(filled) => if !filled { PeekMut::pop(entry); },
-                    Err(e) => return Some(Err(e.convert_merge_error())),
+        match count {
+            0 => None,
+            1 => {
+                self.stats.output_keys += 1;
+                Some(Ok((&self.cur_key, &self.cur_vals[0])))
+            },
+            _ if self.forbid_duplicates => {
+                Some(Err(Error::Mtbl(MtblError::DuplicateKey { key: self.cur_key.clone() })))
+            },
+            _ => {
+                self.stats.merge_calls += 1;
+                match (self.merge)(&self.cur_key, &self.cur_vals[..count]) {
+                    Ok(val) => self.merged_val = val,
+                    Err(error) => return Some(Err(Error::MergeKey { key: self.cur_key.clone(), error })),
                 }
-            } else {
+                self.stats.output_keys += 1;
+                Some(Ok((&self.cur_key, &self.merged_val)))
+            },
+        }
+    }
+
+    /// Like `next` but the merged values are moved out of the iterator, for
+    /// a writer to keep them without a copy, see `Writer::insert_taking`.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn next_taking(&mut self) -> Option<Result<(&[u8], Cow<'_, [u8]>), Error<U>>> {
+        let merge_calls = self.stats.merge_calls;
+        if let Err(e) = self.next()? {
+            return Some(Err(e));
+        }
+        let val = if self.stats.merge_calls > merge_calls {
+            Cow::Owned(mem::take(&mut self.merged_val))
+        } else {
+            Cow::Borrowed(self.cur_vals[0].as_slice())
+        };
+        Some(Ok((&self.cur_key, val)))
+    }
+}
+
+impl<A, MB, U> MergerIter<A, BorrowedMerge<MB>>
+where A: AsRef<[u8]>,
+      MB: Fn(&[u8], &[&[u8]]) -> Result<Vec<u8>, U>,
+{
+    #[allow(clippy::should_implement_trait, clippy::type_complexity)]
+    pub fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error<U>>> {
+        self.cur_views.clear();
+
+        while let Some(entry) = self.sources.peek() {
+            if self.cur_views.is_empty() {
+                debug_assert!(self.stats.source_rows == 0 || entry.key > self.cur_key, "merged keys out of order");
+                self.cur_key.clear();
+                self.cur_key.extend_from_slice(&entry.key);
+            } else if self.cur_key != entry.key {
                 break;
             }
+
+            // the views keep the blocks alive once the sources moved forward
+            let views = &mut self.cur_views;
+            if let Err(e) = self.sources.advance_with(|entry| views.extend(entry.val_view())) {
+                return Some(Err(e.convert_merge_error()));
+            }
+            self.stats.source_rows += 1;
         }
 
-        if self.pending {
-            self.merged_val = if self.cur_vals.len() == 1 {
-                self.cur_vals.pop().unwrap()
-            } else {
-                match (self.merge)(&self.cur_key, &self.cur_vals) {
-                    Ok(val) => val,
-                    Err(e) => return Some(Err(Error::Merge(e))),
+        match self.cur_views.len() {
+            0 => None,
+            1 => {
+                self.stats.output_keys += 1;
+                Some(Ok((&self.cur_key, self.cur_views[0].as_ref())))
+            },
+            _ if self.forbid_duplicates => {
+                Some(Err(Error::Mtbl(MtblError::DuplicateKey { key: self.cur_key.clone() })))
+            },
+            _ => {
+                self.stats.merge_calls += 1;
+                let mut vals: Vec<&[u8]> = mem::take(&mut self.cur_slices);
+                vals.extend(self.cur_views.iter().map(AsRef::as_ref));
+                let result = (self.merge.0)(&self.cur_key, &vals);
+                // the empty vector is collected in place, its allocation is kept for the next key
+                vals.clear();
+                self.cur_slices = vals.into_iter().map(|_| &[][..]).collect();
+
+                match result {
+                    Ok(val) => self.merged_val = val,
+                    Err(error) => return Some(Err(Error::MergeKey { key: self.cur_key.clone(), error })),
                 }
-            };
-            self.pending = false;
-            Some(Ok((&self.cur_key, &self.merged_val)))
-        } else {
-            None
+                self.stats.output_keys += 1;
+                Some(Ok((&self.cur_key, &self.merged_val)))
+            },
+        }
+    }
+
+    /// Like `next` but the merged values are moved out of the iterator, for
+    /// a writer to keep them without a copy, see `Writer::insert_taking`.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn next_taking(&mut self) -> Option<Result<(&[u8], Cow<'_, [u8]>), Error<U>>> {
+        let merge_calls = self.stats.merge_calls;
+        if let Err(e) = self.next()? {
+            return Some(Err(e));
         }
+        let val = if self.stats.merge_calls > merge_calls {
+            Cow::Owned(mem::take(&mut self.merged_val))
+        } else {
+            Cow::Borrowed(self.cur_views[0].as_ref())
+        };
+        Some(Ok((&self.cur_key, val)))
     }
 }
 
 pub struct MultiIter<A> {
-    heap: BinaryHeap<Reverse<Entry<A>>>,
+    sources: Sources<A>,
     cur_key: Vec<u8>,
     cur_vals: Vec<Vec<u8>>,
     pending: bool,
@@ -227,22 +977,17 @@ impl<A: AsRef<[u8]>> Iterator for MultiIter<A> {
         self.cur_key.clear();
         self.cur_vals.clear();
 
-        loop {
-            let mut entry = match self.heap.peek_mut() {
-                Some(e) => e,
-                None => break,
-            };
-
-            if self.cur_key.is_empty() {
-                self.cur_key.extend_from_slice(&entry.0.key);
+        while let Some(entry) = self.sources.peek() {
+            // the empty key is a valid key, a group is started only once
+            if !self.pending {
+                self.cur_key.extend_from_slice(&entry.key);
                 self.cur_vals.clear();
                 self.pending = true;
             }
 
-            if self.cur_key == entry.0.key {
-                self.cur_vals.push(mem::take(&mut entry.0.val));
-                match entry.0.fill() {
-                    Ok(filled) => if !filled { PeekMut::pop(entry); },
+            if self.cur_key == entry.key {
+                match self.sources.advance() {
+                    Ok(val) => self.cur_vals.push(val),
                     Err(e) => return Some(Err(e)),
                 }
             } else {
@@ -302,4 +1047,663 @@ mod tests {
             prev_key = k.to_vec();
         }
     }
+
+    #[test]
+    fn empty_sources() {
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let table = |keys: &[&str]| {
+            let mut writer = WriterBuilder::new().memory();
+            for key in keys {
+                writer.insert(key, key).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        };
+
+        let merged = |sources: Vec<Reader<Vec<u8>>>, strategy| {
+            let mut builder = Merger::builder(concat);
+            builder.strategy(strategy).extend(sources.clone());
+            let mut iter = builder.build().into_merge_iter().unwrap();
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                entries.push((String::from_utf8(key.to_vec()).unwrap(), String::from_utf8(val.to_vec()).unwrap()));
+            }
+            assert!(iter.next().is_none());
+
+            // the other iterators agree
+            let mut builder = Merger::builder(concat);
+            builder.strategy(strategy).extend(sources.clone());
+            let groups: Vec<_> = builder.build().into_iter().unwrap().map(|r| r.unwrap()).collect();
+            assert_eq!(groups.len(), entries.len());
+            assert!(groups.iter().zip(&entries).all(|((k, vals), (ek, ev))| k == ek.as_bytes() && vals.concat() == ev.as_bytes()));
+
+            let mut builder = Merger::builder(concat);
+            builder.strategy(strategy).extend(sources);
+            let mut writer = WriterBuilder::new().memory();
+            builder.build().write_into(&mut writer).unwrap();
+            let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+            assert_eq!(reader.metadata().count_entries, entries.len() as u64);
+
+            entries
+        };
+
+        for &strategy in &[MergeStrategy::Heap, MergeStrategy::LoserTree] {
+            let pairs = |entries: &[(&str, &str)]| {
+                entries.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>()
+            };
+
+            assert!(merged(Vec::new(), strategy).is_empty());
+            assert!(merged(vec![table(&[]), table(&[]), table(&[])], strategy).is_empty());
+            assert_eq!(merged(vec![table(&[]), table(&["a", "c"])], strategy), pairs(&[("a", "a"), ("c", "c")]));
+            assert_eq!(
+                merged(vec![table(&["", "b", "c"]), table(&[]), table(&["", "a", "c"])], strategy),
+                pairs(&[("", ""), ("a", "a"), ("b", "b"), ("c", "cc")]),
+            );
+        }
+    }
+
+    #[test]
+    fn loser_tree_strategy() {
+        fn merge(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let sources: Vec<_> = (0..256).map(|s| {
+            let mut writer = WriterBuilder::new().memory();
+            for i in (s % 7..2000).step_by(1 + s % 13) {
+                writer.insert(format!("{:06}", i), format!("{}:{}", s, i)).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        }).collect();
+
+        let merged = |strategy| {
+            let mut builder = Merger::builder(merge);
+            builder.strategy(strategy).extend(sources.clone());
+
+            COMPARISONS.with(|c| c.set(0));
+            let mut iter = builder.build().into_merge_iter().unwrap();
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (k, v) = result.unwrap();
+                entries.push((k.to_vec(), v.to_vec()));
+            }
+            (entries, COMPARISONS.with(|c| c.get()))
+        };
+
+        let (heap_entries, heap_comparisons) = merged(MergeStrategy::Heap);
+        let (tree_entries, tree_comparisons) = merged(MergeStrategy::LoserTree);
+
+        assert_eq!(heap_entries.len(), 2000);
+        assert_eq!(heap_entries, tree_entries);
+        assert!(tree_comparisons < heap_comparisons, "{} >= {}", tree_comparisons, heap_comparisons);
+    }
+
+    #[test]
+    fn key_filter() {
+        fn merge(key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            assert!(key[0] % 2 == 0, "filtered key reached the merge function");
+            Ok(values.concat())
+        }
+
+        let sources: Vec<_> = (0..3).map(|s| {
+            let mut writer = WriterBuilder::new().memory();
+            for i in 0..100u8 {
+                writer.insert([i, s], [s]).unwrap();
+                writer.insert([i, 9], [s]).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        }).collect();
+
+        let merged = |filter: Option<KeyFilter>| {
+            let mut builder = Merger::builder(merge);
+            builder.extend(sources.clone());
+            if let Some(filter) = filter {
+                builder.key_filter(filter);
+            }
+            let mut iter = builder.build().into_merge_iter().unwrap();
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (k, v) = result.unwrap();
+                entries.push((k.to_vec(), v.to_vec()));
+            }
+            entries
+        };
+
+        let filtered = merged(Some(Box::new(|key| key[0] % 2 == 0)));
+
+        let builder = {
+            let mut builder = Merger::builder(());
+            builder.extend(sources.clone());
+            builder
+        };
+        let expected: Vec<_> = builder.build().into_iter().unwrap()
+            .map(Result::unwrap)
+            .filter(|(k, _)| k[0] % 2 == 0)
+            .map(|(k, vs)| (k, vs.concat()))
+            .collect();
+
+        assert_eq!(filtered.len(), 50 * 4);
+        assert_eq!(filtered, expected);
+    }
+
+    #[test]
+    fn heterogeneous_sources() {
+        use std::io::Write;
+        use memmap::Mmap;
+
+        fn merge(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let mut writer = WriterBuilder::new().memory();
+        for i in (0..100u32).step_by(2) {
+            writer.insert(i.to_be_bytes(), "v").unwrap();
+        }
+        let vec_reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut writer = WriterBuilder::new().memory();
+        for i in (0..100u32).step_by(3) {
+            writer.insert(i.to_be_bytes(), "m").unwrap();
+        }
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&writer.into_inner().unwrap()).unwrap();
+        let mmap = unsafe { Mmap::map(&file).unwrap() };
+        let mmap_reader = Reader::new(mmap).unwrap();
+
+        let mut builder = Merger::builder(merge);
+        builder.add_dyn(vec_reader).add_dyn(mmap_reader);
+        let mut iter = builder.build().into_merge_iter().unwrap();
+
+        let mut entries = Vec::new();
+        while let Some(result) = iter.next() {
+            let (k, v) = result.unwrap();
+            entries.push((k.to_vec(), v.to_vec()));
+        }
+
+        let expected: Vec<_> = (0..100u32)
+            .filter_map(|i| match (i % 2 == 0, i % 3 == 0) {
+                (true, true) => Some((i, "vm")),
+                (true, false) => Some((i, "v")),
+                (false, true) => Some((i, "m")),
+                (false, false) => None,
+            })
+            .map(|(i, v)| (i.to_be_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn compaction() {
+        fn no_merge(_key: &[u8], _values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            panic!("compaction must not merge")
+        }
+
+        let layer = |entries: &[(&str, &str)]| {
+            let mut writer = WriterBuilder::new().memory();
+            for (key, val) in entries {
+                writer.insert(key, val).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        };
+
+        // newest first
+        let newest = layer(&[("b", "b3"), ("d", "")]);
+        let middle = layer(&[("a", "a2"), ("b", "b2"), ("c", "")]);
+        let oldest = layer(&[("a", "a1"), ("c", "c1"), ("d", "d1"), ("e", "e1")]);
+        let layers = vec![newest, middle, oldest];
+
+        let compact = |policy| {
+            let mut builder = Merger::builder(no_merge);
+            builder.extend(layers.clone());
+            let mut writer = WriterBuilder::new().memory();
+            builder.build().compact_into(&mut writer, policy).unwrap();
+
+            let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+            let mut iter = reader.into_iter().unwrap();
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (k, v) = result.unwrap();
+                entries.push((String::from_utf8(k.to_vec()).unwrap(), String::from_utf8(v.to_vec()).unwrap()));
+            }
+            entries
+        };
+
+        let entries = compact(CompactionPolicy::KeepAll);
+        let expected = [("a", "a2"), ("b", "b3"), ("c", ""), ("d", ""), ("e", "e1")];
+        assert!(entries.iter().map(|(k, v)| (k.as_str(), v.as_str())).eq(expected.iter().cloned()));
+
+        let entries = compact(CompactionPolicy::Tombstone(Vec::new()));
+        let expected = [("a", "a2"), ("b", "b3"), ("e", "e1")];
+        assert!(entries.iter().map(|(k, v)| (k.as_str(), v.as_str())).eq(expected.iter().cloned()));
+    }
+
+    #[test]
+    fn max_fan_in() {
+        use std::collections::HashSet;
+        use std::sync::Mutex;
+
+        /// The sources read from and not yet dropped, and the maximum number of them at once.
+        /// All the sources are open from the start, their bytes are only read by their batch.
+        type InUse = Arc<Mutex<(HashSet<usize>, usize)>>;
+
+        struct Tracked {
+            id: usize,
+            data: Vec<u8>,
+            in_use: InUse,
+        }
+
+        impl AsRef<[u8]> for Tracked {
+            fn as_ref(&self) -> &[u8] {
+                let mut in_use = self.in_use.lock().unwrap();
+                in_use.0.insert(self.id);
+                in_use.1 = cmp::max(in_use.1, in_use.0.len());
+                &self.data
+            }
+        }
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.in_use.lock().unwrap().0.remove(&self.id);
+            }
+        }
+
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let in_use = InUse::default();
+        let mut builder = Merger::builder(concat);
+        for id in 0..1000u32 {
+            let mut writer = WriterBuilder::new().memory();
+            writer.insert(id.to_be_bytes(), id.to_string()).unwrap();
+            writer.insert("all", format!("{:04},", id)).unwrap();
+            let data = writer.into_inner().unwrap();
+            let tracked = Tracked { id: id as usize, data, in_use: in_use.clone() };
+            builder.add(Reader::new(tracked).unwrap());
+        }
+        builder.max_fan_in(8);
+
+        // the readers read their metadata when opened
+        *in_use.lock().unwrap() = (HashSet::new(), 0);
+
+        let mut writer = WriterBuilder::new().memory();
+        builder.build().write_into(&mut writer).unwrap();
+        assert_eq!(in_use.lock().unwrap().1, 8);
+
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let mut iter = reader.into_iter().unwrap();
+        for id in 0..1000u32 {
+            let (key, val) = iter.next().unwrap().unwrap();
+            assert_eq!(key, id.to_be_bytes());
+            assert_eq!(val, id.to_string().as_bytes());
+        }
+        let (key, val) = iter.next().unwrap().unwrap();
+        let expected: String = (0..1000).map(|id| format!("{:04},", id)).collect();
+        assert_eq!(key, b"all");
+        assert_eq!(val, expected.as_bytes());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn max_fan_in_lazy() {
+        use std::sync::Mutex;
+
+        /// The number of open sources and the maximum number of them at once.
+        type Open = Arc<Mutex<(usize, usize)>>;
+
+        struct Opened {
+            data: Arc<Vec<u8>>,
+            open: Open,
+        }
+
+        impl AsRef<[u8]> for Opened {
+            fn as_ref(&self) -> &[u8] {
+                &self.data
+            }
+        }
+
+        impl Drop for Opened {
+            fn drop(&mut self) {
+                self.open.lock().unwrap().0 -= 1;
+            }
+        }
+
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        fn concat_borrowed(_key: &[u8], values: &[&[u8]]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let builder = |open: &Open| {
+            let mut builder = Merger::builder(concat);
+            for id in 0..100u32 {
+                let mut writer = WriterBuilder::new().memory();
+                writer.insert(id.to_be_bytes(), id.to_string()).unwrap();
+                writer.insert("all", format!("{:02},", id)).unwrap();
+                let data = Arc::new(writer.into_inner().unwrap());
+                let open = open.clone();
+                builder.add_lazy(move || {
+                    let mut count = open.lock().unwrap();
+                    count.0 += 1;
+                    count.1 = cmp::max(count.1, count.0);
+                    Reader::new(Opened { data: data.clone(), open: open.clone() })
+                });
+            }
+            builder.max_fan_in(8);
+            builder
+        };
+
+        let open = Open::default();
+        let mut writer = WriterBuilder::new().memory();
+        builder(&open).build().write_into(&mut writer).unwrap();
+        assert_eq!(*open.lock().unwrap(), (0, 8));
+        let owned = writer.into_inner().unwrap();
+
+        let open = Open::default();
+        let mut writer = WriterBuilder::new().memory();
+        builder(&open).merge_borrowed(concat_borrowed).build().write_into(&mut writer).unwrap();
+        assert_eq!(*open.lock().unwrap(), (0, 8));
+        let borrowed = writer.into_inner().unwrap();
+        assert_eq!(owned, borrowed);
+
+        let reader = Reader::new(owned).unwrap();
+        let mut iter = reader.into_iter().unwrap();
+        for id in 0..100u32 {
+            let (key, val) = iter.next().unwrap().unwrap();
+            assert_eq!(key, id.to_be_bytes());
+            assert_eq!(val, id.to_string().as_bytes());
+        }
+        let (key, val) = iter.next().unwrap().unwrap();
+        let expected: String = (0..100).map(|id| format!("{:02},", id)).collect();
+        assert_eq!(key, b"all");
+        assert_eq!(val, expected.as_bytes());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn stats() {
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        fn concat_borrowed(_key: &[u8], values: &[&[u8]]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        // 0..100, 50..150 and every tenth key of 0..150, found in one of the others
+        let mut sources = Vec::new();
+        for keys in [(0..100).step_by(1), (50..150).step_by(1), (0..150).step_by(10)] {
+            let mut writer = WriterBuilder::new().memory();
+            for key in keys {
+                writer.insert((key as u32).to_be_bytes(), "v").unwrap();
+            }
+            sources.push(Reader::new(writer.into_inner().unwrap()).unwrap());
+        }
+        let expected = MergeStats { source_rows: 215, output_keys: 150, merge_calls: 50 + 10 };
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources.clone());
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        assert_eq!(iter.stats(), MergeStats::default());
+        assert_eq!(iter.next().unwrap().unwrap(), (&0u32.to_be_bytes()[..], &b"vv"[..]));
+        assert_eq!(iter.stats(), MergeStats { source_rows: 2, output_keys: 1, merge_calls: 1 });
+        while let Some(result) = iter.next() {
+            result.unwrap();
+        }
+        assert_eq!(iter.stats(), expected);
+
+        let mut builder = Merger::builder_borrowed(concat_borrowed);
+        builder.extend(sources);
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        while let Some(result) = iter.next() {
+            result.unwrap();
+        }
+        assert_eq!(iter.stats(), expected);
+    }
+
+    #[test]
+    fn merge_borrowed() {
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        fn concat_borrowed(_key: &[u8], values: &[&[u8]]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let mut sources = Vec::new();
+        for i in 0..5u32 {
+            let mut writer = WriterBuilder::new().block_size(256).memory();
+            for key in (i..300).step_by(i as usize + 1) {
+                writer.insert(key.to_be_bytes(), format!("{}-{};", i, key).repeat(10)).unwrap();
+            }
+            sources.push(Reader::new(writer.into_inner().unwrap()).unwrap());
+        }
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources.clone());
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        let mut expected = Vec::new();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            expected.push((key.to_vec(), val.to_vec()));
+        }
+        assert_eq!(expected.len(), 300);
+
+        let mut builder = Merger::builder_borrowed(concat_borrowed);
+        builder.extend(sources.clone());
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        let mut entries = Vec::new();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            entries.push((key.to_vec(), val.to_vec()));
+        }
+        assert_eq!(entries, expected);
+
+        let mut builder = Merger::builder_borrowed(concat_borrowed);
+        builder.extend(sources);
+        let mut writer = WriterBuilder::new().memory();
+        builder.build().write_into(&mut writer).unwrap();
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn skip_failing_sources() {
+        use crate::MtblError;
+
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        let table = |range: std::ops::Range<u32>| {
+            let mut writer = WriterBuilder::new().memory();
+            for i in range {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        // the first data block of the middle source does not match its checksum,
+        // it can be opened but not iterated
+        let mut corrupted = table(100..200);
+        corrupted[10] ^= 0xff;
+        let corrupted = Reader::new(corrupted).unwrap();
+        let sources = vec![Reader::new(table(0..100)).unwrap(), corrupted, Reader::new(table(200..300)).unwrap()];
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources.clone());
+        let result = builder.build().into_merge_iter();
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::ChecksumMismatch))));
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources);
+        builder.skip_failing_sources(true);
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        let mut keys = Vec::new();
+        while let Some(result) = iter.next() {
+            keys.push(result.unwrap().0.to_vec());
+        }
+        let expected: Vec<_> = (0..100).chain(200..300u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn forbid_duplicates() {
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            panic!("merge called with {} values", values.len())
+        }
+
+        let table = |range: std::ops::Range<u32>| {
+            let mut writer = WriterBuilder::new().memory();
+            for i in range {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        };
+
+        // disjoint sources
+        let mut builder = Merger::builder(concat);
+        builder.extend(vec![table(0..100), table(200..300), table(100..200)]);
+        builder.forbid_duplicates(true);
+        let mut iter = builder.build().into_merge_iter().unwrap();
+        let mut count = 0u32;
+        while let Some(result) = iter.next() {
+            let (key, _) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 300);
+
+        // overlapping sources, the entries before the duplicate are returned
+        for strategy in [MergeStrategy::Heap, MergeStrategy::LoserTree] {
+            let mut builder = Merger::builder(concat);
+            builder.extend(vec![table(0..100), table(99..200)]);
+            builder.strategy(strategy).forbid_duplicates(true);
+            let mut iter = builder.build().into_merge_iter().unwrap();
+            for i in 0..99u32 {
+                assert_eq!(iter.next().unwrap().unwrap().0, i.to_be_bytes());
+            }
+            match iter.next() {
+                Some(Err(Error::Mtbl(MtblError::DuplicateKey { key }))) => assert_eq!(key, 99u32.to_be_bytes()),
+                _ => panic!("expected a duplicate key error"),
+            }
+            assert_eq!(iter.stats().merge_calls, 0);
+        }
+
+        // with a borrowed merge and through write_into
+        let mut builder = Merger::builder_borrowed(|_: &[u8], _: &[&[u8]]| Ok::<_, ()>(Vec::new()));
+        builder.extend(vec![table(0..10), table(5..15)]);
+        builder.forbid_duplicates(true);
+        let mut writer = WriterBuilder::new().memory();
+        let result = builder.build().write_into(&mut writer);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::DuplicateKey { key })) if key == 5u32.to_be_bytes()));
+    }
+
+    #[test]
+    fn partition_write() {
+        fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(values.concat())
+        }
+
+        // keys spread over all the first bytes, each key is in one or two sources
+        let sources: Vec<_> = (0..5u32).map(|s| {
+            let mut writer = WriterBuilder::new().with_block_size(256).memory();
+            for i in (0..20_000u32).filter(|i| i % 5 == s || (i + 1) % 5 == s) {
+                writer.insert((i * 214_000).to_be_bytes(), s.to_string()).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        }).collect();
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources.clone());
+        let mut writer = WriterBuilder::new().memory();
+        builder.build().write_into(&mut writer).unwrap();
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let expected: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(expected.len(), 20_000);
+
+        let mut builder = Merger::builder(concat);
+        builder.extend(sources);
+        let writers = (0..4).map(|_| WriterBuilder::new().with_block_size(256).memory()).collect();
+        let outputs = builder.build().partition_write(|key| key[0] as usize / 64, writers).unwrap();
+        assert_eq!(outputs.len(), 4);
+
+        let mut union = Vec::new();
+        for (partition, output) in outputs.into_iter().enumerate() {
+            let reader = Reader::new(output).unwrap();
+            let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+            assert!(!entries.is_empty());
+            assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+            assert!(entries.iter().all(|(key, _)| key[0] as usize / 64 == partition));
+            union.extend(entries);
+        }
+        assert_eq!(union, expected);
+    }
+
+    #[test]
+    fn partition_write_invalid_partition() {
+        let partition_write = |partitioner: fn(&[u8]) -> usize| {
+            let mut writer = WriterBuilder::new().memory();
+            for i in 0..100u32 {
+                writer.insert(i.to_be_bytes(), "").unwrap();
+            }
+            let mut builder = Merger::builder(|_: &[u8], _: &[Vec<u8>]| Ok::<_, ()>(Vec::new()));
+            builder.add(Reader::new(writer.into_inner().unwrap()).unwrap());
+
+            let writers = vec![WriterBuilder::new().memory(), WriterBuilder::new().memory()];
+            builder.build().partition_write(partitioner, writers).map(drop)
+        };
+
+        // not monotonic
+        let result = partition_write(|key| (key[3] % 2) as usize);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidPartition { ref key, partition: 0 }))
+            if key[..] == 2u32.to_be_bytes()), "{:?}", result);
+
+        // out of the writers
+        let result = partition_write(|key| key[3] as usize / 10);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidPartition { ref key, partition: 2 }))
+            if key[..] == 20u32.to_be_bytes()), "{:?}", result);
+    }
+
+    #[test]
+    fn builder_multi() {
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("a", "1").unwrap();
+        writer.insert("b", "2").unwrap();
+        let first = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("b", "3").unwrap();
+        writer.insert("c", "4").unwrap();
+        let second = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut builder = Merger::builder_multi();
+        builder.add(first).add(second);
+        let groups: Vec<_> = builder.build().into_iter().unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(groups, vec![
+            (b"a".to_vec(), vec![b"1".to_vec()]),
+            (b"b".to_vec(), vec![b"2".to_vec(), b"3".to_vec()]),
+            (b"c".to_vec(), vec![b"4".to_vec()]),
+        ]);
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+
+        type Merge = fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, ()>;
+        assert_send::<ReaderIntoIter<Vec<u8>>>();
+        assert_send::<Merger<Vec<u8>, Merge>>();
+        assert_send::<MergerBuilder<Vec<u8>, Merge>>();
+        assert_sync::<MergerBuilder<Vec<u8>, Merge>>();
+        assert_send::<MergerIter<Vec<u8>, Merge>>();
+    }
 }
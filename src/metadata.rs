@@ -1,12 +1,34 @@
-use std::{io, mem};
+use core::{fmt, mem};
+#[cfg(feature = "std")]
+use std::io;
 
-use byteorder::{LittleEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ByteOrder};
+#[cfg(feature = "std")]
+use byteorder::WriteBytesExt;
 
+use crate::checksum::{checksum, ChecksumType};
 use crate::compression::CompressionType;
 use crate::error::{Error, MtblError};
 use crate::FileVersion;
 use crate::{METADATA_SIZE, DEFAULT_BLOCK_SIZE, DEFAULT_COMPRESSION_TYPE};
-use crate::{MAGIC, MAGIC_V1};
+use crate::{MAGIC, MAGIC_V1, MAGIC_V3, MAGIC_V4, MAGIC_V5};
+
+/// The number of u64 fields covered by the checksum of the metadata, stored right after them.
+const CHECKSUMMED_FIELDS: usize = 12;
+/// Set on the stored checksum of the metadata, a zero field means the file has none.
+const CHECKSUM_PRESENT: u64 = 1 << 32;
+/// The index of the field of the index block compression, stored after the checksum.
+const INDEX_COMPRESSION_FIELD: usize = CHECKSUMMED_FIELDS + 1;
+/// The index of the field of the number of index segments, see `FileVersion::FormatV4`.
+const INDEX_SEGMENTS_FIELD: usize = INDEX_COMPRESSION_FIELD + 1;
+/// The number of fields stored after the checksum, they are covered by it.
+const TRAILING_FIELDS: usize = 3;
+/// The index of the field of the checksum of the key range, zero when the metadata has none.
+const KEY_RANGE_FIELD: usize = INDEX_COMPRESSION_FIELD + TRAILING_FIELDS;
+/// The offset of the sizes of the first and the last keys, followed by the keys.
+const KEY_RANGE_OFFSET: usize = (KEY_RANGE_FIELD + 1) * mem::size_of::<u64>();
+/// The room left for the first and the last keys, before the magic number.
+const KEY_RANGE_CAPACITY: usize = METADATA_SIZE - mem::size_of::<u32>() - KEY_RANGE_OFFSET - mem::size_of::<u64>();
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
@@ -15,70 +37,324 @@ pub struct Metadata {
     pub index_block_offset: u64,
     pub data_block_size: u64,
     pub compression_algorithm: CompressionType,
+    /// The compression of the index block, `None` for the files written by older versions.
+    pub index_compression_algorithm: CompressionType,
     pub count_entries: u64,
     pub count_data_blocks: u64,
     pub bytes_data_blocks: u64,
     pub bytes_index_block: u64,
     pub bytes_keys: u64,
     pub bytes_values: u64,
+    /// The id of the zstd dictionary the blocks were compressed with, zero if none.
+    pub compression_dictionary_id: u32,
+    /// The algorithm used to compute the checksums of the blocks.
+    pub checksum_type: ChecksumType,
+    /// The size of the data blocks before compression, zero for the files
+    /// written by older versions.
+    pub bytes_raw_data_blocks: u64,
+    /// The number of segments of the index, zero when the index is a single block,
+    /// see `WriterBuilder::two_level_index`.
+    pub count_index_segments: u64,
+    /// The size of the segments of the index, written right before the top-level
+    /// index block, `bytes_index_block` being the size of the latter.
+    pub bytes_index_segments: u64,
+    /// The first and the last keys of the table, see `WriterBuilder::store_key_range`.
+    pub(crate) key_range: Option<KeyRange>,
+}
+
+/// The first and the last keys of a table, stored one after the other.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct KeyRange {
+    min_len: usize,
+    max_len: usize,
+    bytes: [u8; KEY_RANGE_CAPACITY],
+}
+
+impl KeyRange {
+    /// Returns `None` if the keys do not fit in the metadata.
+    fn new(min: &[u8], max: &[u8]) -> Option<KeyRange> {
+        if min.len() + max.len() > KEY_RANGE_CAPACITY {
+            return None;
+        }
+
+        let mut bytes = [0; KEY_RANGE_CAPACITY];
+        bytes[..min.len()].copy_from_slice(min);
+        bytes[min.len()..min.len() + max.len()].copy_from_slice(max);
+        Some(KeyRange { min_len: min.len(), max_len: max.len(), bytes })
+    }
+
+    fn min(&self) -> &[u8] {
+        &self.bytes[..self.min_len]
+    }
+
+    fn max(&self) -> &[u8] {
+        &self.bytes[self.min_len..self.min_len + self.max_len]
+    }
+}
+
+impl fmt::Debug for KeyRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRange").field("min", &self.min()).field("max", &self.max()).finish()
+    }
+}
+
+/// The statistics of a table derived from its metadata, see `Reader::info`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TableInfo {
+    pub file_version: FileVersion,
+    /// The size of the table, its metadata included.
+    pub file_size: u64,
+    pub count_entries: u64,
+    pub count_data_blocks: u64,
+    /// The average size of the keys, zero for an empty table.
+    pub average_key_size: f64,
+    /// The average size of the values, zero for an empty table.
+    pub average_value_size: f64,
+    /// The average number of entries in a data block, zero for an empty table.
+    pub entries_per_block: f64,
+    /// See `Metadata::compression_ratio`.
+    pub compression_ratio: Option<f64>,
 }
 
 impl Metadata {
-    pub(crate) fn read_from_bytes(bytes: &[u8]) -> Result<Metadata, Error> {
+    /// Reads the metadata, its checksum is verified if `verify` is `true` and the file has one.
+    pub(crate) fn read_from_bytes(bytes: &[u8], verify: bool) -> Result<Metadata, Error> {
         let magic = LittleEndian::read_u32(&bytes[METADATA_SIZE - mem::size_of::<u32>()..]);
         let file_version = match magic {
             MAGIC_V1 => FileVersion::FormatV1,
             MAGIC => FileVersion::FormatV2,
+            MAGIC_V3 => FileVersion::FormatV3,
+            MAGIC_V4 => FileVersion::FormatV4,
+            MAGIC_V5 => FileVersion::FormatV5,
             _ => return Err(Error::from(MtblError::InvalidFormatVersion)),
         };
 
-        let mut b = bytes;
-        let index_block_offset = b.read_u64::<LittleEndian>()?;
-        let data_block_size = b.read_u64::<LittleEndian>()?;
-        let compression_algorithm = b.read_u64::<LittleEndian>()?;
+        // the fields are stored one after the other as little-endian u64s
+        let field = |i: usize| LittleEndian::read_u64(&bytes[i * mem::size_of::<u64>()..]);
+
+        let index_block_offset = field(0);
+        let data_block_size = field(1);
+        let compression_algorithm = field(2);
         let compression_algorithm = CompressionType::from_u64(compression_algorithm).ok_or(MtblError::InvalidCompressionAlgorithm)?;
-        let count_entries = b.read_u64::<LittleEndian>()?;
-        let count_data_blocks = b.read_u64::<LittleEndian>()?;
-        let bytes_data_blocks = b.read_u64::<LittleEndian>()?;
-        let bytes_index_block = b.read_u64::<LittleEndian>()?;
-        let bytes_keys = b.read_u64::<LittleEndian>()?;
-        let bytes_values = b.read_u64::<LittleEndian>()?;
+        if compression_algorithm == CompressionType::SnappyFramed && !file_version.extra_compression_types() {
+            return Err(Error::from(MtblError::InvalidFormatVersion));
+        }
+        let count_entries = field(3);
+        let count_data_blocks = field(4);
+        let bytes_data_blocks = field(5);
+        let bytes_index_block = field(6);
+        let bytes_keys = field(7);
+        let bytes_values = field(8);
+        let compression_dictionary_id = field(9) as u32;
+        let checksum_type = field(10);
+        let checksum_type = ChecksumType::from_u64(checksum_type).ok_or(MtblError::InvalidChecksumAlgorithm)?;
+        if checksum_type != ChecksumType::Crc32c && !file_version.checksum_types() {
+            return Err(Error::from(MtblError::InvalidFormatVersion));
+        }
+        if !checksum_type.is_supported() {
+            return Err(Error::from(MtblError::UnsupportedChecksumAlgorithm));
+        }
+        let bytes_raw_data_blocks = field(11);
+        let index_compression_algorithm = field(INDEX_COMPRESSION_FIELD);
+        let index_compression_algorithm = CompressionType::from_u64(index_compression_algorithm).ok_or(MtblError::InvalidCompressionAlgorithm)?;
+        if index_compression_algorithm != CompressionType::None && !file_version.index_compression() {
+            return Err(Error::from(MtblError::InvalidFormatVersion));
+        }
+        let count_index_segments = field(INDEX_SEGMENTS_FIELD);
+        let bytes_index_segments = field(INDEX_SEGMENTS_FIELD + 1);
+        if count_index_segments != 0 && !file_version.index_segments() {
+            return Err(Error::from(MtblError::InvalidFormatVersion));
+        }
+
+        let stored = field(CHECKSUMMED_FIELDS);
+        if verify && stored != 0 {
+            if let Some(crc) = metadata_checksum(checksum_type, bytes) {
+                if stored != crc {
+                    return Err(Error::from(MtblError::ChecksumMismatch));
+                }
+            }
+        }
+
+        let key_range = match field(KEY_RANGE_FIELD) {
+            0 => None,
+            stored => {
+                let crc = key_range_checksum(checksum_type, bytes)?;
+                if verify && crc.is_some_and(|crc| crc != stored) {
+                    return Err(Error::from(MtblError::ChecksumMismatch));
+                }
+                let (min, max) = key_range_keys(bytes).ok_or(MtblError::InvalidKeyRange)?;
+                Some(KeyRange::new(min, max).ok_or(MtblError::InvalidKeyRange)?)
+            },
+        };
 
         Ok(Metadata {
             file_version,
             index_block_offset,
             data_block_size,
             compression_algorithm,
+            index_compression_algorithm,
             count_entries,
             count_data_blocks,
             bytes_data_blocks,
             bytes_index_block,
             bytes_keys,
             bytes_values,
+            compression_dictionary_id,
+            checksum_type,
+            bytes_raw_data_blocks,
+            count_index_segments,
+            bytes_index_segments,
+            key_range,
         })
     }
 
+    /// The first key of the table, if stored, see `WriterBuilder::store_key_range`.
+    pub fn min_key(&self) -> Option<&[u8]> {
+        self.key_range.as_ref().map(KeyRange::min)
+    }
+
+    /// The last key of the table, if stored, see `WriterBuilder::store_key_range`.
+    pub fn max_key(&self) -> Option<&[u8]> {
+        self.key_range.as_ref().map(KeyRange::max)
+    }
+
+    /// Stores the first and the last keys of the table, if they fit in the metadata.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_key_range(&mut self, min: &[u8], max: &[u8]) {
+        self.key_range = KeyRange::new(min, max);
+    }
+
+    /// The offset of the index in the file, its segments included, right after the data blocks.
+    pub(crate) fn index_start(&self) -> u64 {
+        self.index_block_offset.saturating_sub(self.bytes_index_segments)
+    }
+
+    /// The size of the compressed data blocks relative to their raw size,
+    /// `None` if the raw size is unknown or there is no data block.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match self.bytes_raw_data_blocks {
+            0 => None,
+            raw => Some(self.bytes_data_blocks as f64 / raw as f64),
+        }
+    }
+
+    /// Derives the statistics of the table of `file_size` bytes described by this metadata.
+    pub(crate) fn info(&self, file_size: u64) -> TableInfo {
+        let average = |total: u64, count: u64| if count == 0 { 0.0 } else { total as f64 / count as f64 };
+        TableInfo {
+            file_version: self.file_version,
+            file_size,
+            count_entries: self.count_entries,
+            count_data_blocks: self.count_data_blocks,
+            average_key_size: average(self.bytes_keys, self.count_entries),
+            average_value_size: average(self.bytes_values, self.count_entries),
+            entries_per_block: average(self.count_entries, self.count_data_blocks),
+            compression_ratio: self.compression_ratio(),
+        }
+    }
+
+    #[cfg(feature = "std")]
     pub(crate) fn write_to_bytes(&self, bytes: &mut [u8]) -> io::Result<()> {
         bytes.iter_mut().for_each(|x| *x = 0);
+        self.write_fields(bytes)?;
+
+        // zero when the checksum algorithm is not available
+        let crc = metadata_checksum(self.checksum_type, bytes).unwrap_or(0);
+        LittleEndian::write_u64(&mut bytes[CHECKSUMMED_FIELDS * mem::size_of::<u64>()..], crc);
 
+        if self.key_range.is_some() {
+            // never zero, the checksum marks the presence of the key range
+            let crc = key_range_checksum(self.checksum_type, bytes).ok().flatten().unwrap_or(CHECKSUM_PRESENT);
+            LittleEndian::write_u64(&mut bytes[KEY_RANGE_FIELD * mem::size_of::<u64>()..], crc);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn write_fields(&self, bytes: &mut [u8]) -> io::Result<()> {
         // split, left part for data, right part for magic number
         let (mut data, magic) = bytes.split_at_mut(METADATA_SIZE - mem::size_of::<u32>());
 
         data.write_u64::<LittleEndian>(self.index_block_offset)?;
         data.write_u64::<LittleEndian>(self.data_block_size)?;
-        data.write_u64::<LittleEndian>(self.compression_algorithm as u64)?;
+        data.write_u64::<LittleEndian>(self.compression_algorithm.as_u64())?;
         data.write_u64::<LittleEndian>(self.count_entries)?;
         data.write_u64::<LittleEndian>(self.count_data_blocks)?;
         data.write_u64::<LittleEndian>(self.bytes_data_blocks)?;
         data.write_u64::<LittleEndian>(self.bytes_index_block)?;
         data.write_u64::<LittleEndian>(self.bytes_keys)?;
         data.write_u64::<LittleEndian>(self.bytes_values)?;
+        data.write_u64::<LittleEndian>(self.compression_dictionary_id as u64)?;
+        data.write_u64::<LittleEndian>(self.checksum_type as u64)?;
+        data.write_u64::<LittleEndian>(self.bytes_raw_data_blocks)?;
+        // the checksum is written later, in between
+        data.write_u64::<LittleEndian>(0)?;
+        data.write_u64::<LittleEndian>(self.index_compression_algorithm.as_u64())?;
+        data.write_u64::<LittleEndian>(self.count_index_segments)?;
+        data.write_u64::<LittleEndian>(self.bytes_index_segments)?;
+
+        if let Some(range) = &self.key_range {
+            // the checksum of the key range is written later, in between
+            data.write_u64::<LittleEndian>(0)?;
+            data.write_u64::<LittleEndian>(range.min_len as u64 | (range.max_len as u64) << 32)?;
+            io::Write::write_all(&mut data, range.min())?;
+            io::Write::write_all(&mut data, range.max())?;
+        }
 
         // Write the magic number at the end of the buffer
-        Ok(LittleEndian::write_u32(magic, MAGIC))
+        let magic_number = match self.file_version {
+            FileVersion::FormatV1 => MAGIC_V1,
+            FileVersion::FormatV2 => MAGIC,
+            FileVersion::FormatV3 => MAGIC_V3,
+            FileVersion::FormatV4 => MAGIC_V4,
+            FileVersion::FormatV5 => MAGIC_V5,
+        };
+        LittleEndian::write_u32(magic, magic_number);
+        Ok(())
     }
 }
 
+/// The checksum of the fields of the metadata, as stored after them.
+///
+/// The fields stored after the checksum are covered too, up to the last one that
+/// is set, the checksums of the files written before these fields existed are
+/// therefore unchanged.
+fn metadata_checksum(checksum_type: ChecksumType, bytes: &[u8]) -> Option<u64> {
+    const SIZE: usize = mem::size_of::<u64>();
+    let mut fields = [0u8; (CHECKSUMMED_FIELDS + TRAILING_FIELDS) * SIZE];
+    fields[..CHECKSUMMED_FIELDS * SIZE].copy_from_slice(&bytes[..CHECKSUMMED_FIELDS * SIZE]);
+
+    let trailing = &bytes[INDEX_COMPRESSION_FIELD * SIZE..(INDEX_COMPRESSION_FIELD + TRAILING_FIELDS) * SIZE];
+    let count = trailing.chunks(SIZE).rposition(|field| field.iter().any(|&b| b != 0)).map_or(0, |i| i + 1);
+    fields[CHECKSUMMED_FIELDS * SIZE..][..count * SIZE].copy_from_slice(&trailing[..count * SIZE]);
+
+    checksum(checksum_type, &fields[..(CHECKSUMMED_FIELDS + count) * SIZE]).map(|crc| CHECKSUM_PRESENT | crc as u64)
+}
+
+/// The sizes of the first and the last keys followed by the keys, as stored in the metadata.
+fn key_range_bytes(bytes: &[u8]) -> Option<&[u8]> {
+    let sizes = LittleEndian::read_u64(&bytes[KEY_RANGE_OFFSET..]);
+    let len = (sizes as u32 as usize).checked_add((sizes >> 32) as usize)?;
+    if len > KEY_RANGE_CAPACITY {
+        return None;
+    }
+    Some(&bytes[KEY_RANGE_OFFSET..KEY_RANGE_OFFSET + mem::size_of::<u64>() + len])
+}
+
+/// The first and the last keys stored in the metadata.
+fn key_range_keys(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let sizes = LittleEndian::read_u64(&bytes[KEY_RANGE_OFFSET..]);
+    let keys = &key_range_bytes(bytes)?[mem::size_of::<u64>()..];
+    Some(keys.split_at(sizes as u32 as usize))
+}
+
+/// The checksum of the key range as stored in the metadata, with its presence bit set.
+fn key_range_checksum(checksum_type: ChecksumType, bytes: &[u8]) -> Result<Option<u64>, MtblError> {
+    let range = key_range_bytes(bytes).ok_or(MtblError::InvalidKeyRange)?;
+    Ok(checksum(checksum_type, range).map(|crc| CHECKSUM_PRESENT | crc as u64))
+}
+
 impl Default for Metadata {
     fn default() -> Metadata {
         Metadata {
@@ -86,12 +362,145 @@ impl Default for Metadata {
             index_block_offset: 0,
             data_block_size: DEFAULT_BLOCK_SIZE,
             compression_algorithm: DEFAULT_COMPRESSION_TYPE,
+            index_compression_algorithm: CompressionType::None,
             count_entries: 0,
             count_data_blocks: 0,
             bytes_data_blocks: 0,
             bytes_index_block: 0,
             bytes_keys: 0,
             bytes_values: 0,
+            compression_dictionary_id: 0,
+            checksum_type: ChecksumType::Crc32c,
+            bytes_raw_data_blocks: 0,
+            count_index_segments: 0,
+            bytes_index_segments: 0,
+            key_range: None,
         }
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Reader, ReaderBuilder, WriterBuilder};
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    fn checksum() {
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("hello", "world").unwrap();
+        let mut vec = writer.into_inner().unwrap();
+        let start = vec.len() - METADATA_SIZE;
+
+        let stored = LittleEndian::read_u64(&vec[start + CHECKSUMMED_FIELDS * 8..]);
+        assert_ne!(stored, 0);
+
+        // the files written by older versions have no checksum
+        let mut legacy = vec.clone();
+        LittleEndian::write_u64(&mut legacy[start + CHECKSUMMED_FIELDS * 8..], 0);
+        assert!(Reader::new(legacy.as_slice()).unwrap().contains_key(b"hello").unwrap());
+
+        // the index block offset points to the data block, detected before reading any block
+        LittleEndian::write_u64(&mut vec[start..], 0);
+        let result = Reader::new(vec.as_slice());
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::ChecksumMismatch))), "{:?}", result.map(drop));
+
+        let result = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice());
+        assert!(!matches!(result, Err(Error::Mtbl(MtblError::ChecksumMismatch))));
+    }
+
+    #[test]
+    fn info() {
+        let mut writer = WriterBuilder::new().with_block_size(256).memory();
+        for i in 0..1000u32 {
+            writer.insert(format!("key-{:04}", i), "value".repeat(i as usize % 7)).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let metadata = reader.metadata();
+        let info = reader.info();
+        assert_eq!(info.file_version, FileVersion::FormatV2);
+        assert_eq!(info.file_size, vec.len() as u64);
+        assert_eq!(info.count_entries, 1000);
+        assert!(info.count_data_blocks > 1);
+        assert_eq!(info.average_key_size, metadata.bytes_keys as f64 / 1000.0);
+        assert_eq!(info.average_value_size, metadata.bytes_values as f64 / 1000.0);
+        assert_eq!(info.entries_per_block, 1000.0 / metadata.count_data_blocks as f64);
+        assert_eq!(info.compression_ratio, metadata.compression_ratio());
+
+        #[cfg(feature = "serde")]
+        assert!(!bincode::serialize(&info).unwrap().is_empty());
+
+        // an empty table has no average
+        let vec = WriterBuilder::new().memory().into_inner().unwrap();
+        let info = Reader::new(vec).unwrap().info();
+        assert_eq!(info.count_entries, 0);
+        assert_eq!((info.average_key_size, info.average_value_size, info.entries_per_block), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn index_compression_version() {
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("hello", "world").unwrap();
+        let mut vec = writer.into_inner().unwrap();
+        let start = vec.len() - METADATA_SIZE;
+        let read = |vec: &[u8]| ReaderBuilder::new().verify_checksums(false).read(vec).map(drop);
+
+        // a compressed index is not part of the V2 format
+        let field = start + INDEX_COMPRESSION_FIELD * 8;
+        LittleEndian::write_u64(&mut vec[field..], CompressionType::Snappy.as_u64());
+        let result = read(&vec);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidFormatVersion))), "{:?}", result);
+
+        LittleEndian::write_u64(&mut vec[field..], 42);
+        let result = read(&vec);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidCompressionAlgorithm))), "{:?}", result);
+    }
+
+    #[test]
+    fn checksum_type_version() {
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("hello", "world").unwrap();
+        let mut vec = writer.into_inner().unwrap();
+        let start = vec.len() - METADATA_SIZE;
+
+        // the other checksums than crc32c are not part of the V2 format
+        LittleEndian::write_u64(&mut vec[start + 10 * 8..], ChecksumType::None as u64);
+        let result = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice()).map(drop);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidFormatVersion))), "{:?}", result);
+    }
+
+    #[test]
+    fn snappy_framed_version() {
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert("hello", "world").unwrap();
+        let mut vec = writer.into_inner().unwrap();
+        let start = vec.len() - METADATA_SIZE;
+
+        // the framed snappy compression is not part of the V2 format
+        LittleEndian::write_u64(&mut vec[start + 2 * 8..], CompressionType::SnappyFramed as u64);
+        let result = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice()).map(drop);
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidFormatVersion))), "{:?}", result);
+    }
+
+    #[test]
+    #[cfg(not(feature = "xxh3"))]
+    fn xxh3_disabled() {
+        let mut writer = WriterBuilder::new().checksum_type(ChecksumType::None).memory();
+        writer.insert("hello", "world").unwrap();
+        let mut vec = writer.into_inner().unwrap();
+
+        // the xxh3 checksums can't be verified without the feature
+        let start = vec.len() - METADATA_SIZE;
+        LittleEndian::write_u64(&mut vec[start + 10 * 8..], ChecksumType::Xxh3 as u64);
+        let result = Reader::new(vec.as_slice());
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::UnsupportedChecksumAlgorithm))), "{:?}", result.map(drop));
+
+        // nor computed
+        assert!(!ChecksumType::Xxh3.is_supported());
+        let mut writer = WriterBuilder::new().checksum_type(ChecksumType::Xxh3).memory();
+        writer.insert("hello", "world").unwrap();
+        assert!(writer.into_inner().is_err());
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::mem;
+
+/// The buffers the data blocks are decompressed into, see `ReaderBuilder::decompress_buffer_pool`.
+///
+/// A buffer is taken for every decompressed block and given back once the block
+/// and all the views of its entries are dropped.
+pub trait BufferPool: Send + Sync {
+    /// Returns a buffer to decompress a block into, its content is cleared first.
+    fn take(&self) -> Vec<u8>;
+
+    /// Gives back a buffer that was returned by `take`.
+    fn recycle(&self, buffer: Vec<u8>);
+}
+
+/// A decompressed block, its buffer goes back to the pool when dropped.
+#[cfg(feature = "std")]
+pub(crate) struct PooledBytes {
+    bytes: Vec<u8>,
+    pool: Arc<dyn BufferPool>,
+}
+
+#[cfg(feature = "std")]
+impl PooledBytes {
+    pub(crate) fn new(bytes: Vec<u8>, pool: Arc<dyn BufferPool>) -> PooledBytes {
+        PooledBytes { bytes, pool }
+    }
+}
+
+#[cfg(feature = "std")]
+impl AsRef<[u8]> for PooledBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for PooledBytes {
+    fn drop(&mut self) {
+        self.pool.recycle(mem::take(&mut self.bytes));
+    }
+}
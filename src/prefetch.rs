@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::block::Block;
+use crate::compression::{decompress_into, CompressionType};
+use crate::error::Error;
+use crate::index::IndexIter;
+use crate::pool::BufferPool;
+use crate::reader::Reader;
+use crate::BytesView;
+
+/// What the background thread needs to decompress the blocks of a reader.
+pub(crate) struct Decompressor {
+    pub(crate) compression: CompressionType,
+    pub(crate) dictionary: Option<Arc<[u8]>>,
+    pub(crate) max_size: usize,
+    pub(crate) pool: Option<Arc<dyn BufferPool>>,
+}
+
+impl Decompressor {
+    fn decompress(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buffer = match &self.pool {
+            Some(pool) => pool.take(),
+            None => Vec::new(),
+        };
+        match decompress_into(self.compression, self.dictionary.as_deref(), raw, self.max_size, &mut buffer) {
+            Ok(()) => Ok(buffer),
+            Err(e) => {
+                if let Some(pool) = &self.pool {
+                    pool.recycle(buffer);
+                }
+                Err(Error::from(e))
+            },
+        }
+    }
+}
+
+/// Decompresses the blocks that follow the current one on a background thread,
+/// at most `depth` blocks in advance. The compressed bytes are copied to the
+/// thread, the reader itself is not shared with it.
+pub(crate) struct Prefetcher<A> {
+    /// Positioned on the last block sent to the thread.
+    ahead: IndexIter<A>,
+    depth: usize,
+    /// The number of blocks sent to the thread and not received yet.
+    pending: usize,
+    /// Whether the last block has been sent, or an error.
+    exhausted: bool,
+    pool: Option<Arc<dyn BufferPool>>,
+    sender: Option<Sender<Result<Vec<u8>, Error>>>,
+    receiver: Receiver<Result<Vec<u8>, Error>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<A: AsRef<[u8]>> Prefetcher<A> {
+    /// Spawns a thread that decompresses the blocks starting with the one indexed by `index_key`.
+    pub(crate) fn spawn(reader: &Reader<A>, index_key: &[u8], depth: usize) -> Prefetcher<A> {
+        let (sender, raw_blocks) = mpsc::channel::<Result<Vec<u8>, Error>>();
+        let (blocks, receiver) = mpsc::channel();
+        let decompressor = reader.decompressor();
+        let pool = decompressor.pool.clone();
+
+        let handle = thread::spawn(move || {
+            // the sender is dropped once the scan is stopped
+            for raw in raw_blocks {
+                let block = raw.and_then(|raw| decompressor.decompress(&raw));
+                if blocks.send(block).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut ahead = IndexIter::init(Arc::clone(reader.index()));
+        let (exhausted, first) = match ahead.seek(index_key) {
+            Ok(()) => (false, None),
+            Err(e) => (true, Some(Err(Error::from(e)))),
+        };
+
+        let mut prefetcher = Prefetcher {
+            ahead,
+            depth: depth.max(1),
+            pending: 0,
+            exhausted,
+            pool,
+            sender: Some(sender),
+            receiver,
+            handle: Some(handle),
+        };
+        match first {
+            Some(error) => prefetcher.send(error),
+            None => prefetcher.send_current(reader),
+        }
+        prefetcher.fill(reader);
+        prefetcher
+    }
+
+    /// Returns the next block, `None` when all the blocks have been read.
+    pub(crate) fn next_block(&mut self, reader: &Reader<A>) -> Result<Option<Block<A>>, Error> {
+        if self.pending == 0 {
+            return Ok(None);
+        }
+
+        let result = match self.receiver.recv() {
+            Ok(result) => result,
+            // the thread panicked
+            Err(_) => return Ok(None),
+        };
+        self.pending -= 1;
+        self.fill(reader);
+
+        let bytes = result?;
+        let data = match &self.pool {
+            Some(pool) => BytesView::from_pooled(bytes, pool.clone()),
+            None => BytesView::from_bytes(bytes),
+        };
+        let file_version = reader.metadata().file_version;
+        let block = Block::init(data)?
+            .with_repeated_values(file_version.repeated_values())
+            .with_value_checksums(file_version.value_checksums());
+        Ok(Some(block))
+    }
+
+    /// Sends the next blocks until `depth` blocks are pending.
+    fn fill(&mut self, reader: &Reader<A>) {
+        while !self.exhausted && self.pending < self.depth {
+            match self.ahead.next() {
+                Ok(true) => self.send_current(reader),
+                Ok(false) => self.exhausted = true,
+                Err(e) => self.send(Err(Error::from(e))),
+            }
+        }
+    }
+
+    /// Sends a copy of the compressed bytes of the block `ahead` is positioned on.
+    fn send_current(&mut self, reader: &Reader<A>) {
+        match reader.block_offset_at(&self.ahead) {
+            Some(offset) => self.send(reader.raw_block(offset).map(<[u8]>::to_vec)),
+            None => self.exhausted = true,
+        }
+    }
+
+    /// The blocks that follow an error are not sent.
+    fn send(&mut self, raw: Result<Vec<u8>, Error>) {
+        self.exhausted |= raw.is_err();
+        if let Some(sender) = &self.sender {
+            if sender.send(raw).is_ok() {
+                self.pending += 1;
+            }
+        }
+    }
+}
+
+impl<A> Drop for Prefetcher<A> {
+    fn drop(&mut self) {
+        // disconnect the channel first to stop the thread, then wait for it
+        drop(self.sender.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -1,25 +1,74 @@
-use std::borrow::Cow;
-use std::mem;
-use std::sync::Arc;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{fmt, mem};
 
 use byteorder::{ByteOrder, LittleEndian};
 
-use crate::block::{Block, BlockIter};
-use crate::compression::decompress;
+use crate::block::{Block, BlockIter, RestartWidth};
+use crate::checksum::{checksum, ChecksumType};
+#[cfg(feature = "std")]
+use crate::compression::decompress_into;
+use crate::compression::{decompress, zstd_dictionary_id, CompressionType};
 use crate::error::{Error, MtblError};
+use crate::index::{Index, IndexIter};
+#[cfg(feature = "std")]
+use crate::prefetch::{Decompressor, Prefetcher};
 use crate::METADATA_SIZE;
 use crate::varint::varint_decode64;
-use crate::{BytesView, FileVersion, Metadata};
+use crate::{BufferPool, BytesView, DynBytes, FileVersion, KeyFilter, KeyTransform, Metadata, TableInfo};
 
-#[derive(Debug, Clone, Copy)]
+type KeyTransformFn = dyn Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync;
+
+/// The data blocks decoded in advance with their offsets, see `ReaderBuilder::prewarm`.
+type PrewarmedBlocks<A> = Arc<[(usize, Block<A>)]>;
+
+/// Normalizes the keys searched for, see `ReaderBuilder::key_transform`.
+#[derive(Clone)]
+struct SharedKeyTransform(Arc<KeyTransformFn>);
+
+impl fmt::Debug for SharedKeyTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedKeyTransform")
+    }
+}
+
+/// The buffers of the decompressed blocks, see `ReaderBuilder::decompress_buffer_pool`.
+#[derive(Clone)]
+struct SharedBufferPool(Arc<dyn BufferPool>);
+
+impl fmt::Debug for SharedBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedBufferPool")
+    }
+}
+
+/// Not `Copy` because of the dictionary, the key transform and the buffer pool,
+/// a clone shares them.
+#[derive(Debug, Clone)]
 pub struct ReaderBuilder {
-    verify_checksums: bool,
+    pub(crate) verify_checksums: bool,
+    compression_dictionary: Option<Arc<[u8]>>,
+    key_transform: Option<SharedKeyTransform>,
+    pub(crate) max_decompressed_block_size: usize,
+    #[cfg(feature = "std")]
+    prefetch: usize,
+    prewarm: bool,
+    decompress_buffer_pool: Option<SharedBufferPool>,
 }
 
 impl ReaderBuilder {
     pub fn new() -> ReaderBuilder {
         ReaderBuilder {
             verify_checksums: true,
+            compression_dictionary: None,
+            key_transform: None,
+            max_decompressed_block_size: usize::MAX,
+            #[cfg(feature = "std")]
+            prefetch: 0,
+            prewarm: false,
+            decompress_buffer_pool: None,
         }
     }
 
@@ -28,23 +77,164 @@ impl ReaderBuilder {
         self
     }
 
+    /// The zstd dictionary to decompress the blocks with, it must be the one
+    /// the file was written with, this is checked against the stored dictionary id.
+    #[cfg(feature = "zstd_dict")]
+    pub fn compression_dictionary(&mut self, dictionary: Vec<u8>) -> &mut Self {
+        self.compression_dictionary = Some(Arc::from(dictionary));
+        self
+    }
+
+    /// The number of blocks decompressed in advance by a background thread during the
+    /// forward scans of the iterators, zero disables the prefetching. The thread is
+    /// spawned once a scan moves to its second block and stops with the iterator.
+    /// The blocks of a table that is not compressed are never prefetched.
+    #[cfg(feature = "std")]
+    pub fn prefetch(&mut self, depth: usize) -> &mut Self {
+        self.prefetch = depth;
+        self
+    }
+
+    /// Transforms the keys given to `get`, `contains_key`, the iterators and the cursors
+    /// before searching for them, e.g. to lowercase them.
+    ///
+    /// The keys of the file must have been written already transformed, their
+    /// order is the one of the transformed keys.
+    pub fn key_transform(&mut self, transform: KeyTransform) -> &mut Self {
+        self.key_transform = Some(SharedKeyTransform(Arc::from(transform)));
+        self
+    }
+
+    /// The maximum size of a decompressed block, the index block included. A block that
+    /// would be bigger returns a `DecompressedSizeExceeded` error before being fully
+    /// allocated, it protects against the corrupted or malicious files.
+    pub fn max_decompressed_block_size(&mut self, size: usize) -> &mut Self {
+        self.max_decompressed_block_size = size;
+        self
+    }
+
+    /// Decodes the first and the last data blocks when the file is read, they are kept by
+    /// the reader and its clones, the first lookups in these blocks then decode nothing.
+    /// An error is returned by `read` if they cannot be decoded. The lookups of the keys
+    /// before the first one then read no block, even without `WriterBuilder::store_key_range`.
+    pub fn prewarm(&mut self, prewarm: bool) -> &mut Self {
+        self.prewarm = prewarm;
+        self
+    }
+
+    /// Decompresses the data blocks into buffers taken from `pool` instead of allocating
+    /// a buffer for every block, a buffer is given back once its block is dropped.
+    /// The uncompressed blocks and the index block do not use the pool.
+    pub fn decompress_buffer_pool(&mut self, pool: Arc<dyn BufferPool>) -> &mut Self {
+        self.decompress_buffer_pool = Some(SharedBufferPool(pool));
+        self
+    }
+
+    pub fn with_verify_checksums(mut self, verify: bool) -> Self {
+        self.verify_checksums(verify);
+        self
+    }
+
+    #[cfg(feature = "zstd_dict")]
+    pub fn with_compression_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.compression_dictionary(dictionary);
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_prefetch(mut self, depth: usize) -> Self {
+        self.prefetch(depth);
+        self
+    }
+
+    pub fn with_key_transform(mut self, transform: KeyTransform) -> Self {
+        self.key_transform(transform);
+        self
+    }
+
+    pub fn with_max_decompressed_block_size(mut self, size: usize) -> Self {
+        self.max_decompressed_block_size(size);
+        self
+    }
+
+    pub fn with_prewarm(mut self, prewarm: bool) -> Self {
+        self.prewarm(prewarm);
+        self
+    }
+
+    pub fn with_decompress_buffer_pool(mut self, pool: Arc<dyn BufferPool>) -> Self {
+        self.decompress_buffer_pool(pool);
+        self
+    }
+
+    /// Returns the dictionary to decompress the blocks of this file with,
+    /// checking that it is the one the file was written with.
+    pub(crate) fn dictionary_for(&self, metadata: &Metadata) -> Result<Option<Arc<[u8]>>, Error> {
+        match metadata.compression_dictionary_id {
+            0 => Ok(None),
+            id => match &self.compression_dictionary {
+                Some(dictionary) if zstd_dictionary_id(dictionary) == id => Ok(Some(dictionary.clone())),
+                Some(_) => Err(Error::from(MtblError::InvalidCompressionDictionary)),
+                None => Err(Error::from(MtblError::MissingCompressionDictionary)),
+            },
+        }
+    }
+
     pub fn read<A: AsRef<[u8]>>(&mut self, data: A) -> Result<Reader<A>, Error> {
+        self.read_view(BytesView::from(data))
+    }
+
+    /// Reads the tables written one after the other in `data`, in the order they appear.
+    /// The tables are found from the end, using the metadata of each one to find its start.
+    pub fn read_all_tables<A: AsRef<[u8]>>(&mut self, data: A) -> Result<Vec<Reader<A>>, Error> {
+        let data = BytesView::from(data);
+        let mut readers = Vec::new();
+        let mut end = data.len();
+
+        loop {
+            if end < METADATA_SIZE {
+                return Err(Error::from(MtblError::InvalidMetadataSize));
+            }
+
+            let metadata_bytes = &data.as_ref()[end - METADATA_SIZE..end];
+            let metadata = Metadata::read_from_bytes(metadata_bytes, self.verify_checksums)?;
+
+            // the data blocks, the index block and the metadata
+            let len = metadata.index_block_offset
+                .checked_add(metadata.bytes_index_block)
+                .and_then(|len| len.checked_add(METADATA_SIZE as u64))
+                .filter(|&len| len <= end as u64)
+                .ok_or(MtblError::InvalidIndexBlockOffset)? as usize;
+
+            let start = end - len;
+            readers.push(self.read_view(data.slice(start, len))?);
+            if start == 0 { break }
+            end = start;
+        }
+
+        readers.reverse();
+        Ok(readers)
+    }
+
+    fn read_view<A: AsRef<[u8]>>(&mut self, data: BytesView<A>) -> Result<Reader<A>, Error> {
         if data.as_ref().len() < METADATA_SIZE {
             return Err(Error::from(MtblError::InvalidMetadataSize))
         }
 
         let metadata_offset = data.as_ref().len() - METADATA_SIZE;
         let metadata_bytes = &data.as_ref()[metadata_offset..metadata_offset + METADATA_SIZE];
-        let metadata = Metadata::read_from_bytes(metadata_bytes)?;
+        let metadata = Metadata::read_from_bytes(metadata_bytes, self.verify_checksums)?;
+
+        let compression_dictionary = self.dictionary_for(&metadata)?;
 
         // Sanitize the index block offset.
         // We calculate the maximum possible index block offset for this file to
-        // be the total size of the file (r->len_data) minus the length of the
-        // metadata block (METADATA_SIZE) minus the length of the minimum
-        // sized block, which requires 4 fixed-length 32-bit integers (16 bytes).
-        // FIXME why do I get 13 bytes!
-        let max_index_block_offset = (data.as_ref().len() - METADATA_SIZE - 13) as u64;
-        if metadata.index_block_offset > max_index_block_offset {
+        // be the total size of the file minus the length of the metadata block
+        // (METADATA_SIZE) minus the length of the minimum sized block.
+        let max_index_block_offset = data.as_ref().len()
+            .checked_sub(METADATA_SIZE + min_block_size(metadata.file_version))
+            .ok_or(MtblError::InvalidIndexBlockOffset)?;
+        if metadata.index_block_offset > max_index_block_offset as u64 {
             return Err(Error::from(MtblError::InvalidIndexBlockOffset));
         }
 
@@ -64,29 +254,143 @@ impl ReaderBuilder {
         }
 
         let start = metadata.index_block_offset as usize + index_len_len + mem::size_of::<u32>();
-        let data = BytesView::from(data);
+        if start.checked_add(index_len).is_none_or(|end| end > metadata_offset) {
+            return Err(Error::from(MtblError::InvalidIndexLength));
+        }
+
         let index_data = data.slice(start, index_len);
 
-        #[cfg(feature = "checksum")] {
         if self.verify_checksums {
             let index_crc = LittleEndian::read_u32(&data.as_ref()[metadata.index_block_offset as usize + index_len_len..]);
-            assert_eq!(index_crc, crc32c::crc32c(index_data.as_ref()));
-        } }
+            verify_checksum(metadata.checksum_type, index_crc, index_data.as_ref())?;
+        }
+
+        let max_decompressed_block_size = self.max_decompressed_block_size;
+        let index_data = match decompress(metadata.index_compression_algorithm, None, index_data.as_ref(), max_decompressed_block_size)? {
+            Cow::Borrowed(_) => index_data.clone(),
+            Cow::Owned(bytes) => BytesView::from_bytes(bytes),
+        };
 
-        let index = Block::init(index_data).ok_or(MtblError::InvalidBlock)?;
+        let index = Block::init(index_data)?;
+        let checksum_type = if self.verify_checksums { Some(metadata.checksum_type) } else { None };
+        let index = if metadata.count_index_segments == 0 {
+            Index::single(index)
+        } else {
+            // the segments are written right before the top-level index block
+            let segments = metadata.index_block_offset.checked_sub(metadata.bytes_index_segments)
+                .ok_or(MtblError::InvalidIndexBlockOffset)?..metadata.index_block_offset;
+            Index::two_level(index, |offset| {
+                if !segments.contains(&offset) {
+                    return Err(Error::from(MtblError::InvalidIndexEntry));
+                }
+                decode_block(
+                    &data,
+                    offset as usize,
+                    metadata.file_version,
+                    checksum_type,
+                    metadata.index_compression_algorithm,
+                    None,
+                    max_decompressed_block_size,
+                    None,
+                )
+            })?
+        };
         let index = Arc::new(index);
         let verify_checksums = self.verify_checksums;
 
-        Ok(Reader { metadata, data, verify_checksums, index })
+        let mut reader = Reader {
+            metadata,
+            data,
+            verify_checksums,
+            compression_dictionary,
+            index,
+            key_transform: self.key_transform.clone(),
+            first_key: None,
+            last_key: None,
+            max_decompressed_block_size,
+            #[cfg(feature = "std")]
+            prefetch: self.prefetch,
+            prewarmed_blocks: None,
+            decompress_buffer_pool: self.decompress_buffer_pool.clone(),
+        };
+
+        if self.prewarm {
+            reader.prewarmed_blocks = Some(reader.read_prewarmed_blocks()?);
+        }
+        // the first block is only decoded to find the first key when prewarmed,
+        // the lookups of the smaller keys read this block anyway
+        reader.first_key = match reader.metadata.min_key() {
+            Some(key) => Some(Arc::from(key)),
+            None => reader.prewarmed_first_key()?,
+        };
+        reader.last_key = match reader.metadata.max_key() {
+            Some(key) => Some(Arc::from(key)),
+            None => reader.read_last_key(),
+        };
+
+        Ok(reader)
     }
 }
 
-#[derive(Clone)]
+/// The size of the smallest block: its length, its checksum and a restart
+/// trailer of a single restart point followed by the number of restart points.
+fn min_block_size(file_version: FileVersion) -> usize {
+    let length_size = match file_version {
+        FileVersion::FormatV1 => mem::size_of::<u32>(),
+        // the varint of a zero length
+        FileVersion::FormatV2 | FileVersion::FormatV3 | FileVersion::FormatV4 | FileVersion::FormatV5 => 1,
+    };
+    length_size + mem::size_of::<u32>() + 2 * mem::size_of::<u32>()
+}
+
 pub struct Reader<A> {
     metadata: Metadata,
     data: BytesView<A>,
     verify_checksums: bool,
-    index: Arc<Block<A>>,
+    compression_dictionary: Option<Arc<[u8]>>,
+    index: Arc<Index<A>>,
+    key_transform: Option<SharedKeyTransform>,
+    /// The first key of the file, only known if stored in the metadata, see
+    /// `WriterBuilder::store_key_range`, or if the first block is prewarmed.
+    /// Without it only the keys after the last key are out of bounds.
+    first_key: Option<Arc<[u8]>>,
+    /// The key of the last index entry, greater than or equal to the last key of the file.
+    last_key: Option<Arc<[u8]>>,
+    max_decompressed_block_size: usize,
+    #[cfg(feature = "std")]
+    prefetch: usize,
+    /// The first and the last data blocks, if prewarmed.
+    prewarmed_blocks: Option<PrewarmedBlocks<A>>,
+    decompress_buffer_pool: Option<SharedBufferPool>,
+}
+
+impl<A> Clone for Reader<A> {
+    fn clone(&self) -> Reader<A> {
+        Reader {
+            metadata: self.metadata,
+            data: self.data.clone(),
+            verify_checksums: self.verify_checksums,
+            compression_dictionary: self.compression_dictionary.clone(),
+            index: self.index.clone(),
+            key_transform: self.key_transform.clone(),
+            first_key: self.first_key.clone(),
+            last_key: self.last_key.clone(),
+            max_decompressed_block_size: self.max_decompressed_block_size,
+            #[cfg(feature = "std")]
+            prefetch: self.prefetch,
+            prewarmed_blocks: self.prewarmed_blocks.clone(),
+            decompress_buffer_pool: self.decompress_buffer_pool.clone(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for Reader<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("metadata", &self.metadata)
+            .field("verify_checksums", &self.verify_checksums)
+            .finish()
+    }
 }
 
 impl<A> Reader<A> {
@@ -100,15 +404,118 @@ impl<A: AsRef<[u8]>> Reader<A> {
         ReaderBuilder::new().read(data)
     }
 
+    /// Reads the tables concatenated in `data`, see `ReaderBuilder::read_all_tables`.
+    pub fn read_all_tables(data: A) -> Result<Vec<Reader<A>>, Error> {
+        ReaderBuilder::new().read_all_tables(data)
+    }
+
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
 
+    /// The number of entries, as stored in the metadata.
+    pub fn entry_count(&self) -> u64 {
+        self.metadata.count_entries
+    }
+
+    /// Reads all the data blocks and checks that they contain the number of entries
+    /// stored in the metadata, which is not covered by the checksums of the blocks.
+    pub fn validate_entry_count(&self) -> Result<(), Error> {
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek_to_first()?;
+
+        let mut found = 0;
+        while let Some(block) = self.block_at_index(&index_iter)? {
+            // only the keys are decoded
+            let mut bi = BlockIter::init(Arc::new(block));
+            bi.seek_to_first()?;
+            while bi.key().is_some() {
+                found += 1;
+                bi.next()?;
+            }
+            index_iter.next()?;
+        }
+
+        let declared = self.metadata.count_entries;
+        if declared != found {
+            return Err(Error::from(MtblError::EntryCountMismatch { declared, found }));
+        }
+        Ok(())
+    }
+
+    /// Reads all the data blocks and checks the checksum stored after every value, see
+    /// `WriterBuilder::per_entry_checksum`, the key of the first value that does not match
+    /// is returned in an `EntryChecksumMismatch` error. The blocks whose own checksum does
+    /// not match can only be checked by a reader built with `verify_checksums(false)`.
+    ///
+    /// Nothing is checked for the files written without checksums on the values.
+    pub fn verify_entry_checksums(&self) -> Result<(), Error> {
+        if !self.metadata.file_version.value_checksums() {
+            return Ok(());
+        }
+
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek_to_first()?;
+
+        while let Some(block) = self.block_at_index(&index_iter)? {
+            let mut bi = BlockIter::init(Arc::new(block));
+            bi.seek_to_first()?;
+            while let Some((key, val)) = bi.get() {
+                let crc = checksum(self.metadata.checksum_type, val);
+                if crc.is_some_and(|crc| Some(crc) != bi.value_checksum()) {
+                    return Err(Error::from(MtblError::EntryChecksumMismatch { key: key.to_vec() }));
+                }
+                bi.next()?;
+            }
+            index_iter.next()?;
+        }
+
+        Ok(())
+    }
+
+    /// The size of all the keys, as stored in the metadata.
+    pub fn total_key_bytes(&self) -> u64 {
+        self.metadata.bytes_keys
+    }
+
+    /// The size of all the values, as stored in the metadata.
+    pub fn total_value_bytes(&self) -> u64 {
+        self.metadata.bytes_values
+    }
+
+    /// The compression of the data blocks.
+    pub fn compression(&self) -> CompressionType {
+        self.metadata.compression_algorithm
+    }
+
+    /// The statistics of this table derived from its metadata, without reading any block.
+    pub fn info(&self) -> TableInfo {
+        self.metadata.info(self.as_bytes().len() as u64)
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.data.as_ref()
     }
 
+    /// An estimation of the memory held by this reader: the index, the compression
+    /// dictionary and the decompressed prewarmed blocks. The data of the file itself
+    /// is not counted, nor the prewarmed blocks of a file that is not compressed.
+    pub fn memory_footprint(&self) -> usize {
+        let dictionary = self.compression_dictionary.as_ref().map_or(0, |d| d.len());
+        let prewarmed = match &self.prewarmed_blocks {
+            Some(blocks) if self.metadata.compression_algorithm != CompressionType::None => {
+                blocks.iter().map(|(_offset, block)| block.as_ref().len()).sum()
+            },
+            _ => 0,
+        };
+        self.index.size() + dictionary + prewarmed
+    }
+
     pub fn get(self, key: &[u8]) -> Result<Option<ReaderIntoGet<A>>, Error> {
+        if self.out_of_bounds(&self.transform_key(key)) {
+            return Ok(None);
+        }
+
         let mut iter = ReaderIntoIter::new_get(self, key)?;
         match iter.next() {
             Some(_) => {
@@ -121,189 +528,856 @@ impl<A: AsRef<[u8]>> Reader<A> {
         }
     }
 
+    /// Returns whether the key is in the file, only the keys of the
+    /// block that could contain it are decoded.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, Error> {
+        let key = &*self.transform_key(key);
+        if self.out_of_bounds(key) {
+            return Ok(false);
+        }
+
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek(key)?;
+
+        match self.block_at_index(&index_iter)? {
+            Some(block) => {
+                let mut bi = BlockIter::init(Arc::new(block));
+                bi.seek(key)?;
+                Ok(bi.key() == Some(key))
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Returns a copy of the value of the key, unlike `get` the
+    /// decoded block is released as soon as the value is copied.
+    pub fn get_owned(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let key = &*self.transform_key(key);
+        if self.out_of_bounds(key) {
+            return Ok(None);
+        }
+
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek(key)?;
+
+        match self.block_at_index(&index_iter)? {
+            Some(block) => {
+                let mut bi = BlockIter::init(Arc::new(block));
+                bi.seek(key)?;
+                match bi.get() {
+                    Some((k, v)) if k == key => Ok(Some(v.to_vec())),
+                    _ => Ok(None),
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
     pub fn into_iter(self) -> Result<ReaderIntoIter<A>, Error> {
         ReaderIntoIter::new(self)
     }
 
+    /// Copies all the entries of the file into a map, meant for the small tables.
+    pub fn to_btreemap(&self) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, Error> {
+        let mut map = BTreeMap::new();
+        let mut iter = self.clone().into_iter()?;
+        while let Some(result) = iter.next() {
+            let (key, val) = result?;
+            map.insert(key.to_vec(), val.to_vec());
+        }
+        Ok(map)
+    }
+
+    /// Returns an iterator over the owned entries that can also
+    /// be iterated from the end, see `DoubleEndedIterator`.
+    pub fn into_double_ended_iter(self) -> Result<ReaderIntoDoubleEndedIter<A>, Error> {
+        let back_index = IndexIter::init(self.index.clone());
+        let front = ReaderIntoIter::new(self)?;
+        Ok(ReaderIntoDoubleEndedIter { front, back_index, back: None, front_key: None, back_key: None, done: false })
+    }
+
     pub fn iter_from(self, start: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
         ReaderIntoIter::new_from(self, start)
     }
 
+    /// Iterates from the first key strictly greater than `start`,
+    /// e.g. to resume a scan after the last key seen.
+    pub fn iter_after(self, start: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
+        ReaderIntoIter::new_after(self, start)
+    }
+
     pub fn iter_prefix(self, prefix: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
         ReaderIntoIter::new_get_prefix(self, prefix)
     }
 
+    /// Iterates over the keys that start with `prefix` from the greatest to the smallest,
+    /// e.g. to find the most recent entry of keys suffixed by a timestamp.
+    pub fn iter_prefix_rev(self, prefix: &[u8]) -> ReaderPrefixRev<A> {
+        ReaderPrefixRev::new(self, prefix)
+    }
+
+    /// Returns at most `limit` entries whose keys start with `prefix`.
+    pub fn prefix_scan(&self, prefix: &[u8], limit: usize) -> Result<Vec<Entry>, Error> {
+        let mut iter = self.clone().iter_prefix(prefix)?;
+        let mut entries = Vec::new();
+        while entries.len() < limit {
+            match iter.next() {
+                Some(result) => {
+                    let (key, val) = result?;
+                    entries.push((key.to_vec(), val.to_vec()));
+                },
+                None => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the number of keys that start with `prefix`.
+    pub fn prefix_count(&self, prefix: &[u8]) -> Result<usize, Error> {
+        let mut iter = self.clone().iter_prefix(prefix)?;
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            result?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
     pub fn iter_range(self, start: &[u8], end: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
         ReaderIntoIter::new_get_range(self, start, end)
     }
 
-    fn block(&self, offset: usize) -> Result<Block<A>, Error> {
-        assert!(offset < self.data.len());
+    pub(crate) fn block(&self, offset: usize) -> Result<Block<A>, Error> {
+        let mut prewarmed = self.prewarmed_blocks.iter().flat_map(|blocks| blocks.iter());
+        if let Some((_offset, block)) = prewarmed.find(|(o, _)| *o == offset) {
+            return Ok(block.clone());
+        }
 
-        let raw_contents_size_len: usize;
-        let raw_contents_size: usize;
+        let checksum_type = if self.verify_checksums { Some(self.metadata.checksum_type) } else { None };
+        decode_block(
+            &self.data,
+            offset,
+            self.metadata.file_version,
+            checksum_type,
+            self.metadata.compression_algorithm,
+            self.compression_dictionary.as_deref(),
+            self.max_decompressed_block_size,
+            self.decompress_buffer_pool.as_ref().map(|pool| &pool.0),
+        )
+        .map(|block| {
+            block.with_repeated_values(self.metadata.file_version.repeated_values())
+                .with_value_checksums(self.metadata.file_version.value_checksums())
+        })
+    }
 
-        if self.metadata.file_version == FileVersion::FormatV1 {
-            raw_contents_size_len = mem::size_of::<u32>();
-            raw_contents_size = LittleEndian::read_u32(&self.data.as_ref()[offset..]) as usize;
-        } else {
-            let mut tmp = 0;
-            raw_contents_size_len = varint_decode64(&self.data.as_ref()[offset..], &mut tmp);
-            raw_contents_size = tmp as usize;
-            assert_eq!(raw_contents_size as u64, tmp);
-        }
+    /// Returns the compressed bytes of the data block referenced by the
+    /// `index_entry`-th entry of the index, along with their compression type.
+    /// The checksum is verified but the block is not decompressed.
+    pub fn raw_block_at(&self, index_entry: usize) -> Result<(CompressionType, &[u8]), Error> {
+        let offset = self.block_offset(index_entry as u64)? as usize;
+        Ok((self.metadata.compression_algorithm, self.raw_block(offset)?))
+    }
 
-        let raw_start = offset + raw_contents_size_len + mem::size_of::<u32>();
-        let raw_contents = &self.data.as_ref()[raw_start..raw_start + raw_contents_size];
+    /// The compressed bytes of the data block written at `offset`, see `raw_block_at`.
+    pub(crate) fn raw_block(&self, offset: usize) -> Result<&[u8], Error> {
+        let checksum_type = if self.verify_checksums { Some(self.metadata.checksum_type) } else { None };
+        let data = self.data.as_ref();
+        let (start, len) = raw_block_bounds(data, offset, self.metadata.file_version, checksum_type)?;
+        Ok(&data[start..start + len])
+    }
 
-        #[cfg(feature = "checksum")] {
-        if self.verify_checksums {
-            let block_crc = LittleEndian::read_u32(&self.data.as_ref()[offset + raw_contents_size_len..]);
-            let calc_crc = crc32c::crc32c(raw_contents);
-            assert_eq!(block_crc, calc_crc);
-        } }
-
-        let data = decompress(self.metadata.compression_algorithm, raw_contents)?;
-        let data = match data {
-            Cow::Borrowed(_) => self.data.slice(raw_start, raw_contents_size),
-            Cow::Owned(bytes) => BytesView::from_bytes(bytes),
-        };
+    /// The first and the last keys of the table, as stored in the metadata,
+    /// see `WriterBuilder::store_key_range`. No block is read.
+    pub fn key_range(&self) -> Option<(&[u8], &[u8])> {
+        Some((self.metadata.min_key()?, self.metadata.max_key()?))
+    }
+
+    /// The number of data blocks, as stored in the metadata.
+    pub fn block_count(&self) -> u64 {
+        self.metadata.count_data_blocks
+    }
+
+    /// The offset of the `n`-th data block in the file, read from the index block.
+    pub fn block_offset(&self, n: u64) -> Result<u64, Error> {
+        let index_iter = self.index_entry(n)?;
+        let (_key, val) = index_iter.get().ok_or(MtblError::InvalidIndexEntry)?;
+        let mut offset = 0;
+        varint_decode64(val, &mut offset);
+        Ok(offset)
+    }
 
-        let block = Block::init(data).ok_or(MtblError::InvalidBlock)?;
+    /// Returns an iterator over the entries of the `n`-th data block only.
+    pub fn iter_block(&self, n: u64) -> Result<BlockScan<A>, Error> {
+        let index_iter = self.index_entry(n)?;
+        let block = self.block_at_index(&index_iter)?.ok_or(MtblError::InvalidIndexEntry)?;
+        Ok(BlockScan { iter: BlockIter::init(Arc::new(block)), started: false })
+    }
 
-        Ok(block)
+    /// The width of the restart points of the `n`-th data block, the block is decoded.
+    pub fn block_restart_width(&self, n: u64) -> Result<RestartWidth, Error> {
+        let index_iter = self.index_entry(n)?;
+        let block = self.block_at_index(&index_iter)?.ok_or(MtblError::InvalidIndexEntry)?;
+        Ok(block.restart_width())
     }
 
-    fn block_at_index(&self, index_iter: &BlockIter<A>) -> Result<Option<Block<A>>, Error> {
+    /// Returns an iterator on the `n`-th entry of the index block.
+    fn index_entry(&self, n: u64) -> Result<IndexIter<A>, Error> {
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek_to_first()?;
+        for _ in 0..n {
+            if !index_iter.next()? {
+                break;
+            }
+        }
+
         match index_iter.get() {
-            Some((_key, val)) => {
-                let mut offset = 0;
-                varint_decode64(val, &mut offset);
-                self.block(offset as usize).map(Some)
-            },
-            None => Ok(None),
+            Some(_) => Ok(index_iter),
+            None => Err(Error::from(MtblError::InvalidIndexEntry)),
         }
     }
-}
 
-pub struct ReaderIntoGet<A> {
-    block: Arc<Block<A>>,
-    val_offset: usize,
-    val_len: usize,
-}
+    /// Returns the key as stored in the file, see `ReaderBuilder::key_transform`.
+    pub(crate) fn transform_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match &self.key_transform {
+            Some(transform) => (transform.0)(key),
+            None => Cow::Borrowed(key),
+        }
+    }
 
-impl<A> ReaderIntoGet<A> {
-    fn new(block_iter: BlockIter<A>) -> Option<ReaderIntoGet<A>> {
-        let (offset, length) = block_iter.val?;
-        Some(ReaderIntoGet {
-            block: block_iter.block,
-            val_offset: offset,
-            val_len: length,
-        })
+    pub(crate) fn index(&self) -> &Arc<Index<A>> {
+        &self.index
     }
-}
 
-impl<A: AsRef<[u8]>> AsRef<[u8]> for ReaderIntoGet<A> {
-    fn as_ref(&self) -> &[u8] {
-        &(*self.block).as_ref()[self.val_offset..self.val_offset + self.val_len]
+    #[cfg(feature = "std")]
+    pub(crate) fn data(&self) -> &BytesView<A> {
+        &self.data
     }
-}
 
-enum ReaderIterType {
-    Iter,
-    Get,
-    GetPrefix,
-    GetRange,
-}
+    /// Whether the key is outside of the keys of the file, no block needs to be read then.
+    /// The keys before the first one are only detected when it is known, see `first_key`.
+    pub(crate) fn out_of_bounds(&self, key: &[u8]) -> bool {
+        self.first_key.as_deref().is_some_and(|first| key < first)
+            || self.last_key.as_deref().is_some_and(|last| key > last)
+    }
 
-pub struct ReaderIntoIter<A> {
-    r: Reader<A>,
-    block_offset: u64,
-    bi: Option<BlockIter<A>>,
-    index_iter: BlockIter<A>,
-    k: Vec<u8>,
-    first: bool,
-    valid: bool,
-    it_type: ReaderIterType,
-}
+    /// Decodes the first and the last data blocks, with their offsets.
+    fn read_prewarmed_blocks(&self) -> Result<PrewarmedBlocks<A>, Error> {
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek_to_first()?;
+        let first = self.block_offset_at(&index_iter);
+        index_iter.seek_to_last()?;
+        let last = self.block_offset_at(&index_iter).filter(|&offset| Some(offset) != first);
 
-impl<A: AsRef<[u8]>> ReaderIntoIter<A> {
-    fn new(r: Reader<A>) -> Result<ReaderIntoIter<A>, Error> {
-        let mut index_iter = BlockIter::init(r.index.clone());
-        index_iter.seek_to_first();
+        let mut blocks = Vec::new();
+        for offset in first.into_iter().chain(last) {
+            blocks.push((offset, self.block(offset)?));
+        }
+        Ok(Arc::from(blocks))
+    }
 
-        let bi = match r.block_at_index(&index_iter)? {
-            Some(b) => {
-                let mut bi = BlockIter::init(Arc::new(b));
-                bi.seek_to_first();
-                Some(bi)
-            },
-            None => None,
+    /// The first key of the first prewarmed block, the first key of the file.
+    fn prewarmed_first_key(&self) -> Result<Option<Arc<[u8]>>, Error> {
+        let block = match self.prewarmed_blocks.as_deref() {
+            Some([(_offset, block), ..]) => block.clone(),
+            _ => return Ok(None),
         };
+        let mut iter = BlockIter::init(Arc::new(block));
+        iter.seek_to_first()?;
+        Ok(iter.key().map(Arc::from))
+    }
 
-        Ok(ReaderIntoIter {
-            r,
-            block_offset: 0,
-            bi,
-            index_iter,
-            k: Vec::new(),
-            first: true,
-            valid: true,
-            it_type: ReaderIterType::Iter,
-        })
+    fn read_last_key(&self) -> Option<Arc<[u8]>> {
+        let mut index_iter = IndexIter::init(self.index.clone());
+        index_iter.seek_to_last().ok()?;
+        index_iter.key().map(Arc::from)
     }
 
-    fn new_from(r: Reader<A>, key: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
-        let mut index_iter = BlockIter::init(r.index.clone());
-        index_iter.seek(key);
+    /// What a background thread needs to decompress the blocks, see `ReaderBuilder::prefetch`.
+    #[cfg(feature = "std")]
+    pub(crate) fn decompressor(&self) -> Decompressor {
+        Decompressor {
+            compression: self.metadata.compression_algorithm,
+            dictionary: self.compression_dictionary.clone(),
+            max_size: self.max_decompressed_block_size,
+            pool: self.decompress_buffer_pool.as_ref().map(|pool| pool.0.clone()),
+        }
+    }
 
-        let bi = match r.block_at_index(&index_iter)? {
-            Some(b) => {
-                let mut bi = BlockIter::init(Arc::new(b));
-                bi.seek(key);
-                Some(bi)
-            },
-            None => None,
-        };
+    pub(crate) fn block_at_index(&self, index_iter: &IndexIter<A>) -> Result<Option<Block<A>>, Error> {
+        match self.block_offset_at(index_iter) {
+            Some(offset) => self.block(offset).map(Some),
+            None => Ok(None),
+        }
+    }
 
-        Ok(ReaderIntoIter {
-            r,
-            block_offset: 0,
+    /// The offset of the data block of the current entry of the index.
+    pub(crate) fn block_offset_at(&self, index_iter: &IndexIter<A>) -> Option<usize> {
+        index_iter.get().map(|(_key, val)| {
+            let mut offset = 0;
+            varint_decode64(val, &mut offset);
+            offset as usize
+        })
+    }
+}
+
+impl<A: AsRef<[u8]> + Send + Sync + 'static> Reader<A> {
+    /// Erases the backing type of this reader, readers of different
+    /// types can then be merged together. The data is not copied.
+    pub fn into_dyn(self) -> Reader<DynBytes> {
+        Reader {
+            metadata: self.metadata,
+            data: self.data.into_dyn(),
+            verify_checksums: self.verify_checksums,
+            compression_dictionary: self.compression_dictionary,
+            index: Arc::new(self.index.to_dyn()),
+            key_transform: self.key_transform,
+            first_key: self.first_key,
+            last_key: self.last_key,
+            max_decompressed_block_size: self.max_decompressed_block_size,
+            #[cfg(feature = "std")]
+            prefetch: self.prefetch,
+            prewarmed_blocks: self.prewarmed_blocks.map(|blocks| {
+                blocks.iter().map(|(offset, block)| (*offset, block.clone().into_dyn())).collect()
+            }),
+            decompress_buffer_pool: self.decompress_buffer_pool,
+        }
+    }
+}
+
+/// Decodes the block written at `offset`, the checksum is verified if a `ChecksumType` is given.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decode_block<A: AsRef<[u8]>>(
+    data: &BytesView<A>,
+    offset: usize,
+    file_version: FileVersion,
+    checksum_type: Option<ChecksumType>,
+    compression: CompressionType,
+    dictionary: Option<&[u8]>,
+    max_size: usize,
+    pool: Option<&Arc<dyn BufferPool>>,
+) -> Result<Block<A>, Error>
+{
+    let (raw_start, raw_contents_size) = raw_block_bounds(data.as_ref(), offset, file_version, checksum_type)?;
+    let raw_contents = &data.as_ref()[raw_start..raw_start + raw_contents_size];
+
+    let data = match pool {
+        #[cfg(feature = "std")]
+        Some(pool) if compression != CompressionType::None => {
+            let mut buffer = pool.take();
+            if let Err(e) = decompress_into(compression, dictionary, raw_contents, max_size, &mut buffer) {
+                pool.recycle(buffer);
+                return Err(Error::from(e));
+            }
+            BytesView::from_pooled(buffer, pool.clone())
+        },
+        _ => match decompress(compression, dictionary, raw_contents, max_size)? {
+            Cow::Borrowed(_) => data.slice(raw_start, raw_contents_size),
+            Cow::Owned(bytes) => BytesView::from_bytes(bytes),
+        },
+    };
+
+    let block = Block::init(data)?;
+
+    Ok(block)
+}
+
+/// Returns the start and the length of the still compressed contents of the block
+/// written at `offset`, the checksum is verified if a `ChecksumType` is given.
+/// The bytes can come from a remote storage, a block that does not fit in them is invalid.
+fn raw_block_bounds(
+    data: &[u8],
+    offset: usize,
+    file_version: FileVersion,
+    checksum_type: Option<ChecksumType>,
+) -> Result<(usize, usize), Error>
+{
+    let header = data.get(offset..).filter(|header| !header.is_empty()).ok_or(MtblError::InvalidBlock)?;
+
+    let raw_contents_size_len: usize;
+    let raw_contents_size: usize;
+
+    if file_version == FileVersion::FormatV1 {
+        raw_contents_size_len = mem::size_of::<u32>();
+        let size = header.get(..raw_contents_size_len).ok_or(MtblError::InvalidBlock)?;
+        raw_contents_size = LittleEndian::read_u32(size) as usize;
+    } else {
+        let mut tmp = 0;
+        raw_contents_size_len = varint_decode64(header, &mut tmp);
+        raw_contents_size = tmp as usize;
+        if raw_contents_size_len == 0 || raw_contents_size as u64 != tmp {
+            return Err(Error::from(MtblError::InvalidBlock));
+        }
+    }
+
+    let block_crc = header.get(raw_contents_size_len..raw_contents_size_len + mem::size_of::<u32>());
+    let block_crc = block_crc.ok_or(MtblError::InvalidBlock)?;
+    let raw_start = offset + raw_contents_size_len + mem::size_of::<u32>();
+    let raw_contents = data[raw_start..].get(..raw_contents_size).ok_or(MtblError::InvalidBlock)?;
+
+    if let Some(checksum_type) = checksum_type {
+        verify_checksum(checksum_type, LittleEndian::read_u32(block_crc), raw_contents)?;
+    }
+
+    Ok((raw_start, raw_contents_size))
+}
+
+pub(crate) fn verify_checksum(type_: ChecksumType, expected: u32, data: &[u8]) -> Result<(), Error> {
+    match checksum(type_, data) {
+        Some(crc) if crc != expected => Err(Error::from(MtblError::ChecksumMismatch)),
+        _ => Ok(()),
+    }
+}
+
+/// The entries of a single data block, see `Reader::iter_block`.
+pub struct BlockScan<A> {
+    iter: BlockIter<A>,
+    started: bool,
+}
+
+impl<A: AsRef<[u8]>> BlockScan<A> {
+    /// Returns the next entry, an entry that cannot be decoded returns an error.
+    #[allow(clippy::should_implement_trait, clippy::type_complexity)]
+    pub fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
+        let moved = if self.started {
+            self.iter.next()
+        } else {
+            self.started = true;
+            self.iter.seek_to_first()
+        };
+        match moved {
+            Ok(_) => self.iter.get().map(Ok),
+            Err(e) => Some(Err(Error::from(e))),
+        }
+    }
+}
+
+impl<A: AsRef<[u8]>> IntoIterator for Reader<A> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+    type IntoIter = ReaderEntries<A>;
+
+    fn into_iter(self) -> ReaderEntries<A> {
+        ReaderEntries { reader: Some(self), iter: None }
+    }
+}
+
+impl<A: AsRef<[u8]>> IntoIterator for &Reader<A> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+    type IntoIter = ReaderEntries<A>;
+
+    fn into_iter(self) -> ReaderEntries<A> {
+        ReaderEntries { reader: Some(self.clone()), iter: None }
+    }
+}
+
+/// An iterator over the owned entries of a `Reader`, the first block is
+/// only read on the first call to `next`.
+pub struct ReaderEntries<A> {
+    reader: Option<Reader<A>>,
+    iter: Option<ReaderIntoIter<A>>,
+}
+
+impl<A: AsRef<[u8]>> Iterator for ReaderEntries<A> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(reader) = self.reader.take() {
+            match ReaderIntoIter::new(reader) {
+                Ok(iter) => self.iter = Some(iter),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let result = match self.iter.as_mut()?.next() {
+            Some(Ok((key, val))) => Some(Ok((key.to_vec(), val.to_vec()))),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        };
+
+        // stop at the end or on the first error
+        if !matches!(result, Some(Ok(_))) {
+            self.iter = None;
+        }
+
+        result
+    }
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// An iterator over the entries of a `Reader` as views that share the bytes
+/// of the blocks, they stay valid once the iterator is dropped.
+///
+/// The values are never copied, the keys are only copied when they share a prefix
+/// with the previous key, see `WriterBuilder::disable_prefix_compression`.
+/// A view of a compressed block keeps the whole decompressed block alive.
+pub struct ReaderViews<A> {
+    iter: ReaderIntoIter<A>,
+}
+
+impl<A: AsRef<[u8]>> Iterator for ReaderViews<A> {
+    type Item = Result<(BytesView<A>, BytesView<A>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next()? {
+            Ok(_) => (),
+            Err(e) => return Some(Err(e)),
+        }
+
+        let bi = self.iter.bi.as_ref()?;
+        Some(Ok((bi.key_view()?, bi.val_view()?)))
+    }
+}
+
+/// An iterator over the owned entries of a `Reader` in both directions,
+/// it stops when the front and the back meet. See `Reader::cursor` to move
+/// back and forth over the entries of a borrowed reader instead.
+pub struct ReaderIntoDoubleEndedIter<A> {
+    front: ReaderIntoIter<A>,
+    back_index: IndexIter<A>,
+    /// The block of the last entry returned by `next_back`, if any.
+    back: Option<BlockIter<A>>,
+    front_key: Option<Vec<u8>>,
+    back_key: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<A: AsRef<[u8]>> ReaderIntoDoubleEndedIter<A> {
+    fn prev_entry(&mut self) -> Result<Option<Entry>, Error> {
+        match self.back.as_mut() {
+            Some(bi) => {
+                if bi.prev()? {
+                    return Ok(bi.get().map(|(k, v)| (k.to_vec(), v.to_vec())));
+                }
+                self.back_index.prev()?;
+            },
+            None => { self.back_index.seek_to_last()?; },
+        }
+
+        while let Some(b) = self.front.r.block_at_index(&self.back_index)? {
+            let mut bi = BlockIter::init(Arc::new(b));
+            bi.seek_to_last()?;
+            if let Some((key, val)) = bi.get() {
+                let entry = (key.to_vec(), val.to_vec());
+                self.back = Some(bi);
+                return Ok(Some(entry));
+            }
+            self.back_index.prev()?;
+        }
+
+        Ok(None)
+    }
+}
+
+impl<A: AsRef<[u8]>> Iterator for ReaderIntoDoubleEndedIter<A> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.front.next() {
+            Some(Ok((key, val))) if self.back_key.as_deref().is_none_or(|back| key < back) => {
+                self.front_key = Some(key.to_vec());
+                Some(Ok((key.to_vec(), val.to_vec())))
+            },
+            Some(Err(e)) => {
+                self.done = true;
+                Some(Err(e))
+            },
+            _ => {
+                self.done = true;
+                None
+            },
+        }
+    }
+}
+
+impl<A: AsRef<[u8]>> DoubleEndedIterator for ReaderIntoDoubleEndedIter<A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.prev_entry() {
+            Ok(Some((key, val))) if self.front_key.as_deref().is_none_or(|front| key.as_slice() > front) => {
+                self.back_key = Some(key.clone());
+                Some(Ok((key, val)))
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+            _ => {
+                self.done = true;
+                None
+            },
+        }
+    }
+}
+
+/// An iterator over the owned entries whose keys start with a prefix, from
+/// the greatest key to the smallest, see `Reader::iter_prefix_rev`.
+pub struct ReaderPrefixRev<A> {
+    r: Reader<A>,
+    index_iter: IndexIter<A>,
+    bi: Option<BlockIter<A>>,
+    prefix: Vec<u8>,
+    started: bool,
+    done: bool,
+}
+
+impl<A: AsRef<[u8]>> ReaderPrefixRev<A> {
+    fn new(r: Reader<A>, prefix: &[u8]) -> ReaderPrefixRev<A> {
+        let prefix = r.transform_key(prefix).into_owned();
+        let index_iter = IndexIter::init(r.index.clone());
+        ReaderPrefixRev { r, index_iter, bi: None, prefix, started: false, done: false }
+    }
+
+    /// Moves to the last key lower than the successor of the prefix,
+    /// or to the last key of the file when the prefix has no successor.
+    fn seek_last(&mut self) -> Result<(), Error> {
+        if let Some(successor) = prefix_successor(&self.prefix) {
+            self.index_iter.seek(&successor)?;
+            if let Some(b) = self.r.block_at_index(&self.index_iter)? {
+                let mut bi = BlockIter::init(Arc::new(b));
+                bi.seek(&successor)?;
+                if bi.get().is_some() { bi.prev()?; } else { bi.seek_to_last()?; }
+                if bi.get().is_some() {
+                    self.bi = Some(bi);
+                    return Ok(());
+                }
+                self.index_iter.prev()?;
+                return self.load_prev_block();
+            }
+        }
+
+        self.index_iter.seek_to_last()?;
+        self.load_prev_block()
+    }
+
+    fn prev(&mut self) -> Result<(), Error> {
+        if let Some(bi) = &mut self.bi {
+            if bi.prev()? {
+                return Ok(());
+            }
+        }
+        self.index_iter.prev()?;
+        self.load_prev_block()
+    }
+
+    /// Moves to the last entry of the block pointed by the index, skipping the empty blocks.
+    fn load_prev_block(&mut self) -> Result<(), Error> {
+        while let Some(b) = self.r.block_at_index(&self.index_iter)? {
+            let mut bi = BlockIter::init(Arc::new(b));
+            if bi.seek_to_last()? {
+                self.bi = Some(bi);
+                return Ok(());
+            }
+            self.index_iter.prev()?;
+        }
+
+        self.bi = None;
+        Ok(())
+    }
+}
+
+impl<A: AsRef<[u8]>> Iterator for ReaderPrefixRev<A> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = if self.started { self.prev() } else { self.seek_last() };
+        self.started = true;
+        if let Err(e) = result {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        match self.bi.as_ref().and_then(|bi| bi.get()) {
+            Some((key, val)) if key.starts_with(&self.prefix) => Some(Ok((key.to_vec(), val.to_vec()))),
+            _ => {
+                self.done = true;
+                None
+            },
+        }
+    }
+}
+
+/// The smallest key greater than all the keys that start with `prefix`,
+/// `None` when the prefix is empty or only made of `0xFF` bytes.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&byte| byte != 0xFF)? + 1;
+    let mut successor = prefix[..len].to_vec();
+    successor[len - 1] += 1;
+    Some(successor)
+}
+
+pub struct ReaderIntoGet<A> {
+    block: Arc<Block<A>>,
+    val_offset: usize,
+    val_len: usize,
+}
+
+impl<A> ReaderIntoGet<A> {
+    fn new(block_iter: BlockIter<A>) -> Option<ReaderIntoGet<A>> {
+        let (offset, length) = block_iter.val?;
+        Some(ReaderIntoGet {
+            block: block_iter.block,
+            val_offset: offset,
+            val_len: length,
+        })
+    }
+}
+
+impl<A: AsRef<[u8]>> AsRef<[u8]> for ReaderIntoGet<A> {
+    fn as_ref(&self) -> &[u8] {
+        &(*self.block).as_ref()[self.val_offset..self.val_offset + self.val_len]
+    }
+}
+
+enum ReaderIterType {
+    Iter,
+    Get,
+    GetPrefix,
+    GetRange,
+}
+
+pub struct ReaderIntoIter<A> {
+    r: Reader<A>,
+    /// The offset of the block of `bi`, `None` when it is not known.
+    block_offset: Option<u64>,
+    bi: Option<BlockIter<A>>,
+    index_iter: IndexIter<A>,
+    k: Vec<u8>,
+    first: bool,
+    valid: bool,
+    it_type: ReaderIterType,
+    key_filter: Option<KeyFilter>,
+    #[cfg(feature = "std")]
+    prefetcher: Option<Prefetcher<A>>,
+}
+
+impl<A: AsRef<[u8]>> ReaderIntoIter<A> {
+    fn new(r: Reader<A>) -> Result<ReaderIntoIter<A>, Error> {
+        let mut index_iter = IndexIter::init(r.index.clone());
+        index_iter.seek_to_first()?;
+
+        let bi = match r.block_at_index(&index_iter)? {
+            Some(b) => {
+                let mut bi = BlockIter::init(Arc::new(b));
+                bi.seek_to_first()?;
+                Some(bi)
+            },
+            None => None,
+        };
+        let block_offset = r.block_offset_at(&index_iter).map(|offset| offset as u64);
+
+        Ok(ReaderIntoIter {
+            r,
+            block_offset,
+            bi,
+            index_iter,
+            k: Vec::new(),
+            first: true,
+            valid: true,
+            it_type: ReaderIterType::Iter,
+            key_filter: None,
+            #[cfg(feature = "std")]
+            prefetcher: None,
+        })
+    }
+
+    fn new_from(r: Reader<A>, key: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
+        let key = r.transform_key(key);
+        ReaderIntoIter::new_at(r, &key)
+    }
+
+    fn new_after(r: Reader<A>, key: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
+        let key = r.transform_key(key);
+        let mut iter = ReaderIntoIter::new_at(r, &key)?;
+        // the first call to next moves past an exact match
+        if iter.bi.as_ref().and_then(BlockIter::key) == Some(&key[..]) {
+            iter.first = false;
+        }
+        Ok(iter)
+    }
+
+    /// Like `new_from` but the key is not transformed.
+    fn new_at(r: Reader<A>, key: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
+        let mut index_iter = IndexIter::init(r.index.clone());
+        index_iter.seek(key)?;
+
+        let bi = match r.block_at_index(&index_iter)? {
+            Some(b) => {
+                let mut bi = BlockIter::init(Arc::new(b));
+                bi.seek(key)?;
+                Some(bi)
+            },
+            None => None,
+        };
+        let block_offset = r.block_offset_at(&index_iter).map(|offset| offset as u64);
+
+        Ok(ReaderIntoIter {
+            r,
+            block_offset,
             bi,
             index_iter,
             k: Vec::new(),
             first: true,
             valid: true,
             it_type: ReaderIterType::Iter,
+            key_filter: None,
+            #[cfg(feature = "std")]
+            prefetcher: None,
         })
     }
 
     fn new_get(r: Reader<A>, key: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
-        let mut iter = ReaderIntoIter::new_from(r, key)?;
-        iter.k.extend_from_slice(key);
+        let key = r.transform_key(key);
+        let mut iter = ReaderIntoIter::new_at(r, &key)?;
+        iter.k.extend_from_slice(&key);
         iter.it_type = ReaderIterType::Get;
         Ok(iter)
     }
 
     fn new_get_prefix(r: Reader<A>, prefix: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
-        let mut iter = ReaderIntoIter::new_from(r, prefix)?;
-        iter.k.extend_from_slice(prefix);
+        let prefix = r.transform_key(prefix);
+        let mut iter = ReaderIntoIter::new_at(r, &prefix)?;
+        iter.k.extend_from_slice(&prefix);
         iter.it_type = ReaderIterType::GetPrefix;
         Ok(iter)
     }
 
     fn new_get_range(r: Reader<A>, start: &[u8], end: &[u8]) -> Result<ReaderIntoIter<A>, Error> {
+        let end = r.transform_key(end).into_owned();
         let mut iter = ReaderIntoIter::new_from(r, start)?;
-        iter.k.extend_from_slice(end);
+        iter.k = end;
         iter.it_type = ReaderIterType::GetRange;
         Ok(iter)
     }
 
+    /// Only the entries for which the filter returns `true` are returned by `next`,
+    /// the iteration bounds (prefix, range) are still checked against every key.
+    pub fn filter_keys(mut self, filter: KeyFilter) -> Self {
+        self.key_filter = Some(filter);
+        self
+    }
+
     pub fn seek(&mut self, key: &[u8]) -> Result<bool, Error> {
-        self.index_iter.seek(key);
+        // the prefetched blocks are the ones following the current block
+        #[cfg(feature = "std")] {
+            self.prefetcher = None;
+        }
 
-        let (key, val) = match self.index_iter.get() {
-            Some((key, val)) => (key, val),
+        let key = self.r.transform_key(key);
+        self.index_iter.seek(&key)?;
+
+        let val = match self.index_iter.get() {
+            Some((_index_key, val)) => val,
             None => {
                 // This seek puts us after the last key, so we mark the
                 // iterator as invalid and return success. The next
@@ -318,14 +1392,14 @@ impl<A: AsRef<[u8]>> ReaderIntoIter<A> {
 
         // We can skip decoding a new block if our new key is within the
         // currently-decoded block.
-        if self.block_offset != new_offset {
-            self.block_offset = new_offset;
+        if self.block_offset != Some(new_offset) {
             let b = self.r.block(new_offset as usize)?;
             self.bi = Some(BlockIter::init(Arc::new(b)));
+            self.block_offset = Some(new_offset);
         }
 
         if let Some(bi) = self.bi.as_mut() {
-            bi.seek(key);
+            bi.seek(&key)?;
         }
 
         self.first = true;
@@ -334,73 +1408,1402 @@ impl<A: AsRef<[u8]>> ReaderIntoIter<A> {
         return Ok(true);
     }
 
-    pub fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
-        if !self.valid {
-            return None;
-        }
+    /// Returns the offset of the block being read and the size of the data blocks,
+    /// the offset reaches this size once the iteration is over. It can be used to
+    /// render the progress of a scan.
+    pub fn progress(&self) -> (u64, u64) {
+        let total = self.r.metadata.index_start();
+        let current = match self.index_iter.get() {
+            Some((_key, val)) if self.valid => {
+                let mut offset = 0;
+                varint_decode64(val, &mut offset);
+                offset
+            },
+            _ => total,
+        };
+        (current, total)
+    }
 
-        let bi = self.bi.as_mut()?;
+    /// The entry returned by the last call to `next`.
+    pub(crate) fn current(&self) -> Option<(&[u8], &[u8])> {
+        self.bi.as_ref().and_then(|bi| bi.get())
+    }
 
-        if !self.first {
-            bi.next();
-        }
-        self.first = false;
+    /// A view of the value returned by the last call to `next`, it shares the block.
+    #[cfg(feature = "std")]
+    pub(crate) fn current_val_view(&self) -> Option<BytesView<A>> {
+        self.bi.as_ref().and_then(|bi| bi.val_view())
+    }
 
-        let (key, val) = match bi.get() {
-            Some((key, val)) => {
-                // This is a trick to make the compiler happy...
-                // https://github.com/rust-lang/rust/issues/47680
-                let key: &'static _ = unsafe { mem::transmute(key) };
-                let val: &'static _ = unsafe { mem::transmute(val) };
-                (key, val)
-            },
-            None => {
-                self.valid = false;
-                if !self.index_iter.next() {
-                    return None;
+    fn next_block(&mut self) -> Result<Option<Block<A>>, Error> {
+        #[cfg(feature = "std")] {
+            let prefetched = self.r.prefetch > 0
+                && self.r.metadata.compression_algorithm != CompressionType::None
+                && !matches!(self.it_type, ReaderIterType::Get);
+            if prefetched && self.prefetcher.is_none() {
+                if let Some((key, _)) = self.index_iter.get() {
+                    self.prefetcher = Some(Prefetcher::spawn(&self.r, key, self.r.prefetch));
                 }
-                match self.r.block_at_index(&self.index_iter) {
-                    Ok(Some(b)) => {
-                        self.bi = Some(BlockIter::init(Arc::new(b)));
-                        let bi = self.bi.as_mut().unwrap();
-                        bi.seek_to_first();
-
-                        let entry = bi.get();
-                        self.valid = entry.is_some();
+            }
+            if let Some(prefetcher) = &mut self.prefetcher {
+                return prefetcher.next_block(&self.r);
+            }
+        }
+        self.r.block_at_index(&self.index_iter)
+    }
 
-                        entry?
-                    },
-                    Ok(None) => {
-                        self.valid = false;
-                        return None;
-                    },
-                    Err(e) => {
-                        self.valid = false;
-                        return Some(Err(e))
-                    },
-                }
+    /// Returns the next entry, a block that cannot be read (e.g. a corrupted one)
+    /// returns an error and ends the iteration, a `seek` can resume it.
+    pub fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
+        loop {
+            if !self.valid {
+                return None;
             }
-        };
 
-        match self.it_type {
-            ReaderIterType::Iter => (),
-            ReaderIterType::Get => {
-                if key != self.k.as_slice() {
+            let bi = self.bi.as_mut()?;
+
+            if !self.first {
+                if let Err(e) = bi.next() {
                     self.valid = false;
+                    return Some(Err(Error::from(e)));
                 }
             }
-            ReaderIterType::GetPrefix => {
-                if !(self.k.len() <= key.len() && key.starts_with(&self.k)) {
+            self.first = false;
+
+            // The entry is only borrowed to be checked here, it is borrowed again to be
+            // returned below, the borrow checker cannot track a borrow that is returned
+            // in one branch of the loop while the block is replaced in another.
+            let accepted = match bi.get() {
+                Some((key, _val)) => {
+                    let in_bounds = match self.it_type {
+                        ReaderIterType::Iter => true,
+                        ReaderIterType::Get => key == self.k.as_slice(),
+                        ReaderIterType::GetPrefix => key.starts_with(&self.k),
+                        ReaderIterType::GetRange => key <= self.k.as_slice(),
+                    };
+                    if !in_bounds {
+                        self.valid = false;
+                        return None;
+                    }
+                    self.key_filter.as_ref().is_none_or(|filter| filter(key))
+                },
+                None => {
                     self.valid = false;
+                    match self.index_iter.next() {
+                        Ok(true) => (),
+                        Ok(false) => return None,
+                        Err(e) => return Some(Err(Error::from(e))),
+                    }
+                    match self.next_block() {
+                        Ok(Some(b)) => {
+                            // The first entry of the new block is read by the next loop.
+                            let mut bi = BlockIter::init(Arc::new(b));
+                            if let Err(e) = bi.seek_to_first() {
+                                return Some(Err(Error::from(e)));
+                            }
+                            self.bi = Some(bi);
+                            self.block_offset = self.r.block_offset_at(&self.index_iter).map(|offset| offset as u64);
+                            self.first = true;
+                            self.valid = true;
+                            continue;
+                        },
+                        Ok(None) => {
+                            self.valid = false;
+                            return None;
+                        },
+                        Err(e) => {
+                            self.valid = false;
+                            return Some(Err(e))
+                        },
+                    }
                 }
+            };
+
+            if accepted {
+                return self.bi.as_ref().and_then(|bi| bi.get()).map(Ok);
             }
-            ReaderIterType::GetRange => {
-                if key > self.k.as_slice() {
-                    self.valid = false;
-                }
+        }
+    }
+
+    /// Turns this iterator into one that returns the entries as views of the blocks,
+    /// which can be kept once the iteration is over, see `ReaderViews`.
+    pub fn into_views(self) -> ReaderViews<A> {
+        ReaderViews { iter: self }
+    }
+
+    /// Collects at most the `n` next entries into an owned batch, e.g. to send them
+    /// to another thread at once, `None` once the iteration is over. The last batch
+    /// can be shorter, a batch holds at least one entry.
+    pub fn next_chunk(&mut self, n: usize) -> Option<Result<Vec<Entry>, Error>> {
+        let n = n.max(1);
+        let mut chunk = Vec::new();
+        while chunk.len() < n {
+            match self.next() {
+                Some(Ok((key, val))) => chunk.push((key.to_vec(), val.to_vec())),
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+
+        if chunk.is_empty() { None } else { Some(Ok(chunk)) }
+    }
+
+    /// Collects the next entries while their keys and values fit in `max_bytes`, e.g.
+    /// to scan in bounded slices and yield to other tasks in between, `None` once the
+    /// iteration is over. A batch only exceeds `max_bytes` when its single entry does.
+    pub fn next_bounded(&mut self, max_bytes: usize) -> Option<Result<Vec<Entry>, Error>> {
+        let mut batch = Vec::new();
+        let mut bytes = 0;
+        loop {
+            let fits = match self.next() {
+                Some(Ok((key, val))) => {
+                    let size = key.len() + val.len();
+                    let fits = batch.is_empty() || bytes + size <= max_bytes;
+                    if fits {
+                        bytes += size;
+                        batch.push((key.to_vec(), val.to_vec()));
+                    }
+                    fits
+                },
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            };
+
+            if !fits {
+                // the iterator stays on this entry, it starts the next batch
+                self.first = true;
+                break;
+            }
+            if bytes >= max_bytes {
+                break;
+            }
+        }
+
+        if batch.is_empty() { None } else { Some(Ok(batch)) }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::WriterBuilder;
+
+    /// The big-endian numbers below 1000 with their decimal strings as values.
+    fn numbers_table(builder: &mut WriterBuilder) -> Vec<u8> {
+        let mut writer = builder.memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn numbers_reader(block_size: usize) -> Reader<Vec<u8>> {
+        Reader::new(numbers_table(WriterBuilder::new().block_size(block_size as u64))).unwrap()
+    }
+
+    #[test]
+    fn for_loop() {
+        let reader = numbers_reader(1024);
+
+        let mut count = 0u32;
+        for result in &reader {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+
+        let entries: Vec<_> = IntoIterator::into_iter(reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries.len(), 1000);
+        assert_eq!(entries[999], (999u32.to_be_bytes().to_vec(), b"999".to_vec()));
+    }
+
+    fn zstd_table(count: u32) -> Vec<u8> {
+        let mut writer = WriterBuilder::new()
+            .compression_type(crate::CompressionType::Zstd)
+            .block_size(4096)
+            .memory();
+        for i in 0..count {
+            writer.insert(i.to_be_bytes(), format!("{:010}", i).repeat(8)).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn collect(mut iter: ReaderIntoIter<Vec<u8>>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            entries.push((key.to_vec(), val.to_vec()));
+        }
+        entries
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn prefetch() {
+        let vec = zstd_table(10_000);
+        let serial = collect(Reader::new(vec.clone()).unwrap().into_iter().unwrap());
+
+        // a backing type that cannot be sent to the thread
+        struct Local(std::rc::Rc<Vec<u8>>);
+
+        impl AsRef<[u8]> for Local {
+            fn as_ref(&self) -> &[u8] { &self.0 }
+        }
+
+        for depth in [0, 1, 4] {
+            let reader = ReaderBuilder::new().prefetch(depth).read(vec.clone()).unwrap();
+            assert!(reader.metadata().count_data_blocks > 10);
+            assert_eq!(collect(reader.clone().into_iter().unwrap()), serial);
+            assert_eq!(IntoIterator::into_iter(&reader).collect::<Result<Vec<_>, _>>().unwrap(), serial);
+
+            let reader = ReaderBuilder::new().prefetch(depth).read(Local(std::rc::Rc::new(vec.clone()))).unwrap();
+            let entries: Vec<_> = IntoIterator::into_iter(reader).collect::<Result<_, _>>().unwrap();
+            assert_eq!(entries, serial);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn index_compression() {
+        let write = |compression| {
+            let mut writer = WriterBuilder::new().block_size(64).index_compression_type(compression).memory();
+            for i in 0..20_000u32 {
+                let key = format!("a/long/and/repetitive/path/to/the/key/{:010}", i);
+                writer.insert(key, i.to_string()).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let plain = Reader::new(write(CompressionType::None)).unwrap();
+        let reader = Reader::new(write(CompressionType::Zstd)).unwrap();
+        assert_eq!(plain.metadata().index_compression_algorithm, CompressionType::None);
+        assert_eq!(reader.metadata().index_compression_algorithm, CompressionType::Zstd);
+        // the readers unaware of the compressed index refuse the file
+        assert_eq!(plain.metadata().file_version, FileVersion::FormatV2);
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV3);
+        assert!(reader.metadata().count_data_blocks > 1000);
+        assert!(reader.metadata().bytes_index_block * 2 < plain.metadata().bytes_index_block);
+
+        assert_eq!(collect(reader.clone().into_iter().unwrap()), collect(plain.into_iter().unwrap()));
+        for i in (0..20_000u32).step_by(97) {
+            let key = format!("a/long/and/repetitive/path/to/the/key/{:010}", i);
+            assert_eq!(reader.get_owned(key.as_bytes()).unwrap(), Some(i.to_string().into_bytes()));
+        }
+        assert_eq!(reader.get_owned(b"b").unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn prefetch_stopped_early() {
+        struct Pool;
+
+        impl BufferPool for Pool {
+            fn take(&self) -> Vec<u8> { Vec::new() }
+            fn recycle(&self, _buffer: Vec<u8>) {}
+        }
+
+        let pool: Arc<dyn BufferPool> = Arc::new(Pool);
+        let reader = ReaderBuilder::new()
+            .prefetch(2)
+            .decompress_buffer_pool(pool.clone())
+            .read(zstd_table(10_000))
+            .unwrap();
+
+        // past the first blocks
+        let mut iter = reader.into_iter().unwrap();
+        for _ in 0..500 {
+            iter.next().unwrap().unwrap();
+        }
+
+        // the thread is joined and has released its pool
+        drop(iter);
+        assert_eq!(Arc::strong_count(&pool), 1);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "zstd")]
+    fn prefetch_throughput() {
+        use std::time::Instant;
+
+        let vec = zstd_table(500_000);
+        // simulates a consumer doing some work with the entries
+        let consume = |mut iter: ReaderIntoIter<Vec<u8>>| {
+            let mut sum = 0u64;
+            while let Some(result) = iter.next() {
+                let (_, val) = result.unwrap();
+                sum = val.iter().fold(sum, |s, b| s.wrapping_mul(31).wrapping_add(*b as u64));
+            }
+            sum
+        };
+
+        let before = Instant::now();
+        let serial = consume(Reader::new(vec.clone()).unwrap().into_iter().unwrap());
+        let serial_time = before.elapsed();
+
+        let before = Instant::now();
+        let reader = ReaderBuilder::new().prefetch(4).read(vec).unwrap();
+        let prefetched = consume(reader.into_iter().unwrap());
+        let prefetch_time = before.elapsed();
+
+        assert_eq!(serial, prefetched);
+
+        // the decompression can only overlap the consumption with more than one core
+        if std::thread::available_parallelism().is_ok_and(|n| n.get() > 1) {
+            assert!(
+                prefetch_time < serial_time,
+                "serial scan: {:.2?}, prefetched scan: {:.2?}",
+                serial_time,
+                prefetch_time,
+            );
+        }
+    }
+
+    #[test]
+    fn contains_key() {
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for i in (0..1000u32).filter(|i| i % 2 == 0) {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 1);
+
+        for i in 0..1000u32 {
+            assert_eq!(reader.contains_key(&i.to_be_bytes()).unwrap(), i % 2 == 0);
+        }
+        assert!(!reader.contains_key(&[]).unwrap());
+        assert!(!reader.contains_key(&1000u32.to_be_bytes()).unwrap());
+        assert!(!reader.contains_key(&[0xff; 8]).unwrap());
+
+        let empty = Reader::new(WriterBuilder::new().memory().into_inner().unwrap()).unwrap();
+        assert!(!empty.contains_key(b"hello").unwrap());
+        assert!(!empty.contains_key(&[]).unwrap());
+    }
+
+    #[test]
+    #[cfg(all(feature = "snappy", feature = "zstd"))]
+    fn max_decompressed_block_size() {
+        let is_exceeded = |result: Result<_, Error>| matches!(result, Err(Error::Mtbl(MtblError::DecompressedSizeExceeded)));
+
+        let write = |compression| {
+            let mut writer = WriterBuilder::new().block_size(64 * 1024).compression_type(compression).memory();
+            writer.insert("hello", vec![0; 20_000]).unwrap();
+            writer.insert("world", vec![0; 20_000]).unwrap();
+            writer.into_inner().unwrap()
+        };
+
+        for compression in [CompressionType::Snappy, CompressionType::Zstd] {
+            let bytes = write(compression);
+            let reader = ReaderBuilder::new().max_decompressed_block_size(16 * 1024).read(bytes.as_slice()).unwrap();
+            assert!(is_exceeded(reader.get_owned(b"world").map(drop)));
+            let reader = ReaderBuilder::new().max_decompressed_block_size(1024 * 1024).read(bytes.as_slice()).unwrap();
+            assert_eq!(reader.get_owned(b"world").unwrap().unwrap().len(), 20_000);
+        }
+
+        // a snappy block claiming to be 2MB when decompressed, the length is checked before allocating
+        let mut bytes = write(CompressionType::Snappy);
+        let reader = Reader::new(bytes.as_slice()).unwrap();
+        let (_, raw) = reader.raw_block_at(0).unwrap();
+        let start = raw.as_ptr() as usize - bytes.as_ptr() as usize;
+        assert_eq!(raw[2] & 0x80, 0, "a three bytes varint");
+        bytes[start..start + 3].copy_from_slice(&[0xff, 0xff, 0x7f]);
+
+        let mut builder = ReaderBuilder::new();
+        builder.verify_checksums(false).max_decompressed_block_size(1024 * 1024);
+        let reader = builder.read(bytes.as_slice()).unwrap();
+        assert!(is_exceeded(reader.get_owned(b"hello").map(drop)));
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn out_of_bounds_lookups() {
+        use crate::compression::decompressions;
+
+        let write = |store_key_range| {
+            let mut writer = WriterBuilder::new()
+                .with_block_size(64)
+                .with_compression_type(CompressionType::Snappy)
+                .with_store_key_range(store_key_range)
+                .memory();
+            for i in 100..1000u32 {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        // without a key range only the last index entry bounds the keys, no block is read on open
+        let before = decompressions();
+        let reader = Reader::new(write(false)).unwrap();
+        assert_eq!(decompressions(), before);
+        for i in 1000..1100u32 {
+            assert!(!reader.contains_key(&i.to_be_bytes()).unwrap());
+        }
+        assert_eq!(decompressions(), before);
+
+        assert!(!reader.out_of_bounds(&99u32.to_be_bytes()));
+
+        // the prewarmed first block bounds the smaller keys
+        let reader = ReaderBuilder::new().prewarm(true).read(write(false)).unwrap();
+        assert!(reader.out_of_bounds(&99u32.to_be_bytes()));
+        assert!(!reader.out_of_bounds(&100u32.to_be_bytes()));
+        assert!(!reader.contains_key(&99u32.to_be_bytes()).unwrap());
+
+        let reader = Reader::new(write(true)).unwrap();
+        assert!(reader.metadata().count_data_blocks > 1);
+
+        let before = decompressions();
+        for i in (0..100u32).chain(1000..1100) {
+            let key = i.to_be_bytes();
+            assert!(!reader.contains_key(&key).unwrap());
+            assert_eq!(reader.get_owned(&key).unwrap(), None);
+            assert!(reader.clone().get(&key).unwrap().is_none());
+        }
+        assert!(!reader.contains_key(&[]).unwrap());
+        assert!(!reader.contains_key(&[0xff; 8]).unwrap());
+        assert_eq!(decompressions(), before);
+
+        // the keys in the bounds are still found
+        assert!(reader.contains_key(&100u32.to_be_bytes()).unwrap());
+        assert!(reader.contains_key(&999u32.to_be_bytes()).unwrap());
+        assert_eq!(reader.clone().get(&999u32.to_be_bytes()).unwrap().unwrap().as_ref(), b"999");
+        assert!(decompressions() > before);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn prewarm() {
+        use crate::compression::decompressions;
+
+        let vec = numbers_table(WriterBuilder::new().block_size(64).compression_type(CompressionType::Snappy));
+
+        let reader = ReaderBuilder::new().prewarm(true).read(vec.as_slice()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 2);
+
+        // the first and the last blocks are already decoded
+        let before = decompressions();
+        assert_eq!(reader.get_owned(&0u32.to_be_bytes()).unwrap().unwrap(), b"0");
+        assert_eq!(reader.clone().get(&999u32.to_be_bytes()).unwrap().unwrap().as_ref(), b"999");
+        let mut iter = reader.clone().into_iter().unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().1, b"0");
+        assert_eq!(decompressions(), before);
+
+        assert_eq!(reader.get_owned(&500u32.to_be_bytes()).unwrap().unwrap(), b"500");
+        assert!(decompressions() > before);
+
+        // without it the first lookup decodes its block
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let before = decompressions();
+        assert_eq!(reader.get_owned(&0u32.to_be_bytes()).unwrap().unwrap(), b"0");
+        assert!(decompressions() > before);
+    }
+
+    #[test]
+    fn next_across_blocks() {
+        // small blocks so that most of the entries are at a block boundary, meant to also run under Miri
+        let mut writer = WriterBuilder::new().block_size(32).block_restart_interval(2).memory();
+        for i in 0..200u32 {
+            writer.insert(format!("{:02}/{:03}", i / 50, i), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 50);
+
+        let collect = |mut iter: ReaderIntoIter<_>| {
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                let key = String::from_utf8(key.to_vec()).unwrap();
+                assert_eq!(val, key[3..].parse::<u32>().unwrap().to_string().as_bytes());
+                entries.push(key);
+            }
+            // exhausted iterators stay exhausted
+            assert!(iter.next().is_none());
+            entries
+        };
+
+        let all = collect(reader.clone().into_iter().unwrap());
+        assert_eq!(all.len(), 200);
+        assert!(all.windows(2).all(|w| w[0] < w[1]));
+
+        let prefix = collect(reader.clone().iter_prefix(b"02/").unwrap());
+        assert_eq!(prefix, all[100..150]);
+
+        let range = collect(reader.clone().iter_range(b"01/060", b"03/160").unwrap());
+        assert_eq!(range, all[60..161]);
+
+        let filtered = collect(reader.clone().into_iter().unwrap().filter_keys(Box::new(|k| k.ends_with(b"7"))));
+        assert_eq!(filtered.len(), 20);
+
+        let got = reader.get(b"03/199").unwrap().unwrap();
+        assert_eq!(got.as_ref(), b"199");
+    }
+
+    #[test]
+    fn progress() {
+        let reader = numbers_reader(64);
+        let total = reader.metadata().index_block_offset;
+        let last_block = reader.block_offset(reader.block_count() - 1).unwrap();
+
+        let mut iter = reader.into_iter().unwrap();
+        assert_eq!(iter.progress(), (0, total));
+        let mut offsets = Vec::new();
+        while let Some(result) = iter.next() {
+            result.unwrap();
+            let (offset, size) = iter.progress();
+            assert_eq!(size, total);
+            offsets.push(offset);
+        }
+        assert!(offsets.windows(2).all(|w| w[0] <= w[1]));
+        assert!(offsets.windows(2).filter(|w| w[0] < w[1]).count() > 10);
+        assert_eq!(*offsets.last().unwrap(), last_block);
+        assert_eq!(iter.progress(), (total, total));
+    }
+
+    #[test]
+    fn get_owned() {
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for i in (0..1000u32).filter(|i| i % 2 == 0) {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+
+        let values: Vec<_> = {
+            let reader = Reader::new(bytes.as_slice()).unwrap();
+            (0..1000u32).map(|i| reader.get_owned(&i.to_be_bytes()).unwrap()).collect()
+        };
+        // the values outlive the reader and its bytes
+        drop(bytes);
+
+        for (i, value) in (0..1000u32).zip(values) {
+            let expected = if i % 2 == 0 { Some(i.to_string().into_bytes()) } else { None };
+            assert_eq!(value, expected);
+        }
+
+        let empty = Reader::new(WriterBuilder::new().memory().into_inner().unwrap()).unwrap();
+        assert_eq!(empty.get_owned(b"hello").unwrap(), None);
+    }
+
+    #[test]
+    fn double_ended_iter() {
+        let vec = numbers_table(WriterBuilder::new().block_size(64).block_restart_interval(3));
+        let reader = Reader::new(vec).unwrap();
+        assert!(reader.metadata().count_data_blocks > 10);
+
+        let forward: Vec<_> = reader.clone().into_double_ended_iter().unwrap().collect::<Result<_, _>>().unwrap();
+        let mut backward: Vec<_> = reader.clone().into_double_ended_iter().unwrap().rev().collect::<Result<_, _>>().unwrap();
+        assert_eq!(forward.len(), 1000);
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        // alternate both ends until they meet in the middle
+        for taken in [0, 1, 499, 500, 999, 1000] {
+            let mut iter = reader.clone().into_double_ended_iter().unwrap();
+            let mut front = Vec::new();
+            for _ in 0..taken {
+                front.push(iter.next().unwrap().unwrap());
+            }
+            let mut back = Vec::new();
+            while let Some(result) = iter.next_back() {
+                back.push(result.unwrap());
+            }
+            assert!(iter.next().is_none());
+            back.reverse();
+            front.extend(back);
+            assert_eq!(front, forward);
+        }
+
+        let mut iter = reader.into_double_ended_iter().unwrap();
+        let mut count = 0;
+        loop {
+            match (iter.next(), iter.next_back()) {
+                (Some(_), Some(_)) => count += 2,
+                (Some(_), None) | (None, Some(_)) => count += 1,
+                (None, None) => break,
+            }
+        }
+        assert_eq!(count, 1000);
+
+        let empty = Reader::new(WriterBuilder::new().memory().into_inner().unwrap()).unwrap();
+        assert!(empty.clone().into_double_ended_iter().unwrap().next().is_none());
+        assert!(empty.into_double_ended_iter().unwrap().next_back().is_none());
+    }
+
+    #[test]
+    fn debug() {
+        let mut writer = WriterBuilder::new().memory();
+        for i in 0..42u32 {
+            writer.insert(i.to_be_bytes(), "value").unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let debug = format!("{:?}", reader);
+        assert!(debug.contains("count_entries: 42"), "{}", debug);
+        assert!(debug.contains("verify_checksums: true"), "{}", debug);
+    }
+
+    #[test]
+    fn index_block_offset_bounds() {
+        use crate::FileVersion;
+
+        let set_index_block_offset = |vec: &mut Vec<u8>, offset: u64| {
+            let start = vec.len() - METADATA_SIZE;
+            LittleEndian::write_u64(&mut vec[start..], offset);
+            // like the files without a metadata checksum, to reach the bounds checks
+            LittleEndian::write_u64(&mut vec[start + 12 * 8..], 0);
+        };
+
+        for &(version, min_size) in &[(FileVersion::FormatV1, 16), (FileVersion::FormatV2, 13)] {
+            // an empty file only contains an empty index block, the smallest possible block
+            let mut vec = WriterBuilder::new().with_file_version(version).memory().into_inner().unwrap();
+            assert_eq!(vec.len(), METADATA_SIZE + min_size);
+            assert_eq!(min_block_size(version), min_size);
+            let reader = Reader::new(vec.as_slice()).unwrap();
+            assert!(reader.into_iter().unwrap().next().is_none());
+
+            set_index_block_offset(&mut vec, 1);
+            let result = Reader::new(vec.as_slice());
+            assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidIndexBlockOffset))));
+
+            // a tightly packed file with a single key
+            let mut writer = WriterBuilder::new().with_file_version(version).memory();
+            writer.insert("a", "").unwrap();
+            let mut vec = writer.into_inner().unwrap();
+            let index_block_offset = Reader::new(vec.as_slice()).unwrap().metadata().index_block_offset;
+            assert!(Reader::new(vec.as_slice()).unwrap().contains_key(b"a").unwrap());
+
+            set_index_block_offset(&mut vec, index_block_offset + 1);
+            let result = Reader::new(vec.as_slice());
+            assert!(matches!(result, Err(Error::Mtbl(_))));
+
+            let max = (vec.len() - METADATA_SIZE - min_size) as u64;
+            set_index_block_offset(&mut vec, max + 1);
+            let result = Reader::new(vec.as_slice());
+            assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidIndexBlockOffset))));
+        }
+
+        let mut vec = WriterBuilder::new().memory().into_inner().unwrap();
+        vec.drain(..1);
+        assert!(matches!(Reader::new(vec.as_slice()), Err(Error::Mtbl(MtblError::InvalidIndexBlockOffset))));
+    }
+
+    #[test]
+    fn prefix_scan() {
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for word in ["app", "apple", "application", "apply", "banana", "band", "can"] {
+            writer.insert(word, word.to_uppercase()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let keys = |prefix: &[u8], limit| -> Vec<Vec<u8>> {
+            reader.prefix_scan(prefix, limit).unwrap().into_iter().map(|(k, _)| k).collect()
+        };
+
+        assert_eq!(keys(b"app", 2), vec![b"app".to_vec(), b"apple".to_vec()]);
+        assert_eq!(keys(b"appl", 10), vec![b"apple".to_vec(), b"application".to_vec(), b"apply".to_vec()]);
+        assert_eq!(keys(b"app", 0), Vec::<Vec<u8>>::new());
+        assert_eq!(keys(b"cz", 10), Vec::<Vec<u8>>::new());
+        assert_eq!(keys(b"zzz", 10), Vec::<Vec<u8>>::new());
+        assert_eq!(reader.prefix_scan(b"can", 10).unwrap(), vec![(b"can".to_vec(), b"CAN".to_vec())]);
+
+        assert_eq!(reader.prefix_count(b"app").unwrap(), 4);
+        assert_eq!(reader.prefix_count(b"ban").unwrap(), 2);
+        assert_eq!(reader.prefix_count(b"band").unwrap(), 1);
+        assert_eq!(reader.prefix_count(b"b").unwrap(), 2);
+        assert_eq!(reader.prefix_count(b"ca").unwrap(), 1);
+        assert_eq!(reader.prefix_count(b"d").unwrap(), 0);
+        assert_eq!(reader.prefix_count(b"").unwrap(), 7);
+    }
+
+    #[test]
+    fn zeroed_index_block() {
+        let mut vec = WriterBuilder::new().memory().into_inner().unwrap();
+        assert!(Reader::new(vec.as_slice()).unwrap().into_iter().unwrap().next().is_none());
+
+        // the index block is right before the metadata
+        let end = vec.len() - METADATA_SIZE;
+        vec[end - 8..end].iter_mut().for_each(|b| *b = 0);
+        let result = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice());
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::InvalidBlock))));
+    }
+
+    #[test]
+    fn memory_footprint() {
+        let mut writer = WriterBuilder::new().block_size(256).memory();
+        for i in 0..10_000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let footprint = reader.memory_footprint();
+        let index_block = reader.metadata().bytes_index_block as usize;
+        // the index block is stored with its length and checksum
+        assert!(footprint <= index_block && footprint + 16 >= index_block, "{} {}", footprint, index_block);
+        assert!(footprint < reader.as_bytes().len() / 10);
+
+        // the prewarmed blocks are slices of the file
+        let prewarmed = ReaderBuilder::new().prewarm(true).read(reader.as_bytes()).unwrap();
+        assert_eq!(prewarmed.memory_footprint(), footprint);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn memory_footprint_prewarmed() {
+        let mut writer = WriterBuilder::new().compression_type(CompressionType::Snappy).block_size(256).memory();
+        for i in 0..10_000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+        let reader = Reader::new(bytes.as_slice()).unwrap();
+        let prewarmed = ReaderBuilder::new().prewarm(true).read(bytes.as_slice()).unwrap();
+
+        // the first and the last blocks are decompressed
+        let last = reader.block_count() - 1;
+        let blocks: usize = [0, last].iter()
+            .map(|&n| reader.block(reader.block_offset(n).unwrap() as usize).unwrap().as_ref().len())
+            .sum();
+        assert!(blocks > 0);
+        assert_eq!(prewarmed.memory_footprint(), reader.memory_footprint() + blocks);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn raw_block_at() {
+        let vec = numbers_table(WriterBuilder::new().compression_type(CompressionType::Snappy).block_size(256));
+        let reader = Reader::new(vec).unwrap();
+        let count = reader.metadata().count_data_blocks as usize;
+        assert!(count > 1);
+
+        let mut index_iter = IndexIter::init(reader.index().clone());
+        index_iter.seek_to_first().unwrap();
+        for i in 0..count {
+            let (type_, raw) = reader.raw_block_at(i).unwrap();
+            assert_eq!(type_, CompressionType::Snappy);
+            let block = reader.block_at_index(&index_iter).unwrap().unwrap();
+            assert_eq!(decompress(type_, None, raw, usize::MAX).unwrap().as_ref(), block.as_ref());
+            index_iter.next().unwrap();
+        }
+
+        assert!(matches!(reader.raw_block_at(count), Err(Error::Mtbl(MtblError::InvalidIndexEntry))));
+    }
+
+    #[test]
+    fn read_all_tables() {
+        let table = |range: std::ops::Range<u32>| {
+            let mut writer = WriterBuilder::new().block_size(256).memory();
+            for i in range {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let first = table(0..1000);
+        let second = table(500..600);
+        let mut data = first.clone();
+        data.extend_from_slice(&second);
+        data.extend_from_slice(&table(0..0));
+
+        let readers = Reader::read_all_tables(data.as_slice()).unwrap();
+        assert_eq!(readers.len(), 3);
+        assert_eq!(readers[0].as_bytes(), first.as_slice());
+        assert_eq!(readers[1].as_bytes(), second.as_slice());
+
+        let entries = |reader: &Reader<_>| IntoIterator::into_iter(reader).map(|r| r.unwrap().0).collect::<Vec<_>>();
+        let keys = |range: std::ops::Range<u32>| range.map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>();
+        assert_eq!(entries(&readers[0]), keys(0..1000));
+        assert_eq!(entries(&readers[1]), keys(500..600));
+        assert_eq!(entries(&readers[2]), keys(0..0));
+        assert!(readers[1].contains_key(&550u32.to_be_bytes()).unwrap());
+
+        // a single table
+        assert_eq!(Reader::read_all_tables(first.as_slice()).unwrap().len(), 1);
+
+        // garbage before the first table
+        let mut data = vec![0; 10];
+        data.extend_from_slice(&first);
+        assert!(Reader::read_all_tables(data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn iter_block() {
+        let reader = numbers_reader(256);
+        assert!(reader.block_count() > 10);
+
+        for n in 0..reader.block_count() {
+            assert_eq!(reader.block_restart_width(n).unwrap(), RestartWidth::Bits32);
+        }
+        assert!(reader.block_restart_width(reader.block_count()).is_err());
+
+        let mut entries = Vec::new();
+        let mut offsets = Vec::new();
+        for n in 0..reader.block_count() {
+            offsets.push(reader.block_offset(n).unwrap());
+            let mut scan = reader.iter_block(n).unwrap();
+            while let Some(result) = scan.next() {
+                let (key, val) = result.unwrap();
+                entries.push((key.to_vec(), val.to_vec()));
+            }
+            assert!(scan.next().is_none());
+        }
+        assert_eq!(offsets[0], 0);
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+
+        let expected: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, expected);
+
+        let count = reader.block_count();
+        assert!(matches!(reader.block_offset(count), Err(Error::Mtbl(MtblError::InvalidIndexEntry))));
+        assert!(matches!(reader.iter_block(count), Err(Error::Mtbl(MtblError::InvalidIndexEntry))));
+        assert!(reader.iter_block(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn key_transform() {
+        let lowercase: fn() -> KeyTransform = || Box::new(|key: &[u8]| {
+            if key.iter().any(u8::is_ascii_uppercase) { Cow::Owned(key.to_ascii_lowercase()) } else { Cow::Borrowed(key) }
+        });
+
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for word in &["apple", "banana", "cherry", "date", "elderberry", "fig", "grape"] {
+            writer.insert(word, word.to_uppercase()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert!(!reader.contains_key(b"CHERRY").unwrap());
+
+        let reader = ReaderBuilder::new().with_key_transform(lowercase()).read(vec.as_slice()).unwrap();
+        assert!(reader.contains_key(b"CHERRY").unwrap());
+        assert!(reader.contains_key(b"Fig").unwrap());
+        assert!(!reader.contains_key(b"Kiwi").unwrap());
+        assert_eq!(reader.clone().get(b"BaNaNa").unwrap().unwrap().as_ref(), b"BANANA");
+
+        let keys = |mut iter: ReaderIntoIter<&[u8]>| {
+            let mut keys = Vec::new();
+            while let Some(result) = iter.next() {
+                keys.push(String::from_utf8(result.unwrap().0.to_vec()).unwrap());
+            }
+            keys
+        };
+        assert_eq!(keys(reader.clone().iter_from(b"ELDER").unwrap()), ["elderberry", "fig", "grape"]);
+        assert_eq!(keys(reader.clone().iter_range(b"B", b"DATE").unwrap()), ["banana", "cherry", "date"]);
+        assert_eq!(keys(reader.clone().iter_prefix(b"Gr").unwrap()), ["grape"]);
+
+        let mut cursor = reader.cursor();
+        assert!(cursor.seek(b"DATE").unwrap());
+        assert_eq!(cursor.current(), Some((&b"date"[..], &b"DATE"[..])));
+    }
+
+    #[test]
+    fn filter_keys() {
+        let reader = numbers_reader(1024);
+
+        let mut iter = reader.clone().into_iter().unwrap().filter_keys(Box::new(|k| k[3] % 3 == 0));
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key[3] % 3, 0);
+            assert_eq!(val, u32::from_be_bytes([key[0], key[1], key[2], key[3]]).to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, (0..1000u32).filter(|i| (i & 0xff) % 3 == 0).count());
+
+        let end = 500u32.to_be_bytes();
+        let mut iter = reader.iter_range(&[], &end).unwrap().filter_keys(Box::new(|k| k[3] == 0));
+        let mut keys = Vec::new();
+        while let Some(result) = iter.next() {
+            keys.push(result.unwrap().0.to_vec());
+        }
+        assert_eq!(keys, vec![0u32.to_be_bytes().to_vec(), 256u32.to_be_bytes().to_vec()]);
+    }
+
+    #[test]
+    fn iter_after() {
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for i in (0..2000u32).step_by(2) {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let first_after = |start: u32| {
+            let mut iter = reader.clone().iter_after(&start.to_be_bytes()).unwrap();
+            iter.next().map(|result| {
+                let (key, val) = result.unwrap();
+                assert_eq!(val, u32::from_be_bytes([key[0], key[1], key[2], key[3]]).to_string().as_bytes());
+                u32::from_be_bytes([key[0], key[1], key[2], key[3]])
+            })
+        };
+
+        // resumes after a present key, at any position in the blocks, or after an absent one
+        for i in 0..1998 {
+            let expected = if i % 2 == 0 { i + 2 } else { i + 1 };
+            assert_eq!(first_after(i), Some(expected), "after {}", i);
+        }
+        assert_eq!(first_after(1998), None);
+        assert_eq!(first_after(5000), None);
+
+        let mut iter = reader.clone().iter_after(&[]).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, 0u32.to_be_bytes());
+
+        // the following keys are all returned
+        let mut iter = reader.iter_after(&1000u32.to_be_bytes()).unwrap();
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            result.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 499);
+    }
+
+    #[test]
+    fn next_chunk() {
+        let reader = numbers_reader(256);
+        let expected = reader.to_btreemap().unwrap().into_iter().collect::<Vec<_>>();
+
+        let mut iter = reader.clone().into_iter().unwrap();
+        let mut chunks = Vec::new();
+        while let Some(result) = iter.next_chunk(300) {
+            chunks.push(result.unwrap());
+        }
+        let lens: Vec<_> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(lens, [300, 300, 300, 100]);
+        assert_eq!(chunks.concat(), expected);
+        assert!(iter.next_chunk(300).is_none());
+
+        // the bounds of the iterator are respected
+        let mut iter = reader.iter_range(&10u32.to_be_bytes(), &14u32.to_be_bytes()).unwrap();
+        let chunk = iter.next_chunk(0).unwrap().unwrap();
+        assert_eq!(chunk, [(10u32.to_be_bytes().to_vec(), b"10".to_vec())]);
+        assert_eq!(iter.next_chunk(100).unwrap().unwrap().len(), 4);
+        assert!(iter.next_chunk(100).is_none());
+    }
+
+    #[test]
+    fn next_bounded() {
+        let mut writer = WriterBuilder::new().block_size(256).memory();
+        for i in 0..1000u32 {
+            // a few values bigger than the budget
+            let val = if i % 100 == 50 { vec![b'x'; 500] } else { i.to_string().into_bytes() };
+            writer.insert(i.to_be_bytes(), val).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let expected = reader.to_btreemap().unwrap().into_iter().collect::<Vec<_>>();
+
+        let size = |batch: &[Entry]| batch.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>();
+        let mut iter = reader.clone().into_iter().unwrap();
+        let mut batches = Vec::new();
+        while let Some(result) = iter.next_bounded(100) {
+            let batch = result.unwrap();
+            assert!(size(&batch) <= 100 || batch.len() == 1, "{:?}", batch);
+            batches.push(batch);
+        }
+        assert_eq!(batches.iter().filter(|b| size(b) > 100).count(), 10);
+        assert_eq!(batches.concat(), expected);
+        assert!(iter.next_bounded(100).is_none());
+
+        // the bounds of the iterator are respected
+        let mut iter = reader.iter_range(&10u32.to_be_bytes(), &14u32.to_be_bytes()).unwrap();
+        assert_eq!(iter.next_bounded(12).unwrap().unwrap().len(), 2);
+        assert_eq!(iter.next_bounded(0).unwrap().unwrap().len(), 1);
+        assert_eq!(iter.next_bounded(100).unwrap().unwrap().len(), 2);
+        assert!(iter.next_bounded(100).is_none());
+    }
+
+    #[test]
+    fn metadata_accessors() {
+        let reader = numbers_reader(256);
+
+        // the metadata is `Copy`, it can be kept once the reader is dropped
+        let metadata = *reader.metadata();
+        assert_eq!(&metadata, reader.metadata());
+        assert_eq!(reader.entry_count(), 1000);
+        assert_eq!(reader.block_count(), metadata.count_data_blocks);
+        assert_eq!(reader.total_key_bytes(), 4 * 1000);
+        assert_eq!(reader.total_value_bytes(), (0..1000u32).map(|i| i.to_string().len() as u64).sum::<u64>());
+        assert_eq!(reader.compression(), CompressionType::None);
+    }
+
+    #[test]
+    fn views() {
+        let write = |prefix_compression: bool, compression: CompressionType| {
+            let mut builder = WriterBuilder::new();
+            builder.block_size(512).compression_type(compression).dedup_repeated_values(true);
+            if !prefix_compression {
+                builder.disable_prefix_compression();
+            }
+            let mut writer = builder.memory();
+            for i in 0..1000u32 {
+                writer.insert(format!("key-{:04}", i), (i / 3).to_string()).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let mut types = vec![CompressionType::None];
+        if cfg!(feature = "snappy") {
+            types.push(CompressionType::Snappy);
+        }
+
+        for &compression in &types {
+            for &prefix_compression in &[true, false] {
+                let bytes = write(prefix_compression, compression);
+                let range = bytes.as_ptr_range();
+
+                let reader = Reader::new(bytes.as_slice()).unwrap();
+                let views: Vec<_> = reader.into_iter().unwrap().into_views().collect::<Result<_, _>>().unwrap();
+                assert_eq!(views.len(), 1000);
+
+                for (i, (key, val)) in views.iter().enumerate() {
+                    assert_eq!(key.as_ref(), format!("key-{:04}", i).as_bytes());
+                    assert_eq!(val.as_ref(), (i / 3).to_string().as_bytes());
+
+                    // the uncompressed blocks are not copied
+                    if compression == CompressionType::None {
+                        assert!(range.contains(&val.as_ref().as_ptr()));
+                        if !prefix_compression {
+                            assert!(range.contains(&key.as_ref().as_ptr()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn iter_prefix_rev() {
+        let mut keys = Vec::new();
+        for user in &["user:1:", "user:10:", "user:2:"] {
+            for timestamp in (0..300u64).map(|t| t * 1000) {
+                let mut key = user.as_bytes().to_vec();
+                key.extend_from_slice(&timestamp.to_be_bytes());
+                keys.push(key);
             }
         }
+        keys.push(vec![0xff]);
+        keys.push(vec![0xff, 0xff, 0]);
+        keys.push(vec![0xff, 0xff, 0xff]);
+        keys.sort();
+
+        let mut writer = WriterBuilder::new().block_size(256).memory();
+        for key in &keys {
+            writer.insert(key, key.len().to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let rev_keys = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            reader.clone().iter_prefix_rev(prefix).map(|r| r.unwrap().0).collect()
+        };
+        let expected = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            keys.iter().rev().filter(|k| k.starts_with(prefix)).cloned().collect()
+        };
+
+        // the most recent entry first, without the keys of "user:10:"
+        let user1 = rev_keys(b"user:1:");
+        assert_eq!(user1.len(), 300);
+        assert_eq!(&user1[0][7..], 299_000u64.to_be_bytes());
+        assert_eq!(user1, expected(b"user:1:"));
+
+        for prefix in &[&b"user:1"[..], b"user:2:", b"user:", b"user:3", b"a", b"zzz", b"", b"\xff", b"\xff\xff"] {
+            assert_eq!(rev_keys(prefix), expected(prefix), "{:?}", prefix);
+        }
+
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(b"\xff\xff"), None);
+        assert_eq!(prefix_successor(b""), None);
+    }
+
+    #[test]
+    fn validate_entry_count() {
+        use crate::ChecksumType;
+
+        let mut bytes = numbers_table(WriterBuilder::new().block_size(256).checksum_type(ChecksumType::None));
+        Reader::new(bytes.as_slice()).unwrap().validate_entry_count().unwrap();
+
+        // the fourth field of the metadata
+        let start = bytes.len() - METADATA_SIZE + 3 * 8;
+        bytes[start..start + 8].copy_from_slice(&1001u64.to_le_bytes());
+
+        let reader = Reader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.entry_count(), 1001);
+        match reader.validate_entry_count() {
+            Err(Error::Mtbl(MtblError::EntryCountMismatch { declared, found })) => assert_eq!((declared, found), (1001, 1000)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn decompress_buffer_pool() {
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct CountingPool {
+            buffers: Mutex<Vec<Vec<u8>>>,
+            allocated: AtomicUsize,
+            reused: AtomicUsize,
+        }
+
+        impl BufferPool for CountingPool {
+            fn take(&self) -> Vec<u8> {
+                match self.buffers.lock().unwrap().pop() {
+                    Some(buffer) => {
+                        self.reused.fetch_add(1, Ordering::Relaxed);
+                        buffer
+                    },
+                    None => {
+                        self.allocated.fetch_add(1, Ordering::Relaxed);
+                        Vec::new()
+                    },
+                }
+            }
+
+            fn recycle(&self, buffer: Vec<u8>) {
+                self.buffers.lock().unwrap().push(buffer);
+            }
+        }
+
+        let mut writer = WriterBuilder::new().block_size(512).compression_type(CompressionType::Snappy).memory();
+        for i in 0..10_000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+
+        let pool = Arc::new(CountingPool::default());
+        let reader = ReaderBuilder::new().decompress_buffer_pool(pool.clone()).read(bytes.as_slice()).unwrap();
+        assert!(reader.block_count() > 50);
+
+        let mut count = 0u32;
+        let mut iter = reader.clone().into_iter().unwrap();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
+        drop(iter);
+
+        // a block is dropped before the next one is decompressed
+        let allocated = pool.allocated.load(Ordering::Relaxed);
+        let reused = pool.reused.load(Ordering::Relaxed);
+        assert!(allocated <= 2, "{}", allocated);
+        assert_eq!(allocated + reused, reader.block_count() as usize);
+        assert_eq!(pool.buffers.lock().unwrap().len(), allocated);
+
+        // the views of an entry keep its buffer out of the pool
+        let mut iter = reader.into_iter().unwrap().into_views();
+        let (key, val) = iter.next().unwrap().unwrap();
+        drop(iter);
+        assert_eq!(pool.buffers.lock().unwrap().len(), allocated - 1);
+        assert_eq!(val.as_ref(), b"0");
+        drop((key, val));
+        assert_eq!(pool.buffers.lock().unwrap().len(), allocated);
+    }
+
+    #[test]
+    fn two_level_index() {
+        let build = |count: u32| {
+            let mut writer = WriterBuilder::new().block_size(64).two_level_index(true).memory();
+            for i in (0..count).map(|i| i * 2) {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        };
+
+        // an index smaller than a data block is kept whole
+        let reader = build(3);
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV3);
+        assert_eq!(reader.metadata().count_index_segments, 0);
+        assert!(!reader.index().is_two_level());
+
+        let reader = build(5000);
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV4);
+        assert!(reader.metadata().count_index_segments > 10);
+        assert!(reader.index().is_two_level());
+        reader.validate_entry_count().unwrap();
+
+        for i in 0..10_001u32 {
+            let val = reader.clone().get(&i.to_be_bytes()).unwrap();
+            let expected = if i % 2 == 0 && i < 10_000 { Some(i.to_string()) } else { None };
+            assert_eq!(val.as_ref().map(AsRef::as_ref), expected.as_ref().map(String::as_bytes));
+        }
+
+        let mut iter = reader.clone().iter_from(&1001u32.to_be_bytes()).unwrap();
+        let (key, _) = iter.next().unwrap().unwrap();
+        assert_eq!(key, 1002u32.to_be_bytes());
+        assert_eq!(reader.to_btreemap().unwrap().len(), 5000);
+
+        let entries: Vec<_> = reader.clone().into_double_ended_iter().unwrap().rev().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 5000);
+        assert_eq!(entries[0].0, 9998u32.to_be_bytes());
+
+        let mut cursor = reader.cursor();
+        assert!(cursor.seek(&5001u32.to_be_bytes()).unwrap());
+        for i in (0..=2501u32).rev().map(|i| i * 2) {
+            assert_eq!(cursor.current().unwrap().0, i.to_be_bytes());
+            assert_eq!(cursor.prev().unwrap(), i > 0);
+        }
+
+        // the segments are written between the data blocks and the top-level index
+        let last_block = reader.block_offset(reader.block_count() - 1).unwrap();
+        assert!(last_block < reader.metadata().index_start());
+        assert_eq!(reader.metadata().index_start(), reader.metadata().bytes_data_blocks);
+    }
+
+    #[test]
+    fn key_range() {
+        let write = |store: bool, keys: &[Vec<u8>]| {
+            let mut writer = WriterBuilder::new().block_size(128).store_key_range(store).memory();
+            for key in keys {
+                writer.insert(key, "value").unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let keys: Vec<_> = (0..1000u32).map(|i| format!("key-{:05}", i * 7).into_bytes()).collect();
+        let reader = Reader::new(write(true, &keys)).unwrap();
+        let first = reader.clone().into_iter().unwrap().next().unwrap().unwrap().0.to_vec();
+        let last = reader.clone().into_double_ended_iter().unwrap().next_back().unwrap().unwrap().0;
+        assert_eq!(reader.key_range(), Some((&first[..], &last[..])));
+        assert_eq!(reader.metadata().min_key(), Some(&b"key-00000"[..]));
+        assert_eq!(reader.metadata().max_key(), Some(&b"key-06993"[..]));
+        assert!(reader.clone().get(b"key-06994").unwrap().is_none());
+        assert!(reader.clone().get(b"key-06993").unwrap().is_some());
+
+        // absent from the files written without it or with keys too big to fit
+        assert_eq!(Reader::new(write(false, &keys)).unwrap().key_range(), None);
+        let big = vec![vec![0; 200], vec![1; 200]];
+        let reader = Reader::new(write(true, &big)).unwrap();
+        assert_eq!(reader.key_range(), None);
+        assert_eq!(reader.get_owned(&[1; 200]).unwrap(), Some(b"value".to_vec()));
+
+        // the range has its own checksum, the one of the metadata does not cover it
+        let mut vec = write(true, &keys);
+        let len = vec.len();
+        vec[len - METADATA_SIZE + 148] ^= 1;
+        #[cfg(feature = "checksum")]
+        assert!(matches!(Reader::new(vec.as_slice()), Err(Error::Mtbl(MtblError::ChecksumMismatch))));
+        let reader = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice()).unwrap();
+        assert_ne!(reader.metadata().min_key(), Some(&b"key-00000"[..]));
+    }
+
+    #[test]
+    fn corrupted_block_mid_scan() {
+        let mut vec = numbers_table(WriterBuilder::new().block_size(256));
+
+        // corrupts the third data block
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 4);
+        let (_, raw) = reader.raw_block_at(2).unwrap();
+        let middle = raw.as_ptr() as usize - vec.as_ptr() as usize + raw.len() / 2;
+        vec[middle] ^= 0xff;
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let mut iter = reader.into_iter().unwrap();
+        let mut keys = Vec::new();
+        let error = loop {
+            match iter.next() {
+                Some(Ok((key, _val))) => keys.push(key.to_vec()),
+                Some(Err(e)) => break e,
+                None => panic!("expected an error"),
+            }
+        };
+        assert!(matches!(error, Error::Mtbl(MtblError::ChecksumMismatch)), "{:?}", error);
+        assert!(!keys.is_empty());
+        assert!(keys.iter().zip(0u32..).all(|(key, i)| key[..] == i.to_be_bytes()));
+        assert!(iter.next().is_none());
+
+        // the iteration resumes before the corrupted block, or past it
+        iter.seek(&0u32.to_be_bytes()).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, 0u32.to_be_bytes());
+
+        iter.seek(&900u32.to_be_bytes()).unwrap();
+        let mut i = 900u32;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!((key, val), (&i.to_be_bytes()[..], i.to_string().as_bytes()));
+            i += 1;
+        }
+        assert_eq!(i, 1000);
+    }
+
+    #[test]
+    fn garbage_block_entries() {
+        let mut vec = numbers_table(WriterBuilder::new().compression_type(CompressionType::None).block_size(256));
+
+        // garbage entries in the third data block, under a valid checksum
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let (_, raw) = reader.raw_block_at(2).unwrap();
+        let start = raw.as_ptr() as usize - vec.as_ptr() as usize;
+        let len = raw.len();
+        let checksum_type = reader.metadata().checksum_type;
+        vec[start..start + 16].fill(0xff);
+        if let Some(crc) = checksum(checksum_type, &vec[start..start + len]) {
+            LittleEndian::write_u32(&mut vec[start - 4..start], crc);
+        }
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let mut iter = reader.clone().into_iter().unwrap();
+        let error = loop {
+            match iter.next() {
+                Some(Ok(_)) => (),
+                Some(Err(e)) => break e,
+                None => panic!("the garbage entries were not detected"),
+            }
+        };
+        assert!(matches!(error, Error::Mtbl(MtblError::InvalidBlock)), "{:?}", error);
+        assert!(iter.next().is_none());
+
+        let mut cursor = reader.cursor();
+        assert!(cursor.seek_to_first().unwrap());
+        let error = loop {
+            match cursor.next() {
+                Ok(true) => (),
+                Ok(false) => panic!("the garbage entries were not detected"),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(error, Error::Mtbl(MtblError::InvalidBlock)), "{:?}", error);
+        assert!(reader.validate_entry_count().is_err());
+        assert!((&reader).into_iter().any(|result| result.is_err()));
+    }
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    fn per_entry_checksum() {
+        let write = |builder: &mut WriterBuilder| {
+            numbers_table(builder.per_entry_checksum(true))
+        };
+
+        let mut vec = write(WriterBuilder::new().block_size(256));
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV5);
+        assert_eq!(reader.total_value_bytes(), (0..1000u32).map(|i| i.to_string().len() as u64).sum());
+        reader.verify_entry_checksums().unwrap();
+
+        // the checksums are stripped from the values
+        let entries = reader.to_btreemap().unwrap();
+        assert!(entries.into_iter().eq((0..1000u32).map(|i| (i.to_be_bytes().to_vec(), i.to_string().into_bytes()))));
+        assert_eq!(reader.get_owned(&500u32.to_be_bytes()).unwrap(), Some(b"500".to_vec()));
+
+        // a corrupted value, after the last byte of its key
+        let pos = vec.windows(4).position(|w| w == [244, b'5', b'0', b'0']).unwrap();
+        vec[pos + 3] = b'1';
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert!(matches!(reader.verify_entry_checksums(), Err(Error::Mtbl(MtblError::ChecksumMismatch))));
+
+        let reader = ReaderBuilder::new().verify_checksums(false).read(vec.as_slice()).unwrap();
+        match reader.verify_entry_checksums() {
+            Err(Error::Mtbl(MtblError::EntryChecksumMismatch { key })) => assert_eq!(key, 500u32.to_be_bytes()),
+            result => panic!("expected a mismatch on the key 500, got {:?}", result),
+        }
 
-        if self.valid { Some(Ok((key, val))) } else { None }
+        // the index segments of a V5 file
+        let reader = Reader::new(write(WriterBuilder::new().block_size(64).two_level_index(true))).unwrap();
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV5);
+        assert!(reader.index().is_two_level());
+        reader.verify_entry_checksums().unwrap();
+        assert_eq!(reader.get_owned(&999u32.to_be_bytes()).unwrap(), Some(b"999".to_vec()));
     }
 }
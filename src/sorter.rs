@@ -1,24 +1,47 @@
 use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
-use std::{cmp, io};
+use std::{cmp, fmt, io};
 
 use log::debug;
 use memmap::Mmap;
+use tempfile::TempPath;
 
 use crate::INITIAL_SORTER_VEC_SIZE;
 use crate::{DEFAULT_COMPRESSION_LEVEL, DEFAULT_SORTER_MEMORY, MIN_SORTER_MEMORY};
 use crate::{DEFAULT_NB_CHUNKS, MIN_NB_CHUNKS};
-use crate::{Merger, MergerIter};
-use crate::{Reader, Error};
+use crate::stream::BlockStream;
+use crate::{Merger, MergerBuilder, MergerIter};
+use crate::{Reader, Error, MtblError};
 use crate::{Writer, WriterBuilder, CompressionType};
 
+/// How the chunks are read when they are merged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkReaderMode {
+    /// The chunks are memory mapped.
+    #[default]
+    Mmap,
+    /// The chunks are read block by block through a `BufReader`, only the current
+    /// block of each chunk is in memory, e.g. when the chunks are bigger than the RAM.
+    Buffered,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SorterBuilder<MF> {
     pub max_memory: usize,
     pub max_nb_chunks: usize,
     pub chunk_compression_type: CompressionType,
     pub chunk_compression_level: u32,
+    /// The block size of the chunks, the default block size of the writer if `None`.
+    pub chunk_block_size: Option<u64>,
+    pub chunk_block_restart_interval: Option<usize>,
+    pub max_disk_usage: Option<u64>,
+    /// The number of values of a key merged at once when writing a chunk.
+    pub max_values_per_key: Option<usize>,
+    pub chunk_reader_mode: ChunkReaderMode,
     pub merge: MF,
 }
 
@@ -29,6 +52,11 @@ impl<MF> SorterBuilder<MF> {
             max_nb_chunks: DEFAULT_NB_CHUNKS,
             chunk_compression_type: CompressionType::Snappy,
             chunk_compression_level: DEFAULT_COMPRESSION_LEVEL,
+            chunk_block_size: None,
+            chunk_block_restart_interval: None,
+            max_disk_usage: None,
+            max_values_per_key: None,
+            chunk_reader_mode: ChunkReaderMode::Mmap,
             merge,
         }
     }
@@ -55,6 +83,89 @@ impl<MF> SorterBuilder<MF> {
         self
     }
 
+    /// The size of the data blocks of the chunks written on disk, smaller blocks
+    /// decompress less data to seek into a chunk when the chunks are merged.
+    pub fn chunk_block_size(&mut self, block_size: u64) -> &mut Self {
+        self.chunk_block_size = Some(block_size);
+        self
+    }
+
+    pub fn chunk_block_restart_interval(&mut self, interval: usize) -> &mut Self {
+        self.chunk_block_restart_interval = Some(interval);
+        self
+    }
+
+    /// The maximum number of bytes of the chunks on disk, including the chunk being
+    /// written while merging the others. A `DiskBudgetExceeded` error is returned
+    /// when a new chunk does not fit, this chunk is then removed. The size of a chunk
+    /// written from memory is estimated beforehand, it is not written if it cannot fit.
+    pub fn max_disk_usage(&mut self, bytes: u64) -> &mut Self {
+        self.max_disk_usage = Some(bytes);
+        self
+    }
+
+    /// Merges the values of a key by batches of at most `count` values when a chunk
+    /// is written, the merged value of a batch is then merged with the next values.
+    /// It bounds the memory used to merge the keys with a lot of values.
+    ///
+    /// The merge function must then be associative, it receives the already
+    /// merged values of the previous batches. `count` is at least two.
+    pub fn max_values_per_key(&mut self, count: usize) -> &mut Self {
+        self.max_values_per_key = Some(cmp::max(count, 2));
+        self
+    }
+
+    /// How the chunks are read when they are merged, memory mapped by default.
+    pub fn chunk_reader_mode(&mut self, mode: ChunkReaderMode) -> &mut Self {
+        self.chunk_reader_mode = mode;
+        self
+    }
+
+    pub fn with_max_memory(mut self, memory: usize) -> Self {
+        self.max_memory(memory);
+        self
+    }
+
+    pub fn with_max_nb_chunks(mut self, nb_chunks: usize) -> Self {
+        self.max_nb_chunks(nb_chunks);
+        self
+    }
+
+    pub fn with_chunk_compression_type(mut self, compression: CompressionType) -> Self {
+        self.chunk_compression_type(compression);
+        self
+    }
+
+    pub fn with_chunk_compression_level(mut self, level: u32) -> Self {
+        self.chunk_compression_level(level);
+        self
+    }
+
+    pub fn with_chunk_block_size(mut self, block_size: u64) -> Self {
+        self.chunk_block_size(block_size);
+        self
+    }
+
+    pub fn with_chunk_block_restart_interval(mut self, interval: usize) -> Self {
+        self.chunk_block_restart_interval(interval);
+        self
+    }
+
+    pub fn with_max_disk_usage(mut self, bytes: u64) -> Self {
+        self.max_disk_usage(bytes);
+        self
+    }
+
+    pub fn with_max_values_per_key(mut self, count: usize) -> Self {
+        self.max_values_per_key(count);
+        self
+    }
+
+    pub fn with_chunk_reader_mode(mut self, mode: ChunkReaderMode) -> Self {
+        self.chunk_reader_mode(mode);
+        self
+    }
+
     pub fn build(self) -> Sorter<MF> {
         Sorter {
             chunks: Vec::new(),
@@ -64,6 +175,18 @@ impl<MF> SorterBuilder<MF> {
             max_nb_chunks: self.max_nb_chunks,
             chunk_compression_type: self.chunk_compression_type,
             chunk_compression_level: self.chunk_compression_level,
+            chunk_block_size: self.chunk_block_size,
+            chunk_block_restart_interval: self.chunk_block_restart_interval,
+            max_disk_usage: self.max_disk_usage,
+            disk_usage: 0,
+            // the size of the compressed chunks is only known once one is written
+            disk_ratio: if self.chunk_compression_type == CompressionType::None { Some(1.0) } else { None },
+            chunk_writer: None,
+            next_chunk_path: None,
+            named_tempfiles: None,
+            max_values_per_key: self.max_values_per_key,
+            chunk_reader_mode: self.chunk_reader_mode,
+            merge_vals: Vec::new(),
             merge: self.merge,
         }
     }
@@ -92,8 +215,49 @@ impl Entry {
     }
 }
 
+/// A chunk written on disk, its file is removed when the path is dropped if it is named.
+struct Chunk {
+    file: Arc<File>,
+    path: Option<TempPath>,
+}
+
+/// A chunk file shared by its readers, read through its shared reference.
+struct SharedFile(Arc<File>);
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        (&*self.0).seek(pos)
+    }
+}
+
+/// The file of the chunk being written, the reused writer holds none in between
+/// two chunks, the file of a chunk is only created when the chunk is written.
+struct ChunkFile(Option<File>);
+
+impl io::Write for ChunkFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("no chunk file to write into")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
 pub struct Sorter<MF> {
-    chunks: Vec<File>,
+    chunks: Vec<Chunk>,
     entries: Vec<Entry>,
     /// The number of bytes allocated by the entries.
     entry_bytes: usize,
@@ -101,9 +265,63 @@ pub struct Sorter<MF> {
     max_nb_chunks: usize,
     chunk_compression_type: CompressionType,
     chunk_compression_level: u32,
+    chunk_block_size: Option<u64>,
+    chunk_block_restart_interval: Option<usize>,
+    max_disk_usage: Option<u64>,
+    /// The number of bytes of the chunks on disk.
+    disk_usage: u64,
+    /// The number of bytes on disk per byte of entries of the last chunk written
+    /// from memory, to estimate the size of the next one.
+    disk_ratio: Option<f64>,
+    /// The writer of the chunks, its buffers are reused from one chunk to the next.
+    chunk_writer: Option<Writer<ChunkFile>>,
+    /// The path of the file held by the chunk writer, if named.
+    next_chunk_path: Option<TempPath>,
+    named_tempfiles: Option<(PathBuf, String)>,
+    max_values_per_key: Option<usize>,
+    chunk_reader_mode: ChunkReaderMode,
+    /// The values of a key to merge, the buffers are reused from one key to the next.
+    merge_vals: Vec<Vec<u8>>,
     merge: MF,
 }
 
+/// Adds the chunks as sources of the merger, read as requested by the chunk reader mode.
+fn push_chunks<M, U>(
+    builder: &mut MergerBuilder<Mmap, M>,
+    chunks: &[Chunk],
+    mode: ChunkReaderMode,
+) -> Result<(), Error<U>>
+{
+    for chunk in chunks {
+        match mode {
+            ChunkReaderMode::Mmap => {
+                let mmap = unsafe { Mmap::map(&chunk.file)? };
+                builder.push(Reader::new(mmap).map_err(Error::convert_merge_error)?);
+            },
+            ChunkReaderMode::Buffered => {
+                let file = chunk.file.clone();
+                builder.push_stream(Arc::new(move || {
+                    BlockStream::new(BufReader::new(SharedFile(file.clone())))
+                }));
+            },
+        }
+    }
+    Ok(())
+}
+
+impl<MF> fmt::Debug for Sorter<MF> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sorter")
+            .field("chunk_count", &self.chunks.len())
+            .field("len", &self.entries.len())
+            .field("entry_bytes", &self.entry_bytes)
+            .field("max_memory", &self.max_memory)
+            .field("max_nb_chunks", &self.max_nb_chunks)
+            .field("chunk_compression_type", &self.chunk_compression_type)
+            .finish()
+    }
+}
+
 impl<MF> Sorter<MF> {
     pub fn builder(merge: MF) -> SorterBuilder<MF> {
         SorterBuilder::new(merge)
@@ -112,6 +330,41 @@ impl<MF> Sorter<MF> {
     pub fn new(merge: MF) -> Sorter<MF> {
         SorterBuilder::new(merge).build()
     }
+
+    /// Writes the next chunks into named files created in `dir` and starting with `prefix`,
+    /// see `Sorter::chunk_paths`. The remaining files are removed when the sorter is dropped.
+    pub fn named_tempfiles<P: Into<PathBuf>, S: Into<String>>(&mut self, dir: P, prefix: S) -> &mut Self {
+        self.named_tempfiles = Some((dir.into(), prefix.into()));
+        self
+    }
+
+    pub fn with_named_tempfiles<P: Into<PathBuf>, S: Into<String>>(mut self, dir: P, prefix: S) -> Self {
+        self.named_tempfiles(dir, prefix);
+        self
+    }
+
+    /// Returns the paths of the chunks on disk, empty if the chunks are not named.
+    pub fn chunk_paths(&self) -> Vec<PathBuf> {
+        self.chunks.iter().filter_map(|c| c.path.as_ref().map(|p| p.to_path_buf())).collect()
+    }
+
+    /// Creates the file of a new chunk, named if requested.
+    fn chunk_file(&self) -> io::Result<(File, Option<TempPath>)> {
+        match &self.named_tempfiles {
+            Some((dir, prefix)) => {
+                let (file, path) = tempfile::Builder::new().prefix(prefix).tempfile_in(dir)?.into_parts();
+                Ok((file, Some(path)))
+            },
+            None => Ok((tempfile::tempfile()?, None)),
+        }
+    }
+
+    /// Finishes the chunk being written and returns it, the writer is left without file.
+    fn finish_chunk(&mut self, writer: &mut Writer<ChunkFile>) -> io::Result<Chunk> {
+        let file = writer.finish_and_reset(ChunkFile(None))?.0.expect("chunk writer without file");
+        Ok(Chunk { file: Arc::new(file), path: self.next_chunk_path.take() })
+    }
+
 }
 
 impl<MF, U> Sorter<MF>
@@ -143,52 +396,55 @@ where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>
         debug!("writing a chunk...");
         let before_write = Instant::now();
 
-        let file = tempfile::tempfile()?;
-        let mut writer = WriterBuilder::new()
-            .compression_type(self.chunk_compression_type)
-            .compression_level(self.chunk_compression_level)
-            .build(file);
+        if let Some(ratio) = self.disk_ratio {
+            let estimated_size = (self.entry_bytes as f64 * ratio) as u64;
+            self.checked_disk_usage(self.disk_usage + estimated_size)?;
+        }
+
+        let mut writer = self.take_chunk_writer()?;
 
         self.entries.sort_unstable_by(|a, b| a.key().cmp(&b.key()));
 
-        let mut current = None;
-        for entry in self.entries.drain(..) {
-            match current.as_mut() {
-                None => {
-                    let key = entry.key().to_vec();
-                    let val = entry.val().to_vec();
-                    current = Some((key, vec![val]));
-                },
-                Some((key, vals)) => {
-                    if key == &entry.key() {
-                        vals.push(entry.val().to_vec());
-                    } else {
-                        let merged_val = if vals.len() == 1 {
-                            vals.pop().unwrap()
-                        } else {
-                            (self.merge)(&key, &vals).map_err(Error::Merge)?
-                        };
-                        writer.insert(&key, &merged_val)?;
-                        key.clear();
-                        vals.clear();
-                        key.extend_from_slice(entry.key());
-                        vals.push(entry.val().to_vec());
+        let mut entries = &self.entries[..];
+        while let Some(first) = entries.first() {
+            let count = entries.iter().take_while(|e| e.key() == first.key()).count();
+            if count == 1 {
+                writer.insert(first.key(), first.val())?;
+            } else {
+                let max_vals = self.max_values_per_key.unwrap_or(count);
+                let mut nb_vals = 0;
+                for entry in &entries[..count] {
+                    if nb_vals == max_vals {
+                        // the batch is merged into the first buffer, the next values follow it
+                        let merged_val = (self.merge)(first.key(), &self.merge_vals[..nb_vals]).map_err(|error| Error::MergeKey { key: first.key().to_vec(), error })?;
+                        self.merge_vals[0] = merged_val;
+                        nb_vals = 1;
+                    }
+                    // the buffers of the values are reused from one key to the next
+                    if self.merge_vals.len() == nb_vals {
+                        self.merge_vals.push(Vec::new());
                     }
+                    let buf = &mut self.merge_vals[nb_vals];
+                    buf.clear();
+                    buf.extend_from_slice(entry.val());
+                    nb_vals += 1;
                 }
+                let merged_val = (self.merge)(first.key(), &self.merge_vals[..nb_vals]).map_err(|error| Error::MergeKey { key: first.key().to_vec(), error })?;
+                writer.insert_taking(first.key(), merged_val)?;
             }
+            entries = &entries[count..];
         }
 
-        if let Some((key, mut vals)) = current.take() {
-            let merged_val = if vals.len() == 1 {
-                vals.pop().unwrap()
-            } else {
-                (self.merge)(&key, &vals).map_err(Error::Merge)?
-            };
-            writer.insert(&key, &merged_val)?;
+        // The entries are kept until the chunk fits in the budget.
+        let chunk = self.finish_chunk(&mut writer)?;
+        self.chunk_writer = Some(writer);
+        let chunk_size = chunk.file.metadata()?.len();
+        if self.entry_bytes > 0 {
+            self.disk_ratio = Some(chunk_size as f64 / self.entry_bytes as f64);
         }
-
-        let file = writer.into_inner()?;
-        self.chunks.push(file);
+        self.disk_usage = self.checked_disk_usage(self.disk_usage + chunk_size)?;
+        self.chunks.push(chunk);
+        self.entries.clear();
         self.entry_bytes = 0;
 
         debug!("writing a chunk took {:.02?}", before_write.elapsed());
@@ -196,36 +452,64 @@ where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>
         Ok(())
     }
 
+    /// Returns the writer of the chunks with the new file of the next chunk.
+    fn take_chunk_writer(&mut self) -> Result<Writer<ChunkFile>, Error<U>> {
+        let (file, path) = self.chunk_file()?;
+        self.next_chunk_path = path;
+        match self.chunk_writer.take() {
+            Some(mut writer) => {
+                writer.reset(ChunkFile(Some(file)))?;
+                Ok(writer)
+            },
+            None => {
+                let mut builder = WriterBuilder::new();
+                builder.compression_type(self.chunk_compression_type);
+                builder.compression_level(self.chunk_compression_level);
+                if let Some(block_size) = self.chunk_block_size {
+                    builder.block_size(block_size);
+                }
+                if let Some(interval) = self.chunk_block_restart_interval {
+                    builder.block_restart_interval(interval);
+                }
+                Ok(builder.build(ChunkFile(Some(file))))
+            },
+        }
+    }
+
+    /// Returns the given disk usage, an error if it exceeds the budget.
+    fn checked_disk_usage(&self, disk_usage: u64) -> Result<u64, Error<U>> {
+        match self.max_disk_usage {
+            Some(max) if disk_usage > max => Err(Error::from(MtblError::DiskBudgetExceeded)),
+            _ => Ok(disk_usage),
+        }
+    }
+
     fn merge_chunks(&mut self) -> Result<(), Error<U>> {
         debug!("merging {} chunks...", self.chunks.len());
         let before_merge = Instant::now();
         let original_num_chunks = self.chunks.len();
 
-        let file = tempfile::tempfile()?;
-        let mut writer = WriterBuilder::new()
-            .compression_type(self.chunk_compression_type)
-            .compression_level(self.chunk_compression_level)
-            .build(file);
-
-        // Drain the chunks to mmap them and store them into a vector.
-        let sources: Result<Vec<_>, Error<U>> = self.chunks.drain(..).map(|f| unsafe {
-            let mmap = Mmap::map(&f)?;
-            Reader::new(mmap).map_err(Error::convert_merge_error)
-        }).collect();
+        let mut writer = self.take_chunk_writer()?;
 
-        // Create a merger to merge all those chunks.
+        // Create a merger to merge all those chunks, they are only replaced
+        // once the new chunk is written and fits in the budget.
         let mut builder = Merger::builder(&self.merge);
-        builder.extend(sources?);
+        push_chunks(&mut builder, &self.chunks, self.chunk_reader_mode)?;
         let merger = builder.build();
 
         let mut iter = merger.into_merge_iter().map_err(Error::convert_merge_error)?;
-        while let Some(result) = iter.next() {
+        while let Some(result) = iter.next_taking() {
             let (key, val) = result?;
-            writer.insert(key, val)?;
+            writer.insert_cow(key, val)?;
         }
+        drop(iter);
 
-        let file = writer.into_inner()?;
-        self.chunks.push(file);
+        // The merged chunks are still on disk while the new chunk is written.
+        let chunk = self.finish_chunk(&mut writer)?;
+        self.chunk_writer = Some(writer);
+        let peak_disk_usage = self.checked_disk_usage(self.disk_usage + chunk.file.metadata()?.len())?;
+        self.disk_usage = peak_disk_usage - self.disk_usage;
+        self.chunks = vec![chunk];
 
         debug!("merging {} chunks took {:.02?}", original_num_chunks, before_merge.elapsed());
 
@@ -234,24 +518,47 @@ where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>
 
     pub fn write_into<W: io::Write>(self, writer: &mut Writer<W>) -> Result<(), Error<U>> {
         let mut iter = self.into_iter()?;
-        while let Some(result) = iter.next() {
+        while let Some(result) = iter.next_taking() {
             let (key, val) = result?;
-            writer.insert(key, val)?;
+            writer.insert_cow(key, val)?;
         }
         Ok(())
     }
 
+    /// Creates the file at `path`, or truncates it, and writes the sorted
+    /// and merged entries into it with a writer built from `writer_builder`.
+    pub fn into_file<P: AsRef<Path>>(self, path: P, mut writer_builder: WriterBuilder) -> Result<(), Error<U>> {
+        let file = File::create(path)?;
+        let mut writer = writer_builder.buffered(file);
+        self.write_into(&mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Writes the sorted and merged entries into an anonymous temporary file and
+    /// returns a reader of its memory mapping, the file is removed once the reader
+    /// and its clones are dropped. The file is created in the directory of the
+    /// named chunks, if any.
+    pub fn into_reader(self) -> Result<Reader<Mmap>, Error<U>> {
+        let file = match &self.named_tempfiles {
+            Some((dir, _prefix)) => tempfile::tempfile_in(dir)?,
+            None => tempfile::tempfile()?,
+        };
+
+        let mut writer = WriterBuilder::new().buffered(file);
+        self.write_into(&mut writer)?;
+        let file = writer.into_inner()?.into_inner().map_err(|e| e.into_error())?;
+
+        let mmap = unsafe { Mmap::map(&file)? };
+        Reader::new(mmap).map_err(Error::convert_merge_error)
+    }
+
     pub fn into_iter(mut self) -> Result<MergerIter<Mmap, MF>, Error<U>> {
         // Flush the pending unordered entries.
         self.write_chunk()?;
 
-        let sources: Result<Vec<_>, Error<U>> = self.chunks.into_iter().map(|f| unsafe {
-            let mmap = Mmap::map(&f)?;
-            Reader::new(mmap).map_err(Error::convert_merge_error)
-        }).collect();
-
         let mut builder = Merger::builder(self.merge);
-        builder.extend(sources?);
+        push_chunks(&mut builder, &self.chunks, self.chunk_reader_mode)?;
 
         builder.build().into_merge_iter().map_err(Error::convert_merge_error)
     }
@@ -259,6 +566,8 @@ where MF: Fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, U>
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+
     use super::*;
 
     #[test]
@@ -293,4 +602,346 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn debug() {
+        let mut sorter = Sorter::new(|_: &[u8], _: &[Vec<u8>]| Ok::<_, ()>(Vec::new()));
+        for i in 0..42u32 {
+            sorter.insert(i.to_be_bytes(), "value").unwrap();
+        }
+        let debug = format!("{:?}", sorter);
+        assert!(debug.contains("len: 42"), "{}", debug);
+        assert!(debug.contains("chunk_count: 0"), "{}", debug);
+    }
+
+    #[test]
+    fn into_file() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.concat())
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("sorted.mtbl");
+        let mut sorter = Sorter::new(merge);
+        for i in (0..1000u32).rev() {
+            sorter.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let builder = WriterBuilder::new().with_compression_type(CompressionType::Snappy).with_block_size(1024);
+        sorter.into_file(&path, builder).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let reader = Reader::new(bytes).unwrap();
+        assert_eq!(reader.metadata().compression_algorithm, CompressionType::Snappy);
+        assert_eq!(reader.metadata().data_block_size, 1024);
+
+        let mut iter = reader.into_iter().unwrap();
+        let mut count = 0u32;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+
+        let path = dir.path().join("empty.mtbl");
+        Sorter::new(merge).into_file(&path, WriterBuilder::new()).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let reader = Reader::new(bytes).unwrap();
+        assert_eq!(reader.metadata().count_entries, 0);
+        assert!(reader.into_iter().unwrap().next().is_none());
+    }
+
+    #[test]
+    fn disk_budget() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.concat())
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sorter = SorterBuilder::new(merge)
+            .with_max_memory(MIN_SORTER_MEMORY)
+            .with_chunk_compression_type(CompressionType::None)
+            .with_max_disk_usage(1024)
+            .build()
+            .with_named_tempfiles(dir.path(), "chunk-");
+
+        // the first chunk would be written after MIN_SORTER_MEMORY bytes
+        let val = vec![42; 1024];
+        let result = (0..20_000u32).try_for_each(|i| sorter.insert(i.to_be_bytes(), &val));
+        assert!(matches!(result, Err(Error::Mtbl(MtblError::DiskBudgetExceeded))));
+        // its estimated size does not fit, it is not written
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // the size of the compressed chunks is estimated from the previous ones
+        #[cfg(feature = "snappy")]
+        {
+            let mut sorter = SorterBuilder::new(merge)
+                .with_chunk_compression_type(CompressionType::Snappy)
+                .with_max_disk_usage(64 * 1024)
+                .build()
+                .with_named_tempfiles(dir.path(), "chunk-");
+            (0..100u32).for_each(|i| sorter.insert(i.to_be_bytes(), &val).unwrap());
+            sorter.write_chunk().unwrap();
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+            (0..10_000u32).for_each(|i| sorter.insert(i.to_be_bytes(), &val).unwrap());
+            assert!(matches!(sorter.write_chunk(), Err(Error::Mtbl(MtblError::DiskBudgetExceeded))));
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        }
+
+        // the metadata alone is bigger than this budget
+        let mut sorter = SorterBuilder::new(merge).with_max_disk_usage(256).build();
+        sorter.insert(b"hello", "world").unwrap();
+        assert!(matches!(sorter.write_chunk(), Err(Error::Mtbl(MtblError::DiskBudgetExceeded))));
+        // the entries of the chunk that does not fit are kept
+        assert_eq!(sorter.entries.len(), 1);
+        assert!(sorter.chunks.is_empty());
+        assert!(matches!(sorter.into_iter(), Err(Error::Mtbl(MtblError::DiskBudgetExceeded))));
+
+        let mut sorter = SorterBuilder::new(merge).with_max_disk_usage(1024 * 1024).build();
+        sorter.insert(b"hello", "world").unwrap();
+        let mut iter = sorter.into_iter().unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), (&b"hello"[..], &b"world"[..]));
+    }
+
+    #[test]
+    fn failed_merge_keeps_chunks() {
+        let fail = Cell::new(false);
+        let merge = |_key: &[u8], vals: &[Vec<u8>]| {
+            if fail.get() { Err(()) } else { Ok(vals.concat()) }
+        };
+
+        let mut sorter = SorterBuilder::new(merge).with_max_nb_chunks(1).build();
+        sorter.insert(b"a", "1").unwrap();
+        sorter.insert(b"b", "2").unwrap();
+        sorter.write_chunk().unwrap();
+        fail.set(true);
+        sorter.insert(b"a", "3").unwrap();
+        sorter.insert(b"c", "4").unwrap();
+        sorter.write_chunk().unwrap();
+        assert!(matches!(sorter.merge_chunks(), Err(Error::MergeKey { .. })));
+        assert_eq!(sorter.chunks.len(), 2);
+
+        fail.set(false);
+        let mut iter = sorter.into_iter().unwrap();
+        let mut entries = Vec::new();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            entries.push((key.to_vec(), val.to_vec()));
+        }
+        assert_eq!(entries, vec![
+            (b"a".to_vec(), b"13".to_vec()),
+            (b"b".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"4".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn named_tempfiles() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.concat())
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let files = || std::fs::read_dir(dir.path()).unwrap().count();
+
+        let mut sorter = SorterBuilder::new(merge)
+            .with_max_memory(MIN_SORTER_MEMORY)
+            .build()
+            .with_named_tempfiles(dir.path(), "chunk-");
+        let val = vec![42; 1024];
+        for i in 0..30_000u32 {
+            sorter.insert(i.to_be_bytes(), &val).unwrap();
+        }
+
+        let paths = sorter.chunk_paths();
+        assert!(paths.len() >= 2, "{:?}", paths);
+        for path in &paths {
+            assert!(path.starts_with(dir.path()) && path.exists());
+            assert!(path.file_name().unwrap().to_str().unwrap().starts_with("chunk-"));
+        }
+        // no file is created in advance for the next chunk
+        assert_eq!(files(), paths.len());
+
+        drop(sorter);
+        assert_eq!(files(), 0);
+        assert!(paths.iter().all(|p| !p.exists()));
+
+        // the anonymous chunks have no path
+        let mut sorter = SorterBuilder::new(merge).with_max_memory(MIN_SORTER_MEMORY).build();
+        for i in 0..30_000u32 {
+            sorter.insert(i.to_be_bytes(), &val).unwrap();
+        }
+        assert!(sorter.chunk_paths().is_empty());
+    }
+
+    #[test]
+    fn into_reader() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.iter().max().unwrap().clone())
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sorter = SorterBuilder::new(merge)
+            .with_max_memory(MIN_SORTER_MEMORY)
+            .build()
+            .with_named_tempfiles(dir.path(), "chunk-");
+        let val = vec![42; 256];
+        for i in 0..100_000u32 {
+            // every key is inserted twice, in no particular order
+            let key = (i % 50_000).wrapping_mul(2_654_435_761).to_be_bytes();
+            sorter.insert(key, &val[..(i as usize / 50_000 + 1) * 100]).unwrap();
+        }
+        assert!(sorter.chunks.len() >= 2);
+
+        let reader = sorter.into_reader().unwrap();
+        // the chunks are removed and the file of the reader is anonymous
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert_eq!(reader.metadata().count_entries, 50_000);
+
+        for i in (0..50_000u32).step_by(997) {
+            let key = i.wrapping_mul(2_654_435_761).to_be_bytes();
+            assert_eq!(reader.get_owned(&key).unwrap().unwrap(), &val[..200]);
+        }
+        assert!(reader.get_owned(&50_000u32.wrapping_mul(2_654_435_761).to_be_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn max_values_per_key() {
+        use std::cell::Cell;
+        use std::convert::TryInto;
+
+        let max_seen = Cell::new(0);
+        let merge = |_key: &[u8], vals: &[Vec<u8>]| {
+            max_seen.set(cmp::max(max_seen.get(), vals.len()));
+            Ok::<_, ()>(vals.concat())
+        };
+
+        let mut sorter = SorterBuilder::new(merge).with_max_values_per_key(100).build();
+        sorter.insert("cold", "value").unwrap();
+        for i in 0..100_000u32 {
+            sorter.insert("hot", i.to_be_bytes()).unwrap();
+        }
+
+        // the buffers of the values never exceed the cap
+        sorter.write_chunk().unwrap();
+        assert!(sorter.merge_vals.len() <= 100, "{}", sorter.merge_vals.len());
+        assert_eq!(max_seen.get(), 100);
+
+        let mut iter = sorter.into_iter().unwrap();
+        let (key, val) = iter.next().unwrap().unwrap();
+        assert_eq!((key, val), (&b"cold"[..], &b"value"[..]));
+
+        let (key, val) = iter.next().unwrap().unwrap();
+        assert_eq!(key, b"hot");
+        let mut numbers: Vec<_> = val.chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap())).collect();
+        numbers.sort_unstable();
+        assert!(numbers.into_iter().eq(0..100_000));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn merge_error_key() {
+        fn merge(key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, &'static str> {
+            if key == b"bad" { Err("refused") } else { Ok(vals.concat()) }
+        }
+
+        // the values of the key are merged when the chunk is written
+        let mut sorter = Sorter::new(merge);
+        sorter.insert("bad", "a").unwrap();
+        sorter.insert("good", "b").unwrap();
+        sorter.insert("bad", "c").unwrap();
+        match sorter.write_chunk() {
+            Err(Error::MergeKey { key, error }) => assert_eq!((&key[..], error), (&b"bad"[..], "refused")),
+            _ => panic!("expected a merge error"),
+        }
+
+        // the values of the key are spread among the chunks
+        let mut sorter = Sorter::new(merge);
+        sorter.insert("bad", "a").unwrap();
+        sorter.insert("good", "b").unwrap();
+        sorter.write_chunk().unwrap();
+        sorter.insert("bad", "c").unwrap();
+        let mut iter = sorter.into_iter().unwrap();
+        match iter.next() {
+            Some(Err(Error::MergeKey { key, error })) => assert_eq!((&key[..], error), (&b"bad"[..], "refused")),
+            _ => panic!("expected a merge error"),
+        }
+    }
+
+    #[test]
+    fn chunk_block_size() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.concat())
+        }
+
+        type Merge = fn(&[u8], &[Vec<u8>]) -> Result<Vec<u8>, ()>;
+
+        let count_blocks = |builder: SorterBuilder<Merge>| {
+            let mut sorter = builder.build();
+            for i in (0..2000u32).rev() {
+                sorter.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            sorter.write_chunk().unwrap();
+            for i in 0..2000u32 {
+                sorter.insert(i.to_be_bytes(), "!").unwrap();
+            }
+            sorter.write_chunk().unwrap();
+            sorter.merge_chunks().unwrap();
+
+            let bytes = unsafe { Mmap::map(&sorter.chunks[0].file).unwrap() };
+            let blocks = Reader::new(bytes).unwrap().metadata().count_data_blocks;
+
+            let mut iter = sorter.into_iter().unwrap();
+            let mut count = 0;
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                let mut buf = [0; 4];
+                buf.copy_from_slice(key);
+                assert_eq!(val, format!("{}!", u32::from_be_bytes(buf)).as_bytes());
+                count += 1;
+            }
+            assert_eq!(count, 2000);
+            blocks
+        };
+
+        let default_blocks = count_blocks(SorterBuilder::new(merge as Merge));
+        let small_blocks = count_blocks(SorterBuilder::new(merge as Merge)
+            .with_chunk_block_size(256)
+            .with_chunk_block_restart_interval(4));
+        assert!(small_blocks > default_blocks * 4, "{} <= {} * 4", small_blocks, default_blocks);
+    }
+
+    #[test]
+    fn chunk_reader_mode() {
+        fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+            Ok(vals.concat())
+        }
+
+        let sort = |mode| {
+            let mut sorter = SorterBuilder::new(merge)
+                .with_max_memory(MIN_SORTER_MEMORY)
+                .with_max_nb_chunks(2)
+                .with_chunk_reader_mode(mode)
+                .build();
+            let val = vec![42; 1024];
+            for i in 0..40_000u32 {
+                // every key is inserted twice
+                let key = (i % 20_000).wrapping_mul(2_654_435_761).to_be_bytes();
+                sorter.insert(key, &val).unwrap();
+            }
+            assert!(!sorter.chunks.is_empty());
+
+            let mut writer = WriterBuilder::new().memory();
+            sorter.write_into(&mut writer).unwrap();
+            writer.into_inner().unwrap()
+        };
+
+        let mapped = sort(ChunkReaderMode::Mmap);
+        let buffered = sort(ChunkReaderMode::Buffered);
+        assert_eq!(mapped, buffered);
+        assert_eq!(Reader::new(buffered).unwrap().metadata().count_entries, 20_000);
+    }
 }
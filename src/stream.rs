@@ -0,0 +1,228 @@
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::block::{Block, BlockIter};
+use crate::compression::decompress;
+use crate::reader::verify_checksum;
+use crate::{BytesView, Error, FileVersion, Metadata, MtblError, METADATA_SIZE};
+
+/// Reads the data blocks of a table one after the other from its start, without
+/// random access nor memory mapping, e.g. through a `BufReader` over a file.
+/// Only one block is held in memory at a time.
+pub(crate) struct BlockStream {
+    reader: Box<dyn Read + Send>,
+    metadata: Metadata,
+    /// The number of data blocks not read yet.
+    remaining_blocks: u64,
+    /// The buffer of the compressed blocks, reused from one block to the next.
+    raw: Vec<u8>,
+}
+
+impl BlockStream {
+    /// Reads the metadata at the end of the table then rewinds to its first data block.
+    pub(crate) fn new<R: Read + Seek + Send + 'static>(mut reader: R) -> Result<BlockStream, Error> {
+        let size = reader.seek(SeekFrom::End(0))?;
+        if size < METADATA_SIZE as u64 {
+            return Err(Error::from(MtblError::InvalidMetadataSize));
+        }
+
+        reader.seek(SeekFrom::Start(size - METADATA_SIZE as u64))?;
+        let mut metadata_bytes = [0; METADATA_SIZE];
+        reader.read_exact(&mut metadata_bytes)?;
+        let metadata = Metadata::read_from_bytes(&metadata_bytes, true)?;
+        if metadata.compression_dictionary_id != 0 {
+            return Err(Error::from(MtblError::MissingCompressionDictionary));
+        }
+
+        reader.seek(SeekFrom::Start(0))?;
+        let remaining_blocks = metadata.count_data_blocks;
+        Ok(BlockStream { reader: Box::new(reader), metadata, remaining_blocks, raw: Vec::new() })
+    }
+
+    /// Reads and decodes the next data block, `None` once they have all been read.
+    /// The blocks that follow an error are not read.
+    pub(crate) fn next_block<A: AsRef<[u8]>>(&mut self) -> Result<Option<Block<A>>, Error> {
+        if self.remaining_blocks == 0 {
+            return Ok(None);
+        }
+
+        match self.read_block() {
+            Ok(block) => {
+                self.remaining_blocks -= 1;
+                Ok(Some(block))
+            },
+            Err(e) => {
+                self.remaining_blocks = 0;
+                Err(e)
+            },
+        }
+    }
+
+    fn read_block<A: AsRef<[u8]>>(&mut self) -> Result<Block<A>, Error> {
+        let file_version = self.metadata.file_version;
+        let raw_size = match file_version {
+            FileVersion::FormatV1 => {
+                let mut size = [0; mem::size_of::<u32>()];
+                self.reader.read_exact(&mut size)?;
+                LittleEndian::read_u32(&size) as u64
+            },
+            _ => read_varint64(&mut self.reader)?,
+        };
+
+        // a corrupted size must not allocate more than the data blocks
+        if raw_size > self.metadata.index_start() {
+            return Err(Error::from(MtblError::InvalidBlock));
+        }
+
+        let mut block_crc = [0; mem::size_of::<u32>()];
+        self.reader.read_exact(&mut block_crc)?;
+        self.raw.resize(raw_size as usize, 0);
+        self.reader.read_exact(&mut self.raw)?;
+        verify_checksum(self.metadata.checksum_type, LittleEndian::read_u32(&block_crc), &self.raw)?;
+
+        let compression = self.metadata.compression_algorithm;
+        let decompressed = match decompress(compression, None, &self.raw, usize::MAX)? {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(bytes) => Some(bytes),
+        };
+        // the uncompressed blocks are kept as read
+        let data = decompressed.unwrap_or_else(|| mem::take(&mut self.raw));
+
+        let block = Block::init(BytesView::from_bytes(data))?;
+        Ok(block.with_repeated_values(file_version.repeated_values())
+            .with_value_checksums(file_version.value_checksums()))
+    }
+}
+
+/// Reads a varint from the stream, byte by byte.
+fn read_varint64<R: Read + ?Sized>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::from(MtblError::InvalidBlock))
+}
+
+/// Iterates over the entries of a `BlockStream`, like a `ReaderIntoIter`.
+pub(crate) struct StreamIter<A> {
+    stream: BlockStream,
+    bi: Option<BlockIter<A>>,
+    first: bool,
+}
+
+impl<A: AsRef<[u8]>> StreamIter<A> {
+    pub(crate) fn new(stream: BlockStream) -> StreamIter<A> {
+        StreamIter { stream, bi: None, first: true }
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn next(&mut self) -> Option<Result<(&[u8], &[u8]), Error>> {
+        loop {
+            let positioned = match &mut self.bi {
+                Some(bi) if self.first => {
+                    self.first = false;
+                    bi.status().map(|()| bi.key().is_some())
+                },
+                Some(bi) => bi.next(),
+                None => Ok(false),
+            };
+            match positioned {
+                Ok(true) => return self.bi.as_ref().and_then(BlockIter::get).map(Ok),
+                Ok(false) => (),
+                Err(e) => {
+                    // the next blocks are not read
+                    self.bi = None;
+                    self.stream.remaining_blocks = 0;
+                    return Some(Err(Error::from(e)));
+                },
+            }
+
+            match self.stream.next_block() {
+                Ok(Some(block)) => {
+                    let mut bi = BlockIter::init(Arc::new(block));
+                    // an error is returned by the next loop
+                    let _ = bi.seek_to_first();
+                    self.bi = Some(bi);
+                    self.first = true;
+                },
+                Ok(None) => {
+                    self.bi = None;
+                    return None;
+                },
+                Err(e) => {
+                    self.bi = None;
+                    return Some(Err(e));
+                },
+            }
+        }
+    }
+
+    /// The entry returned by the last call to `next`.
+    pub(crate) fn current(&self) -> Option<(&[u8], &[u8])> {
+        self.bi.as_ref().and_then(BlockIter::get)
+    }
+
+    /// A view of the value returned by the last call to `next`, it shares the block.
+    pub(crate) fn current_val_view(&self) -> Option<BytesView<A>> {
+        self.bi.as_ref().and_then(BlockIter::val_view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{Reader, WriterBuilder};
+
+    fn numbers_table() -> Vec<u8> {
+        let mut writer = WriterBuilder::new().block_size(256).memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn same_entries_as_reader() {
+        let bytes = numbers_table();
+        let mut iter = StreamIter::<Vec<u8>>::new(BlockStream::new(Cursor::new(bytes.clone())).unwrap());
+        let mut reader_iter = Reader::new(bytes).unwrap().into_iter().unwrap();
+
+        let mut count = 0;
+        while let Some(result) = reader_iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(iter.next().unwrap().unwrap(), (key, val));
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let mut bytes = numbers_table();
+        bytes[300] ^= 0xff;
+        let mut iter = StreamIter::<Vec<u8>>::new(BlockStream::new(Cursor::new(bytes)).unwrap());
+
+        let error = loop {
+            match iter.next() {
+                Some(Ok(_)) => (),
+                Some(Err(e)) => break e,
+                None => panic!("the corrupted block was not detected"),
+            }
+        };
+        assert!(matches!(error, Error::Mtbl(MtblError::ChecksumMismatch)), "{:?}", error);
+        assert!(iter.next().is_none());
+    }
+}
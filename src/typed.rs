@@ -0,0 +1,190 @@
+use std::io;
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Error, Reader, ReaderIntoIter, Writer};
+
+/// Encodes keys into bytes that sort in the same order as the keys themselves,
+/// the table being sorted by the raw bytes of the keys.
+pub trait KeyCodec<K> {
+    fn encode(&self, key: &K, bytes: &mut Vec<u8>);
+    fn decode(&self, bytes: &[u8]) -> Option<K>;
+}
+
+/// Encodes unsigned integers in big-endian, which preserves their numeric order.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BigEndian;
+
+macro_rules! big_endian_codec {
+    ($($ty:ty),*) => {
+        $(
+            impl KeyCodec<$ty> for BigEndian {
+                fn encode(&self, key: &$ty, bytes: &mut Vec<u8>) {
+                    bytes.extend_from_slice(&key.to_be_bytes());
+                }
+
+                fn decode(&self, bytes: &[u8]) -> Option<$ty> {
+                    let mut array = [0; std::mem::size_of::<$ty>()];
+                    if bytes.len() != array.len() { return None }
+                    array.copy_from_slice(bytes);
+                    Some(<$ty>::from_be_bytes(array))
+                }
+            }
+        )*
+    };
+}
+
+big_endian_codec!(u8, u16, u32, u64, u128);
+
+fn bincode_error(error: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn decode_value<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, Error> {
+    bincode::deserialize(bytes).map_err(|e| Error::from(bincode_error(e)))
+}
+
+/// A `Writer` of keys encoded with a `KeyCodec` and values serialized with bincode.
+pub struct TypedWriter<K, V, W, C = BigEndian> {
+    writer: Writer<W>,
+    codec: C,
+    key_buffer: Vec<u8>,
+    val_buffer: Vec<u8>,
+    _marker: PhantomData<fn(&K, &V)>,
+}
+
+impl<K, V, W, C> TypedWriter<K, V, W, C>
+where W: io::Write,
+      V: Serialize,
+      C: KeyCodec<K>,
+{
+    pub fn new(writer: Writer<W>, codec: C) -> TypedWriter<K, V, W, C> {
+        TypedWriter {
+            writer,
+            codec,
+            key_buffer: Vec::new(),
+            val_buffer: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn insert(&mut self, key: &K, val: &V) -> Result<(), Error> {
+        self.key_buffer.clear();
+        self.codec.encode(key, &mut self.key_buffer);
+
+        self.val_buffer.clear();
+        bincode::serialize_into(&mut self.val_buffer, val).map_err(bincode_error)?;
+
+        self.writer.insert(&self.key_buffer, &self.val_buffer)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish()
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner()
+    }
+}
+
+/// A `Reader` of keys encoded with a `KeyCodec` and values serialized with bincode.
+pub struct TypedReader<K, V, A, C = BigEndian> {
+    reader: Reader<A>,
+    codec: C,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, A, C> TypedReader<K, V, A, C>
+where A: AsRef<[u8]>,
+      V: DeserializeOwned,
+      C: KeyCodec<K>,
+{
+    pub fn new(reader: Reader<A>, codec: C) -> TypedReader<K, V, A, C> {
+        TypedReader { reader, codec, _marker: PhantomData }
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+        let mut bytes = Vec::new();
+        self.codec.encode(key, &mut bytes);
+        match self.reader.clone().get(&bytes)? {
+            Some(val) => decode_value(val.as_ref()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter(self) -> Result<TypedReaderIntoIter<K, V, A, C>, Error> {
+        let iter = self.reader.into_iter()?;
+        Ok(TypedReaderIntoIter { iter, codec: self.codec, _marker: PhantomData })
+    }
+}
+
+pub struct TypedReaderIntoIter<K, V, A, C = BigEndian> {
+    iter: ReaderIntoIter<A>,
+    codec: C,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, A, C> Iterator for TypedReaderIntoIter<K, V, A, C>
+where A: AsRef<[u8]>,
+      V: DeserializeOwned,
+      C: KeyCodec<K>,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, val) = match self.iter.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let key = match self.codec.decode(key) {
+            Some(key) => key,
+            None => {
+                let error = io::Error::new(io::ErrorKind::InvalidData, "invalid key encoding");
+                return Some(Err(Error::from(error)));
+            },
+        };
+
+        Some(decode_value(val).map(|val| (key, val)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use crate::WriterBuilder;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct MyStruct {
+        name: String,
+        tags: Vec<u32>,
+    }
+
+    fn my_struct(i: u64) -> MyStruct {
+        MyStruct { name: format!("number {}", i), tags: vec![i as u32; (i % 4) as usize] }
+    }
+
+    #[test]
+    fn typed() {
+        // inserted in numeric order, 256 would be before 2 in little-endian
+        let keys: Vec<u64> = vec![0, 1, 2, 255, 256, 1000, 65_536, u64::MAX];
+
+        let mut writer = TypedWriter::new(WriterBuilder::new().memory(), BigEndian);
+        for key in &keys {
+            writer.insert(key, &my_struct(*key)).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader: TypedReader<u64, MyStruct, _> = TypedReader::new(Reader::new(vec.as_slice()).unwrap(), BigEndian);
+        assert_eq!(reader.get(&256).unwrap(), Some(my_struct(256)));
+        assert_eq!(reader.get(&3).unwrap(), None);
+
+        let entries: Vec<_> = reader.into_iter().unwrap().map(Result::unwrap).collect();
+        let expected: Vec<_> = keys.iter().map(|k| (*k, my_struct(*k))).collect();
+        assert_eq!(entries, expected);
+    }
+}
@@ -0,0 +1,101 @@
+use alloc::vec::Vec;
+
+/// An entry that a `Writer` would have refused, see `Validator`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OrderViolation {
+    /// The position of the entry among all the inserted ones, starting at zero.
+    pub index: u64,
+    /// The last key accepted before this entry.
+    pub prev_key: Vec<u8>,
+    /// The key of the entry, lower than or equal to `prev_key`.
+    pub key: Vec<u8>,
+}
+
+/// Checks the order of the keys like a `Writer` without writing anything,
+/// all the out of order or duplicate keys are reported instead of the first one.
+///
+/// A refused entry is skipped, the next keys are compared with the last accepted one.
+#[derive(Debug, Default, Clone)]
+pub struct Validator {
+    last_key: Option<Vec<u8>>,
+    count_entries: u64,
+    violations: Vec<OrderViolation>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    /// Checks the key of the entry, the value is ignored.
+    pub fn insert<K, V>(&mut self, key: K, _val: V)
+    where K: AsRef<[u8]>,
+          V: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let index = self.count_entries;
+        self.count_entries += 1;
+
+        match &mut self.last_key {
+            Some(last_key) if !is_in_order(last_key, key) => {
+                let violation = OrderViolation { index, prev_key: last_key.clone(), key: key.to_vec() };
+                self.violations.push(violation);
+            },
+            Some(last_key) => {
+                last_key.clear();
+                last_key.extend_from_slice(key);
+            },
+            None => self.last_key = Some(key.to_vec()),
+        }
+    }
+
+    /// The number of entries inserted, refused ones included.
+    pub fn len(&self) -> u64 {
+        self.count_entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count_entries == 0
+    }
+
+    pub fn violations(&self) -> &[OrderViolation] {
+        &self.violations
+    }
+
+    pub fn into_violations(self) -> Vec<OrderViolation> {
+        self.violations
+    }
+}
+
+/// Whether `key` can follow `last_key` in a table, the keys are strictly increasing.
+pub(crate) fn is_in_order(last_key: &[u8], key: &[u8]) -> bool {
+    key > last_key
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn violations() {
+        let mut validator = Validator::new();
+        let keys: &[&[u8]] = &[b"a", b"b", b"d", b"c", b"e", b"f", b"f", b"g"];
+        for key in keys {
+            validator.insert(key, "value");
+        }
+
+        assert_eq!(validator.len(), keys.len() as u64);
+        assert_eq!(validator.into_violations(), vec![
+            OrderViolation { index: 3, prev_key: b"d".to_vec(), key: b"c".to_vec() },
+            OrderViolation { index: 6, prev_key: b"f".to_vec(), key: b"f".to_vec() },
+        ]);
+
+        let mut validator = Validator::new();
+        for i in 0..1000u32 {
+            validator.insert(i.to_be_bytes(), []);
+        }
+        assert!(validator.violations().is_empty());
+    }
+}
@@ -9,6 +9,7 @@ pub fn varint_length_packed(data: &[u8]) -> u32 {
     if i == data.len() { 0 } else { i as u32 + 1 }
 }
 
+#[cfg(feature = "std")]
 #[must_use]
 pub fn varint_encode32(bytes: &mut [u8], value: u32) -> &[u8] {
     let b = 128;
@@ -60,6 +61,7 @@ pub fn varint_decode32(data: &[u8], value: &mut u32) -> usize {
     len as usize
 }
 
+#[cfg(feature = "std")]
 #[must_use]
 pub fn varint_encode64(bytes: &mut [u8], mut value: u64) -> &[u8] {
     let b = 128;
@@ -96,7 +98,8 @@ pub fn varint_decode64(data: &[u8], value: &mut u64) -> usize {
     len as usize
 }
 
-#[cfg(test)]
+// the encoders are only built with std, for the writer
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
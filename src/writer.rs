@@ -1,23 +1,78 @@
-use std::{cmp, mem, io};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::{cmp, fmt, mem, io};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::sync::mpsc::SyncSender;
 
-use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 
+use crate::block::{Block, BlockIter};
 use crate::block_builder::BlockBuilder;
-use crate::compression::compress;
+use crate::compression::{compress, decompress, zstd_dictionary_id, ZstdParams};
+use crate::checksum::{checksum, ChecksumType};
 use crate::compression::CompressionType;
+use crate::index::IndexIter;
+use crate::validator::is_in_order;
 use crate::varint::varint_encode64;
-use crate::{FileVersion, Metadata};
+use crate::{BytesView, Error, FileVersion, Metadata, MtblError, Reader};
 
 use crate::{DEFAULT_COMPRESSION_TYPE, DEFAULT_COMPRESSION_LEVEL};
 use crate::{DEFAULT_BLOCK_SIZE, DEFAULT_BLOCK_RESTART_INTERVAL};
-use crate::{MIN_BLOCK_SIZE, METADATA_SIZE};
+use crate::{MIN_BLOCK_SIZE, METADATA_SIZE, DEFAULT_BUFFER_CAPACITY, VALUE_CHECKSUM_SIZE};
 
-#[derive(Debug, Clone, Copy)]
+type DuplicateMergeFn = dyn Fn(&[u8], &[u8], &[u8]) -> Vec<u8> + Send + Sync;
+type SeparatorFn = dyn Fn(&mut Vec<u8>, &[u8]) + Send + Sync;
+
+/// Merges the value of a key inserted twice in a row, see `WriterBuilder::on_duplicate`.
+#[derive(Clone)]
+struct DuplicateMerge(Arc<DuplicateMergeFn>);
+
+impl fmt::Debug for DuplicateMerge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DuplicateMerge")
+    }
+}
+
+/// Shortens the key of an index entry, see `WriterBuilder::shortest_separator`.
+#[derive(Clone)]
+struct Separator(Arc<SeparatorFn>);
+
+impl fmt::Debug for Separator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Separator")
+    }
+}
+
+/// Not `Copy`, it holds the zstd dictionary and the closures given to it,
+/// they are shared between the clones which are therefore cheap.
+#[derive(Debug, Clone)]
 pub struct WriterBuilder {
     compression_type: CompressionType,
     compression_level: u32,
+    compression_dictionary: Option<Arc<[u8]>>,
+    zstd_params: ZstdParams,
+    index_compression_type: CompressionType,
     block_size: u64,
     block_restart_interval: usize,
+    buffer_capacity: usize,
+    prefix_compression: bool,
+    dedup_repeated_values: bool,
+    two_level_index: bool,
+    store_key_range: bool,
+    per_entry_checksum: bool,
+    checksum_type: ChecksumType,
+    target_block_count: Option<usize>,
+    expected_entries: Option<u64>,
+    file_version: FileVersion,
+    on_duplicate: Option<DuplicateMerge>,
+    shortest_separator: Option<Separator>,
+    reorder_window: usize,
+    max_block_size_hard_cap: Option<u64>,
+    max_key_size: Option<usize>,
+    max_value_size: Option<usize>,
 }
 
 impl WriterBuilder {
@@ -25,21 +80,79 @@ impl WriterBuilder {
         WriterBuilder {
             compression_type: DEFAULT_COMPRESSION_TYPE,
             compression_level: DEFAULT_COMPRESSION_LEVEL,
+            compression_dictionary: None,
+            zstd_params: ZstdParams::default(),
+            index_compression_type: CompressionType::None,
             block_size: DEFAULT_BLOCK_SIZE,
             block_restart_interval: DEFAULT_BLOCK_RESTART_INTERVAL,
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            prefix_compression: true,
+            dedup_repeated_values: false,
+            two_level_index: false,
+            store_key_range: false,
+            per_entry_checksum: false,
+            checksum_type: ChecksumType::Crc32c,
+            target_block_count: None,
+            expected_entries: None,
+            file_version: FileVersion::FormatV2,
+            on_duplicate: None,
+            shortest_separator: None,
+            reorder_window: 0,
+            max_block_size_hard_cap: None,
+            max_key_size: None,
+            max_value_size: None,
         }
     }
 
+    /// `CompressionType::SnappyFramed` is written in the `FileVersion::FormatV3` format,
+    /// whatever the version asked for, which can only be read by this library.
     pub fn compression_type(&mut self, compression: CompressionType) -> &mut Self {
         self.compression_type = compression;
         self
     }
 
+    /// The level is clamped to the levels of the compression type when the writer
+    /// is built, see `CompressionType::effective_level` and `Writer::compression_level`.
     pub fn compression_level(&mut self, level: u32) -> &mut Self {
         self.compression_level = level;
         self
     }
 
+    /// Compresses the data blocks with the given zstd dictionary, only used with
+    /// `CompressionType::Zstd`. The dictionary id is stored in the metadata and the
+    /// same dictionary must be given to the `ReaderBuilder` to read the file back,
+    /// the raw content dictionaries have no id and are identified by a hash of their content.
+    #[cfg(feature = "zstd_dict")]
+    pub fn compression_dictionary(&mut self, dictionary: Vec<u8>) -> &mut Self {
+        self.compression_dictionary = Some(Arc::from(dictionary));
+        self
+    }
+
+    /// Enables the long distance matching of zstd, which finds the repetitions far
+    /// apart in big blocks, only used with `CompressionType::Zstd`.
+    pub fn zstd_long_distance(&mut self, enable: bool) -> &mut Self {
+        self.zstd_params.long_distance = enable;
+        self
+    }
+
+    /// Compresses each block with `workers` zstd threads, zero compresses on the calling
+    /// thread, only used with `CompressionType::Zstd`. Worth it with big blocks only.
+    pub fn zstd_workers(&mut self, workers: u32) -> &mut Self {
+        self.zstd_params.workers = workers;
+        self
+    }
+
+    /// The compression of the index block, stored in the metadata. The index is not
+    /// compressed by default, compressing it is worth it with many blocks and long keys.
+    ///
+    /// A compressed index is written in the `FileVersion::FormatV3` format, whatever the
+    /// version asked for, which can only be read by this library. The values are only
+    /// deduplicated with `dedup_repeated_values`.
+    pub fn index_compression_type(&mut self, compression: CompressionType) -> &mut Self {
+        self.index_compression_type = compression;
+        self
+    }
+
     pub fn block_size(&mut self, block_size: u64) -> &mut Self {
         self.block_size = cmp::max(block_size, MIN_BLOCK_SIZE);
         self
@@ -50,33 +163,402 @@ impl WriterBuilder {
         self
     }
 
+    /// The entries bigger than `bytes` are written alone in their own block, they do
+    /// not inflate a block shared with other entries. It defaults to the block size
+    /// and can only be lower than it.
+    pub fn max_block_size_hard_cap(&mut self, bytes: u64) -> &mut Self {
+        self.max_block_size_hard_cap = Some(bytes);
+        self
+    }
+
+    /// The format of the file, `FormatV1` stores the lengths of the blocks as
+    /// fixed 32-bit integers and can be read by the older versions of mtbl.
+    pub fn file_version(&mut self, version: FileVersion) -> &mut Self {
+        self.file_version = version;
+        self
+    }
+
+    /// Sizes the data blocks to write approximately `count` blocks, given the number
+    /// of entries announced with `expected_entries`, without it this setting is ignored.
+    ///
+    /// A block is written as soon as it reaches its share of the entries or the
+    /// `block_size`, whichever comes first, a small `block_size` can therefore
+    /// produce more blocks than requested.
+    pub fn target_block_count(&mut self, count: usize) -> &mut Self {
+        self.target_block_count = Some(count);
+        self
+    }
+
+    /// The number of entries that will be inserted, used by `target_block_count`.
+    pub fn expected_entries(&mut self, count: u64) -> &mut Self {
+        self.expected_entries = Some(count);
+        self
+    }
+
+    /// The algorithm used to compute the checksums of the blocks, stored in the metadata.
+    ///
+    /// Another type than crc32c is written in the `FileVersion::FormatV3` format,
+    /// whatever the version asked for, which can only be read by this library. The values
+    /// are only deduplicated with `dedup_repeated_values`. A type not supported by this
+    /// build fails the writes of the blocks.
+    pub fn checksum_type(&mut self, checksum_type: ChecksumType) -> &mut Self {
+        self.checksum_type = checksum_type;
+        self
+    }
+
+    /// Stores every key of the data blocks in full instead of only the suffix not shared
+    /// with the previous key, every entry can then be decoded independently.
+    ///
+    /// Files are bigger when keys share long prefixes, the index block is not affected.
+    pub fn disable_prefix_compression(&mut self) -> &mut Self {
+        self.prefix_compression = false;
+        self
+    }
+
+    /// Stores a value equal to the value of the previous entry of the data block as a
+    /// reference to it, the file is then written in the `FileVersion::FormatV3` format,
+    /// whatever the version asked for, which can only be read by this library.
+    pub fn dedup_repeated_values(&mut self, dedup: bool) -> &mut Self {
+        self.dedup_repeated_values = dedup;
+        self
+    }
+
+    /// Splits an index bigger than a data block in segments of about a data block,
+    /// written under a sparse top-level index of their last keys, a seek then only
+    /// searches the top-level index and a single segment.
+    ///
+    /// The file is written in the `FileVersion::FormatV3` format, and in the
+    /// `FileVersion::FormatV4` one when the index is split. The values are only
+    /// deduplicated with `dedup_repeated_values`.
+    pub fn two_level_index(&mut self, enabled: bool) -> &mut Self {
+        self.two_level_index = enabled;
+        self
+    }
+
+    /// Stores the first and the last keys of the table in the metadata, see
+    /// `Reader::key_range`. They are not stored when they do not fit in it,
+    /// the keys of a few hundred bytes in total do.
+    pub fn store_key_range(&mut self, store: bool) -> &mut Self {
+        self.store_key_range = store;
+        self
+    }
+
+    /// Stores the checksum of every value after it, computed with the checksum type
+    /// of the file, see `Reader::verify_entry_checksums`. It finds the entries of a
+    /// corrupted block, the values are returned without their checksum.
+    ///
+    /// The file is written in the `FileVersion::FormatV5` format, whatever the version
+    /// asked for, which can only be read by this library. The values are only
+    /// deduplicated with `dedup_repeated_values`.
+    pub fn per_entry_checksum(&mut self, enabled: bool) -> &mut Self {
+        self.per_entry_checksum = enabled;
+        self
+    }
+
+    /// Accepts the same key inserted several times in a row, the values are merged
+    /// with `merge(key, previous value, new value)` before being written.
+    /// Without it, and for keys inserted out of order, `insert` panics.
+    pub fn on_duplicate<F>(&mut self, merge: F) -> &mut Self
+    where F: Fn(&[u8], &[u8], &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.on_duplicate = Some(DuplicateMerge(Arc::new(merge)));
+        self
+    }
+
+    /// Replaces the computation of the index keys, `separator(start, limit)` must
+    /// shorten `start`, the last key of a block, into a key greater than or equal
+    /// to it and smaller than `limit`, the first key of the next block, in the order
+    /// the keys are sorted by. The default one is for the lexicographic byte order.
+    /// The separators are checked against the keys in debug builds.
+    pub fn shortest_separator<F>(&mut self, separator: F) -> &mut Self
+    where F: Fn(&mut Vec<u8>, &[u8]) + Send + Sync + 'static,
+    {
+        self.shortest_separator = Some(Separator(Arc::new(separator)));
+        self
+    }
+
+    /// Accepts keys inserted up to `size` positions out of order, the last `size`
+    /// entries are kept in a buffer and written in order. Keys that arrive later
+    /// than that make `insert` return an `InvalidInput` error.
+    ///
+    /// Entries inserted within the window are reordered, the duplicates keep
+    /// their insertion order. A `size` of zero disables the window.
+    pub fn reorder_window(&mut self, size: usize) -> &mut Self {
+        self.reorder_window = size;
+        self
+    }
+
+    /// Makes `insert` refuse the keys longer than `size` bytes with an `InvalidInput`
+    /// error wrapping `MtblError::KeyTooLarge`, nothing is written. Unlimited by default.
+    pub fn max_key_size(&mut self, size: usize) -> &mut Self {
+        self.max_key_size = Some(size);
+        self
+    }
+
+    /// Makes `insert` refuse the values longer than `size` bytes with an `InvalidInput`
+    /// error wrapping `MtblError::ValueTooLarge`, nothing is written. Unlimited by default.
+    pub fn max_value_size(&mut self, size: usize) -> &mut Self {
+        self.max_value_size = Some(size);
+        self
+    }
+
+    /// The capacity of the buffer used by the `buffered` writers.
+    pub fn buffer_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    pub fn with_compression_type(mut self, compression: CompressionType) -> Self {
+        self.compression_type(compression);
+        self
+    }
+
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression_level(level);
+        self
+    }
+
+    pub fn with_index_compression_type(mut self, compression: CompressionType) -> Self {
+        self.index_compression_type(compression);
+        self
+    }
+
+    #[cfg(feature = "zstd_dict")]
+    pub fn with_compression_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.compression_dictionary(dictionary);
+        self
+    }
+
+    pub fn with_zstd_long_distance(mut self, enable: bool) -> Self {
+        self.zstd_long_distance(enable);
+        self
+    }
+
+    pub fn with_zstd_workers(mut self, workers: u32) -> Self {
+        self.zstd_workers(workers);
+        self
+    }
+
+    pub fn with_block_size(mut self, block_size: u64) -> Self {
+        self.block_size(block_size);
+        self
+    }
+
+    pub fn with_block_restart_interval(mut self, interval: usize) -> Self {
+        self.block_restart_interval(interval);
+        self
+    }
+
+    pub fn with_max_block_size_hard_cap(mut self, bytes: u64) -> Self {
+        self.max_block_size_hard_cap(bytes);
+        self
+    }
+
+    pub fn with_dedup_repeated_values(mut self, dedup: bool) -> Self {
+        self.dedup_repeated_values(dedup);
+        self
+    }
+
+    pub fn with_two_level_index(mut self, enabled: bool) -> Self {
+        self.two_level_index(enabled);
+        self
+    }
+
+    pub fn with_store_key_range(mut self, store: bool) -> Self {
+        self.store_key_range(store);
+        self
+    }
+
+    pub fn with_per_entry_checksum(mut self, enabled: bool) -> Self {
+        self.per_entry_checksum(enabled);
+        self
+    }
+
+    pub fn with_file_version(mut self, version: FileVersion) -> Self {
+        self.file_version(version);
+        self
+    }
+
+    pub fn with_target_block_count(mut self, count: usize) -> Self {
+        self.target_block_count(count);
+        self
+    }
+
+    pub fn with_expected_entries(mut self, count: u64) -> Self {
+        self.expected_entries(count);
+        self
+    }
+
+    pub fn with_checksum_type(mut self, checksum_type: ChecksumType) -> Self {
+        self.checksum_type(checksum_type);
+        self
+    }
+
+    pub fn with_max_key_size(mut self, size: usize) -> Self {
+        self.max_key_size(size);
+        self
+    }
+
+    pub fn with_max_value_size(mut self, size: usize) -> Self {
+        self.max_value_size(size);
+        self
+    }
+
+    pub fn with_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_capacity(capacity);
+        self
+    }
+
+    pub fn with_on_duplicate<F>(mut self, merge: F) -> Self
+    where F: Fn(&[u8], &[u8], &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.on_duplicate(merge);
+        self
+    }
+
+    pub fn with_shortest_separator<F>(mut self, separator: F) -> Self
+    where F: Fn(&mut Vec<u8>, &[u8]) + Send + Sync + 'static,
+    {
+        self.shortest_separator(separator);
+        self
+    }
+
+    pub fn with_reorder_window(mut self, size: usize) -> Self {
+        self.reorder_window(size);
+        self
+    }
+
     pub fn build<W: io::Write>(&mut self, writer: W) -> Writer<W> {
+        let compression_dictionary = match self.compression_type {
+            CompressionType::Zstd => self.compression_dictionary.clone(),
+            _ => None,
+        };
+
+        let file_version = if self.per_entry_checksum {
+            FileVersion::FormatV5
+        } else if self.dedup_repeated_values
+            || self.two_level_index
+            || self.index_compression_type != CompressionType::None
+            || self.checksum_type != ChecksumType::Crc32c
+            || self.compression_type == CompressionType::SnappyFramed
+        {
+            FileVersion::FormatV3
+        } else {
+            self.file_version
+        };
+
         // derive default eventually
         let metadata = Metadata {
+            file_version,
             data_block_size: self.block_size,
             compression_algorithm: self.compression_type,
+            index_compression_algorithm: self.index_compression_type,
+            compression_dictionary_id: compression_dictionary.as_deref().map_or(0, zstd_dictionary_id),
+            checksum_type: self.checksum_type,
             ..Metadata::default()
         };
 
         let last_offset = 0;
 
+        let block_entries = match (self.target_block_count, self.expected_entries) {
+            (Some(blocks), Some(entries)) if blocks > 0 => {
+                Some(cmp::max(entries.div_ceil(blocks as u64), 1) as usize)
+            },
+            _ => None,
+        };
+
+        let compression = BlockCompression {
+            type_: self.compression_type,
+            level: self.compression_type.effective_level(self.compression_level),
+            dictionary: compression_dictionary,
+            zstd_params: self.zstd_params,
+        };
+
+        // the dictionary is trained on the data blocks, not on the index
+        let index_compression = BlockCompression {
+            type_: self.index_compression_type,
+            level: self.index_compression_type.effective_level(self.compression_level),
+            dictionary: None,
+            zstd_params: self.zstd_params,
+        };
+
+        let mut data = BlockBuilder::new(self.block_restart_interval);
+        data.set_prefix_compression(self.prefix_compression);
+        data.set_repeated_values(file_version.repeated_values());
+        data.set_keep_repeated_values(!self.dedup_repeated_values);
+
         Writer {
             writer,
             metadata,
-            compression_type: self.compression_type,
-            compression_level: self.compression_level,
+            compression,
+            index_compression,
             last_offset,
             pending_offset: last_offset,
             last_key: Vec::with_capacity(256),
-            data: BlockBuilder::new(self.block_restart_interval),
+            first_key: if self.store_key_range { Some(Vec::new()) } else { None },
+            data,
             index: BlockBuilder::new(self.block_restart_interval),
+            index_segment: if self.two_level_index { Some(BlockBuilder::new(self.block_restart_interval)) } else { None },
             pending_index_entry: false,
+            block_entries,
+            on_duplicate: self.on_duplicate.clone(),
+            shortest_separator: self.shortest_separator.clone(),
+            pending_entry: None,
+            reorder_window: self.reorder_window,
+            window: VecDeque::with_capacity(self.reorder_window),
+            window_last: None,
+            flush_blocks: false,
+            entry_size_cap: self.max_block_size_hard_cap.map_or(self.block_size, |cap| cmp::min(cap, self.block_size)),
+            max_key_size: self.max_key_size,
+            max_value_size: self.max_value_size,
+            checksummed_value: Vec::new(),
         }
     }
 
+    /// Builds a writer that buffers the many small writes of the blocks,
+    /// useful when the underlying writer is a socket or an unbuffered file.
+    ///
+    /// The buffer is flushed by `into_inner` and `finish`, `into_unbuffered`
+    /// can be used to retrieve the underlying writer.
+    pub fn buffered<W: io::Write>(&mut self, writer: W) -> Writer<BufWriter<W>> {
+        self.build(BufWriter::with_capacity(self.buffer_capacity, writer))
+    }
+
     pub fn memory(&mut self) -> Writer<Vec<u8>> {
         self.build(Vec::new())
     }
+
+    /// Builds a writer that sends every block down the channel as soon as it is
+    /// written, the metadata is sent last. The messages concatenated form the file.
+    ///
+    /// A bounded channel blocks the writer until the consumer catches up.
+    pub fn channel(&mut self, sender: SyncSender<Vec<u8>>) -> Writer<BlockSender> {
+        let mut writer = self.build(BlockSender { sender, buffer: Vec::new() });
+        writer.flush_blocks = true;
+        writer
+    }
+}
+
+/// Sends the bytes written since the last flush down a channel, see `WriterBuilder::channel`.
+pub struct BlockSender {
+    sender: SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl io::Write for BlockSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() { return Ok(()) }
+
+        let bytes = mem::take(&mut self.buffer);
+        self.sender.send(bytes).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "the block receiver has been dropped")
+        })
+    }
 }
 
 pub struct Writer<W> {
@@ -84,12 +566,47 @@ pub struct Writer<W> {
     metadata: Metadata,
     data: BlockBuilder,
     index: BlockBuilder,
-    compression_type: CompressionType,
-    compression_level: u32,
+    /// The builder of the index segments, see `WriterBuilder::two_level_index`.
+    index_segment: Option<BlockBuilder>,
+    compression: BlockCompression,
+    index_compression: BlockCompression,
     last_key: Vec<u8>,
+    /// The first key of the table, if stored in the metadata, see `WriterBuilder::store_key_range`.
+    first_key: Option<Vec<u8>>,
     last_offset: u64,
     pending_index_entry: bool,
     pending_offset: u64,
+    block_entries: Option<usize>,
+    on_duplicate: Option<DuplicateMerge>,
+    shortest_separator: Option<Separator>,
+    /// The last inserted entry, only written once a different key
+    /// is inserted when the duplicates are merged.
+    pending_entry: Option<(Vec<u8>, Vec<u8>)>,
+    reorder_window: usize,
+    /// The entries not yet written, sorted by key, see `WriterBuilder::reorder_window`.
+    window: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// The key of the last entry that left the window.
+    window_last: Option<Vec<u8>>,
+    /// Whether the underlying writer is flushed after every block.
+    flush_blocks: bool,
+    /// The entries of this size or bigger are written in their own block.
+    entry_size_cap: u64,
+    max_key_size: Option<usize>,
+    max_value_size: Option<usize>,
+    /// The value being added followed by its checksum, see `WriterBuilder::per_entry_checksum`.
+    checksummed_value: Vec<u8>,
+}
+
+impl<W> fmt::Debug for Writer<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("count_entries", &self.metadata.count_entries)
+            .field("count_data_blocks", &self.metadata.count_data_blocks)
+            .field("pending_offset", &self.pending_offset)
+            .field("compression_type", &self.compression.type_)
+            .field("compression_level", &self.compression.level)
+            .finish()
+    }
 }
 
 impl Writer<Vec<u8>> {
@@ -113,129 +630,740 @@ impl<W: io::Write> Writer<W> {
     where K: AsRef<[u8]>,
           V: AsRef<[u8]>,
     {
-        let key = key.as_ref();
-        let val = val.as_ref();
+        self.insert_cow(key.as_ref(), Cow::Borrowed(val.as_ref()))
+    }
+
+    /// Like `insert` but the value is moved instead of copied when it is kept
+    /// for later, by the reorder window or to be merged with the duplicates.
+    /// The bytes are always copied once into the block being built. The `write_into`
+    /// methods of the merger and the sorter give their merged values this way.
+    pub fn insert_taking(&mut self, key: &[u8], val: Vec<u8>) -> io::Result<()> {
+        self.insert_cow(key, Cow::Owned(val))
+    }
+
+    pub(crate) fn insert_cow(&mut self, key: &[u8], val: Cow<'_, [u8]>) -> io::Result<()> {
+        if self.max_key_size.is_some_and(|max| key.len() > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, MtblError::KeyTooLarge));
+        }
+        if self.max_value_size.is_some_and(|max| val.len() > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, MtblError::ValueTooLarge));
+        }
+
+        if self.reorder_window == 0 {
+            return self.insert_sorted(key, val);
+        }
+
+        if self.window_last.as_ref().is_some_and(|last| key < last.as_slice()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "key out of order beyond the reorder window"));
+        }
+
+        // after the equal keys to keep the insertion order of the duplicates
+        let pos = self.window.partition_point(|(k, _)| k.as_slice() <= key);
+        self.window.insert(pos, (key.to_vec(), val.into_owned()));
+
+        if self.window.len() > self.reorder_window {
+            let (key, val) = self.window.pop_front().unwrap();
+            self.insert_sorted(&key, Cow::Owned(val))?;
+            self.window_last = Some(key);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts an entry that is in order, merging the duplicates if required.
+    fn insert_sorted(&mut self, key: &[u8], val: Cow<'_, [u8]>) -> io::Result<()> {
+        let merge = match &self.on_duplicate {
+            Some(merge) => merge.clone(),
+            None => return self.add(key, &val),
+        };
+
+        match self.pending_entry.take() {
+            Some((pkey, pval)) if pkey == key => {
+                let merged = (merge.0)(key, &pval, &val);
+                self.pending_entry = Some((pkey, merged));
+            },
+            Some((mut pkey, mut pval)) => {
+                if key < pkey.as_slice() {
+                    panic!("out-of-order key");
+                }
+                self.add(&pkey, &pval)?;
+                // reuse the buffers of the written entry
+                pkey.clear();
+                pkey.extend_from_slice(key);
+                let pval = match val {
+                    Cow::Borrowed(val) => {
+                        pval.clear();
+                        pval.extend_from_slice(val);
+                        pval
+                    },
+                    Cow::Owned(val) => val,
+                };
+                self.pending_entry = Some((pkey, pval));
+            },
+            // the entry of the key was written by flush_block, it can no longer be merged
+            None if self.metadata.count_entries > 0 && key == self.last_key.as_slice() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "duplicate of a key written by flush_block"));
+            },
+            None => self.pending_entry = Some((key.to_vec(), val.into_owned())),
+        }
 
+        Ok(())
+    }
+
+    fn add(&mut self, key: &[u8], val: &[u8]) -> io::Result<()> {
         if self.metadata.count_entries > 0 {
-            if key <= &*self.last_key {
+            if !is_in_order(&self.last_key, key) {
                 panic!("out-of-order key");
             }
         }
 
-        let estimated_block_size = self.data.current_size_estimate();
-        let estimated_block_size = estimated_block_size + 3 * 5 + key.len() + val.len();
+        let checksum_size = if self.metadata.file_version.value_checksums() { VALUE_CHECKSUM_SIZE } else { 0 };
+        let entry_size = 3 * 5 + key.len() + val.len() + checksum_size;
+        let estimated_block_size = self.data.current_size_estimate() + entry_size;
+
+        let block_full = self.block_entries.is_some_and(|max| self.data.len() >= max);
+        let oversized = entry_size as u64 >= self.entry_size_cap;
 
-        if estimated_block_size >= self.metadata.data_block_size as usize {
+        if block_full || oversized || estimated_block_size >= self.metadata.data_block_size as usize {
            self.flush()?;
         }
 
         if self.pending_index_entry {
             let mut enc = [0; 10];
             assert!(self.data.is_empty());
-            bytes_shortest_separator(&mut self.last_key, key);
+            self.shorten_last_key(key);
             self.index.add(&self.last_key, varint_encode64(&mut enc, self.last_offset));
             self.pending_index_entry = false;
         }
 
         self.last_key.clear();
         self.last_key.extend_from_slice(key);
+        self.set_first_key(key);
 
         self.metadata.count_entries += 1;
         self.metadata.bytes_keys += key.len() as u64;
         self.metadata.bytes_values += val.len() as u64;
-        self.data.add(key, val);
+        if self.metadata.file_version.value_checksums() {
+            let crc = checksum(self.metadata.checksum_type, val).unwrap_or(0);
+            self.checksummed_value.clear();
+            self.checksummed_value.extend_from_slice(val);
+            self.checksummed_value.extend_from_slice(&crc.to_le_bytes());
+            self.data.add(key, &self.checksummed_value);
+        } else {
+            self.data.add(key, val);
+        }
+
+        if oversized {
+            self.flush()?;
+        }
 
         Ok(())
     }
 
-    pub fn finish(self) -> io::Result<()> {
-        self.into_inner().map(drop)
+    /// Shortens the last key into the key of the index entry of its block,
+    /// `limit` being the first key of the next block.
+    fn shorten_last_key(&mut self, limit: &[u8]) {
+        match &self.shortest_separator {
+            Some(Separator(separator)) => {
+                #[cfg(debug_assertions)]
+                let last_key = self.last_key.clone();
+                separator(&mut self.last_key, limit);
+                #[cfg(debug_assertions)]
+                debug_assert!(
+                    last_key <= self.last_key && self.last_key.as_slice() < limit,
+                    "the separator must be between the last key of the block and the next key",
+                );
+            },
+            None => bytes_shortest_separator(&mut self.last_key, limit),
+        }
     }
 
-    pub fn into_inner(mut self) -> io::Result<W> {
-        self.flush()?;
+    /// The level the data blocks are compressed with, once clamped to the compression type.
+    pub fn compression_level(&self) -> u32 {
+        self.compression.level
+    }
 
-        if self.pending_index_entry {
-            let mut enc = [0; 10];
-            self.index.add(&self.last_key, varint_encode64(&mut enc, self.last_offset));
-            self.pending_index_entry = false;
+    /// Writes an already compressed data block, as returned by `Reader::raw_block_at`,
+    /// and its index entry without encoding its entries again. The block must be
+    /// encoded and compressed like the blocks of this writer and its keys must come after the
+    /// keys already inserted, `first_key` and `last_key` are the keys it starts and ends with.
+    ///
+    /// The block is decompressed to check its keys and count its entries for the metadata.
+    /// It is refused like `insert` when one of its entries exceeds `max_key_size` or
+    /// `max_value_size`. With `on_duplicate`, its first key cannot be the last key
+    /// inserted, the values of a raw block are not merged.
+    pub fn append_raw_block(
+        &mut self,
+        compressed: &[u8],
+        compression: CompressionType,
+        first_key: &[u8],
+        last_key: &[u8],
+    ) -> io::Result<()>
+    {
+        if compression != self.compression.type_ {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "block compressed with another compression type"));
         }
 
-        self.metadata.index_block_offset = self.pending_offset as u64;
-        self.metadata.bytes_index_block += write_block(
-            &mut self.writer,
-            CompressionType::None,
-            0,
+        // the entries inserted before the block are written first
+        self.write_pending()?;
+
+        if self.metadata.count_entries > 0 && !is_in_order(&self.last_key, first_key) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "out-of-order block"));
+        }
+
+        let stats = raw_block_stats(
+            compressed,
+            compression,
+            self.compression.dictionary.as_deref(),
             self.metadata.file_version,
-            &mut self.last_offset,
-            &mut self.pending_offset,
-            &mut self.index,
-        )? as u64;
+            first_key,
+            last_key,
+        )?;
 
-        // We must write exactly 512 bytes at the end to store the metadata
-        let mut tbuf = [0u8; METADATA_SIZE];
-        self.metadata.write_to_bytes(&mut tbuf)?;
-        self.writer.write_all(&tbuf)?;
+        if self.max_key_size.is_some_and(|max| stats.max_key_len > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, MtblError::KeyTooLarge));
+        }
+        if self.max_value_size.is_some_and(|max| stats.max_value_len > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, MtblError::ValueTooLarge));
+        }
 
-        Ok(self.writer)
-    }
+        self.flush()?;
+        self.set_first_key(first_key);
 
-    fn flush(&mut self) -> io::Result<()> {
-        if self.data.is_empty() { return Ok(()) }
+        if self.pending_index_entry {
+            let mut enc = [0; 10];
+            self.shorten_last_key(first_key);
+            self.index.add(&self.last_key, varint_encode64(&mut enc, self.last_offset));
+        }
 
-        assert!(!self.pending_index_entry);
-        self.metadata.bytes_data_blocks += write_block(
+        let bytes_written = write_block_content(
             &mut self.writer,
-            self.compression_type,
-            self.compression_level,
             self.metadata.file_version,
+            self.metadata.checksum_type,
             &mut self.last_offset,
             &mut self.pending_offset,
-            &mut self.data,
-        )? as u64;
+            compressed,
+        )?;
+
+        self.metadata.bytes_data_blocks += bytes_written as u64;
+        self.metadata.bytes_raw_data_blocks += stats.raw_size as u64;
         self.metadata.count_data_blocks += 1;
+        self.metadata.count_entries += stats.count_entries;
+        self.metadata.bytes_keys += stats.bytes_keys;
+        self.metadata.bytes_values += stats.bytes_values;
         self.pending_index_entry = true;
 
+        self.last_key.clear();
+        self.last_key.extend_from_slice(last_key);
+        if self.reorder_window > 0 {
+            self.window_last = Some(last_key.to_vec());
+        }
+
+        if self.flush_blocks {
+            self.writer.flush()?;
+        }
+
         Ok(())
     }
-}
 
-fn write_block<W: io::Write>(
-    writer: &mut W,
-    compression_type: CompressionType,
-    compression_level: u32,
-    file_version: FileVersion,
-    last_offset: &mut u64,
-    pending_offset: &mut u64,
-    block: &mut BlockBuilder,
-) -> io::Result<usize>
-{
-    let raw_content = block.finish();
-    let block_content = compress(compression_type, compression_level, &raw_content)?;
-    assert!(file_version == FileVersion::FormatV2);
+    /// Copies the entries of `reader` whose keys are in `start..end`, they must come after
+    /// the keys already inserted, and returns the number of entries copied.
+    ///
+    /// When the reader is compressed and encoded like this writer, the data blocks
+    /// entirely in the range are appended as is, see `append_raw_block`, the entries
+    /// of the blocks at the edges of the range are inserted one by one. The copied entries
+    /// are checked against `max_key_size` and `max_value_size` like the inserted ones.
+    ///
+    /// With `on_duplicate`, the entries of the first block are always inserted one by one,
+    /// its first key can be a duplicate of the last key inserted and is merged with it.
+    pub fn copy_range_from<A: AsRef<[u8]>>(
+        &mut self,
+        reader: &Reader<A>,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<usize, Error>
+    {
+        let metadata = reader.metadata();
+        let raw_compatible = metadata.compression_algorithm == self.compression.type_
+            && metadata.compression_dictionary_id == self.metadata.compression_dictionary_id
+            && metadata.file_version.repeated_values() == self.metadata.file_version.repeated_values()
+            && metadata.file_version.value_checksums() == self.metadata.file_version.value_checksums()
+            && (!metadata.file_version.value_checksums() || metadata.checksum_type == self.metadata.checksum_type);
 
-    #[cfg(feature = "checksum")]
-    let crc = crc32c::crc32c(&block_content).to_le_bytes();
-    #[cfg(not(feature = "checksum"))]
-    let crc = 0u32.to_le_bytes();
+        let mut count = 0;
+        if start >= end { return Ok(count) }
 
-    let mut len = [0; 10];
-    let len = varint_encode64(&mut len, block_content.len() as u64);
-    writer.write_all(len)?;
-    // already performed conversion before...
+        let mut index_iter = IndexIter::init(reader.index().clone());
+        index_iter.seek(start)?;
+        let mut first_block = true;
+        while let Some(offset) = reader.block_offset_at(&index_iter) {
+            // the next blocks only contain keys greater than the keys already copied
+            let raw_compatible = raw_compatible && !(first_block && self.on_duplicate.is_some());
+            first_block = false;
+
+            let mut bi = BlockIter::init(Arc::new(reader.block(offset)?));
+            bi.seek_to_last()?;
+            let last_key = bi.key().map(<[u8]>::to_vec);
+            bi.seek_to_first()?;
+
+            match (bi.key(), last_key.as_deref()) {
+                (Some(first_key), Some(last_key)) if raw_compatible && first_key >= start && last_key < end => {
+                    // the entries waiting in the writer are not counted
+                    self.write_pending()?;
+                    let before = self.metadata.count_entries;
+                    let compressed = reader.raw_block(offset)?;
+                    self.append_raw_block(compressed, self.compression.type_, first_key, last_key)?;
+                    count += (self.metadata.count_entries - before) as usize;
+                },
+                _ => {
+                    bi.seek(start)?;
+                    while let Some((key, val)) = bi.get() {
+                        if key >= end { return Ok(count) }
+                        self.insert(key, val)?;
+                        count += 1;
+                        bi.next()?;
+                    }
+                },
+            }
+
+            if last_key.is_some_and(|key| key.as_slice() >= end) { break }
+            index_iter.next()?;
+        }
+
+        Ok(count)
+    }
+
+    /// Writes the current data block even if it is not full, the next entry starts
+    /// a new block. The entries held by the reorder window or waiting for their
+    /// duplicates are written first. Nothing is done if the block is empty.
+    ///
+    /// With `WriterBuilder::on_duplicate`, inserting again the last key written
+    /// by this method returns an `InvalidInput` error, its values cannot be merged.
+    pub fn flush_block(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        if self.reorder_window > 0 && self.metadata.count_entries > 0 {
+            self.window_last = Some(self.last_key.clone());
+        }
+        self.flush()
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.into_inner().map(drop)
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.into_inner_with_metadata().map(|(writer, _)| writer)
+    }
+
+    /// Like `into_inner` but also returns the metadata written at the end of the file.
+    pub fn into_inner_with_metadata(mut self) -> io::Result<(W, Metadata)> {
+        self.write_end()?;
+        Ok((self.writer, self.metadata))
+    }
+
+    /// Writes the end of the current file, then resets this writer to start
+    /// a new file in `new_writer` and returns the writer of the finished file.
+    pub fn finish_and_reset(&mut self, new_writer: W) -> io::Result<W> {
+        self.write_end()?;
+        let writer = mem::replace(&mut self.writer, new_writer);
+        self.reset_state();
+        Ok(writer)
+    }
+
+    /// Replaces the underlying writer and starts a new file, reusing the buffers
+    /// of this writer. Nothing is written to the previous writer, the entries
+    /// inserted since the creation or the last reset are discarded.
+    pub fn reset(&mut self, new_writer: W) -> io::Result<()> {
+        self.writer = new_writer;
+        self.reset_state();
+        Ok(())
+    }
+
+    /// Writes the entries of the reorder window and the entry waiting for its duplicates.
+    fn write_pending(&mut self) -> io::Result<()> {
+        for (key, val) in mem::take(&mut self.window) {
+            self.insert_sorted(&key, Cow::Owned(val))?;
+        }
+
+        if let Some((key, val)) = self.pending_entry.take() {
+            self.add(&key, &val)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_end(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.flush()?;
+
+        if self.pending_index_entry {
+            let mut enc = [0; 10];
+            self.index.add(&self.last_key, varint_encode64(&mut enc, self.last_offset));
+            self.pending_index_entry = false;
+        }
+
+        if self.index_segment.is_some() && self.index.current_size_estimate() as u64 > self.metadata.data_block_size {
+            self.write_index_segments()?;
+        }
+
+        self.metadata.index_block_offset = self.pending_offset as u64;
+        let (bytes_written, _raw_size) = write_block(
+            &mut self.writer,
+            &self.index_compression,
+            self.metadata.file_version,
+            self.metadata.checksum_type,
+            &mut self.last_offset,
+            &mut self.pending_offset,
+            &mut self.index,
+        )?;
+        self.metadata.bytes_index_block += bytes_written as u64;
+        if let Some(first_key) = self.first_key.as_ref().filter(|_| self.metadata.count_entries > 0) {
+            self.metadata.set_key_range(first_key, &self.last_key);
+        }
+        if self.flush_blocks {
+            self.writer.flush()?;
+        }
+
+        // We must write exactly 512 bytes at the end to store the metadata
+        let mut tbuf = [0u8; METADATA_SIZE];
+        self.metadata.write_to_bytes(&mut tbuf)?;
+        self.writer.write_all(&tbuf)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Keeps the key of the first entry of the table, if the key range is stored.
+    fn set_first_key(&mut self, key: &[u8]) {
+        match &mut self.first_key {
+            Some(first_key) if self.metadata.count_entries == 0 => {
+                first_key.clear();
+                first_key.extend_from_slice(key);
+            },
+            _ => (),
+        }
+    }
+
+    /// Writes the entries of the index in segments of about a data block, the
+    /// index then becomes the top-level index of the last keys of the segments.
+    fn write_index_segments(&mut self) -> io::Result<()> {
+        let segment = self.index_segment.as_mut().unwrap();
+        let index = Block::init(BytesView::from(self.index.finish())).map_err(io::Error::other)?;
+        self.index.reset();
+
+        let mut enc = [0; 10];
+        let mut last_key = Vec::new();
+        let mut index_iter = BlockIter::init(Arc::new(index));
+        index_iter.seek_to_first().map_err(io::Error::other)?;
+        while let Some((key, val)) = index_iter.get() {
+            segment.add(key, val);
+            last_key.clear();
+            last_key.extend_from_slice(key);
+
+            let last = !index_iter.next().map_err(io::Error::other)?;
+            if last || segment.current_size_estimate() as u64 >= self.metadata.data_block_size {
+                let (bytes_written, _raw_size) = write_block(
+                    &mut self.writer,
+                    &self.index_compression,
+                    self.metadata.file_version,
+                    self.metadata.checksum_type,
+                    &mut self.last_offset,
+                    &mut self.pending_offset,
+                    segment,
+                )?;
+                self.metadata.bytes_index_segments += bytes_written as u64;
+                self.metadata.count_index_segments += 1;
+                self.index.add(&last_key, varint_encode64(&mut enc, self.last_offset));
+            }
+        }
+
+        self.metadata.file_version = cmp::max(self.metadata.file_version, FileVersion::FormatV4);
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {
+        // a table with a two-level index is the only one written in the V4 format
+        let file_version = match self.metadata.file_version {
+            FileVersion::FormatV4 if self.index_segment.is_some() => FileVersion::FormatV3,
+            file_version => file_version,
+        };
+        self.metadata = Metadata {
+            file_version,
+            data_block_size: self.metadata.data_block_size,
+            compression_algorithm: self.metadata.compression_algorithm,
+            index_compression_algorithm: self.metadata.index_compression_algorithm,
+            compression_dictionary_id: self.metadata.compression_dictionary_id,
+            checksum_type: self.metadata.checksum_type,
+            ..Metadata::default()
+        };
+        self.last_key.clear();
+        self.last_offset = 0;
+        self.pending_offset = 0;
+        self.pending_index_entry = false;
+        self.pending_entry = None;
+        self.window.clear();
+        self.window_last = None;
+        self.data.reset();
+        self.index.reset();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.data.is_empty() { return Ok(()) }
+
+        assert!(!self.pending_index_entry);
+        let (bytes_written, raw_size) = write_block(
+            &mut self.writer,
+            &self.compression,
+            self.metadata.file_version,
+            self.metadata.checksum_type,
+            &mut self.last_offset,
+            &mut self.pending_offset,
+            &mut self.data,
+        )?;
+        self.metadata.bytes_data_blocks += bytes_written as u64;
+        self.metadata.bytes_raw_data_blocks += raw_size as u64;
+        self.metadata.count_data_blocks += 1;
+        self.pending_index_entry = true;
+
+        if self.flush_blocks {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the entries of the map into an in-memory file built with `writer_builder`.
+/// The keys must be ordered like their bytes, e.g. `Vec<u8>` or `String`.
+pub fn write_btreemap<K, V>(map: &BTreeMap<K, V>, mut writer_builder: WriterBuilder) -> io::Result<Vec<u8>>
+where K: AsRef<[u8]>,
+      V: AsRef<[u8]>,
+{
+    let mut writer = writer_builder.memory();
+    for (key, val) in map {
+        writer.insert(key, val)?;
+    }
+    writer.into_inner()
+}
+
+impl<W: io::Write> Writer<BufWriter<W>> {
+    /// Writes the end of the file, flushes the buffer and returns the underlying writer.
+    pub fn into_unbuffered(self) -> io::Result<W> {
+        let writer = self.into_inner()?;
+        writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: SyncWrite> Writer<W> {
+    /// Like `finish` but also makes the file durable, see `into_inner_synced`.
+    pub fn finish_synced(self) -> io::Result<()> {
+        self.into_inner_synced().map(drop)
+    }
+
+    /// Writes the end of the file then syncs it to the disk before returning the
+    /// underlying writer, a crash can no longer lose a file that looks complete.
+    pub fn into_inner_synced(self) -> io::Result<W> {
+        let mut writer = self.into_inner()?;
+        writer.sync()?;
+        Ok(writer)
+    }
+}
+
+/// The writers that can make their content durable, see `Writer::finish_synced`.
+///
+/// Only the files are synced to the disk, the other writers are only flushed. It can
+/// be implemented for any other writer, the default `sync` only flushes it, the plain
+/// `Writer::finish` is available for the writers that do not implement it.
+///
+/// ```
+/// use std::io;
+/// use oxidized_mtbl::{SyncWrite, WriterBuilder};
+///
+/// struct Upload(Vec<u8>);
+///
+/// impl io::Write for Upload {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         self.0.extend_from_slice(buf);
+///         Ok(buf.len())
+///     }
+///     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+/// }
+///
+/// // nothing more to make durable than a flush
+/// impl SyncWrite for Upload {}
+///
+/// let mut writer = WriterBuilder::new().build(Upload(Vec::new()));
+/// writer.insert("hello", "world")?;
+/// writer.finish_synced()?;
+/// # Ok::<(), io::Error>(())
+/// ```
+pub trait SyncWrite: io::Write {
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl SyncWrite for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl<W: SyncWrite> SyncWrite for BufWriter<W> {
+    fn sync(&mut self) -> io::Result<()> {
+        io::Write::flush(self)?;
+        self.get_mut().sync()
+    }
+}
+
+impl<W: SyncWrite + ?Sized> SyncWrite for &mut W {
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+impl<W: SyncWrite + ?Sized> SyncWrite for Box<W> {
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+impl SyncWrite for Vec<u8> {}
+impl SyncWrite for io::Cursor<Vec<u8>> {}
+
+/// How the content of a block must be compressed before being written.
+struct BlockCompression {
+    type_: CompressionType,
+    level: u32,
+    dictionary: Option<Arc<[u8]>>,
+    zstd_params: ZstdParams,
+}
+
+fn write_block<W: io::Write>(
+    writer: &mut W,
+    compression: &BlockCompression,
+    file_version: FileVersion,
+    checksum_type: ChecksumType,
+    last_offset: &mut u64,
+    pending_offset: &mut u64,
+    block: &mut BlockBuilder,
+) -> io::Result<(usize, usize)>
+{
+    let raw_content = block.finish();
+    let block_content = compress(compression.type_, compression.level, compression.dictionary.as_deref(), compression.zstd_params, &raw_content)?;
+    let bytes_written = write_block_content(writer, file_version, checksum_type, last_offset, pending_offset, &block_content)?;
+
+    block.reset();
+
+    Ok((bytes_written, raw_content.len()))
+}
+
+/// Writes the length, the checksum and the already compressed content of a block.
+fn write_block_content<W: io::Write>(
+    writer: &mut W,
+    file_version: FileVersion,
+    checksum_type: ChecksumType,
+    last_offset: &mut u64,
+    pending_offset: &mut u64,
+    block_content: &[u8],
+) -> io::Result<usize>
+{
+    if !checksum_type.is_supported() {
+        return Err(io::Error::other(MtblError::UnsupportedChecksumAlgorithm));
+    }
+    let crc = checksum(checksum_type, block_content).unwrap_or(0).to_le_bytes();
+
+    let mut len = [0; 10];
+    let len = match file_version {
+        FileVersion::FormatV1 => {
+            let block_len = u32::try_from(block_content.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "block too big for the V1 format")
+            })?;
+            LittleEndian::write_u32(&mut len, block_len);
+            &len[..mem::size_of::<u32>()]
+        },
+        FileVersion::FormatV2 | FileVersion::FormatV3 | FileVersion::FormatV4 | FileVersion::FormatV5 => {
+            varint_encode64(&mut len, block_content.len() as u64)
+        },
+    };
+    writer.write_all(len)?;
+    // already performed conversion before...
     writer.write_all(&crc)?;
-    writer.write_all(&block_content)?;
+    writer.write_all(block_content)?;
 
     let bytes_written = len.len() + crc.len() + block_content.len();
 
     *last_offset = *pending_offset;
     *pending_offset += bytes_written as u64;
 
-    block.reset();
-
     Ok(bytes_written)
 }
 
+struct RawBlockStats {
+    raw_size: usize,
+    count_entries: u64,
+    bytes_keys: u64,
+    bytes_values: u64,
+    max_key_len: usize,
+    max_value_len: usize,
+}
+
+/// Decodes a compressed block to count its entries, checking that
+/// it starts with `first_key` and ends with `last_key`.
+fn raw_block_stats(
+    compressed: &[u8],
+    compression: CompressionType,
+    dictionary: Option<&[u8]>,
+    file_version: FileVersion,
+    first_key: &[u8],
+    last_key: &[u8],
+) -> io::Result<RawBlockStats>
+{
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let raw = decompress(compression, dictionary, compressed, usize::MAX)?.into_owned();
+    let raw_size = raw.len();
+    let block = Block::<Vec<u8>>::init(BytesView::from_bytes(raw)).map_err(|_| invalid("invalid block"))?;
+    let block = block.with_repeated_values(file_version.repeated_values())
+        .with_value_checksums(file_version.value_checksums());
+
+    let mut stats = RawBlockStats {
+        raw_size,
+        count_entries: 0,
+        bytes_keys: 0,
+        bytes_values: 0,
+        max_key_len: 0,
+        max_value_len: 0,
+    };
+    let undecodable = |_| invalid("the entries of the block cannot be decoded");
+    let mut iter = BlockIter::init(Arc::new(block));
+    iter.seek_to_first().map_err(undecodable)?;
+    if iter.key() != Some(first_key) {
+        return Err(invalid("the block does not start with the first key"));
+    }
+
+    let mut prev_key = Vec::new();
+    while let Some((key, val)) = iter.get() {
+        if stats.count_entries > 0 && key <= prev_key.as_slice() {
+            return Err(invalid("out-of-order key in the block"));
+        }
+        stats.count_entries += 1;
+        stats.bytes_keys += key.len() as u64;
+        stats.bytes_values += val.len() as u64;
+        stats.max_key_len = cmp::max(stats.max_key_len, key.len());
+        stats.max_value_len = cmp::max(stats.max_value_len, val.len());
+        prev_key.clear();
+        prev_key.extend_from_slice(key);
+        iter.next().map_err(undecodable)?;
+    }
+
+    if prev_key != last_key {
+        return Err(invalid("the block does not end with the last key"));
+    }
+
+    Ok(stats)
+}
+
 fn bytes_shortest_separator(start: &mut Vec<u8>, limit: &[u8]) {
     let min_length = if start.len() < limit.len() { start.len() } else { limit.len() };
 
@@ -247,6 +1375,8 @@ fn bytes_shortest_separator(start: &mut Vec<u8>, limit: &[u8]) {
 
     if diff_index >= min_length { return }
 
+    if start.as_slice() >= limit { return }
+
     let diff_byte = start[diff_index];
     if diff_byte < u8::max_value() && diff_byte + 1 < limit[diff_index] {
         start[diff_index] += 1;
@@ -255,13 +1385,14 @@ fn bytes_shortest_separator(start: &mut Vec<u8>, limit: &[u8]) {
         // awww yeah, big endian arithmetic on strings
         let u_start = BigEndian::read_u16(&start[diff_index..]);
         let u_limit = BigEndian::read_u16(&limit[diff_index..]);
-        let u_between = u_start + 1;
+        let u_between = u_start.wrapping_add(1);
         if u_start <= u_between && u_between <= u_limit {
-            let _ = start.write_u16::<BigEndian>(u_between);
+            start.truncate(diff_index + mem::size_of::<u16>());
+            BigEndian::write_u16(&mut start[diff_index..], u_between);
         }
     }
 
-    assert!(start.as_slice() < limit);
+    debug_assert!(start.as_slice() < limit);
 }
 
 #[cfg(test)]
@@ -269,6 +1400,19 @@ mod tests {
     use super::*;
     use crate::Reader;
 
+    /// The big-endian numbers below 1000 with their decimal strings as values.
+    fn numbers_table(builder: &mut WriterBuilder) -> Vec<u8> {
+        let mut writer = builder.memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn numbers_reader(block_size: usize) -> Reader<Vec<u8>> {
+        Reader::new(numbers_table(WriterBuilder::new().block_size(block_size as u64))).unwrap()
+    }
+
     #[test]
     fn empty() {
         let writer = WriterBuilder::new().memory();
@@ -297,10 +1441,1032 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn chained_builder() {
+        let builder = WriterBuilder::new().with_block_size(1024);
+        let builder = builder.with_compression_type(CompressionType::Snappy).with_block_restart_interval(4);
+
+        let mut writer = builder.clone().memory();
+        for i in 0..100u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = crate::ReaderBuilder::new().with_verify_checksums(false).read(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().data_block_size, 1024);
+        assert_eq!(reader.metadata().compression_algorithm, CompressionType::Snappy);
+        assert_eq!(reader.metadata().count_entries, 100);
+    }
+
+    #[test]
+    fn tiny_blocks() {
+        let mut writer = WriterBuilder::new().block_size(64).memory();
+        for i in 0..20u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().data_block_size, 64);
+        assert!(reader.metadata().count_data_blocks > 1);
+
+        for i in 0..20u32 {
+            let val = reader.clone().get(&i.to_be_bytes()).unwrap().unwrap();
+            assert_eq!(val.as_ref(), i.to_string().as_bytes());
+        }
+
+        let mut count = 0u32;
+        let mut iter = reader.into_iter().unwrap();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 20);
+    }
+
+    #[test]
+    fn into_inner_with_metadata() {
+        let mut writer = WriterBuilder::new().with_block_size(1024).memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let (vec, metadata) = writer.into_inner_with_metadata().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(&metadata, reader.metadata());
+        assert_eq!(metadata.count_entries, 1000);
+        assert!(metadata.count_data_blocks > 1);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn compression_ratio() {
+        let ratio = |val: &dyn Fn(u32) -> Vec<u8>| {
+            let mut writer = WriterBuilder::new().with_compression_type(CompressionType::Snappy).memory();
+            for i in 0..1000u32 {
+                writer.insert(i.to_be_bytes(), val(i)).unwrap();
+            }
+            let (_, metadata) = writer.into_inner_with_metadata().unwrap();
+            assert!(metadata.bytes_raw_data_blocks > 100_000);
+            metadata.compression_ratio().unwrap()
+        };
+
+        let compressible = ratio(&|_| vec![b'a'; 128]);
+        assert!(compressible < 0.2, "{}", compressible);
+
+        // xorshift, incompressible bytes
+        let incompressible = ratio(&|i| {
+            let mut x = i as u64 + 1;
+            (0..128).map(|_| { x ^= x << 13; x ^= x >> 7; x ^= x << 17; x as u8 }).collect()
+        });
+        assert!(incompressible > 0.9 && incompressible < 1.1, "{}", incompressible);
+
+        assert_eq!(Metadata::default().compression_ratio(), None);
+    }
+
+    #[test]
+    fn debug() {
+        let mut writer = WriterBuilder::new().with_compression_type(CompressionType::Zlib).memory();
+        for i in 0..42u32 {
+            writer.insert(i.to_be_bytes(), "value").unwrap();
+        }
+        let debug = format!("{:?}", writer);
+        assert!(debug.contains("count_entries: 42"), "{}", debug);
+        assert!(debug.contains("Zlib"), "{}", debug);
+    }
+
+    #[test]
+    fn format_v1() {
+        use crate::{ReaderBuilder, MAGIC_V1};
+
+        for &compression in &[CompressionType::None, CompressionType::Zlib] {
+            let vec = numbers_table(WriterBuilder::new()
+                .file_version(FileVersion::FormatV1)
+                .compression_type(compression)
+                .block_size(256));
+
+            // the magic number of the V1 format ends the file
+            assert_eq!(LittleEndian::read_u32(&vec[vec.len() - 4..]), MAGIC_V1);
+
+            // the first block starts with its fixed 32-bit length followed by the checksum
+            let block_len = LittleEndian::read_u32(&vec) as usize;
+            let crc = LittleEndian::read_u32(&vec[4..]);
+            assert_eq!(checksum(ChecksumType::Crc32c, &vec[8..8 + block_len]), Some(crc));
+
+            let reader = ReaderBuilder::new().read(vec.as_slice()).unwrap();
+            assert_eq!(reader.metadata().file_version, FileVersion::FormatV1);
+            assert!(reader.metadata().count_data_blocks > 1);
+
+            for i in (0..1000u32).step_by(7) {
+                let val = reader.clone().get(&i.to_be_bytes()).unwrap().unwrap();
+                assert_eq!(val.as_ref(), i.to_string().as_bytes());
+            }
+
+            let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+            assert_eq!(entries.len(), 1000);
+            assert!(entries.iter().enumerate().all(|(i, (k, _))| *k == (i as u32).to_be_bytes()));
+        }
+    }
+
+    #[test]
+    fn on_duplicate() {
+        let concat = |_key: &[u8], old: &[u8], new: &[u8]| [old, new].concat();
+        let mut writer = WriterBuilder::new().with_block_size(64).with_on_duplicate(concat).memory();
+        writer.insert("a", "1").unwrap();
+        writer.insert("a", "2").unwrap();
+        for i in 0..100u32 {
+            let key = format!("b{:03}", i);
+            for j in 0..3 {
+                writer.insert(&key, j.to_string()).unwrap();
+            }
+        }
+        writer.insert("c", "alone").unwrap();
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().count_entries, 102);
+        let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries[0], (b"a".to_vec(), b"12".to_vec()));
+        assert_eq!(entries[1], (b"b000".to_vec(), b"012".to_vec()));
+        assert_eq!(entries[100], (b"b099".to_vec(), b"012".to_vec()));
+        assert_eq!(entries[101], (b"c".to_vec(), b"alone".to_vec()));
+    }
+
+    #[test]
+    fn on_duplicate_after_flush_block() {
+        let concat = |_key: &[u8], old: &[u8], new: &[u8]| [old, new].concat();
+        let mut writer = WriterBuilder::new().with_on_duplicate(concat).memory();
+        writer.insert("a", "1").unwrap();
+        writer.flush_block().unwrap();
+        let err = writer.insert("a", "2").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // the writer is still usable
+        writer.insert("b", "3").unwrap();
+        writer.insert("b", "4").unwrap();
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"34".to_vec())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out-of-order key")]
+    fn on_duplicate_out_of_order() {
+        let mut writer = WriterBuilder::new().with_on_duplicate(|_, _, new| new.to_vec()).memory();
+        writer.insert("b", "1").unwrap();
+        writer.insert("a", "2").unwrap();
+    }
+
+    #[test]
+    fn oversized_entries() {
+        let block_entries = |reader: &Reader<Vec<u8>>| {
+            (0..reader.block_count()).map(|n| {
+                let mut scan = reader.iter_block(n).unwrap();
+                let mut keys = Vec::new();
+                while let Some(result) = scan.next() {
+                    let (key, _) = result.unwrap();
+                    keys.push(key.to_vec());
+                }
+                keys
+            }).collect::<Vec<_>>()
+        };
+
+        for &(cap, big) in &[(None, 10_000), (Some(1024), 2000)] {
+            let mut builder = WriterBuilder::new();
+            builder.block_size(4096);
+            if let Some(cap) = cap {
+                builder.max_block_size_hard_cap(cap);
+            }
+            let mut writer = builder.memory();
+            for i in 0..300u32 {
+                let val = if i == 150 { vec![b'x'; big] } else { i.to_string().into_bytes() };
+                writer.insert(i.to_be_bytes(), val).unwrap();
+            }
+            let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+            let blocks = block_entries(&reader);
+            let big_block = blocks.iter().position(|keys| keys.contains(&150u32.to_be_bytes().to_vec())).unwrap();
+            assert_eq!(blocks[big_block].len(), 1);
+            assert!(blocks[big_block - 1].len() > 1 && blocks[big_block + 1].len() > 1);
+            assert_eq!(IntoIterator::into_iter(&reader).count(), 300);
+            assert!(reader.contains_key(&150u32.to_be_bytes()).unwrap());
+            assert!(reader.contains_key(&151u32.to_be_bytes()).unwrap());
+        }
+
+        // the cap cannot be higher than the block size
+        let mut writer = WriterBuilder::new().with_block_size(256).with_max_block_size_hard_cap(1 << 20).memory();
+        writer.insert("a", "").unwrap();
+        writer.insert("b", vec![0; 300]).unwrap();
+        writer.insert("c", "").unwrap();
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(block_entries(&reader).iter().map(Vec::len).collect::<Vec<_>>(), [1, 1, 1]);
+    }
+
+    #[test]
+    fn btreemap() {
+        let mut map = BTreeMap::new();
+        for i in 0..2000u32 {
+            map.insert(format!("key-{:05}", i), i.to_string());
+        }
+
+        let bytes = write_btreemap(&map, WriterBuilder::new().with_block_size(256)).unwrap();
+        let reader = Reader::new(bytes).unwrap();
+        assert!(reader.metadata().count_data_blocks > 1);
+        assert_eq!(reader.metadata().count_entries, 2000);
+
+        let copy = reader.to_btreemap().unwrap();
+        let expected: BTreeMap<_, _> = map.into_iter().map(|(k, v)| (k.into_bytes(), v.into_bytes())).collect();
+        assert_eq!(copy, expected);
+
+        // and back to a file
+        let bytes = write_btreemap(&copy, WriterBuilder::new()).unwrap();
+        assert_eq!(Reader::new(bytes).unwrap().to_btreemap().unwrap(), expected);
+
+        let empty = write_btreemap(&BTreeMap::<Vec<u8>, Vec<u8>>::new(), WriterBuilder::new()).unwrap();
+        assert!(Reader::new(empty).unwrap().to_btreemap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn flush_block() {
+        let mut writer = WriterBuilder::new().memory();
+        // an empty block is not written
+        writer.flush_block().unwrap();
+
+        // a group of entries per day
+        for day in 0..10u32 {
+            for entry in 0..(day + 1) * 3 {
+                writer.insert([day.to_be_bytes(), entry.to_be_bytes()].concat(), "value").unwrap();
+            }
+            writer.flush_block().unwrap();
+            writer.flush_block().unwrap();
+        }
+
+        let (bytes, metadata) = writer.into_inner_with_metadata().unwrap();
+        assert_eq!(metadata.count_data_blocks, 10);
+
+        let reader = Reader::new(bytes).unwrap();
+        for day in 0..10u32 {
+            let mut scan = reader.iter_block(day as u64).unwrap();
+            let mut count = 0;
+            while let Some(result) = scan.next() {
+                let (key, _) = result.unwrap();
+                assert_eq!(key[..4], day.to_be_bytes());
+                count += 1;
+            }
+            assert_eq!(count, (day + 1) * 3);
+        }
+
+        // the entries of the reorder window are part of the block
+        let mut writer = WriterBuilder::new().with_reorder_window(4).memory();
+        writer.insert("b", "").unwrap();
+        writer.insert("a", "").unwrap();
+        writer.flush_block().unwrap();
+        writer.insert("c", "").unwrap();
+        let (_, metadata) = writer.into_inner_with_metadata().unwrap();
+        assert_eq!((metadata.count_data_blocks, metadata.count_entries), (2, 3));
+    }
+
+    #[test]
+    fn copy_range_from() {
+        let key = |i: u32| i.to_be_bytes();
+        let table = |range: std::ops::Range<u32>| {
+            let mut writer = WriterBuilder::new().block_size(128).memory();
+            for i in range {
+                writer.insert(key(i), i.to_string()).unwrap();
+            }
+            Reader::new(writer.into_inner().unwrap()).unwrap()
+        };
+        let first = table(0..1000);
+        let second = table(1000..3000);
+        let raw_blocks: Vec<_> = (0..second.block_count())
+            .map(|n| second.raw_block_at(n as usize).unwrap().1.to_vec())
+            .collect();
+
+        let expected: Vec<_> = (0..10).chain(250..750).chain(1500..2500).collect();
+        let builders = vec![
+            WriterBuilder::new().with_block_size(128),
+            // the blocks cannot be appended as is to a file of another encoding
+            WriterBuilder::new().with_block_size(128).with_dedup_repeated_values(true),
+        ];
+
+        for mut builder in builders {
+            let mut writer = builder.memory();
+            writer.insert(key(0), "0").unwrap();
+            assert_eq!(writer.copy_range_from(&first, &key(1), &key(10)).unwrap(), 9);
+            assert_eq!(writer.copy_range_from(&first, &key(250), &key(750)).unwrap(), 500);
+            assert_eq!(writer.copy_range_from(&second, &key(1500), &key(2500)).unwrap(), 1000);
+            // the empty ranges
+            assert_eq!(writer.copy_range_from(&second, &key(2500), &key(2500)).unwrap(), 0);
+            assert_eq!(writer.copy_range_from(&second, &key(5000), &key(6000)).unwrap(), 0);
+            let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+            let mut iter = reader.clone().into_iter().unwrap();
+            for &i in &expected {
+                let (k, v) = iter.next().unwrap().unwrap();
+                assert_eq!((k, v), (&key(i)[..], i.to_string().as_bytes()));
+            }
+            assert!(iter.next().is_none());
+            assert_eq!(reader.metadata().count_entries, expected.len() as u64);
+
+            let copied = (0..reader.block_count())
+                .filter(|&n| raw_blocks.iter().any(|raw| raw == reader.raw_block_at(n as usize).unwrap().1))
+                .count();
+            if reader.metadata().file_version.repeated_values() {
+                assert_eq!(copied, 0);
+            } else {
+                assert!(copied > 10, "{} blocks copied as is", copied);
+            }
+        }
+    }
+
+    #[test]
+    fn copy_range_from_limits() {
+        let key = |i: u32| i.to_be_bytes();
+        let source = numbers_reader(128);
+
+        let mut writer = WriterBuilder::new().with_block_size(128).with_max_value_size(2).memory();
+        let err = writer.copy_range_from(&source, &key(0), &key(1000)).unwrap_err();
+        assert!(matches!(err, Error::Mtbl(MtblError::ValueTooLarge)), "{:?}", err);
+
+        let mut writer = WriterBuilder::new().with_block_size(128).with_max_key_size(2).memory();
+        let (compression, raw) = source.raw_block_at(0).unwrap();
+        let mut scan = source.iter_block(0).unwrap();
+        let mut last = Vec::new();
+        while let Some(result) = scan.next() {
+            last = result.unwrap().0.to_vec();
+        }
+        let err = writer.append_raw_block(raw, compression, &key(0), &last).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(err.get_ref().and_then(|e| e.downcast_ref()), Some(MtblError::KeyTooLarge)));
+        assert_eq!(writer.into_inner().unwrap(), WriterBuilder::new().with_block_size(128).memory().into_inner().unwrap());
+    }
+
+    #[test]
+    fn copy_range_from_on_duplicate() {
+        let key = |i: u32| i.to_be_bytes();
+        let source = numbers_reader(128);
+        // the range starts with a block, it could be appended as is
+        let mut scan = source.iter_block(3).unwrap();
+        let (start, _) = scan.next().unwrap().unwrap();
+        let start = u32::from_be_bytes(<[u8; 4]>::try_from(start).unwrap());
+
+        let concat = |_key: &[u8], old: &[u8], new: &[u8]| [old, new].concat();
+        let mut writer = WriterBuilder::new().with_block_size(128).with_on_duplicate(concat).memory();
+        writer.insert(key(start), "x").unwrap();
+        assert_eq!(writer.copy_range_from(&source, &key(start), &key(1000)).unwrap(), (1000 - start) as usize);
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+        let mut iter = reader.into_iter().unwrap();
+        let (k, v) = iter.next().unwrap().unwrap();
+        assert_eq!((k, v), (&key(start)[..], format!("x{}", start).as_bytes()));
+        for i in start + 1..1000 {
+            let (k, v) = iter.next().unwrap().unwrap();
+            assert_eq!((k, v), (&key(i)[..], i.to_string().as_bytes()));
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "snappy")]
+    fn append_raw_block() {
+        let key = |i: u32| i.to_be_bytes();
+
+        let mut writer = WriterBuilder::new().block_size(128).compression_type(CompressionType::Snappy).memory();
+        for i in 100..2000u32 {
+            writer.insert(key(i), i.to_string()).unwrap();
+        }
+        let source = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert!(source.block_count() > 10);
+
+        let mut blocks = Vec::new();
+        for n in 0..source.block_count() {
+            let (compression, raw) = source.raw_block_at(n as usize).unwrap();
+            let mut scan = source.iter_block(n).unwrap();
+            let (first, _) = scan.next().unwrap().unwrap();
+            let first = first.to_vec();
+            let mut last = first.clone();
+            while let Some(result) = scan.next() {
+                let (key, _) = result.unwrap();
+                last = key.to_vec();
+            }
+            blocks.push((raw.to_vec(), compression, first, last));
+        }
+
+        // the copied blocks between inserted entries
+        let mut builder = WriterBuilder::new();
+        builder.block_size(128).compression_type(CompressionType::Snappy);
+        let mut writer = builder.memory();
+        let mut expected = builder.memory();
+        for i in 0..100u32 {
+            writer.insert(key(i), i.to_string()).unwrap();
+        }
+        for (raw, compression, first, last) in &blocks {
+            writer.append_raw_block(raw, *compression, first, last).unwrap();
+        }
+        for i in 2000..2100u32 {
+            writer.insert(key(i), i.to_string()).unwrap();
+        }
+        for i in 0..2100u32 {
+            expected.insert(key(i), i.to_string()).unwrap();
+        }
+
+        let (bytes, metadata) = writer.into_inner_with_metadata().unwrap();
+        let (expected_bytes, expected_metadata) = expected.into_inner_with_metadata().unwrap();
+        assert_eq!(metadata.count_entries, expected_metadata.count_entries);
+        assert_eq!(metadata.bytes_keys, expected_metadata.bytes_keys);
+        assert_eq!(metadata.bytes_values, expected_metadata.bytes_values);
+
+        let reader = Reader::new(bytes).unwrap();
+        let expected = Reader::new(expected_bytes).unwrap();
+        let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        let expected_entries: Vec<_> = IntoIterator::into_iter(&expected).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, expected_entries);
+        for i in (0..2100u32).step_by(7) {
+            assert_eq!(reader.get_owned(&key(i)).unwrap(), Some(i.to_string().into_bytes()));
+        }
+
+        let (raw, compression, first, last) = &blocks[1];
+        let mut writer = WriterBuilder::new().with_compression_type(CompressionType::Snappy).memory();
+        writer.insert(key(5000), "").unwrap();
+        let err = writer.append_raw_block(raw, *compression, first, last).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut writer = WriterBuilder::new().memory();
+        let err = writer.append_raw_block(raw, *compression, first, last).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut writer = WriterBuilder::new().with_compression_type(CompressionType::Snappy).memory();
+        let err = writer.append_raw_block(raw, *compression, &key(0), last).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = writer.append_raw_block(raw, *compression, first, &key(0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn channel() {
+        use std::sync::mpsc::sync_channel;
+        use std::thread;
+
+        fn write<W: io::Write>(writer: &mut Writer<W>) {
+            for i in 0..1000u32 {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+        }
+
+        let mut expected = WriterBuilder::new().with_block_size(512).memory();
+        write(&mut expected);
+        let expected = expected.into_inner().unwrap();
+
+        let (sender, receiver) = sync_channel(1);
+        let consumer = thread::spawn(move || receiver.into_iter().collect::<Vec<Vec<u8>>>());
+        let mut writer = WriterBuilder::new().with_block_size(512).channel(sender);
+        write(&mut writer);
+        drop(writer.into_inner().unwrap());
+        let messages = consumer.join().unwrap();
+
+        let reader = Reader::new(messages.concat()).unwrap();
+        assert_eq!(reader.as_bytes(), expected.as_slice());
+        // the data blocks, the index block then the metadata
+        assert_eq!(messages.len() as u64, reader.metadata().count_data_blocks + 2);
+        assert_eq!(messages.last().unwrap().len(), METADATA_SIZE);
+        assert_eq!(IntoIterator::into_iter(&reader).count(), 1000);
+
+        // the receiver is gone
+        let (sender, receiver) = sync_channel(1);
+        drop(receiver);
+        let mut writer = WriterBuilder::new().channel(sender);
+        writer.insert("hello", "world").unwrap();
+        assert_eq!(writer.finish().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn reorder_window() {
+        // every pair of keys is swapped
+        let keys: Vec<u32> = (0..1000).map(|i| i ^ 1).collect();
+        let mut writer = WriterBuilder::new().with_reorder_window(1).with_block_size(256).memory();
+        for key in &keys {
+            writer.insert(key.to_be_bytes(), key.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+        let expected: Vec<_> = (0..1000u32).map(|i| (i.to_be_bytes().to_vec(), i.to_string().into_bytes())).collect();
+        assert_eq!(entries, expected);
+
+        // the key 3 arrives three positions late
+        let mut writer = WriterBuilder::new().with_reorder_window(2).memory();
+        for key in &[0u32, 1, 2, 4, 5, 6] {
+            writer.insert(key.to_be_bytes(), "").unwrap();
+        }
+        let err = writer.insert(3u32.to_be_bytes(), "").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // but only two positions late with a bigger window
+        let mut writer = WriterBuilder::new().with_reorder_window(3).memory();
+        for key in &[0u32, 1, 2, 4, 5, 6, 3, 7] {
+            writer.insert(key.to_be_bytes(), "").unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        let keys: Vec<_> = IntoIterator::into_iter(&reader).map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, (0..8u32).map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn target_block_count() {
+        for &(count, target) in &[(10_000u32, 10), (10_000, 7), (1000, 64), (5, 10)] {
+            let mut writer = WriterBuilder::new()
+                .with_block_size(1 << 20)
+                .with_expected_entries(count as u64)
+                .with_target_block_count(target)
+                .memory();
+            for i in 0..count {
+                writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+            }
+            let vec = writer.into_inner().unwrap();
+
+            let reader = Reader::new(vec.as_slice()).unwrap();
+            let blocks = reader.metadata().count_data_blocks;
+            let expected = cmp::min(target as u64, count as u64);
+            assert!(blocks <= expected && blocks + 1 >= expected, "{} blocks, expected {}", blocks, expected);
+            assert_eq!(IntoIterator::into_iter(&reader).count(), count as usize);
+        }
+
+        // the block size is reached before the entry budget
+        let mut writer = WriterBuilder::new()
+            .with_block_size(1024)
+            .with_expected_entries(10_000)
+            .with_target_block_count(2)
+            .memory();
+        for i in 0..10_000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert!(reader.metadata().count_data_blocks > 2);
+    }
+
+    #[test]
+    fn no_prefix_compression() {
+        use crate::varint::{varint_decode32, varint_decode64};
+
+        let mut writer = WriterBuilder::new().disable_prefix_compression().memory();
+        for i in 0..100u32 {
+            writer.insert(format!("a-shared-prefix-{:04}", i), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.metadata().count_data_blocks, 1);
+
+        let mut iter = reader.into_iter().unwrap();
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, format!("a-shared-prefix-{:04}", count).as_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 100);
+
+        // the data block is the first one: [length][crc][entries][restarts][restarts count]
+        let mut len = 0;
+        let mut offset = varint_decode64(&vec, &mut len) + 4;
+        let block = &vec[offset..offset + len as usize];
+        let nrestarts = byteorder::LittleEndian::read_u32(&block[block.len() - 4..]) as usize;
+        let entries_end = block.len() - 4 - nrestarts * 4;
+
+        offset = 0;
+        let mut entries = 0;
+        while offset < entries_end {
+            let (mut shared, mut non_shared, mut val_len) = (0, 0, 0);
+            offset += varint_decode32(&block[offset..], &mut shared);
+            offset += varint_decode32(&block[offset..], &mut non_shared);
+            offset += varint_decode32(&block[offset..], &mut val_len);
+            assert_eq!(shared, 0);
+            offset += (non_shared + val_len) as usize;
+            entries += 1;
+        }
+        assert_eq!(entries, 100);
+    }
+
+    #[test]
+    fn buffered() {
+        let mut writer = WriterBuilder::new().buffer_capacity(4096).buffered(io::Cursor::new(Vec::new()));
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+
+        let vec = writer.into_unbuffered().unwrap().into_inner();
+        let reader = Reader::new(&vec).unwrap();
+        assert_eq!(reader.metadata().count_entries, 1000);
+
+        let mut count = 0u32;
+        let mut iter = reader.into_iter().unwrap();
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, count.to_string().as_bytes());
+            count += 1;
+        }
+
+        assert_eq!(count, 1000);
+    }
+
+    #[test]
+    fn max_key_value_size() {
+        let error = |result: io::Result<()>| {
+            let err = result.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            Error::<()>::from(err)
+        };
+
+        let mut writer = WriterBuilder::new().with_max_key_size(8).with_max_value_size(16).memory();
+        writer.insert("12345678", "value").unwrap();
+        let err = error(writer.insert("123456789", "value"));
+        assert!(matches!(err, Error::Mtbl(MtblError::KeyTooLarge)), "{:?}", err);
+        let err = error(writer.insert("9", [0; 17]));
+        assert!(matches!(err, Error::Mtbl(MtblError::ValueTooLarge)), "{:?}", err);
+        writer.insert("9", [0; 16]).unwrap();
+
+        // the refused entries are not written
+        let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(reader.entry_count(), 2);
+        assert_eq!(reader.get_owned(b"9").unwrap().unwrap(), [0; 16]);
+
+        let mut writer = WriterBuilder::new().memory();
+        writer.insert(vec![0; 100_000], vec![0; 100_000]).unwrap();
+    }
+
+    #[test]
+    fn finish_synced() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut writer = WriterBuilder::new().block_size(1024).build(tempfile::tempfile().unwrap());
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let mut file = writer.into_inner_synced().unwrap();
+
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut bytes).unwrap();
+        let reader = Reader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.metadata().count_entries, 1000);
+        assert_eq!(reader.get_owned(&999u32.to_be_bytes()).unwrap().unwrap(), b"999");
+
+        // the buffer is flushed before the file is synced
+        let mut file = tempfile::tempfile().unwrap();
+        let mut writer = WriterBuilder::new().buffered(&mut file);
+        writer.insert("hello", "world").unwrap();
+        writer.finish_synced().unwrap();
+
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut bytes).unwrap();
+        let reader = Reader::new(bytes).unwrap();
+        assert_eq!(reader.get_owned(b"hello").unwrap().unwrap(), b"world");
+
+        // the writers in memory are only flushed
+        let mut writer = Writer::memory();
+        writer.insert("hello", "world").unwrap();
+        Reader::new(writer.into_inner_synced().unwrap()).unwrap();
+
+        let mut writer = WriterBuilder::new().build(Box::new(Vec::new()) as Box<dyn SyncWrite>);
+        writer.insert("hello", "world").unwrap();
+        writer.finish_synced().unwrap();
+    }
+
+    #[test]
+    fn reset() {
+        let mut writer = WriterBuilder::new().block_size(1024).memory();
+        for i in 0..1000u32 {
+            writer.insert(i.to_be_bytes(), i.to_string()).unwrap();
+        }
+        let first = writer.finish_and_reset(Vec::new()).unwrap();
+
+        // discarded by the reset
+        writer.insert("garbage", "").unwrap();
+        writer.reset(Vec::new()).unwrap();
+
+        for i in 500..700u32 {
+            writer.insert(i.to_be_bytes(), "second").unwrap();
+        }
+        let second = writer.into_inner().unwrap();
+
+        let read = |vec: &[u8]| {
+            let reader = Reader::new(vec).unwrap();
+            let mut iter = reader.into_iter().unwrap();
+            let mut entries = Vec::new();
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                entries.push((key.to_vec(), val.to_vec()));
+            }
+            (Reader::new(vec).unwrap().metadata().count_entries, entries)
+        };
+
+        let (count, entries) = read(&first);
+        let expected: Vec<_> = (0..1000u32).map(|i| (i.to_be_bytes().to_vec(), i.to_string().into_bytes())).collect();
+        assert_eq!(count, 1000);
+        assert_eq!(entries, expected);
+
+        let (count, entries) = read(&second);
+        let expected: Vec<_> = (500..700u32).map(|i| (i.to_be_bytes().to_vec(), b"second".to_vec())).collect();
+        assert_eq!(count, 200);
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_long_distance() {
+        // 3MB of noise, repeated further away than the default window of zstd
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let noise: Vec<u8> = (0..3 * 1024 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        let vals: Vec<_> = noise.chunks(1024).chain(noise.chunks(1024)).collect();
+
+        let write = |long_distance: bool, workers: u32| {
+            let mut writer = WriterBuilder::new()
+                .with_compression_type(CompressionType::Zstd)
+                .with_block_size(16 * 1024 * 1024)
+                .with_zstd_long_distance(long_distance)
+                .with_zstd_workers(workers)
+                .memory();
+            for (i, val) in vals.iter().enumerate() {
+                writer.insert((i as u32).to_be_bytes(), val).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let plain = write(false, 0);
+        let long_distance = write(true, 0);
+        let threaded = write(true, 2);
+        assert!(long_distance.len() < plain.len() * 2 / 3, "{} {}", long_distance.len(), plain.len());
+
+        for bytes in &[plain, long_distance, threaded] {
+            let reader = Reader::new(bytes.as_slice()).unwrap();
+            let mut iter = reader.into_iter().unwrap();
+            let mut count = 0;
+            while let Some(result) = iter.next() {
+                let (key, val) = result.unwrap();
+                assert_eq!(key, (count as u32).to_be_bytes());
+                assert_eq!(val, vals[count]);
+                count += 1;
+            }
+            assert_eq!(count, vals.len());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zstd_dict")]
+    fn zstd_dictionary() {
+        use crate::{train_dictionary, ReaderBuilder};
+
+        let record = |i: usize| format!(r#"{{"id":{},"name":"user-{}","email":"user-{}@example.com","active":true}}"#, i, i, i);
+        let samples: Vec<_> = (0..2000).map(|i| record(i * 7)).collect();
+        let samples: Vec<_> = samples.iter().map(|s| s.as_bytes()).collect();
+        let dictionary = train_dictionary(&samples, 4096).unwrap();
+
+        let write = |dictionary: Option<&Vec<u8>>| {
+            let mut builder = WriterBuilder::new();
+            builder.compression_type(CompressionType::Zstd).block_size(1024);
+            if let Some(dictionary) = dictionary {
+                builder.compression_dictionary(dictionary.clone());
+            }
+            let mut writer = builder.memory();
+            for i in 0..500 {
+                writer.insert(format!("{:010}", i), record(i)).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let with_dict = write(Some(&dictionary));
+        let without_dict = write(None);
+        assert!(with_dict.len() < without_dict.len());
+
+        assert!(Reader::new(with_dict.as_slice()).is_err());
+
+        let reader = ReaderBuilder::new()
+            .compression_dictionary(dictionary)
+            .read(with_dict.as_slice())
+            .unwrap();
+        let mut iter = reader.into_iter().unwrap();
+        let mut count = 0;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, format!("{:010}", count).as_bytes());
+            assert_eq!(val, record(count).as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 500);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd_dict")]
+    fn zstd_raw_dictionary() {
+        use crate::ReaderBuilder;
+
+        let dictionary = b"raw content dictionary".to_vec();
+        let mut writer = WriterBuilder::new()
+            .with_compression_type(CompressionType::Zstd)
+            .with_compression_dictionary(dictionary.clone())
+            .memory();
+        writer.insert("hello", "raw content dictionary").unwrap();
+        let bytes = writer.into_inner().unwrap();
+
+        // identified by a hash of its content
+        let read = |dictionary: &[u8]| {
+            ReaderBuilder::new().compression_dictionary(dictionary.to_vec()).read(bytes.as_slice())
+        };
+        assert!(matches!(read(b"another dictionary"), Err(Error::Mtbl(MtblError::InvalidCompressionDictionary))));
+        let reader = read(&dictionary).unwrap();
+        assert_ne!(reader.metadata().compression_dictionary_id, 0);
+        assert_eq!(reader.get(b"hello").unwrap().unwrap().as_ref(), b"raw content dictionary");
+    }
+
     #[test]
     fn bytes_shortest_separator_to_short() {
         let mut start = vec![49, 115, 116];
         let limit = &[50];
         bytes_shortest_separator(&mut start, limit);
     }
+
+    #[test]
+    fn bytes_shortest_separator_exhaustive() {
+        fn keys(prefix: Vec<u8>, out: &mut Vec<Vec<u8>>) {
+            out.push(prefix.clone());
+            if prefix.len() < 4 {
+                for &b in &[0, 1, 2, 127, 254, 255] {
+                    let mut key = prefix.clone();
+                    key.push(b);
+                    keys(key, out);
+                }
+            }
+        }
+
+        let mut all = Vec::new();
+        keys(Vec::new(), &mut all);
+        all.sort();
+
+        for (i, start) in all.iter().enumerate() {
+            for limit in &all[i + 1..] {
+                let mut sep = start.clone();
+                bytes_shortest_separator(&mut sep, limit);
+                assert!(start <= &sep && &sep < limit, "{:?} <= {:?} < {:?}", start, sep, limit);
+                assert!(sep.len() <= start.len().max(limit.len()));
+            }
+        }
+
+        // the two bytes big endian increment
+        let mut start = vec![0, 1, 255, 9];
+        bytes_shortest_separator(&mut start, &[0, 2, 0, 1]);
+        assert_eq!(start, [0, 2, 0]);
+
+        // unordered inputs are left untouched
+        let mut start = vec![5, 6];
+        bytes_shortest_separator(&mut start, &[5, 5]);
+        assert_eq!(start, [5, 6]);
+    }
+
+    #[test]
+    fn shortest_separator() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_cloned = calls.clone();
+
+        // the last keys of the blocks are kept as is in the index
+        let mut writer = WriterBuilder::new()
+            .with_block_size(64)
+            .with_shortest_separator(move |_start, _limit| {
+                calls_cloned.fetch_add(1, Ordering::SeqCst);
+            })
+            .memory();
+        for i in 0..1000u32 {
+            writer.insert(format!("{:05}", i), i.to_string()).unwrap();
+        }
+        let vec = writer.into_inner().unwrap();
+
+        let reader = Reader::new(&vec).unwrap();
+        let count_data_blocks = reader.metadata().count_data_blocks as usize;
+        assert_eq!(calls.load(Ordering::SeqCst), count_data_blocks - 1);
+
+        for i in 0..1000u32 {
+            let key = format!("{:05}", i);
+            assert_eq!(reader.get_owned(key.as_bytes()).unwrap(), Some(i.to_string().into_bytes()));
+        }
+        assert!(reader.get(b"1").unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "the separator must be between")]
+    fn invalid_shortest_separator() {
+        let mut writer = WriterBuilder::new()
+            .with_block_size(64)
+            .with_shortest_separator(|start, _limit| start.truncate(1))
+            .memory();
+        for i in 0..1000u32 {
+            writer.insert(format!("{:05}", i), i.to_string()).unwrap();
+        }
+    }
+
+    #[test]
+    fn dedup_repeated_values() {
+        // runs of ten identical values, crossing the restart points
+        let value = |i: u32| format!("value-{:04}", i / 10).repeat(10);
+        let write = |dedup| {
+            let mut writer = WriterBuilder::new()
+                .with_block_size(1024)
+                .with_block_restart_interval(4)
+                .with_dedup_repeated_values(dedup)
+                .memory();
+            for i in 0..5000u32 {
+                writer.insert(i.to_be_bytes(), value(i)).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let plain = write(false);
+        let dedup = write(true);
+        assert!(dedup.len() * 2 < plain.len(), "{} vs {}", dedup.len(), plain.len());
+
+        let reader = Reader::new(dedup.as_slice()).unwrap();
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV3);
+        assert_eq!(reader.metadata().bytes_values, 5000 * 100);
+
+        let mut iter = reader.clone().into_iter().unwrap();
+        let mut count = 0u32;
+        while let Some(result) = iter.next() {
+            let (key, val) = result.unwrap();
+            assert_eq!(key, count.to_be_bytes());
+            assert_eq!(val, value(count).as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 5000);
+
+        // the lookups and the backward moves start from the restart points
+        for i in (0..5000u32).step_by(7) {
+            assert_eq!(reader.get_owned(&i.to_be_bytes()).unwrap(), Some(value(i).into_bytes()));
+        }
+        let mut cursor = reader.cursor();
+        assert!(cursor.seek_to_last().unwrap());
+        for i in (0..5000u32).rev() {
+            assert_eq!(cursor.current(), Some((&i.to_be_bytes()[..], value(i).as_bytes())));
+            assert_eq!(cursor.prev().unwrap(), i > 0);
+        }
+
+        // the values that are not repeated are stored as usual
+        let vec = numbers_table(WriterBuilder::new().dedup_repeated_values(true));
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        let entries: Vec<_> = (&reader).into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries.len(), 1000);
+        assert_eq!(entries[999], (999u32.to_be_bytes().to_vec(), b"999".to_vec()));
+
+        // the other options of the V3 format do not deduplicate the values
+        let write = |checksum_type| {
+            let mut writer = WriterBuilder::new()
+                .with_compression_type(CompressionType::None)
+                .with_checksum_type(checksum_type)
+                .memory();
+            for i in 0..5000u32 {
+                writer.insert(i.to_be_bytes(), value(i)).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+        let v2 = write(ChecksumType::Crc32c);
+        let v3 = write(ChecksumType::None);
+        let reader = Reader::new(v3.as_slice()).unwrap();
+        assert_eq!(reader.metadata().file_version, FileVersion::FormatV3);
+        assert_eq!(v3.len(), v2.len());
+        let entries: Vec<_> = (&reader).into_iter().collect::<Result<_, _>>().unwrap();
+        assert!(entries.iter().enumerate().all(|(i, (_, val))| val[..] == *value(i as u32).as_bytes()));
+    }
+
+    #[test]
+    fn insert_taking() {
+        let concat = |_key: &[u8], old: &[u8], new: &[u8]| [old, new].concat();
+        let build = |taking: bool| {
+            let mut writer = WriterBuilder::new()
+                .with_block_size(64)
+                .with_on_duplicate(concat)
+                .with_reorder_window(4)
+                .memory();
+            for i in (0..200u32).map(|i| (i / 3) ^ 1) {
+                let key = i.to_be_bytes();
+                let val = i.to_string().into_bytes();
+                if taking { writer.insert_taking(&key, val).unwrap() } else { writer.insert(key, val).unwrap() }
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let vec = build(true);
+        assert_eq!(vec, build(false));
+
+        let reader = Reader::new(vec.as_slice()).unwrap();
+        assert_eq!(reader.clone().get(&9u32.to_be_bytes()).unwrap().unwrap().as_ref(), b"999");
+    }
 }
@@ -0,0 +1,213 @@
+//! Counts the allocations of the sorter, the merger and the cursors, with a global
+//! allocator of its own to not slow down the other tests.
+
+#![cfg(feature = "std")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use oxidized_mtbl::{Merger, Reader, SorterBuilder, WriterBuilder};
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // the counter can already be destroyed when the thread exits
+        let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The number of allocations done by the current thread so far.
+fn allocations() -> usize {
+    ALLOCATIONS.with(|c| c.get())
+}
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+#[test]
+fn sorter_multi_spill() {
+    fn merge(_key: &[u8], vals: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+        Ok(vals.iter().max().unwrap().clone())
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut sorter = SorterBuilder::new(merge)
+        // the smallest memory budget
+        .with_max_memory(0)
+        .with_max_nb_chunks(2)
+        .build()
+        .with_named_tempfiles(dir.path(), "chunk-");
+
+    let mut expected = BTreeMap::new();
+    for i in 0..200_000u32 {
+        // every key is inserted twice
+        let key = (i % 100_000).wrapping_mul(2_654_435_761).to_be_bytes();
+        let val = format!("{:0100}", i);
+        sorter.insert(key, &val).unwrap();
+        let max = expected.entry(key.to_vec()).or_insert_with(String::new);
+        if *max < val { *max = val; }
+    }
+    // the chunks have been merged
+    assert_eq!(sorter.chunk_paths().len(), 1);
+
+    let before = allocations();
+    let mut writer = WriterBuilder::new().memory();
+    sorter.write_into(&mut writer).unwrap();
+    let allocated = allocations() - before;
+    // the values are not allocated one by one, only the merged ones
+    assert!(allocated < expected.len() / 2, "{} allocations", allocated);
+
+    let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+    let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+    let expected: Vec<_> = expected.into_iter().map(|(k, v)| (k, v.into_bytes())).collect();
+    assert_eq!(entries, expected);
+}
+
+#[test]
+fn merger_unique_keys() {
+    fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+        Ok(values.concat())
+    }
+
+    // the keys are spread over the sources, one in a thousand is found twice
+    let mut writers: Vec<_> = (0..4).map(|_| WriterBuilder::new().memory()).collect();
+    for i in 0..200_000u32 {
+        let val = format!("value-{}", i);
+        writers[i as usize % 4].insert(key(i), &val).unwrap();
+        if i % 1000 == 0 {
+            writers[(i as usize + 1) % 4].insert(key(i), &val).unwrap();
+        }
+    }
+
+    let mut builder = Merger::builder(concat);
+    for writer in writers {
+        builder.push(Reader::new(writer.into_inner().unwrap()).unwrap());
+    }
+    let merger = builder.build();
+
+    let mut writer = WriterBuilder::new().memory();
+    let before = allocations();
+    merger.write_into(&mut writer).unwrap();
+    let allocated = allocations() - before;
+    // the values are not allocated one by one
+    assert!(allocated < 200_000 / 50, "{} allocations", allocated);
+
+    let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+    assert_eq!(reader.metadata().count_entries, 200_000);
+    let val = reader.get_owned(&key(1000)).unwrap().unwrap();
+    assert_eq!(val, b"value-1000value-1000");
+}
+
+#[test]
+fn merger_taken_values() {
+    fn concat(_key: &[u8], values: &[Vec<u8>]) -> Result<Vec<u8>, ()> {
+        Ok(values.concat())
+    }
+
+    // every key is found in both sources, all the values are merged
+    let merger = || {
+        let mut builder = Merger::builder(concat);
+        for s in 0..2 {
+            let mut writer = WriterBuilder::new().memory();
+            for i in 0..10_000u32 {
+                writer.insert(key(i), format!("{:0100}", s)).unwrap();
+            }
+            builder.push(Reader::new(writer.into_inner().unwrap()).unwrap());
+        }
+        builder.build()
+    };
+    // the reorder window keeps the values until they are written
+    let window = || WriterBuilder::new().with_reorder_window(16).memory();
+
+    let mut writer = window();
+    let mut iter = merger().into_merge_iter().unwrap();
+    let before = allocations();
+    while let Some(result) = iter.next() {
+        let (key, val) = result.unwrap();
+        writer.insert(key, val).unwrap();
+    }
+    let copied = allocations() - before;
+    let expected = writer.into_inner().unwrap();
+
+    let mut writer = window();
+    let merger = merger();
+    let before = allocations();
+    merger.write_into(&mut writer).unwrap();
+    let taken = allocations() - before;
+    assert_eq!(writer.into_inner().unwrap(), expected);
+
+    // the merged values are moved into the window instead of being copied
+    assert!(taken + 9_000 < copied, "{} allocations instead of {}", taken, copied);
+}
+
+#[test]
+fn cursor_get() {
+    const LOOKUPS: u32 = 1_000_000;
+
+    // only the even keys
+    let mut writer = WriterBuilder::new().with_block_size(128).memory();
+    for i in (0..20_000u32).step_by(2) {
+        writer.insert(key(i), format!("value-{}", i)).unwrap();
+    }
+    let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+
+    // the key buffers are reused, only the shared block of each lookup is allocated
+    let mut cursor = reader.cursor();
+    let before = allocations();
+    let mut found = 0;
+    for i in 0..LOOKUPS {
+        let i = i.wrapping_mul(7919) % 21_000;
+        let value = cursor.get(&key(i)).unwrap();
+        assert_eq!(value.is_some(), i % 2 == 0 && i < 20_000);
+        found += value.is_some() as u32;
+    }
+    let allocated = allocations() - before;
+
+    assert!(found > LOOKUPS / 3);
+    assert!(allocated <= LOOKUPS as usize, "{} allocations for {} lookups", allocated, LOOKUPS);
+
+    // the reader allocates the iterator and its buffers for every lookup
+    let before = allocations();
+    for i in 0..1000 {
+        reader.get_owned(&key(i * 2)).unwrap().unwrap();
+    }
+    assert!(allocations() - before >= 3 * 1000);
+}
+
+#[test]
+fn probe() {
+    // only the even keys
+    let mut writer = WriterBuilder::new().with_block_size(128).memory();
+    for i in (0..2000u32).step_by(2) {
+        writer.insert(key(i), i.to_string()).unwrap();
+    }
+    let reader = Reader::new(writer.into_inner().unwrap()).unwrap();
+    let blocks = reader.metadata().count_data_blocks as usize;
+    assert!(blocks > 10);
+
+    let keys: Vec<_> = (0..2100u32).map(key).collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+
+    // every block is read once, the index is searched when leaving a block
+    let mut found = Vec::with_capacity(1000);
+    let before = allocations();
+    found.extend(reader.probe(&keys).map(Result::unwrap));
+    let allocated = allocations() - before;
+    assert!(allocated <= blocks + 4, "{} allocations for {} blocks", allocated, blocks);
+    assert_eq!(found.len(), 1000);
+}
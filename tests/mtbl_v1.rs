@@ -0,0 +1,45 @@
+#![cfg(all(feature = "zlib", feature = "checksum"))]
+
+use oxidized_mtbl::{FileVersion, ReaderBuilder};
+
+/// A table laid out by the V1 writer of libmtbl: fixed 32-bit block lengths,
+/// zlib data blocks and shortened keys in the index, with a last key of `0xff` bytes.
+const FIXTURE: &[u8] = include_bytes!("fixtures/mtbl-v1.mtbl");
+
+fn expected() -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut entries: Vec<_> = (0..200)
+        .map(|i| (format!("key-{:03}", i).into_bytes(), format!("value-{}", i).into_bytes()))
+        .collect();
+    entries.push((b"key-\xff\xff".to_vec(), Vec::new()));
+    entries
+}
+
+#[test]
+fn metadata() {
+    let reader = ReaderBuilder::new().read(FIXTURE).unwrap();
+    let metadata = reader.metadata();
+    assert_eq!(metadata.file_version, FileVersion::FormatV1);
+    assert_eq!(metadata.count_entries, 201);
+    assert_eq!(metadata.count_data_blocks, 6);
+    assert_eq!(metadata.data_block_size, 512);
+}
+
+#[test]
+fn iterate() {
+    let reader = ReaderBuilder::new().read(FIXTURE).unwrap();
+    let entries: Vec<_> = IntoIterator::into_iter(&reader).collect::<Result<_, _>>().unwrap();
+    assert_eq!(entries, expected());
+}
+
+#[test]
+fn get() {
+    let reader = ReaderBuilder::new().read(FIXTURE).unwrap();
+    for (key, value) in expected() {
+        assert_eq!(reader.get_owned(&key).unwrap(), Some(value));
+    }
+
+    // between the keys and after the shortened separators of the index
+    assert_eq!(reader.get_owned(b"key-0005").unwrap(), None);
+    assert_eq!(reader.get_owned(b"key-\xff\xff\x00").unwrap(), None);
+    assert_eq!(reader.get_owned(b"key-").unwrap(), None);
+}